@@ -1,9 +1,26 @@
-/// Module that manages the database connection, queries and mutations.
+//! Module that manages the database connection, queries and mutations.
 
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use chrono::{Duration, NaiveDate};
 use sqlx::migrate::MigrateDatabase;
 use sqlx::{Sqlite, SqlitePool};
 use sqlx::sqlite::SqlitePoolOptions;
 use db_types::*;
+pub use db_types::{
+    DBApiToken, DBDream, DBHypnogramInterval, DBSleepSession, DBSleepStages, DBUser, DreamDetails, DreamFilter, DreamUpdate, HypnogramInterval,
+    DBTagAlias, DBTagCategory, DBTagHierarchy, MergePolicy, SessionKind, SleepStage, SleepStages, SleepTimes, SleepUpsert, UpsertedSleep
+};
+
+/// Number of connections in the connection pool when no pool size is given
+pub const DEFAULT_POOL_SIZE: u32 = 4;
+
+/// id of the user that owns the data of databases created before there were users,
+/// a newly opened manager reads and changes the data of this user
+pub const DEFAULT_USER_ID: i64 = 1;
 
 /// Struct to manage the connection pool to the sqlite database
 /// Also provides an interface to interact with the db with queries and mutations
@@ -11,6 +28,15 @@ use db_types::*;
 pub struct DBManager {
     /// sqlx connection pool to a sqlite database
     connection_pool: SqlitePool,
+
+    /// Number of queries sent to the database, shared between clones of the manager
+    query_count: Arc<AtomicUsize>,
+
+    /// id of the user whose data is read and changed, every query is limited to the rows of this user
+    user_id: i64,
+
+    /// Transaction the queries run inside of, None to commit each query on its own
+    unit_of_work: Option<UnitOfWork>,
 }
 
 /// An intermediate representation of a sleep struct
@@ -23,15 +49,32 @@ pub struct DbmSleep {
 }
 
 impl DBManager {
-    /// Returns a result containing either a DBManager or sqlx error
-    /// Checks if the provided database exists, and if it doesn't
-    /// it will create a new one and migrate to the current schema.
+    /// Returns a result containing either a DBManager or an error
+    /// Opens the database with a pool of [DEFAULT_POOL_SIZE] connections, see [init_with_pool_size](DBManager::init_with_pool_size)
+    /// 
+    /// # Arguments
+    ///
+    /// * `db_path` - A string slice that holds the path to the sqlite db file
+    ///
+    pub async fn init(db_path: &str) -> Result<DBManager, DBError> {
+        DBManager::init_with_pool_size(db_path, DEFAULT_POOL_SIZE).await
+    }
+
+    /// Returns a result containing either a DBManager or an error
+    /// Checks if the provided database exists, and if it doesn't it will create a new one.
+    /// The database is then migrated to the current schema, refusing to open
+    /// a database with a schema that is newer than this binary supports.
     /// 
     /// # Arguments
     ///
     /// * `db_path` - A string slice that holds the path to the sqlite db file
+    /// * `pool_size` - The maximum number of connections in the connection pool
     ///
-    pub async fn init(db_path: &str) -> Result<DBManager, sqlx::Error> {
+    pub async fn init_with_pool_size(db_path: &str, pool_size: u32) -> Result<DBManager, DBError> {
+        if pool_size == 0 {
+            return Err(DBError::Validation(String::from("the connection pool needs at least one connection")));
+        }
+
         // Checks if db exists, and creates one if it doesn't
         let db_doesnt_exist = !Sqlite::database_exists(db_path).await.unwrap_or(false);
         if db_doesnt_exist {
@@ -40,7 +83,7 @@ impl DBManager {
                 Ok(_) => println!("DB Created!"),
                 Err(e) => {
                     println!("Unable to create DB! {}", e);
-                    return Err(e);
+                    return Err(e.into());
                 }
             }
         }
@@ -49,441 +92,2089 @@ impl DBManager {
         }
 
         let connection_pool = SqlitePoolOptions::new()
-            .max_connections(4)
+            .max_connections(pool_size)
             .connect(db_path).await?;
 
-        let dbm = DBManager { connection_pool };
+        let dbm = DBManager { connection_pool, query_count: Arc::new(AtomicUsize::new(0)), user_id: DEFAULT_USER_ID, unit_of_work: None };
         println!("db opened with {} connections.", dbm.connection_pool.size());
 
-        // Migrate db to current schema if it did not exist
-        if db_doesnt_exist {
-            match db_migrations::initalize_db(&dbm.connection_pool).await {
-                Ok(_) => println!("DB Initalized!"),
-                Err(e) => {
-                    println!("Unable to initalize DB! {}", e);
-                    return Err(e);
-                }
+        // Migrate db to the current schema, this is a no-op if the db is already up to date
+        match db_migrations::migrate(&dbm.connection_pool).await {
+            Ok(version) => println!("DB at schema version {}", version),
+            Err(e) => {
+                println!("Unable to migrate DB! {}", e);
+                dbm.close_connection().await;
+                return Err(e);
             }
         }
 
         Ok(dbm)
     }
 
-    /// Closes all of the connections in the connection pool. Maybe unneccessary
-    /// sqlx might close connections on drop, but I have not confirmed that yet
-    pub async fn close_connection(&self) {
-        self.connection_pool.close().await;
-    }
-
-    /// Adds a night, amount and quality to the sleep table in the database.
-    /// Returns the pk of the newly added row.
+    /// Returns a manager that shares the connection pool of this manager, but only reads
+    /// and changes the data of the given user
     /// 
     /// # Arguments
     /// 
-    /// * `night` - A string slice representing the date. Should be in yyyy-mm-dd format
-    /// * `amount` - A float representing how much time was spent sleeping during the night
-    /// * `quality` - An int representing the quality of sleep on a night
-    /// 
-    /// # Examples
+    /// * `user_id` - the id of the user whose data the manager uses
     /// 
-    /// let pk = insert_sleep("2023-05-13", 7.5, 5).await;
-    pub async fn insert_sleep(&self, night: &str, amount: f64, quality: i64) -> i64 {
-        DBSleep::insert(&self.connection_pool, night, amount, quality).await.unwrap()
+    pub fn for_user(&self, user_id: i64) -> DBManager {
+        DBManager { user_id, ..self.clone() }
     }
 
-    /// Gets a sleep from the database with the given id.
-    /// Optionally includes all tags associated to the sleep.
-    /// Returns Some [DbmSleep](DbmSleep) if the sleep was queried successfully, otherwise None
+    /// Returns the id of the user whose data the manager reads and changes
+    pub fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    /// Returns the schema version of the database
+    pub async fn schema_version(&self) -> Result<i64, DBError> {
+        Ok(db_migrations::get_user_version(self.pool()).await?)
+    }
+
+    /// Writes a consistent copy of the live database to a new file with `VACUUM INTO`,
+    /// which can be done while other connections read and write the database.
+    /// Returns a [Validation](DBError::Validation) error if the file already exists.
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the pk of the sleep to query
-    /// * `include_tags` - boolean dictating if associated tags should also be queried
-    /// 
-    /// # Examples
+    /// * `path` - path of the file to write the copy to
     /// 
-    /// let sleep = get_sleep(1, false).await;
-    pub async fn get_sleep(&self, id: i64, include_tags: bool) -> Option<DbmSleep>  {
-        let result = DBSleep::select_one(&self.connection_pool, id).await;
-        
-        let db_sleep = 
-        match result {
-            Ok(s) => s,
-            Err(_) => return None
-        };
+    pub async fn snapshot_into(&self, path: &Path) -> Result<(), DBError> {
+        if path.exists() {
+            return Err(DBError::Validation(format!("snapshot {} already exists", path.display())));
+        }
 
-        let mut sleep = DbmSleep { sleep: db_sleep, tags: None };
+        sqlx::query("VACUUM INTO ?1")
+            .bind(path.to_string_lossy().as_ref())
+            .execute(self.pool())
+            .await?;
+        Ok(())
+    }
 
-        if include_tags {
-            let sleep_tags = DBSleepTags::select_by_sleep_id(&self.connection_pool, id).await;
+    /// Returns the number of queries this manager, and its clones, have sent to the database.
+    /// Useful to check how many queries a request needs.
+    pub fn query_count(&self) -> usize {
+        self.query_count.load(Ordering::Relaxed)
+    }
 
-            let tag_ids =
-            match sleep_tags {
-                Ok(st) => st.iter().map(|x| x.tag_id).collect(),
-                Err(_) => return None
-            };
+    /// Returns the connection pool to run a query on, counting the query.
+    /// Queries on the pool run outside of the unit of work of the manager.
+    fn pool(&self) -> &SqlitePool {
+        self.query_count.fetch_add(1, Ordering::Relaxed);
+        &self.connection_pool
+    }
 
-            let tags = self.get_multiple_tags(tag_ids).await;
-            sleep.tags = tags;
+    /// Returns the connection to run a query on, counting the query.
+    /// This is the connection of the unit of work of the manager, or a connection of the pool when there is none.
+    /// The connection should only be held for one query, the other queries of the unit of work wait until it is dropped.
+    async fn conn(&self) -> Result<DBConnection, DBError> {
+        self.query_count.fetch_add(1, Ordering::Relaxed);
+        match &self.unit_of_work {
+            Some(unit_of_work) => unit_of_work.connection().await,
+            None => Ok(DBConnection::Pooled(self.connection_pool.acquire().await?)),
         }
+    }
+
+    /// Starts a unit of work, returning a manager whose queries, and the queries of its clones,
+    /// run inside of one transaction until it is [committed](DBManager::commit) or [rolled back](DBManager::rollback).
+    /// The unit of work is rolled back if the manager and its clones are dropped without committing it,
+    /// so an operation made of many queries can return on the first error without leaving part of its changes behind.
+    /// Starting a unit of work on a manager that is already in one joins that unit of work instead,
+    /// committing the joined unit of work is left to the manager that started it.
+    /// Backups, restores and snapshots run outside of the unit of work.
+    /// 
+    /// # Examples
+    /// 
+    /// let uow = dbm.begin().await?;
+    /// let sleep_id = uow.insert_sleep(night, 7.5, 5).await?;
+    /// uow.add_tags_to_sleep(sleep_id, tag_ids).await?;
+    /// uow.commit().await?;
+    pub async fn begin(&self) -> Result<DBManager, DBError> {
+        let unit_of_work = match &self.unit_of_work {
+            Some(unit_of_work) => unit_of_work.join(),
+            None => UnitOfWork::begin(&self.connection_pool).await?,
+        };
+        Ok(DBManager { unit_of_work: Some(unit_of_work), ..self.clone() })
+    }
 
-        Some(sleep)
+    /// Commits the unit of work of the manager, see [begin](DBManager::begin).
+    /// Returns a [Validation](DBError::Validation) error if the manager is not in a unit of work,
+    /// or the unit of work was already committed or rolled back.
+    pub async fn commit(self) -> Result<(), DBError> {
+        match self.unit_of_work {
+            Some(unit_of_work) => unit_of_work.commit().await,
+            None => Err(DBError::Validation(String::from("the manager is not in a unit of work"))),
+        }
     }
 
-    /// Queries all sleeps in the database
-    /// Returns all of the [sleeps](DbmSleep) or None if there was an error.
-    pub async fn get_all_sleeps(&self) -> Option<Vec<DbmSleep>>  {
-        let result = DBSleep::select_all(&self.connection_pool).await;
-        
-        match result {
-            Ok(s) => Some(s.into_iter().map(|x| DbmSleep { sleep: x, tags: None }).collect()),
-            Err(_) => None
+    /// Rolls back the unit of work of the manager, undoing all of its changes, see [begin](DBManager::begin).
+    /// Returns a [Validation](DBError::Validation) error if the manager is not in a unit of work,
+    /// or the unit of work was already committed or rolled back.
+    pub async fn rollback(self) -> Result<(), DBError> {
+        match self.unit_of_work {
+            Some(unit_of_work) => unit_of_work.rollback().await,
+            None => Err(DBError::Validation(String::from("the manager is not in a unit of work"))),
         }
     }
 
-    /// Get sleeps with the given ids and returns an Optional Vector of [DbmSleeps](DbmSleep)
-    /// Note: Ideally a WHERE id IN clause would be used for this, however, that is not directly supported by
-    /// sqlx v0.6 so select all tags and filter them manually
-    /// TODO: Add tag support
+    /// Returns true if the queries of the manager run inside of a unit of work
+    pub fn in_unit_of_work(&self) -> bool {
+        self.unit_of_work.is_some()
+    }
+
+    /// Closes all of the connections in the connection pool. Maybe unneccessary
+    /// sqlx might close connections on drop, but I have not confirmed that yet
+    pub async fn close_connection(&self) {
+        self.connection_pool.close().await;
+    }
+
+    /// Adds a user to the user table in the database.
+    /// Returns the pk of the newly added row, or a [DuplicateUserName](DBError::DuplicateUserName)
+    /// error if the name is already used.
     /// 
     /// # Arguments
     /// 
-    /// * `ids` - vector of sleep ids to query
+    /// * `name` - name of the user, user names are unique
     /// 
-    pub async fn get_multiple_sleeps(&self, ids: Vec<i64>) -> Option<Vec<DbmSleep>> {
-        let result = DBSleep::select_all(&self.connection_pool).await;
+    pub async fn insert_user(&self, name: &str) -> Result<i64, DBError> {
+        validate_not_empty("user name", name)?;
 
-        match result {
-            Ok(sleeps) => Some(sleeps.into_iter()
-            .filter(|s| ids.contains(&s.id))
-            .map(|d| DbmSleep { sleep: d, tags: None}).collect()),
-            Err(_) => None
-        }
+        DBUser::insert(&mut *self.conn().await?, name).await
+            .map_err(|e| DBError::from_user_write(e, name))
     }
 
-    /// Queries all sleeps in the database that have an association with the given tag
-    /// Returns vector of the [sleeps](DbmSleep) that have the tag, or None if there was an error
+    /// Gets a user from the database with the given id.
+    /// Returns the user, or a [NotFound](DBError::NotFound) error if the user does not exist
     /// 
     /// # Arguments
     /// 
-    /// * `tag_id` - the id of the tag associated with the sleeps
+    /// * `id` - the pk of the user to query
     /// 
-    pub async fn get_sleeps_by_tag(&self, tag_id: i64) ->  Option<Vec<DbmSleep>> {
-        let sleep_tags = DBSleepTags::select_by_tag_id(&self.connection_pool, tag_id).await;
-
-        let sleep_ids =
-        match sleep_tags {
-            Ok(st) => st.iter().map(|x| x.sleep_id).collect(),
-            Err(_) => return None
-        };
-
-        self.get_multiple_sleeps(sleep_ids).await
+    pub async fn get_user(&self, id: i64) -> Result<DBUser, DBError> {
+        DBUser::select_one(&mut *self.conn().await?, id).await
+            .map_err(|e| DBError::from_select(e, "user", id))
     }
 
-    /// Queries the sleeps within a given month
-    /// Returns vector of [sleeps](DbmSleep) within the month or None if there is an error
+    /// Gets a user from the database with the given name.
+    /// Returns the user, or None if no user has the name
     /// 
     /// # Arguments
     /// 
-    /// * `month` - month value (1-12) to get the sleeps from
-    /// * `year` - 4 digit year to determine which year to use for the month
-    /// 
-    /// # Examples
+    /// * `name` - the name of the user to query
     /// 
-    /// let sleeps = get_sleeps_by_month(5, 2023).await;
-    /// 
-    pub async fn get_sleeps_by_month(&self, month: u8, year: u16) -> Option<Vec<DbmSleep>> {
-        let result = DBSleep::select_by_month(&self.connection_pool, month, year).await;
+    pub async fn get_user_by_name(&self, name: &str) -> Result<Option<DBUser>, DBError> {
+        Ok(DBUser::select_by_name(&mut *self.conn().await?, name).await?)
+    }
 
-        match result {
-            Ok(s) => Some(s.into_iter().map(|x| DbmSleep { sleep: x, tags: None }).collect()),
-            Err(_) => None
-        }
+    /// Queries all users in the database
+    /// Returns all of the users or an error if the query failed.
+    pub async fn get_all_users(&self) -> Result<Vec<DBUser>, DBError> {
+        Ok(DBUser::select_all(&mut *self.conn().await?).await?)
     }
 
-    /// Updates the amount value of the sleep in the database
-    /// Returns true if the update was successful, otherwise false
+    /// Creates an api token that authenticates as the user of the manager.
+    /// Returns the pk of the token and the token itself, the token is only stored as a hash so it can't be shown again.
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the id of the sleep to update
-    /// * `amount` - the new amount value to update to
+    /// * `name` - name of the token to tell tokens apart, ex: "phone"
+    /// * `read_only` - true if the token can only be used to query data
+    /// * `admin` - true if the token can also manage the server, ex: add users
     /// 
-    pub async fn update_sleep_amount(&self, id: i64, amount: f64) -> bool {
-        DBSleep::update_amount(&self.connection_pool, id, amount).await
-            .unwrap_or(false)
+    pub async fn create_api_token(&self, name: &str, read_only: bool, admin: bool) -> Result<(i64, String), DBError> {
+        validate_not_empty("token name", name)?;
+
+        let token = db_auth::generate_token();
+        let id = DBApiToken::insert(&mut *self.conn().await?, self.user_id, name, &db_auth::hash_token(&token), read_only, admin).await?;
+        Ok((id, token))
     }
 
-    /// Updates the quality value of the sleep in the database
-    /// Returns true if the update was successful, otherwise false
+    /// Queries the api tokens of the user
+    /// Returns all of the tokens or an error if the query failed.
+    pub async fn get_api_tokens(&self) -> Result<Vec<DBApiToken>, DBError> {
+        Ok(DBApiToken::select_all(&mut *self.conn().await?, self.user_id).await?)
+    }
+
+    /// Revokes an api token of the user, so it can no longer be used to authenticate
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the id of the sleep to update
-    /// * `quality` - the new quality value to update to
+    /// * `id` - the pk of the token to revoke
     /// 
-    pub async fn update_sleep_quality(&self, id: i64, quality: i64) -> bool {
-        DBSleep::update_quality(&self.connection_pool, id, quality).await
-            .unwrap_or(false)
+    pub async fn revoke_api_token(&self, id: i64) -> Result<(), DBError> {
+        let deleted = DBApiToken::delete(&mut *self.conn().await?, self.user_id, id).await?;
+        found_or_err(deleted, "api token", id)
     }
 
-    /// Deletes the sleep from the database
-    /// Returns true if the deletion was successful, otherwise false
+    /// Looks up the user an api token authenticates as.
+    /// Returns the principal of the token, or None if the token does not exist or was revoked.
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the id of the sleep to delete
+    /// * `token` - the api token sent with a request
     /// 
-    pub async fn delete_sleep(&self, id: i64) -> bool {
-        DBSleep::delete(&self.connection_pool, id).await
-            .unwrap_or(false)
+    pub async fn authenticate(&self, token: &str) -> Result<Option<Principal>, DBError> {
+        let api_token = match DBApiToken::select_by_hash(&mut *self.conn().await?, &db_auth::hash_token(token)).await? {
+            Some(api_token) => api_token,
+            None => return Ok(None),
+        };
+
+        let user = self.get_user(api_token.user_id).await?;
+        Ok(Some(Principal { user_id: user.id, user_name: user.name, token_id: api_token.id, read_only: api_token.read_only, admin: api_token.admin }))
     }
 
-    /// Adds a name and color to the tag table in the database.
-    /// Returns the pk of the newly added row.
+    /// Adds a night, amount and quality to the sleep table in the database.
+    /// Returns the pk of the newly added row, or a [DuplicateNight](DBError::DuplicateNight)
+    /// error if a sleep already exists for the night.
     /// 
     /// # Arguments
     /// 
-    /// * `name` - A string slice representing the name of the tag. Tag names are unique.
-    /// * `color` - A decimal represntation of the rgb color value
+    /// * `night` - The date of the night
+    /// * `amount` - A float representing how much time was spent sleeping during the night
+    /// * `quality` - An int representing the quality of sleep on a night
     /// 
     /// # Examples
     /// 
-    /// let tag_id = insert_tag("tag name", 9590460).await;
+    /// let pk = insert_sleep(NaiveDate::from_ymd_opt(2023, 5, 13).unwrap(), 7.5, 5).await;
+    pub async fn insert_sleep(&self, night: NaiveDate, amount: f64, quality: i64) -> Result<i64, DBError> {
+        self.insert_sleep_with_times(Some(night), Some(amount), quality, &SleepTimes::default()).await
+    }
+
+    /// Adds a sleep with optional bed, sleep onset and wake times to the sleep table in the database,
+    /// along with the main [session](DBSleepSession) of the night holding the amount and times.
+    /// The night and amount are derived from the times when they are not provided,
+    /// and must be consistent with the times when they are.
+    /// Returns the pk of the newly added row, or a [Validation](DBError::Validation) error if the times
+    /// are out of order or the night or amount can't be determined.
     /// 
-    pub async fn insert_tag(&self, name: &str, color: i64) -> i64 {
-        DBTag::insert(&self.connection_pool, name, color).await
-            .unwrap_or(-1)
+    /// # Arguments
+    /// 
+    /// * `night` - The date of the night, derived from the times when None
+    /// * `amount` - How much time was spent sleeping, derived from the times when None
+    /// * `quality` - An int representing the quality of sleep on a night
+    /// * `times` - Bed, sleep onset and wake times of the sleep
+    /// 
+    pub async fn insert_sleep_with_times(
+        &self,
+        night: Option<NaiveDate>,
+        amount: Option<f64>,
+        quality: i64,
+        times: &SleepTimes)
+        -> Result<i64, DBError> {
+        times.validate().map_err(DBError::Validation)?;
+        let night = night_of(night, times)?;
+
+        let amount = amount.or_else(|| times.amount()).ok_or_else(||
+            DBError::Validation(String::from("an amount, or sleep and wake times, are required")))?;
+        validate_amount(amount)?;
+
+        let uow = self.begin().await?;
+        let sleep_id = DBSleep::insert(&mut *uow.conn().await?, self.user_id, night, amount, quality).await
+            .map_err(|e| match e {
+                e if DBError::is_unique_violation(&e) => DBError::DuplicateNight(night),
+                e => e.into()
+            })?;
+
+        DBSleepSession::insert(&mut *uow.conn().await?, sleep_id, SessionKind::Main, amount, times).await?;
+        uow.commit().await?;
+        Ok(sleep_id)
     }
 
-    /// Gets a tag from the database with the given id.
-    /// Returns the tag if it was queried successfully, otherwise None
+    /// Adds a sleep for a night, or updates the sleep the night already has, so the same sleep can be entered again.
+    /// The quality of an existing sleep is replaced, its times are updated like [update_sleep_times](DBManager::update_sleep_times)
+    /// and its amount is replaced when an amount is given or can be derived from the times.
+    /// Its tags and comments are merged with those of the upsert by their [policies](MergePolicy),
+    /// a new sleep gets all of the tags and comments of the upsert. Everything is done in one unit of work.
+    /// Returns the pk of the sleep and what changed, or a [Validation](DBError::Validation) error if the sleep is invalid
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the pk of the tag to query
+    /// * `upsert` - The sleep with its tags and comments, and how they are merged
     /// 
-    pub async fn get_tag(&self, id: i64) -> Option<DBTag> {
-        let result = DBTag::select_one(&self.connection_pool, id).await;
-        
-        match result {
-            Ok(t) => Some(t),
-            Err(_) => {
-                None
+    pub async fn upsert_sleep(&self, upsert: &SleepUpsert) -> Result<UpsertedSleep, DBError> {
+        upsert.times.validate().map_err(DBError::Validation)?;
+        let night = night_of(upsert.night, &upsert.times)?;
+
+        let uow = self.begin().await?;
+        let existing = DBSleep::select_by_night(&mut *uow.conn().await?, self.user_id, night).await?;
+        let mut upserted = match existing {
+            None => {
+                let sleep_id = uow.insert_sleep_with_times(Some(night), upsert.amount, upsert.quality, &upsert.times).await?;
+                UpsertedSleep { sleep_id, created: true, ..Default::default() }
+            },
+            Some(sleep) => {
+                uow.update_sleep_quality(sleep.id, upsert.quality).await?;
+
+                // derive the amount from the updated times when an amount wasn't provided
+                let mut amount = upsert.amount;
+                if !upsert.times.is_empty() {
+                    let times = uow.update_sleep_times(sleep.id, &upsert.times).await?;
+                    amount = amount.or_else(|| times.amount());
+                }
+                if let Some(amount) = amount {
+                    uow.update_sleep_amount(sleep.id, amount).await?;
+                }
+                UpsertedSleep { sleep_id: sleep.id, created: false, ..Default::default() }
+            },
+        };
+
+        let (tag_policy, comment_policy) = if upserted.created {
+            (MergePolicy::Append, MergePolicy::Append)
+        }
+        else {
+            (upsert.tag_policy, upsert.comment_policy)
+        };
+        uow.merge_sleep_tags(upserted.sleep_id, &upsert.tag_ids, tag_policy).await?;
+        uow.merge_sleep_comments(&mut upserted, &upsert.comments, comment_policy).await?;
+
+        uow.commit().await?;
+        Ok(upserted)
+    }
+
+    /// Merges the tags of a sleep with the given tags by the policy
+    async fn merge_sleep_tags(&self, sleep_id: i64, tag_ids: &[i64], policy: MergePolicy) -> Result<(), DBError> {
+        if policy == MergePolicy::Keep {
+            return Ok(());
+        }
+
+        let current = self.get_tags_by_sleeps(&[sleep_id]).await?.remove(&sleep_id).unwrap_or_default();
+        if policy == MergePolicy::Replace {
+            for tag in current.iter().filter(|t| !tag_ids.contains(&t.id)) {
+                self.remove_tag_from_sleep(sleep_id, tag.id).await?;
             }
-        } 
+        }
+
+        let missing = tag_ids.iter().filter(|id| !current.iter().any(|t| t.id == **id)).copied().collect();
+        self.add_tags_to_sleep(sleep_id, missing).await
     }
 
-    /// Queries all tags in the database
-    /// Returns all of the tags or None if there was an error.
-    pub async fn get_all_tags(&self) -> Option<Vec<DBTag>> {
-        let result = DBTag::select_all(&self.connection_pool).await;
-        
-        match result {
-            Ok(t) => Some(t),
-            Err(_) => {
-                None
+    /// Merges the comments of a sleep with the given comments by the policy, matching comments by their text,
+    /// and records the comments that were added and removed
+    async fn merge_sleep_comments(&self, upserted: &mut UpsertedSleep, comments: &[String], policy: MergePolicy) -> Result<(), DBError> {
+        if policy == MergePolicy::Keep {
+            return Ok(());
+        }
+
+        let sleep_id = upserted.sleep_id;
+        let mut current = self.get_comments_by_sleep(sleep_id).await?;
+        if policy == MergePolicy::Replace {
+            for comment in current.iter().filter(|c| !comments.contains(&c.comment)) {
+                self.delete_comment(comment.id).await?;
+                upserted.deleted_comment_ids.push(comment.id);
+            }
+        }
+
+        for comment in comments {
+            if !current.iter().any(|c| &c.comment == comment) {
+                let id = self.insert_comment(sleep_id, comment).await?;
+                upserted.created_comment_ids.push(id);
+                current.push(DBComment { id, sleep_id, comment: comment.clone() });
             }
-        } 
+        }
+        Ok(())
     }
 
-    /// Queries all tags in the database that have an association with the given sleep
-    /// Returns vector of the tags that are related to the sleep, or None if there was an error
+    /// Gets a sleep from the database with the given id.
+    /// Optionally includes all tags associated to the sleep.
+    /// Returns the [DbmSleep](DbmSleep), or a [NotFound](DBError::NotFound) error if the sleep does not exist
     /// 
     /// # Arguments
     /// 
-    /// * `sleep_id` - the id of the sleep associated with the tags
+    /// * `id` - the pk of the sleep to query
+    /// * `include_tags` - boolean dictating if associated tags should also be queried
+    /// 
+    /// # Examples
     /// 
-    pub async fn get_tags_by_sleep(&self, sleep_id: i64) ->  Option<Vec<DBTag>> {
-        let sleep_tags = DBSleepTags::select_by_sleep_id(&self.connection_pool, sleep_id).await;
+    /// let sleep = get_sleep(1, false).await;
+    pub async fn get_sleep(&self, id: i64, include_tags: bool) -> Result<DbmSleep, DBError>  {
+        let db_sleep = DBSleep::select_one(&mut *self.conn().await?, self.user_id, id).await
+            .map_err(|e| DBError::from_select(e, "sleep", id))?;
 
-        let tag_ids =
-        match sleep_tags {
-            Ok(st) => st.iter().map(|x| x.tag_id).collect(),
-            Err(_) => return None
-        };
+        let mut sleep = DbmSleep { sleep: db_sleep, tags: None };
 
-       self.get_multiple_tags(tag_ids).await
+        if include_tags {
+            sleep.tags = Some(self.get_tags_by_sleep(id).await?);
+        }
+
+        Ok(sleep)
+    }
+
+    /// Queries all sleeps in the database
+    /// Returns all of the [sleeps](DbmSleep) or an error if the query failed.
+    pub async fn get_all_sleeps(&self) -> Result<Vec<DbmSleep>, DBError>  {
+        let sleeps = DBSleep::select_all(&mut *self.conn().await?, self.user_id).await?;
+        Ok(sleeps.into_iter().map(|x| DbmSleep { sleep: x, tags: None }).collect())
     }
 
-    /// Gets multiple tags based on the given ids.
-    /// Returns the tags that match the ids, or None if there was an error
-    /// Note: Ideally a WHERE id IN clause would be used for this, however, that is not directly supported by
-    /// sqlx v0.6 so select all tags and filter them manually
+    /// Get sleeps with the given ids with a single query and returns a Vector of [DbmSleeps](DbmSleep) ordered by id,
+    /// ids that don't match a sleep are skipped
     /// 
     /// # Arguments
     /// 
-    /// * `ids` - list of the tag ids to query
+    /// * `ids` - vector of sleep ids to query
     /// 
-    pub async fn get_multiple_tags(&self, ids: Vec<i64>) -> Option<Vec<DBTag>> {
-        let result = DBTag::select_all(&self.connection_pool).await;
+    pub async fn get_multiple_sleeps(&self, ids: Vec<i64>) -> Result<Vec<DbmSleep>, DBError> {
+        let sleeps = DBSleep::select_by_ids(&mut *self.conn().await?, self.user_id, &ids).await?;
+        Ok(sleeps.into_iter().map(|d| DbmSleep { sleep: d, tags: None }).collect())
+    }
 
-        match result {
-            Ok(tags) => {
-                Some(tags.into_iter().filter(|t| ids.contains(&t.id)).collect())
-            },
-            Err(_) => {
-                None
-            }
-        }
+    /// Queries a page of the sleeps in the database, ordered by night and id.
+    /// Returns the [sleeps](DbmSleep) in the page along with the page info, or a
+    /// [Validation](DBError::Validation) error if the page arguments are invalid
+    /// 
+    /// # Arguments
+    /// 
+    /// * `args` - keyset pagination arguments, the key of a sleep is its night and id
+    /// * `tag_id` - optionally only list the sleeps that have the tag
+    /// 
+    pub async fn get_sleeps_page(&self, args: &PageArgs<(NaiveDate, i64)>, tag_id: Option<i64>) -> Result<Page<DbmSleep>, DBError> {
+        args.validate()?;
+
+        let sleeps = DBSleep::select_page(&mut *self.conn().await?, self.user_id, args.after, args.before, tag_id, args.limit(), args.is_backward()).await?;
+        let total_count = DBSleep::count(&mut *self.conn().await?, self.user_id, tag_id).await?;
+
+        Ok(Page::from_rows(sleeps, args, total_count).map(|x| DbmSleep { sleep: x, tags: None }))
     }
 
-    /// Updates the name value of the tag in the database
-    /// Returns true if the update was successful, otherwise false
+    /// Queries all sleeps in the database that have an association with the given tag
+    /// Returns vector of the [sleeps](DbmSleep) that have the tag, or an error if the query failed
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the id of the tag to update
-    /// * `name` - the new name value to update to
+    /// * `tag_id` - the id of the tag associated with the sleeps
     /// 
-    pub async fn update_tag_name(&self, id: i64, name: &str) -> bool {
-        DBTag::update_name(&self.connection_pool, id, name).await
-            .unwrap_or(false)
+    pub async fn get_sleeps_by_tag(&self, tag_id: i64) ->  Result<Vec<DbmSleep>, DBError> {
+        let sleep_tags = DBSleepTags::select_by_tag_id(&mut *self.conn().await?, self.user_id, tag_id).await?;
+        let sleep_ids = sleep_tags.iter().map(|x| x.sleep_id).collect();
+
+        self.get_multiple_sleeps(sleep_ids).await
     }
 
-    /// Updates the color value of the tag in the database
-    /// Returns true if the update was successful, otherwise false
+    /// Queries the sleeps within a given month
+    /// Returns vector of [sleeps](DbmSleep) within the month or an error if the query failed
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the id of the tag to update
-    /// * `color` - the new decimal represntation of the rgb color value value to update to.
-    ///
+    /// * `month` - month value (1-12) to get the sleeps from
+    /// * `year` - 4 digit year to determine which year to use for the month
+    /// 
     /// # Examples
     /// 
-    /// let success = update_tag_color(2, 65535).await;
+    /// let sleeps = get_sleeps_by_month(5, 2023).await;
     /// 
-    pub async fn update_tag_color(&self, id: i64, color: i64) -> bool {
-        DBTag::update_color(&self.connection_pool, id, color).await
-            .unwrap_or(false)
+    pub async fn get_sleeps_by_month(&self, month: u8, year: u16) -> Result<Vec<DbmSleep>, DBError> {
+        if !(1..=12).contains(&month) {
+            return Err(DBError::Validation(format!("{} is not a valid month", month)));
+        }
+
+        let sleeps = DBSleep::select_by_month(&mut *self.conn().await?, self.user_id, month, year).await?;
+        Ok(sleeps.into_iter().map(|x| DbmSleep { sleep: x, tags: None }).collect())
     }
 
-    /// Deletes the tag from the database
-    /// Returns true if the deletion was successful, otherwise false
+    /// Queries the sleeps with a night between the start and end dates, inclusive, ordered by night
+    /// Returns vector of [sleeps](DbmSleep) within the range or an error if the query failed
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the id of the tag to delete
-    ///
-    pub async fn delete_tag(&self, id: i64) -> bool {
-        DBTag::delete(&self.connection_pool, id).await
-            .unwrap_or(false)
+    /// * `start` - first night of the range
+    /// * `end` - last night of the range, must not be before the start
+    /// 
+    pub async fn get_sleeps_in_range(&self, start: NaiveDate, end: NaiveDate) -> Result<Vec<DbmSleep>, DBError> {
+        if start > end {
+            return Err(DBError::Validation(format!("range start {} is after the range end {}", start, end)));
+        }
+
+        let sleeps = DBSleep::select_in_range(&mut *self.conn().await?, self.user_id, start, end).await?;
+        Ok(sleeps.into_iter().map(|x| DbmSleep { sleep: x, tags: None }).collect())
     }
 
-    /// Adds an association between a list of tags and a sleep
-    /// returns true if all of the relationships are created successfully, otherwise false
+    /// Computes statistics of the sleeps with a night between the start and end dates, inclusive.
+    /// Returns the overall statistics, the statistics of each group with the average share of each stage of sleep,
+    /// and the 7 and 30 day rolling averages,
+    /// or an error if the query failed
     /// 
     /// # Arguments
     /// 
-    /// * `sleep_id` - the id of the sleep to add the tags to
-    /// * `tag_ids` - the ids of the tags to add to the sleep
+    /// * `start` - first night of the range
+    /// * `end` - last night of the range, must not be before the start
+    /// * `group_by` - how the sleeps in the range are grouped
     /// 
-    pub async fn add_tags_to_sleep(&self, sleep_id: i64, tag_ids: Vec<i64>) -> bool {
-        let mut result = true;
-        for tag_id in tag_ids {
-            match DBSleepTags::insert(&self.connection_pool, sleep_id, tag_id).await {
-                Ok(_) => result = true,
-                Err(_) => {
-                    result = false;
-                    break;
+    pub async fn get_sleep_stats(&self, start: NaiveDate, end: NaiveDate, group_by: StatsGroupBy) -> Result<SleepStats, DBError> {
+        if start > end {
+            return Err(DBError::Validation(format!("range start {} is after the range end {}", start, end)));
+        }
+
+        // include the nights before the start so the rolling windows of the first nights are complete
+        let window_start = start - Duration::days(db_stats::LONG_WINDOW_DAYS - 1);
+        let sleeps = DBSleep::select_in_range(&mut *self.conn().await?, self.user_id, window_start, end).await?;
+        let stages = DBSleepStages::select_in_range(&mut *self.conn().await?, self.user_id, start, end).await?;
+        Ok(db_stats::compute(&sleeps, &stages, start, end, group_by))
+    }
+
+    /// Imports sleeps from a csv file with a header row, creating the tags that don't exist yet.
+    /// A tag name that is an alias of a merged or renamed tag is imported as that tag.
+    /// Rows with a night that already has a sleep, or that repeat the night of an earlier row, are not imported
+    /// and are reported as issues along with the rows that could not be read.
+    /// Returns a report of the import, or a [Validation](DBError::Validation) error if a required column is missing.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `csv` - contents of the csv file
+    /// * `options` - names of the columns, the tag delimiter, the date format and whether this is a dry run
+    /// 
+    pub async fn import_csv(&self, csv: &str, options: &CsvImportOptions) -> Result<ImportReport, DBError> {
+        let (rows, mut issues) = db_csv::parse(csv, options)?;
+        let mut report = ImportReport { dry_run: options.dry_run, rows: rows.len() + issues.len(), ..Default::default() };
+
+        let uow = self.begin().await?;
+        let mut nights = DBSleep::select_all(&mut *uow.conn().await?, self.user_id).await?
+            .into_iter().map(|s| s.night).collect::<HashSet<NaiveDate>>();
+        let mut tag_ids = DBTagAlias::select_all(&mut *uow.conn().await?, self.user_id).await?
+            .into_iter().map(|a| (a.name, a.tag_id)).collect::<HashMap<String, i64>>();
+        tag_ids.extend(DBTag::select_all(&mut *uow.conn().await?, self.user_id).await?.into_iter().map(|t| (t.name, t.id)));
+        let existing_nights = nights.clone();
+
+        for row in rows {
+            if !nights.insert(row.night) {
+                let (kind, message) = if existing_nights.contains(&row.night) {
+                    (ImportIssueKind::DuplicateNight, DBError::DuplicateNight(row.night).to_string())
+                }
+                else {
+                    (ImportIssueKind::RepeatedNight, format!("an earlier row is also for {}", row.night))
+                };
+                issues.push(ImportIssue::new(row.line, Some(row.night), kind, message));
+                continue;
+            }
+
+            let mut row_tag_ids = Vec::new();
+            for name in &row.tags {
+                match tag_ids.get(name) {
+                    Some(id) => row_tag_ids.push(*id),
+                    None => {
+                        report.created_tags.push(name.clone());
+                        if options.dry_run {
+                            tag_ids.insert(name.clone(), 0);
+                        }
+                        else {
+                            let id = uow.insert_tag(name, 0).await?;
+                            report.tag_ids.push(id);
+                            tag_ids.insert(name.clone(), id);
+                            row_tag_ids.push(id);
+                        }
+                    }
                 }
-            }  
+            }
+
+            report.imported += 1;
+            if options.dry_run {
+                continue;
+            }
+
+            let sleep_id = uow.insert_sleep(row.night, row.amount, row.quality).await?;
+            uow.add_tags_to_sleep(sleep_id, row_tag_ids).await?;
+            for comment in &row.comments {
+                uow.insert_comment(sleep_id, comment).await?;
+            }
+            report.sleep_ids.push(sleep_id);
         }
 
-        result
+        issues.sort_by_key(|i| i.line);
+        report.issues = issues;
+        uow.commit().await?;
+        Ok(report)
     }
 
-    /// Removes a relationship between a tag and a sleep
-    /// returns true if the relationship was deleted successfully, otherwise false
+    /// Exports the sleeps with their tags and comments as a csv file, ordered by night,
+    /// with the columns that [import_csv](DBManager::import_csv) reads by default
     /// 
     /// # Arguments
     /// 
-    /// * `sleep_id` - id of the sleep to remove the relationship from
-    /// * `tag_id` - id of tag to remove the relationship from
+    /// * `start` - first night to export, or None to export from the first sleep
+    /// * `end` - last night to export, or None to export until the last sleep
     /// 
-    pub async fn remove_tag_from_sleep(&self, sleep_id: i64, tag_id: i64) -> bool {
-        DBSleepTags::delete(&self.connection_pool, sleep_id, tag_id).await
-            .unwrap_or(false)
+    pub async fn export_csv(&self, start: Option<NaiveDate>, end: Option<NaiveDate>) -> Result<String, DBError> {
+        // nights are compared as yyyy-mm-dd strings, so the open ends are limited to four digit years
+        let start = start.unwrap_or_else(|| NaiveDate::from_ymd_opt(1, 1, 1).unwrap());
+        let end = end.unwrap_or_else(|| NaiveDate::from_ymd_opt(9999, 12, 31).unwrap());
+        let sleeps = DBSleep::select_in_range(&mut *self.conn().await?, self.user_id, start, end).await?;
+
+        let ids = sleeps.iter().map(|s| s.id).collect::<Vec<i64>>();
+        let tags = self.get_tags_by_sleeps(&ids).await?;
+        let comments = self.get_comments_by_sleeps(&ids).await?;
+        Ok(db_csv::write(&sleeps, &tags, &comments))
     }
 
-    /// Adds a comment to the comment table in the database and relates it to a sleep.
-    /// Returns the pk of the newly added row.
+    /// Imports the sleep analysis records of an Apple Health `export.xml`, reading the export one record at a time.
+    /// The records are grouped into nights, see [parse](db_apple_health::parse), and each night is imported as a sleep
+    /// with the Apple Health source. Nights that were imported before are updated instead, so an export can be imported
+    /// again as it grows. Nights that already have a sleep from another source are reported as duplicates.
+    /// Returns a report of the import, or a [Validation](DBError::Validation) error if the export is not valid xml.
     /// 
     /// # Arguments
     /// 
-    /// * `sleep_id` - sleep to relate the comment to
-    /// * `comment` - text comment to add
+    /// * `export` - reader of the export.xml file
+    /// * `options` - quality of the imported sleeps and whether this is a dry run
     /// 
-    pub async fn insert_comment(&self, sleep_id: i64, comment: &str) -> i64 {
-        DBComment::insert(&self.connection_pool, sleep_id, comment).await
-            .unwrap_or(-1)
+    pub async fn import_apple_health<R: BufRead>(&self, export: R, options: &AppleHealthOptions) -> Result<ImportReport, DBError> {
+        let (nights, issues) = db_apple_health::parse(export)?;
+        self.import_nights(nights, issues, APPLE_HEALTH_SOURCE, None, options.quality, options.dry_run).await
     }
 
-    /// Gets a comment from the database with the given id.
-    /// Returns the comment if it was queried successfully, otherwise None
+    /// Imports the main sleep of each night from the `sleep-*.json` files of the directory of a Fitbit export,
+    /// with the efficiency of each sleep mapped to the quality scale of the options, see [parse](db_fitbit::parse).
+    /// The imported sleeps have the Fitbit source and the [FITBIT_TAG], which is created if it doesn't exist yet.
+    /// Nights that were imported before are updated instead, and nights that already have a sleep from another
+    /// source are reported as duplicates. Returns a report of the import, or a [Validation](DBError::Validation)
+    /// error if the quality scale is reversed or the directory has no sleep files.
     /// 
     /// # Arguments
     /// 
-    /// * `comment_id` - the pk of the comment to query
-    ///
-    pub async fn get_comment(&self, comment_id: i64) -> Option<DBComment> {
-        let result = DBComment::select_by_id(&self.connection_pool, comment_id).await;
-        match result {
-            Ok(c) => Some(c),
-            Err(_) => {
-                None
+    /// * `dir` - directory of the export with the sleep files
+    /// * `options` - quality scale of the imported sleeps and whether this is a dry run
+    /// 
+    pub async fn import_fitbit(&self, dir: &Path, options: &FitbitOptions) -> Result<ImportReport, DBError> {
+        options.validate()?;
+        let (nights, issues) = db_fitbit::parse(dir, options)?;
+        self.import_nights(nights, issues, FITBIT_SOURCE, Some(FITBIT_TAG), options.min_quality, options.dry_run).await
+    }
+
+    /// Imports nights read from the export of a device as sleeps with the given source, and the given tag if any,
+    /// creating the tag if it doesn't exist yet.
+    /// A night that already has a sleep with the same source updates the amount, times, quality and stages of that sleep,
+    /// a night that has a sleep from another source, or that was already imported, is reported as an issue.
+    async fn import_nights(
+        &self,
+        nights: Vec<ImportedNight>,
+        mut issues: Vec<ImportIssue>,
+        source: &str,
+        tag: Option<&str>,
+        quality: i64,
+        dry_run: bool)
+        -> Result<ImportReport, DBError> {
+        let mut report = ImportReport { dry_run, rows: nights.len() + issues.len(), ..Default::default() };
+        let uow = self.begin().await?;
+        let existing = DBSleep::select_sources(&mut *uow.conn().await?, self.user_id).await?.into_iter()
+            .map(|(id, night, source)| (night, (id, source)))
+            .collect::<HashMap<NaiveDate, (i64, Option<String>)>>();
+        let mut tag_id = match tag {
+            Some(name) => uow.get_tag_by_name(name).await?.map(|t| t.id),
+            None => None,
+        };
+        let mut seen = HashSet::new();
+
+        for night in nights {
+            let issue = |kind: ImportIssueKind, message: String|
+                ImportIssue::new(night.line, Some(night.night), kind, message).in_file(night.file.as_deref());
+            if !seen.insert(night.night) {
+                issues.push(issue(ImportIssueKind::RepeatedNight, format!("an earlier night of the import is also for {}", night.night)));
+                continue;
+            }
+
+            match existing.get(&night.night) {
+                None => {
+                    report.imported += 1;
+                    if let (Some(name), None) = (tag, tag_id) {
+                        if !report.created_tags.iter().any(|t| t == name) {
+                            report.created_tags.push(name.to_string());
+                        }
+                        if !dry_run {
+                            let id = uow.insert_tag(name, 0).await?;
+                            report.tag_ids.push(id);
+                            tag_id = Some(id);
+                        }
+                    }
+                    if !dry_run {
+                        let sleep_id = uow.insert_sleep_with_times(
+                            Some(night.night), Some(night.amount), night.quality.unwrap_or(quality), &night.times).await?;
+                        DBSleep::update_source(&mut *uow.conn().await?, self.user_id, sleep_id, source).await?;
+                        uow.set_imported_stages(sleep_id, &night).await?;
+                        if let Some(id) = tag_id {
+                            uow.add_tags_to_sleep(sleep_id, vec![id]).await?;
+                        }
+                        report.sleep_ids.push(sleep_id);
+                    }
+                },
+                Some((sleep_id, Some(existing_source))) if existing_source == source => {
+                    let main = uow.get_main_session(*sleep_id).await?;
+                    let current_quality = uow.get_sleep(*sleep_id, false).await?.sleep.quality;
+                    let current_stages = uow.get_sleep_stages(*sleep_id).await?.map(|s| s.stages());
+                    let current_hypnogram = uow.get_hypnogram(*sleep_id).await?.iter().map(|i| i.interval()).collect::<Vec<_>>();
+                    let unchanged = main.as_ref()
+                        .is_some_and(|m| (m.amount - night.amount).abs() < 1e-9 && m.times() == night.times)
+                        && night.quality.is_none_or(|q| q == current_quality)
+                        && current_stages == night.stages
+                        && current_hypnogram == night.hypnogram;
+                    if unchanged {
+                        continue;
+                    }
+
+                    report.merged += 1;
+                    if !dry_run {
+                        uow.update_sleep_times(*sleep_id, &night.times).await?;
+                        uow.update_sleep_amount(*sleep_id, night.amount).await?;
+                        if let Some(quality) = night.quality {
+                            uow.update_sleep_quality(*sleep_id, quality).await?;
+                        }
+                        uow.set_imported_stages(*sleep_id, &night).await?;
+                    }
+                },
+                Some(_) => issues.push(issue(ImportIssueKind::DuplicateNight, DBError::DuplicateNight(night.night).to_string())),
             }
         }
+
+        issues.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+        report.issues = issues;
+        uow.commit().await?;
+        Ok(report)
     }
 
-    /// Get all comments associated to a sleep
-    /// Returns the comments related to the sleep, or None if there is an error
+    /// Sets the stages and hypnogram of an imported sleep to those of the night, removing them if the night has none
+    async fn set_imported_stages(&self, sleep_id: i64, night: &ImportedNight) -> Result<(), DBError> {
+        self.set_sleep_stages(sleep_id, &night.stages.unwrap_or_default()).await?;
+        self.set_hypnogram(sleep_id, &night.hypnogram).await
+    }
+
+    /// Returns a backup of every row of the database, made inside of a single transaction.
+    /// Unlike the other methods the backup covers every user, not only the user of the manager.
+    pub async fn export_backup(&self) -> Result<Backup, DBError> {
+        db_backup::export(self.pool()).await
+    }
+
+    /// Restores a backup of the database inside of a single transaction, so nothing changes if any row fails.
+    /// Like [export_backup](DBManager::export_backup) the restore covers every user.
+    /// Returns the number of restored rows, or a [Validation](DBError::Validation) error if the backup
+    /// is of an unknown format or has rows that reference rows it doesn't have.
     /// 
     /// # Arguments
     /// 
-    /// * `sleep-id` - The id of the sleep to get the comments from
+    /// * `backup` - the backup to restore
+    /// * `mode` - whether the backup is merged into the data of the database, or replaces it
     /// 
-    pub async fn get_comments_by_sleep(&self, sleep_id: i64) -> Option<Vec<DBComment>> {
-        let comments = DBComment::select_by_sleep_id(&self.connection_pool, sleep_id).await;
+    pub async fn restore_backup(&self, backup: &Backup, mode: RestoreMode) -> Result<RestoreReport, DBError> {
+        db_backup::restore(self.pool(), backup, mode).await
+    }
+
+    /// Compares the sleeps with each tag to the sleeps without it, for the sleeps with a night between
+    /// the start and end dates, inclusive. Returns the mean amount and quality with and without each tag,
+    /// the sample sizes and Welch's t-test of the difference, or an error if the query failed.
+    /// With roll up a sleep with any of the descendants of a tag counts as a sleep with the tag.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `start` - first night of the range
+    /// * `end` - last night of the range, must not be before the start
+    /// * `tag_ids` - the tags to compare, or all tags when None
+    /// * `roll_up` - whether the sleeps with the descendants of a tag are compared as sleeps with the tag
+    /// 
+    pub async fn get_tag_impact(&self, start: NaiveDate, end: NaiveDate, tag_ids: Option<Vec<i64>>, roll_up: bool) -> Result<Vec<TagImpact>, DBError> {
+        if start > end {
+            return Err(DBError::Validation(format!("range start {} is after the range end {}", start, end)));
+        }
 
-        match comments {
-            Ok(coms) => {
-                Some(coms)
+        let tags = match tag_ids {
+            Some(ids) => {
+                let tags = self.get_multiple_tags(ids.clone()).await?;
+                if let Some(missing) = ids.iter().find(|id| !tags.iter().any(|t| t.id == **id)) {
+                    return Err(DBError::not_found("tag", *missing));
+                }
+                tags
             },
-            Err(_e) => {
-                None
-            }
+            None => self.get_all_tags().await?,
+        };
+
+        let sleeps = DBSleep::select_in_range(&mut *self.conn().await?, self.user_id, start, end).await?;
+        let mut sleep_tags = DBSleepTags::select_in_range(&mut *self.conn().await?, self.user_id, start, end).await?;
+        if roll_up {
+            let parents = DBTagHierarchy::parents(&DBTagHierarchy::select_all(&mut *self.conn().await?, self.user_id).await?);
+            let rolled_up = sleep_tags.iter()
+                .flat_map(|st| DBTagHierarchy::ancestors(&parents, st.tag_id).into_iter().map(|tag_id| DBSleepTags { tag_id, ..st.clone() }))
+                .collect::<Vec<DBSleepTags>>();
+            sleep_tags.extend(rolled_up);
         }
+        Ok(db_stats::tag_impact(&sleeps, &sleep_tags, &tags))
     }
 
-    /// Updates the text of a comment in the database
-    /// Returns true if the update is successful, otherwise false
+    /// Updates the amount of the main session of the sleep in the database,
+    /// creating the main session if the sleep doesn't have one.
+    /// The amount of the sleep is updated to the new total of all of its sessions.
+    /// Returns a [NotFound](DBError::NotFound) error if the sleep does not exist
     /// 
     /// # Arguments
     /// 
-    /// * `comment_id` - the id of the comment to edit
-    /// * `comment` - the new text value to update the comment to
+    /// * `id` - the id of the sleep to update
+    /// * `amount` - the new amount value to update to
     /// 
-    pub async fn update_comment(&self, comment_id: i64, comment: &str) -> bool {
-        DBComment::update_comment(&self.connection_pool, comment_id, comment).await
-            .unwrap_or(false)
+    pub async fn update_sleep_amount(&self, id: i64, amount: f64) -> Result<(), DBError> {
+        match self.get_main_session(id).await? {
+            Some(main) => self.update_sleep_session_amount(main.id, amount).await,
+            None => self.insert_sleep_session(id, SessionKind::Main, Some(amount), &SleepTimes::default()).await.map(|_| ())
+        }
     }
 
-    /// Deletes the comment from the database
-    /// Returns true if the deletion was successful, otherwise false
+    /// Updates the quality value of the sleep in the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
     /// 
     /// # Arguments
     /// 
-    /// * `id` - the id of the comment to delete
-    ///
-    pub async fn delete_comment(&self, id: i64) -> bool {
-        DBComment::delete(&self.connection_pool, id).await
-            .unwrap_or(false)
+    /// * `id` - the id of the sleep to update
+    /// * `quality` - the new quality value to update to
+    /// 
+    pub async fn update_sleep_quality(&self, id: i64, quality: i64) -> Result<(), DBError> {
+        let updated = DBSleep::update_quality(&mut *self.conn().await?, self.user_id, id, quality).await?;
+        found_or_err(updated, "sleep", id)
+    }
+
+    /// Updates the bed, sleep onset and wake times of the main session of the sleep in the database,
+    /// creating the main session if the sleep doesn't have one.
+    /// Times that are None keep their current value. The updated times must be in order
+    /// and belong to the night of the sleep. A created main session has the amount of the times, or the amount of the sleep
+    /// when the times don't give one and the sleep has no other sessions.
+    /// Returns the updated times, a [NotFound](DBError::NotFound) error if the sleep does not exist, or a
+    /// [Validation](DBError::Validation) error if the amount of a created main session can't be determined
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the sleep to update
+    /// * `times` - the times to update
+    /// 
+    pub async fn update_sleep_times(&self, id: i64, times: &SleepTimes) -> Result<SleepTimes, DBError> {
+        let uow = self.begin().await?;
+        let updated = match uow.get_main_session(id).await? {
+            Some(main) => uow.update_sleep_session_times(main.id, times).await?,
+            None => {
+                // the amount of a sleep is the total of its sessions, so it only belongs to the main session on its own
+                let sleep = uow.get_sleep(id, false).await?.sleep;
+                let amount = match times.amount() {
+                    Some(amount) => amount,
+                    None if uow.get_sleep_sessions(id).await?.is_empty() => sleep.amount,
+                    None => return Err(DBError::Validation(
+                        String::from("sleep and wake times are required to add a main session to a sleep with other sessions"))),
+                };
+                uow.insert_sleep_session(id, SessionKind::Main, Some(amount), times).await?;
+                *times
+            }
+        };
+        uow.commit().await?;
+        Ok(updated)
+    }
+
+    /// Deletes the sleep from the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the sleep to delete
+    /// 
+    pub async fn delete_sleep(&self, id: i64) -> Result<(), DBError> {
+        let deleted = DBSleep::delete(&mut *self.conn().await?, self.user_id, id).await?;
+        found_or_err(deleted, "sleep", id)
+    }
+
+    /// Adds a session of sleep, such as a nap, to a night.
+    /// The amount is derived from the times when it is not provided, and the times must belong to the night of the sleep
+    /// without overlapping its other sessions. The amount of the sleep is updated to the new total of all of its sessions.
+    /// Returns the pk of the newly added row, or a [Validation](DBError::Validation) error if the session is invalid
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep (night) to add the session to
+    /// * `kind` - the kind of session, a night has at most one main session
+    /// * `amount` - How much time was spent sleeping, derived from the times when None
+    /// * `times` - Bed, sleep onset and wake times of the session
+    /// 
+    pub async fn insert_sleep_session(
+        &self,
+        sleep_id: i64,
+        kind: SessionKind,
+        amount: Option<f64>,
+        times: &SleepTimes)
+        -> Result<i64, DBError> {
+        let uow = self.begin().await?;
+        let sleep = uow.get_sleep(sleep_id, false).await?.sleep;
+        uow.validate_session_times(&sleep, None, kind, times).await?;
+
+        let amount = amount.or_else(|| times.amount()).ok_or_else(||
+            DBError::Validation(String::from("an amount, or sleep and wake times, are required")))?;
+        validate_amount(amount)?;
+
+        let session_id = DBSleepSession::insert(&mut *uow.conn().await?, sleep_id, kind, amount, times).await
+            .map_err(|e| DBError::from_session_write(e, sleep_id))?;
+
+        DBSleep::update_total_amount(&mut *uow.conn().await?, self.user_id, sleep_id).await?;
+        uow.commit().await?;
+        Ok(session_id)
+    }
+
+    /// Gets a sleep session from the database with the given id.
+    /// Returns the session, or a [NotFound](DBError::NotFound) error if the session does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the pk of the session to query
+    /// 
+    pub async fn get_sleep_session(&self, id: i64) -> Result<DBSleepSession, DBError> {
+        DBSleepSession::select_one(&mut *self.conn().await?, self.user_id, id).await
+            .map_err(|e| DBError::from_select(e, "sleep session", id))
+    }
+
+    /// Get all sessions of a sleep, ordered by the time they started
+    /// Returns the sessions of the sleep, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - The id of the sleep to get the sessions from
+    /// 
+    pub async fn get_sleep_sessions(&self, sleep_id: i64) -> Result<Vec<DBSleepSession>, DBError> {
+        Ok(DBSleepSession::select_by_sleep_id(&mut *self.conn().await?, self.user_id, sleep_id).await?)
+    }
+
+    /// Get the sessions of each of the given sleeps with a single query
+    /// Returns a map from each sleep id to its sessions ordered by the time they started, sleeps without sessions
+    /// map to an empty vector, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_ids` - The ids of the sleeps to get the sessions from
+    /// 
+    pub async fn get_sleep_sessions_by_sleeps(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Vec<DBSleepSession>>, DBError> {
+        let mut sessions: HashMap<i64, Vec<DBSleepSession>> = sleep_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for session in DBSleepSession::select_by_sleep_ids(&mut *self.conn().await?, self.user_id, sleep_ids).await? {
+            sessions.entry(session.sleep_id).or_default().push(session);
+        }
+
+        Ok(sessions)
+    }
+
+    /// Updates the kind of a sleep session in the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the session to update
+    /// * `kind` - the new kind of the session, a night has at most one main session
+    /// 
+    pub async fn update_sleep_session_kind(&self, id: i64, kind: SessionKind) -> Result<(), DBError> {
+        let session = self.get_sleep_session(id).await?;
+        let sleep = self.get_sleep(session.sleep_id, false).await?.sleep;
+        self.validate_session_times(&sleep, Some(id), kind, &session.times()).await?;
+
+        let updated = DBSleepSession::update_kind(&mut *self.conn().await?, self.user_id, id, kind).await
+            .map_err(|e| DBError::from_session_write(e, session.sleep_id))?;
+        found_or_err(updated, "sleep session", id)
+    }
+
+    /// Updates the amount of a sleep session in the database.
+    /// The amount of the sleep is updated to the new total of all of its sessions.
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the session to update
+    /// * `amount` - the new amount value to update to
+    /// 
+    pub async fn update_sleep_session_amount(&self, id: i64, amount: f64) -> Result<(), DBError> {
+        validate_amount(amount)?;
+
+        let uow = self.begin().await?;
+        let session = uow.get_sleep_session(id).await?;
+        let updated = DBSleepSession::update_amount(&mut *uow.conn().await?, self.user_id, id, amount).await?;
+        found_or_err(updated, "sleep session", id)?;
+
+        DBSleep::update_total_amount(&mut *uow.conn().await?, self.user_id, session.sleep_id).await?;
+        uow.commit().await?;
+        Ok(())
+    }
+
+    /// Updates the bed, sleep onset and wake times of a sleep session in the database.
+    /// Times that are None keep their current value. The updated times must be in order,
+    /// belong to the night of the sleep, and not overlap the other sessions of the night.
+    /// Returns the updated times, or a [NotFound](DBError::NotFound) error if the session does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the session to update
+    /// * `times` - the times to update
+    /// 
+    pub async fn update_sleep_session_times(&self, id: i64, times: &SleepTimes) -> Result<SleepTimes, DBError> {
+        let session = self.get_sleep_session(id).await?;
+        let sleep = self.get_sleep(session.sleep_id, false).await?.sleep;
+        let times = session.times().merge(times);
+        self.validate_session_times(&sleep, Some(id), session.kind(), &times).await?;
+
+        let updated = DBSleepSession::update_times(&mut *self.conn().await?, self.user_id, id, &times).await?;
+        found_or_err(updated, "sleep session", id)?;
+        Ok(times)
+    }
+
+    /// Deletes the sleep session from the database.
+    /// The amount of the sleep is updated to the new total of its remaining sessions.
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the session to delete
+    ///
+    pub async fn delete_sleep_session(&self, id: i64) -> Result<(), DBError> {
+        let uow = self.begin().await?;
+        let session = uow.get_sleep_session(id).await?;
+        let deleted = DBSleepSession::delete(&mut *uow.conn().await?, self.user_id, id).await?;
+        found_or_err(deleted, "sleep session", id)?;
+
+        DBSleep::update_total_amount(&mut *uow.conn().await?, self.user_id, session.sleep_id).await?;
+        uow.commit().await?;
+        Ok(())
+    }
+
+    /// Sets the minutes spent in each stage of sleep during the night of a sleep, replacing the stages it had.
+    /// Stages with every value None are removed from the sleep.
+    /// Returns a [NotFound](DBError::NotFound) error if the sleep does not exist, or a
+    /// [Validation](DBError::Validation) error if any of the minutes are negative
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep the stages belong to
+    /// * `stages` - minutes of each stage, None for stages that weren't recorded
+    /// 
+    pub async fn set_sleep_stages(&self, sleep_id: i64, stages: &SleepStages) -> Result<(), DBError> {
+        stages.validate().map_err(DBError::Validation)?;
+        self.get_sleep(sleep_id, false).await?;
+
+        if stages.is_empty() {
+            DBSleepStages::delete(&mut *self.conn().await?, self.user_id, sleep_id).await?;
+        }
+        else {
+            DBSleepStages::upsert(&mut *self.conn().await?, sleep_id, stages).await?;
+        }
+        Ok(())
+    }
+
+    /// Gets the minutes spent in each stage of sleep during the night of a sleep
+    /// Returns the stages, None if the sleep has no stages, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep to get the stages of
+    /// 
+    pub async fn get_sleep_stages(&self, sleep_id: i64) -> Result<Option<DBSleepStages>, DBError> {
+        Ok(DBSleepStages::select_by_sleep_id(&mut *self.conn().await?, self.user_id, sleep_id).await?)
+    }
+
+    /// Gets the stages of each of the given sleeps with a single query
+    /// Returns a map from each sleep id to its stages, sleeps without stages are not in the map,
+    /// or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_ids` - the ids of the sleeps to get the stages of
+    /// 
+    pub async fn get_sleep_stages_by_sleeps(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, DBSleepStages>, DBError> {
+        let stages = DBSleepStages::select_by_sleep_ids(&mut *self.conn().await?, self.user_id, sleep_ids).await?;
+        Ok(stages.into_iter().map(|s| (s.sleep_id, s)).collect())
+    }
+
+    /// Replaces the hypnogram of a sleep, the stages of sleep of the night in the order they happened.
+    /// The intervals must be ordered, must not overlap and must start on the night of the sleep.
+    /// An empty list removes the hypnogram from the sleep.
+    /// Returns a [NotFound](DBError::NotFound) error if the sleep does not exist, or a
+    /// [Validation](DBError::Validation) error if the intervals are not a hypnogram of the night
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep the hypnogram belongs to
+    /// * `intervals` - the stages of sleep, ordered by the time they started
+    /// 
+    pub async fn set_hypnogram(&self, sleep_id: i64, intervals: &[HypnogramInterval]) -> Result<(), DBError> {
+        HypnogramInterval::validate_all(intervals).map_err(DBError::Validation)?;
+        let sleep = self.get_sleep(sleep_id, false).await?.sleep;
+
+        let start = intervals.first().map(|i| SleepTimes { bed_time: Some(i.start), ..Default::default() });
+        if let Some(night) = start.and_then(|s| s.night()).filter(|n| *n != sleep.night) {
+            return Err(DBError::Validation(
+                format!("the hypnogram belongs to the night of {}, not {}", night, sleep.night)));
+        }
+
+        Ok(DBHypnogramInterval::replace(&mut *self.conn().await?, sleep_id, intervals).await?)
+    }
+
+    /// Get the hypnogram of a sleep, ordered by the time each stage started
+    /// Returns the intervals of the hypnogram, empty if the sleep has none, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep to get the hypnogram of
+    /// 
+    pub async fn get_hypnogram(&self, sleep_id: i64) -> Result<Vec<DBHypnogramInterval>, DBError> {
+        Ok(DBHypnogramInterval::select_by_sleep_id(&mut *self.conn().await?, self.user_id, sleep_id).await?)
+    }
+
+    /// Get the hypnograms of each of the given sleeps with a single query
+    /// Returns a map from each sleep id to the intervals of its hypnogram ordered by the time each stage started,
+    /// sleeps without a hypnogram map to an empty vector, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_ids` - the ids of the sleeps to get the hypnograms of
+    /// 
+    pub async fn get_hypnograms_by_sleeps(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Vec<DBHypnogramInterval>>, DBError> {
+        let mut intervals: HashMap<i64, Vec<DBHypnogramInterval>> = sleep_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for interval in DBHypnogramInterval::select_by_sleep_ids(&mut *self.conn().await?, self.user_id, sleep_ids).await? {
+            intervals.entry(interval.sleep_id).or_default().push(interval);
+        }
+
+        Ok(intervals)
+    }
+
+    /// Returns the main session of the sleep, if it has one,
+    /// or a [NotFound](DBError::NotFound) error if the sleep does not exist
+    async fn get_main_session(&self, sleep_id: i64) -> Result<Option<DBSleepSession>, DBError> {
+        self.get_sleep(sleep_id, false).await?;
+        let sessions = self.get_sleep_sessions(sleep_id).await?;
+        Ok(sessions.into_iter().find(|s| s.kind() == SessionKind::Main))
+    }
+
+    /// Validates that the times of a session are in order, belong to the night of the sleep,
+    /// and do not overlap any of the other sessions of the sleep.
+    /// Naps belong to the night when they start on the date of the night or the day after it.
+    async fn validate_session_times(
+        &self,
+        sleep: &DBSleep,
+        session_id: Option<i64>,
+        kind: SessionKind,
+        times: &SleepTimes)
+        -> Result<(), DBError> {
+        times.validate().map_err(DBError::Validation)?;
+
+        if kind == SessionKind::Nap {
+            let start = times.bed_time.or(times.sleep_onset).or(times.wake_time).map(|t| t.date());
+            if let Some(date) = start.filter(|d| *d < sleep.night || *d > sleep.night + Duration::days(1)) {
+                return Err(DBError::Validation(
+                    format!("a nap on {} does not belong to the night of {}", date, sleep.night)));
+            }
+        } else if let Some(night) = times.night().filter(|n| *n != sleep.night) {
+            return Err(DBError::Validation(
+                format!("the provided times belong to the night of {}, not {}", night, sleep.night)));
+        }
+
+        let sessions = self.get_sleep_sessions(sleep.id).await?;
+        if let Some(overlap) = sessions.iter().find(|s| Some(s.id) != session_id && s.times().overlaps(times)) {
+            return Err(DBError::Validation(format!("the provided times overlap sleep session {}", overlap.id)));
+        }
+
+        Ok(())
+    }
+
+    /// Adds a name and color to the tag table in the database, an alias with the same name stops resolving to its tag.
+    /// Returns the pk of the newly added row, or a [DuplicateTagName](DBError::DuplicateTagName)
+    /// error if the name is already used.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - A string slice representing the name of the tag. Tag names are unique.
+    /// * `color` - A decimal represntation of the rgb color value
+    /// 
+    /// # Examples
+    /// 
+    /// let tag_id = insert_tag("tag name", 9590460).await;
+    /// 
+    pub async fn insert_tag(&self, name: &str, color: i64) -> Result<i64, DBError> {
+        validate_not_empty("tag name", name)?;
+
+        let uow = self.begin().await?;
+        DBTagAlias::delete_by_name(&mut *uow.conn().await?, self.user_id, name).await?;
+        let id = DBTag::insert(&mut *uow.conn().await?, self.user_id, name, color).await
+            .map_err(|e| DBError::from_tag_write(e, name))?;
+
+        uow.commit().await?;
+        Ok(id)
+    }
+
+    /// Gets a tag from the database with the given id.
+    /// Returns the tag, or a [NotFound](DBError::NotFound) error if the tag does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the pk of the tag to query
+    /// 
+    pub async fn get_tag(&self, id: i64) -> Result<DBTag, DBError> {
+        DBTag::select_one(&mut *self.conn().await?, self.user_id, id).await
+            .map_err(|e| DBError::from_select(e, "tag", id))
+    }
+
+    /// Gets the tag with the given name, or the tag that the name is an alias of.
+    /// Returns the tag, or None if neither a tag nor an alias has the name
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - the name of the tag, or an old name of a tag that was merged or renamed
+    /// 
+    pub async fn get_tag_by_name(&self, name: &str) -> Result<Option<DBTag>, DBError> {
+        if let Some(tag) = DBTag::select_by_name(&mut *self.conn().await?, self.user_id, name).await? {
+            return Ok(Some(tag));
+        }
+
+        let alias = DBTagAlias::select_by_name(&mut *self.conn().await?, self.user_id, name).await?;
+        match alias {
+            Some(alias) => Ok(Some(self.get_tag(alias.tag_id).await?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Queries the aliases of a tag, the old names that resolve to it
+    /// Returns the aliases ordered by when they were added, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_id` - the id of the tag the aliases resolve to
+    /// 
+    pub async fn get_tag_aliases(&self, tag_id: i64) -> Result<Vec<DBTagAlias>, DBError> {
+        Ok(DBTagAlias::select_by_tag_id(&mut *self.conn().await?, self.user_id, tag_id).await?)
+    }
+
+    /// Queries all tags in the database
+    /// Returns all of the tags or an error if the query failed.
+    pub async fn get_all_tags(&self) -> Result<Vec<DBTag>, DBError> {
+        Ok(DBTag::select_all(&mut *self.conn().await?, self.user_id).await?)
+    }
+
+    /// Queries a page of the tags in the database, ordered by id.
+    /// Returns the tags in the page along with the page info, or a
+    /// [Validation](DBError::Validation) error if the page arguments are invalid
+    /// 
+    /// # Arguments
+    /// 
+    /// * `args` - keyset pagination arguments, the key of a tag is its id
+    /// 
+    pub async fn get_tags_page(&self, args: &PageArgs<i64>) -> Result<Page<DBTag>, DBError> {
+        args.validate()?;
+
+        let tags = DBTag::select_page(&mut *self.conn().await?, self.user_id, args.after, args.before, args.limit(), args.is_backward()).await?;
+        let total_count = DBTag::count(&mut *self.conn().await?, self.user_id).await?;
+
+        Ok(Page::from_rows(tags, args, total_count))
+    }
+
+    /// Queries all tags in the database that have an association with the given sleep
+    /// Returns vector of the tags that are related to the sleep, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep associated with the tags
+    /// 
+    pub async fn get_tags_by_sleep(&self, sleep_id: i64) ->  Result<Vec<DBTag>, DBError> {
+        let mut tags = self.get_tags_by_sleeps(&[sleep_id]).await?;
+        Ok(tags.remove(&sleep_id).unwrap_or_default())
+    }
+
+    /// Queries the tags of each of the given sleeps with a single query
+    /// Returns a map from each sleep id to its tags ordered by id, sleeps without tags map to an empty vector,
+    /// or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_ids` - the ids of the sleeps associated with the tags
+    /// 
+    pub async fn get_tags_by_sleeps(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Vec<DBTag>>, DBError> {
+        let mut tags: HashMap<i64, Vec<DBTag>> = sleep_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for (sleep_id, tag) in DBTag::select_by_sleep_ids(&mut *self.conn().await?, self.user_id, sleep_ids).await? {
+            tags.entry(sleep_id).or_default().push(tag);
+        }
+
+        Ok(tags)
+    }
+
+    /// Gets multiple tags based on the given ids with a single query.
+    /// Returns the tags that match the ids ordered by id, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `ids` - list of the tag ids to query
+    /// 
+    pub async fn get_multiple_tags(&self, ids: Vec<i64>) -> Result<Vec<DBTag>, DBError> {
+        Ok(DBTag::select_by_ids(&mut *self.conn().await?, self.user_id, &ids).await?)
+    }
+
+    /// Returns a [ForeignKeyViolation](DBError::ForeignKeyViolation) error for the first tag that
+    /// does not exist, tags of other users are treated as if they do not exist
+    async fn validate_tags_exist(&self, tag_ids: &[i64]) -> Result<(), DBError> {
+        let tags = self.get_multiple_tags(tag_ids.to_vec()).await?;
+        match tag_ids.iter().find(|id| !tags.iter().any(|t| t.id == **id)) {
+            Some(missing) => Err(DBError::ForeignKeyViolation(format!("tag {} does not exist", missing))),
+            None => Ok(())
+        }
+    }
+
+    /// Updates the name value of the tag in the database, the old name is kept as an alias of the tag
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist, or a
+    /// [DuplicateTagName](DBError::DuplicateTagName) error if another tag has the name
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the tag to update
+    /// * `name` - the new name value to update to
+    /// 
+    pub async fn update_tag_name(&self, id: i64, name: &str) -> Result<(), DBError> {
+        validate_not_empty("tag name", name)?;
+
+        let uow = self.begin().await?;
+        let old_name = uow.get_tag(id).await?.name;
+        let updated = DBTag::update_name(&mut *uow.conn().await?, self.user_id, id, name).await
+            .map_err(|e| DBError::from_tag_write(e, name))?;
+        found_or_err(updated, "tag", id)?;
+
+        if old_name != name {
+            DBTagAlias::delete_by_name(&mut *uow.conn().await?, self.user_id, name).await?;
+            DBTagAlias::upsert(&mut *uow.conn().await?, self.user_id, id, &old_name).await?;
+        }
+
+        uow.commit().await
+    }
+
+    /// Merges tags into a target tag. The sleeps and dreams of the source tags get the target tag instead,
+    /// without adding it twice to those that already have it, then the source tags are deleted.
+    /// The names and aliases of the source tags become aliases of the target tag, and their children become children of the target tag.
+    /// Returns a [Validation](DBError::Validation) error if no source tags are given or the target is one of them,
+    /// or a [NotFound](DBError::NotFound) error if any of the tags does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source_ids` - the ids of the tags to merge into the target
+    /// * `target_id` - the id of the tag to keep
+    /// 
+    pub async fn merge_tags(&self, source_ids: &[i64], target_id: i64) -> Result<(), DBError> {
+        if source_ids.is_empty() {
+            return Err(DBError::Validation(String::from("at least one tag must be merged")));
+        }
+        if source_ids.contains(&target_id) {
+            return Err(DBError::Validation(format!("tag {} can't be merged into itself", target_id)));
+        }
+
+        let uow = self.begin().await?;
+        uow.get_tag(target_id).await?;
+
+        let mut merged = HashSet::new();
+        for source_id in source_ids.iter().filter(|id| merged.insert(**id)) {
+            let source = uow.get_tag(*source_id).await?;
+            // a target below the source takes the place of the source, so moving the children of the source can't make a cycle
+            let parents = DBTagHierarchy::parents(&DBTagHierarchy::select_all(&mut *uow.conn().await?, self.user_id).await?);
+            if DBTagHierarchy::ancestors(&parents, target_id).contains(&source.id) {
+                DBTagHierarchy::upsert_parent(&mut *uow.conn().await?, target_id, parents.get(&source.id).copied()).await?;
+            }
+            DBTagHierarchy::reassign_children(&mut *uow.conn().await?, source.id, target_id).await?;
+            DBSleepTags::reassign(&mut *uow.conn().await?, source.id, target_id).await?;
+            DBDreamTags::reassign(&mut *uow.conn().await?, source.id, target_id).await?;
+            DBTagAlias::reassign(&mut *uow.conn().await?, self.user_id, source.id, target_id).await?;
+            DBTagAlias::upsert(&mut *uow.conn().await?, self.user_id, target_id, &source.name).await?;
+            DBTag::delete(&mut *uow.conn().await?, self.user_id, source.id).await?;
+        }
+
+        uow.commit().await
+    }
+
+    /// Updates the color value of the tag in the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the tag to update
+    /// * `color` - the new decimal represntation of the rgb color value value to update to.
+    ///
+    /// # Examples
+    /// 
+    /// let success = update_tag_color(2, 65535).await;
+    /// 
+    pub async fn update_tag_color(&self, id: i64, color: i64) -> Result<(), DBError> {
+        let updated = DBTag::update_color(&mut *self.conn().await?, self.user_id, id, color).await?;
+        found_or_err(updated, "tag", id)
+    }
+
+    /// Deletes the tag from the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the tag to delete
+    ///
+    pub async fn delete_tag(&self, id: i64) -> Result<(), DBError> {
+        let deleted = DBTag::delete(&mut *self.conn().await?, self.user_id, id).await?;
+        found_or_err(deleted, "tag", id)
+    }
+
+    /// Adds a tag category to the database, a category groups tags ex: "Substances".
+    /// Returns the pk of the newly added row, or a [DuplicateCategoryName](DBError::DuplicateCategoryName)
+    /// error if the name is already used.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `name` - the name of the category. Category names are unique.
+    /// 
+    pub async fn insert_tag_category(&self, name: &str) -> Result<i64, DBError> {
+        validate_not_empty("category name", name)?;
+
+        DBTagCategory::insert(&mut *self.conn().await?, self.user_id, name).await
+            .map_err(|e| DBError::from_category_write(e, name))
+    }
+
+    /// Gets a tag category from the database with the given id.
+    /// Returns the category, or a [NotFound](DBError::NotFound) error if the category does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the pk of the category to query
+    /// 
+    pub async fn get_tag_category(&self, id: i64) -> Result<DBTagCategory, DBError> {
+        DBTagCategory::select_one(&mut *self.conn().await?, self.user_id, id).await
+            .map_err(|e| DBError::from_select(e, "tag category", id))
+    }
+
+    /// Queries all tag categories ordered by id
+    /// Returns all of the categories or an error if the query failed.
+    pub async fn get_tag_categories(&self) -> Result<Vec<DBTagCategory>, DBError> {
+        Ok(DBTagCategory::select_all(&mut *self.conn().await?, self.user_id).await?)
+    }
+
+    /// Updates the name of the tag category in the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist, or a
+    /// [DuplicateCategoryName](DBError::DuplicateCategoryName) error if another category has the name
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the category to update
+    /// * `name` - the new name of the category
+    /// 
+    pub async fn update_tag_category_name(&self, id: i64, name: &str) -> Result<(), DBError> {
+        validate_not_empty("category name", name)?;
+
+        let updated = DBTagCategory::update_name(&mut *self.conn().await?, self.user_id, id, name).await
+            .map_err(|e| DBError::from_category_write(e, name))?;
+        found_or_err(updated, "tag category", id)
+    }
+
+    /// Deletes the tag category from the database, the tags of the category are kept without a category
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the category to delete
+    /// 
+    pub async fn delete_tag_category(&self, id: i64) -> Result<(), DBError> {
+        let deleted = DBTagCategory::delete(&mut *self.conn().await?, self.user_id, id).await?;
+        found_or_err(deleted, "tag category", id)
+    }
+
+    /// Queries the tags of a category ordered by id
+    /// Returns the tags of the category, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `category_id` - the id of the category
+    /// 
+    pub async fn get_tags_by_category(&self, category_id: i64) -> Result<Vec<DBTag>, DBError> {
+        let mut tags = self.get_tags_by_categories(&[category_id]).await?;
+        Ok(tags.remove(&category_id).unwrap_or_default())
+    }
+
+    /// Queries the tags of each of the given categories with a single query
+    /// Returns a map from each category id to its tags ordered by id, categories without tags map to an empty vector,
+    /// or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `category_ids` - the ids of the categories
+    /// 
+    pub async fn get_tags_by_categories(&self, category_ids: &[i64]) -> Result<HashMap<i64, Vec<DBTag>>, DBError> {
+        let mut tags: HashMap<i64, Vec<DBTag>> = category_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for (category_id, tag) in DBTag::select_by_category_ids(&mut *self.conn().await?, self.user_id, category_ids).await? {
+            tags.entry(category_id).or_default().push(tag);
+        }
+
+        Ok(tags)
+    }
+
+    /// Gets the parent and category of a tag
+    /// Returns the hierarchy of the tag, without a parent or category if it has neither,
+    /// or a [NotFound](DBError::NotFound) error if the tag does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_id` - the id of the tag
+    /// 
+    pub async fn get_tag_hierarchy(&self, tag_id: i64) -> Result<DBTagHierarchy, DBError> {
+        let hierarchy = DBTagHierarchy::select_one(&mut *self.conn().await?, self.user_id, tag_id).await?;
+        match hierarchy {
+            Some(hierarchy) => Ok(hierarchy),
+            None => self.get_tag(tag_id).await.map(|_| DBTagHierarchy { tag_id, ..Default::default() })
+        }
+    }
+
+    /// Gets the parent and category of each of the given tags with a single query
+    /// Returns a map from each tag id to its hierarchy, tags without a parent or category and tags that
+    /// don't exist map to a hierarchy without either, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_ids` - the ids of the tags
+    /// 
+    pub async fn get_tag_hierarchies(&self, tag_ids: &[i64]) -> Result<HashMap<i64, DBTagHierarchy>, DBError> {
+        let mut hierarchies: HashMap<i64, DBTagHierarchy> = tag_ids.iter()
+            .map(|id| (*id, DBTagHierarchy { tag_id: *id, ..Default::default() })).collect();
+
+        for hierarchy in DBTagHierarchy::select_by_tag_ids(&mut *self.conn().await?, self.user_id, tag_ids).await? {
+            hierarchies.insert(hierarchy.tag_id, hierarchy);
+        }
+
+        Ok(hierarchies)
+    }
+
+    /// Queries the tags whose parent is the given tag, ordered by id
+    /// Returns the children of the tag, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_id` - the id of the parent tag
+    /// 
+    pub async fn get_tag_children(&self, tag_id: i64) -> Result<Vec<DBTag>, DBError> {
+        let mut children = self.get_tag_children_by_parents(&[tag_id]).await?;
+        Ok(children.remove(&tag_id).unwrap_or_default())
+    }
+
+    /// Queries the children of each of the given tags with a single query
+    /// Returns a map from each tag id to its children ordered by id, tags without children map to an empty vector,
+    /// or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `parent_ids` - the ids of the parent tags
+    /// 
+    pub async fn get_tag_children_by_parents(&self, parent_ids: &[i64]) -> Result<HashMap<i64, Vec<DBTag>>, DBError> {
+        let mut children: HashMap<i64, Vec<DBTag>> = parent_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for (parent_id, tag) in DBTag::select_by_parent_ids(&mut *self.conn().await?, self.user_id, parent_ids).await? {
+            children.entry(parent_id).or_default().push(tag);
+        }
+
+        Ok(children)
+    }
+
+    /// Sets the parent of a tag, a tag can't be below itself so the parent can't be the tag or one of its descendants.
+    /// Returns a [NotFound](DBError::NotFound) error if the tag or parent does not exist,
+    /// or a [Validation](DBError::Validation) error if the parent is the tag or one of its descendants
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_id` - the id of the tag to set the parent of
+    /// * `parent_id` - the id of the parent tag, or None to move the tag to the top of the hierarchy
+    /// 
+    pub async fn set_tag_parent(&self, tag_id: i64, parent_id: Option<i64>) -> Result<(), DBError> {
+        let uow = self.begin().await?;
+        uow.get_tag(tag_id).await?;
+
+        if let Some(parent_id) = parent_id {
+            uow.get_tag(parent_id).await?;
+            let parents = DBTagHierarchy::parents(&DBTagHierarchy::select_all(&mut *uow.conn().await?, self.user_id).await?);
+            if parent_id == tag_id || DBTagHierarchy::ancestors(&parents, parent_id).contains(&tag_id) {
+                return Err(DBError::Validation(format!("tag {} can't be the parent of tag {} that is above it", parent_id, tag_id)));
+            }
+        }
+
+        DBTagHierarchy::upsert_parent(&mut *uow.conn().await?, tag_id, parent_id).await?;
+        uow.commit().await
+    }
+
+    /// Sets the category of a tag
+    /// Returns a [NotFound](DBError::NotFound) error if the tag or category does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_id` - the id of the tag to set the category of
+    /// * `category_id` - the id of the category, or None to remove the tag from its category
+    /// 
+    pub async fn set_tag_category(&self, tag_id: i64, category_id: Option<i64>) -> Result<(), DBError> {
+        let uow = self.begin().await?;
+        uow.get_tag(tag_id).await?;
+        if let Some(category_id) = category_id {
+            uow.get_tag_category(category_id).await?;
+        }
+
+        DBTagHierarchy::upsert_category(&mut *uow.conn().await?, tag_id, category_id).await?;
+        uow.commit().await
+    }
+
+    /// Counts the sleeps that have a tag, or with roll up the sleeps that have the tag or any of its descendants.
+    /// A sleep with several of the tags is counted once.
+    /// Returns the number of sleeps, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_id` - the id of the tag
+    /// * `roll_up` - whether the sleeps of the descendants of the tag are counted for the tag
+    /// 
+    pub async fn count_sleeps_by_tag(&self, tag_id: i64, roll_up: bool) -> Result<i64, DBError> {
+        let tag_ids = if roll_up {
+            let parents = DBTagHierarchy::parents(&DBTagHierarchy::select_all(&mut *self.conn().await?, self.user_id).await?);
+            DBTagHierarchy::with_descendants(&parents, tag_id)
+        }
+        else {
+            vec![tag_id]
+        };
+
+        Ok(DBSleepTags::count_sleeps(&mut *self.conn().await?, self.user_id, &tag_ids).await?)
+    }
+
+    /// Adds an association between a list of tags and a sleep, tags the sleep already has are skipped
+    /// Returns a [NotFound](DBError::NotFound) error if the sleep does not exist, or a
+    /// [ForeignKeyViolation](DBError::ForeignKeyViolation) error for the first tag that does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep to add the tags to
+    /// * `tag_ids` - the ids of the tags to add to the sleep
+    /// 
+    pub async fn add_tags_to_sleep(&self, sleep_id: i64, tag_ids: Vec<i64>) -> Result<(), DBError> {
+        let uow = self.begin().await?;
+        DBSleep::select_one(&mut *uow.conn().await?, self.user_id, sleep_id).await
+            .map_err(|e| DBError::from_select(e, "sleep", sleep_id))?;
+        uow.validate_tags_exist(&tag_ids).await?;
+
+        for tag_id in tag_ids {
+            DBSleepTags::insert(&mut *uow.conn().await?, sleep_id, tag_id).await
+                .map_err(|e| match e {
+                    e if DBError::is_foreign_key_violation(&e) =>
+                        DBError::ForeignKeyViolation(format!("tag {} does not exist", tag_id)),
+                    e => e.into()
+                })?;
+        }
+
+        uow.commit().await?;
+        Ok(())
+    }
+
+    /// Makes the tags of a sleep exactly the given tags, adding the tags it doesn't have and removing the ones that aren't given.
+    /// Nothing is changed if any of the tags does not exist.
+    /// Returns a [NotFound](DBError::NotFound) error if the sleep does not exist, or a
+    /// [ForeignKeyViolation](DBError::ForeignKeyViolation) error for the first tag that does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - the id of the sleep to set the tags of
+    /// * `tag_ids` - the ids of every tag the sleep should have
+    /// 
+    pub async fn set_sleep_tags(&self, sleep_id: i64, tag_ids: &[i64]) -> Result<(), DBError> {
+        // the sleep and tags are checked when the missing tags are added, after the other tags are removed
+        let uow = self.begin().await?;
+        uow.merge_sleep_tags(sleep_id, tag_ids, MergePolicy::Replace).await?;
+        uow.commit().await
+    }
+
+    /// Removes a relationship between a tag and a sleep
+    /// Returns a [NotFound](DBError::NotFound) error if the tag is not associated to the sleep
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - id of the sleep to remove the relationship from
+    /// * `tag_id` - id of tag to remove the relationship from
+    /// 
+    pub async fn remove_tag_from_sleep(&self, sleep_id: i64, tag_id: i64) -> Result<(), DBError> {
+        let deleted = DBSleepTags::delete(&mut *self.conn().await?, self.user_id, sleep_id, tag_id).await?;
+        found_or_err(deleted, "sleep tag", tag_id)
+    }
+
+    /// Adds a comment to the comment table in the database and relates it to a sleep.
+    /// Returns the pk of the newly added row, or a [ForeignKeyViolation](DBError::ForeignKeyViolation)
+    /// error if the sleep does not exist.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - sleep to relate the comment to
+    /// * `comment` - text comment to add
+    /// 
+    pub async fn insert_comment(&self, sleep_id: i64, comment: &str) -> Result<i64, DBError> {
+        validate_not_empty("comment", comment)?;
+
+        DBComment::insert(&mut *self.conn().await?, self.user_id, sleep_id, comment).await
+            .map_err(|e| match e {
+                e if DBError::is_foreign_key_violation(&e) =>
+                    DBError::ForeignKeyViolation(format!("sleep {} does not exist", sleep_id)),
+                e => e.into()
+            })
+    }
+
+    /// Gets a comment from the database with the given id.
+    /// Returns the comment, or a [NotFound](DBError::NotFound) error if the comment does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `comment_id` - the pk of the comment to query
+    ///
+    pub async fn get_comment(&self, comment_id: i64) -> Result<DBComment, DBError> {
+        DBComment::select_by_id(&mut *self.conn().await?, self.user_id, comment_id).await
+            .map_err(|e| DBError::from_select(e, "comment", comment_id))
+    }
+
+    /// Get all comments associated to a sleep
+    /// Returns the comments related to the sleep, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep-id` - The id of the sleep to get the comments from
+    /// 
+    pub async fn get_comments_by_sleep(&self, sleep_id: i64) -> Result<Vec<DBComment>, DBError> {
+        Ok(DBComment::select_by_sleep_id(&mut *self.conn().await?, self.user_id, sleep_id).await?)
+    }
+
+    /// Get the comments of each of the given sleeps with a single query
+    /// Returns a map from each sleep id to its comments ordered by id, sleeps without comments map to an empty vector,
+    /// or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_ids` - The ids of the sleeps to get the comments from
+    /// 
+    pub async fn get_comments_by_sleeps(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Vec<DBComment>>, DBError> {
+        let mut comments: HashMap<i64, Vec<DBComment>> = sleep_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for comment in DBComment::select_by_sleep_ids(&mut *self.conn().await?, self.user_id, sleep_ids).await? {
+            comments.entry(comment.sleep_id).or_default().push(comment);
+        }
+
+        Ok(comments)
+    }
+
+    /// Queries a page of the comments in the database, ordered by id.
+    /// Returns the comments in the page along with the page info, or a
+    /// [Validation](DBError::Validation) error if the page arguments are invalid
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - optionally only list the comments of the sleep
+    /// * `args` - keyset pagination arguments, the key of a comment is its id
+    /// 
+    pub async fn get_comments_page(&self, sleep_id: Option<i64>, args: &PageArgs<i64>) -> Result<Page<DBComment>, DBError> {
+        args.validate()?;
+
+        let comments = DBComment::select_page(&mut *self.conn().await?, self.user_id, sleep_id, args.after, args.before, args.limit(), args.is_backward()).await?;
+        let total_count = DBComment::count(&mut *self.conn().await?, self.user_id, sleep_id).await?;
+
+        Ok(Page::from_rows(comments, args, total_count))
+    }
+
+    /// Updates the text of a comment in the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `comment_id` - the id of the comment to edit
+    /// * `comment` - the new text value to update the comment to
+    /// 
+    pub async fn update_comment(&self, comment_id: i64, comment: &str) -> Result<(), DBError> {
+        validate_not_empty("comment", comment)?;
+
+        let updated = DBComment::update_comment(&mut *self.conn().await?, self.user_id, comment_id, comment).await?;
+        found_or_err(updated, "comment", comment_id)
+    }
+
+    /// Deletes the comment from the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the comment to delete
+    ///
+    pub async fn delete_comment(&self, id: i64) -> Result<(), DBError> {
+        let deleted = DBComment::delete(&mut *self.conn().await?, self.user_id, id).await?;
+        found_or_err(deleted, "comment", id)
+    }
+
+    /// Adds a dream to the dream table in the database and relates it to a sleep.
+    /// Returns the pk of the newly added row, a [Validation](DBError::Validation) error if the title is empty
+    /// or a score is out of range, or a [ForeignKeyViolation](DBError::ForeignKeyViolation) error if the sleep does not exist.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - sleep the dream happened during
+    /// * `details` - title, narrative, flags and scores of the dream
+    /// 
+    pub async fn insert_dream(&self, sleep_id: i64, details: &DreamDetails) -> Result<i64, DBError> {
+        details.validate().map_err(DBError::Validation)?;
+
+        // the dream table has no user, so check the sleep belongs to the user before referencing it
+        self.get_sleep(sleep_id, false).await.map_err(|e| match e {
+            DBError::NotFound { .. } => DBError::ForeignKeyViolation(format!("sleep {} does not exist", sleep_id)),
+            e => e
+        })?;
+
+        DBDream::insert(&mut *self.conn().await?, sleep_id, details).await
+            .map_err(|e| match e {
+                e if DBError::is_foreign_key_violation(&e) =>
+                    DBError::ForeignKeyViolation(format!("sleep {} does not exist", sleep_id)),
+                e => e.into()
+            })
+    }
+
+    /// Gets a dream from the database with the given id.
+    /// Returns the dream, or a [NotFound](DBError::NotFound) error if the dream does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `dream_id` - the pk of the dream to query
+    ///
+    pub async fn get_dream(&self, dream_id: i64) -> Result<DBDream, DBError> {
+        DBDream::select_by_id(&mut *self.conn().await?, self.user_id, dream_id).await
+            .map_err(|e| DBError::from_select(e, "dream", dream_id))
+    }
+
+    /// Get all dreams associated to a sleep
+    /// Returns the dreams related to the sleep, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_id` - The id of the sleep to get the dreams from
+    /// 
+    pub async fn get_dreams_by_sleep(&self, sleep_id: i64) -> Result<Vec<DBDream>, DBError> {
+        Ok(DBDream::select_by_sleep_id(&mut *self.conn().await?, self.user_id, sleep_id).await?)
+    }
+
+    /// Get the dreams of each of the given sleeps with a single query
+    /// Returns a map from each sleep id to its dreams ordered by id, sleeps without dreams map to an empty vector,
+    /// or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `sleep_ids` - The ids of the sleeps to get the dreams from
+    /// 
+    pub async fn get_dreams_by_sleeps(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Vec<DBDream>>, DBError> {
+        let mut dreams: HashMap<i64, Vec<DBDream>> = sleep_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for dream in DBDream::select_by_sleep_ids(&mut *self.conn().await?, self.user_id, sleep_ids).await? {
+            dreams.entry(dream.sleep_id).or_default().push(dream);
+        }
+
+        Ok(dreams)
+    }
+
+    /// Queries the dreams of the sleeps with a night between the start and end dates, inclusive, ordered by night
+    /// Returns vector of dreams within the range or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `start` - first night of the range
+    /// * `end` - last night of the range, must not be before the start
+    /// 
+    pub async fn get_dreams_in_range(&self, start: NaiveDate, end: NaiveDate) -> Result<Vec<DBDream>, DBError> {
+        if start > end {
+            return Err(DBError::Validation(format!("range start {} is after the range end {}", start, end)));
+        }
+
+        Ok(DBDream::select_in_range(&mut *self.conn().await?, self.user_id, start, end).await?)
+    }
+
+    /// Queries all dreams in the database that have an association with the given tag, ordered by night
+    /// Returns vector of the dreams that have the tag, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `tag_id` - the id of the tag associated with the dreams
+    /// 
+    pub async fn get_dreams_by_tag(&self, tag_id: i64) -> Result<Vec<DBDream>, DBError> {
+        Ok(DBDream::select_by_tag_id(&mut *self.conn().await?, self.user_id, tag_id).await?)
+    }
+
+    /// Queries a page of the dreams in the database, ordered by the night of their sleep and id.
+    /// Returns the dreams in the page paired with their night, along with the page info, or a
+    /// [Validation](DBError::Validation) error if the page arguments or the range are invalid
+    /// 
+    /// # Arguments
+    /// 
+    /// * `args` - keyset pagination arguments, the key of a dream is its night and id
+    /// * `filter` - optionally only list the dreams in a range of nights, or with a tag
+    /// 
+    pub async fn get_dreams_page(&self, args: &PageArgs<(NaiveDate, i64)>, filter: &DreamFilter) -> Result<Page<(NaiveDate, DBDream)>, DBError> {
+        args.validate()?;
+        if let Some((start, end)) = filter.range.filter(|(start, end)| start > end) {
+            return Err(DBError::Validation(format!("range start {} is after the range end {}", start, end)));
+        }
+
+        let dreams = DBDream::select_page(&mut *self.conn().await?, self.user_id, args.after, args.before, filter, args.limit(), args.is_backward()).await?;
+        let total_count = DBDream::count(&mut *self.conn().await?, self.user_id, filter).await?;
+
+        Ok(Page::from_rows(dreams, args, total_count))
+    }
+
+    /// Updates the content of a dream in the database. Fields that are None keep their current value.
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist, or a
+    /// [Validation](DBError::Validation) error if the updated dream is invalid
+    /// 
+    /// # Arguments
+    /// 
+    /// * `dream_id` - the id of the dream to edit
+    /// * `update` - the fields of the dream to update
+    /// 
+    pub async fn update_dream(&self, dream_id: i64, update: &DreamUpdate) -> Result<(), DBError> {
+        let dream = self.get_dream(dream_id).await?;
+        let details = update.apply(&dream.details());
+        details.validate().map_err(DBError::Validation)?;
+
+        let updated = DBDream::update(&mut *self.conn().await?, self.user_id, dream_id, &details).await?;
+        found_or_err(updated, "dream", dream_id)
+    }
+
+    /// Deletes the dream from the database
+    /// Returns a [NotFound](DBError::NotFound) error if the row does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id` - the id of the dream to delete
+    ///
+    pub async fn delete_dream(&self, id: i64) -> Result<(), DBError> {
+        let deleted = DBDream::delete(&mut *self.conn().await?, self.user_id, id).await?;
+        found_or_err(deleted, "dream", id)
+    }
+
+    /// Queries all tags in the database that have an association with the given dream
+    /// Returns vector of the tags that are related to the dream, or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `dream_id` - the id of the dream associated with the tags
+    /// 
+    pub async fn get_tags_by_dream(&self, dream_id: i64) -> Result<Vec<DBTag>, DBError> {
+        let dream_tags = DBDreamTags::select_by_dream_id(&mut *self.conn().await?, self.user_id, dream_id).await?;
+        let tag_ids = dream_tags.iter().map(|x| x.tag_id).collect();
+
+        self.get_multiple_tags(tag_ids).await
+    }
+
+    /// Queries the tags of each of the given dreams with a single query
+    /// Returns a map from each dream id to its tags ordered by id, dreams without tags map to an empty vector,
+    /// or an error if the query failed
+    /// 
+    /// # Arguments
+    /// 
+    /// * `dream_ids` - the ids of the dreams associated with the tags
+    /// 
+    pub async fn get_tags_by_dreams(&self, dream_ids: &[i64]) -> Result<HashMap<i64, Vec<DBTag>>, DBError> {
+        let mut tags: HashMap<i64, Vec<DBTag>> = dream_ids.iter().map(|id| (*id, Vec::new())).collect();
+
+        for (dream_id, tag) in DBTag::select_by_dream_ids(&mut *self.conn().await?, self.user_id, dream_ids).await? {
+            tags.entry(dream_id).or_default().push(tag);
+        }
+
+        Ok(tags)
+    }
+
+    /// Adds an association between a list of tags and a dream, tags the dream already has are skipped
+    /// Returns a [NotFound](DBError::NotFound) error if the dream does not exist, or a
+    /// [ForeignKeyViolation](DBError::ForeignKeyViolation) error for the first tag that does not exist
+    /// 
+    /// # Arguments
+    /// 
+    /// * `dream_id` - the id of the dream to add the tags to
+    /// * `tag_ids` - the ids of the tags to add to the dream
+    /// 
+    pub async fn add_tags_to_dream(&self, dream_id: i64, tag_ids: Vec<i64>) -> Result<(), DBError> {
+        let uow = self.begin().await?;
+        uow.get_dream(dream_id).await?;
+        uow.validate_tags_exist(&tag_ids).await?;
+
+        for tag_id in tag_ids {
+            DBDreamTags::insert(&mut *uow.conn().await?, dream_id, tag_id).await
+                .map_err(|e| match e {
+                    e if DBError::is_foreign_key_violation(&e) =>
+                        DBError::ForeignKeyViolation(format!("tag {} does not exist", tag_id)),
+                    e => e.into()
+                })?;
+        }
+
+        uow.commit().await?;
+        Ok(())
+    }
+
+    /// Removes a relationship between a tag and a dream
+    /// Returns a [NotFound](DBError::NotFound) error if the tag is not associated to the dream
+    /// 
+    /// # Arguments
+    /// 
+    /// * `dream_id` - id of the dream to remove the relationship from
+    /// * `tag_id` - id of tag to remove the relationship from
+    /// 
+    pub async fn remove_tag_from_dream(&self, dream_id: i64, tag_id: i64) -> Result<(), DBError> {
+        let deleted = DBDreamTags::delete(&mut *self.conn().await?, self.user_id, dream_id, tag_id).await?;
+        found_or_err(deleted, "dream tag", tag_id)
     }
 }
 
+/// Converts the affected flag returned by an update or delete into a result,
+/// returning a [NotFound](DBError::NotFound) error if no rows were affected
+fn found_or_err(affected: bool, entity: &'static str, id: i64) -> Result<(), DBError> {
+    if affected {
+        Ok(())
+    }
+    else {
+        Err(DBError::not_found(entity, id))
+    }
+}
+
+/// Returns the night of a sleep, derived from the times when None.
+/// Returns a [Validation](DBError::Validation) error if the times belong to another night, or there is neither a night or a bed time.
+fn night_of(night: Option<NaiveDate>, times: &SleepTimes) -> Result<NaiveDate, DBError> {
+    match (night, times.night()) {
+        (Some(night), Some(times_night)) if night != times_night => Err(DBError::Validation(
+            format!("the provided times belong to the night of {}, not {}", times_night, night))),
+        (Some(night), _) | (None, Some(night)) => Ok(night),
+        (None, None) => Err(DBError::Validation(String::from("a night or bed time is required"))),
+    }
+}
+
+/// Validates that the amount of sleep is a non negative number
+fn validate_amount(amount: f64) -> Result<(), DBError> {
+    if amount.is_finite() && amount >= 0.0 {
+        Ok(())
+    }
+    else {
+        Err(DBError::Validation(format!("amount {} must be a non negative number", amount)))
+    }
+}
+
+/// Validates that a text field is not empty
+fn validate_not_empty(field: &str, value: &str) -> Result<(), DBError> {
+    if value.trim().is_empty() {
+        Err(DBError::Validation(format!("{} cannot be empty", field)))
+    }
+    else {
+        Ok(())
+    }
+}
+
+mod db_apple_health;
+pub use db_apple_health::{AppleHealthOptions, APPLE_HEALTH_SOURCE};
+mod db_auth;
+pub use db_auth::Principal;
+mod db_backup;
+pub use db_backup::{Backup, RestoreMode, RestoreReport, BACKUP_FORMAT_VERSION};
+mod db_csv;
+pub use db_csv::{CsvColumns, CsvImportOptions, DEFAULT_DATE_FORMAT, DEFAULT_TAG_DELIMITER};
+mod db_error;
+pub use db_error::DBError;
+mod db_fitbit;
+pub use db_fitbit::{FitbitOptions, FITBIT_SOURCE, FITBIT_TAG};
+mod db_import;
+pub use db_import::{ImportIssue, ImportIssueKind, ImportReport};
+use db_import::ImportedNight;
+
 mod db_migrations;
+mod db_page;
+pub use db_page::{Page, PageArgs, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+mod db_snapshot;
+pub use db_snapshot::{integrity_check, SnapshotSettings, SnapshotStatus, Snapshots};
+mod db_stats;
+pub use db_stats::{Comparison, RollingAverage, SleepStats, StageShares, StatsGroup, StatsGroupBy, Summary, TagImpact, TTest};
 mod db_types;
+mod db_unit_of_work;
+use db_unit_of_work::{DBConnection, UnitOfWork};
 
 /// Module for creating a mock db and testing the CRUD functionality of it
 pub mod db_tests;
\ No newline at end of file
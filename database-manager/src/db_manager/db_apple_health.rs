@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use super::db_import::{ImportIssue, ImportIssueKind, ImportedNight};
+use super::db_types::SleepTimes;
+use super::DBError;
+
+/// Source of the sleeps imported from Apple Health, re-importing an export only updates the sleeps with this source
+pub const APPLE_HEALTH_SOURCE: &str = "apple_health";
+
+/// Type of the records of an export that hold sleep
+const SLEEP_ANALYSIS_TYPE: &str = "HKCategoryTypeIdentifierSleepAnalysis";
+
+/// Format of the start and end dates of the records of an export, ex: "2023-05-13 23:10:00 -0700"
+const RECORD_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+/// How an Apple Health export is imported
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AppleHealthOptions {
+    /// Quality of the imported sleeps, Apple Health doesn't rate sleep
+    pub quality: i64,
+
+    /// Report what the import would do without changing the database
+    pub dry_run: bool,
+}
+
+/// What a sleep analysis record says the sleeper was doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SleepValue {
+    InBed,
+
+    /// Asleep in any stage, or asleep without a stage for records from before watches tracked stages
+    Asleep,
+
+    Awake,
+}
+
+impl SleepValue {
+    fn parse(value: &str) -> Option<SleepValue> {
+        match value {
+            "HKCategoryValueSleepAnalysisInBed" => Some(SleepValue::InBed),
+            "HKCategoryValueSleepAnalysisAwake" => Some(SleepValue::Awake),
+            // Asleep, AsleepUnspecified, AsleepCore, AsleepDeep and AsleepREM
+            v if v.starts_with("HKCategoryValueSleepAnalysisAsleep") => Some(SleepValue::Asleep),
+            _ => None,
+        }
+    }
+}
+
+/// A sleep analysis record
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    line: u64,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    value: SleepValue,
+}
+
+/// Reads the sleep analysis records of an Apple Health `export.xml` one record at a time, without
+/// loading the export into memory, and groups them into nights.
+/// The amount of a night is the time asleep, with overlapping records from multiple devices counted once,
+/// or the time in bed for nights without asleep records. Times are the local times the records were made in.
+/// Returns the nights and an issue for each record that could not be read,
+/// or a [Validation](DBError::Validation) error if the export is not valid xml.
+pub fn parse<R: BufRead>(export: R) -> Result<(Vec<ImportedNight>, Vec<ImportIssue>), DBError> {
+    let mut reader = Reader::from_reader(LineCounter { inner: export, line: 1 });
+    let mut buf = Vec::new();
+    let mut intervals = Vec::new();
+    let mut issues = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if e.name().as_ref() == b"Record" => {
+                let line = reader.get_ref().line;
+                match record(&e, line) {
+                    Ok(Some(interval)) => intervals.push(interval),
+                    Ok(None) => {},
+                    Err(message) => issues.push(ImportIssue::new(line, None, ImportIssueKind::Invalid, message)),
+                }
+            },
+            Ok(Event::Eof) => break,
+            Ok(_) => {},
+            Err(e) => return Err(DBError::Validation(
+                format!("unable to read the export at line {}: {}", reader.get_ref().line, e))),
+        }
+        buf.clear();
+    }
+
+    Ok((nights(intervals), issues))
+}
+
+/// Reads a record, returns None if it is not a sleep analysis record
+fn record(element: &BytesStart, line: u64) -> Result<Option<Interval>, String> {
+    let (mut kind, mut value, mut start, mut end) = (None, None, None, None);
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let text = attribute.unescape_value().map_err(|e| e.to_string())?.into_owned();
+        match attribute.key.as_ref() {
+            b"type" => kind = Some(text),
+            b"value" => value = Some(text),
+            b"startDate" => start = Some(text),
+            b"endDate" => end = Some(text),
+            _ => {},
+        }
+    }
+
+    if kind.as_deref() != Some(SLEEP_ANALYSIS_TYPE) {
+        return Ok(None);
+    }
+    let Some(value) = value.as_deref().and_then(SleepValue::parse) else {
+        return Ok(None);
+    };
+
+    let start = record_date(start.as_deref(), "startDate")?;
+    let end = record_date(end.as_deref(), "endDate")?;
+    if end <= start {
+        return Err(format!("record ends at {} before it starts at {}", end, start));
+    }
+
+    Ok(Some(Interval { line, start, end, value }))
+}
+
+/// Parses a date of a record into the local time it was recorded in
+fn record_date(date: Option<&str>, attribute: &str) -> Result<NaiveDateTime, String> {
+    let date = date.ok_or_else(|| format!("record has no {}", attribute))?;
+    DateTime::parse_from_str(date, RECORD_DATE_FORMAT)
+        .map(|d| d.naive_local())
+        .map_err(|_| format!("\"{}\" is not a {} in the {} format", date, attribute, RECORD_DATE_FORMAT))
+}
+
+/// Groups the records into nights by the time they start, ordered by night
+fn nights(intervals: Vec<Interval>) -> Vec<ImportedNight> {
+    let mut by_night: BTreeMap<NaiveDate, Vec<Interval>> = BTreeMap::new();
+    for interval in intervals.into_iter().filter(|i| i.value != SleepValue::Awake) {
+        let night = SleepTimes { bed_time: Some(interval.start), ..Default::default() }.night()
+            .expect("times with a bed time have a night");
+        by_night.entry(night).or_default().push(interval);
+    }
+
+    by_night.into_iter().map(|(night, intervals)| {
+        let in_bed = spans(&intervals, SleepValue::InBed);
+        let asleep = spans(&intervals, SleepValue::Asleep);
+        let counted = if asleep.is_empty() { &in_bed } else { &asleep };
+
+        let times = SleepTimes {
+            bed_time: if in_bed.is_empty() { None } else { intervals.iter().map(|i| i.start).min() },
+            sleep_onset: asleep.first().map(|(start, _)| *start),
+            wake_time: counted.iter().map(|(_, end)| *end).max(),
+        };
+        let seconds: i64 = counted.iter().map(|(start, end)| (*end - *start).num_seconds()).sum();
+
+        ImportedNight {
+            file: None,
+            line: intervals.iter().map(|i| i.line).min().unwrap_or(1),
+            night,
+            amount: seconds as f64 / 3600.0,
+            quality: None,
+            times,
+            stages: None,
+            hypnogram: Vec::new(),
+        }
+    }).collect()
+}
+
+/// Returns the time spans covered by the records with the given value, ordered by start,
+/// with overlapping records merged so time recorded by more than one device is only counted once
+fn spans(intervals: &[Interval], value: SleepValue) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    let mut sorted = intervals.iter().filter(|i| i.value == value).map(|i| (i.start, i.end)).collect::<Vec<_>>();
+    sorted.sort();
+
+    let mut merged: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+    for (start, end) in sorted {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Counts the lines of a reader as they are read, so records can be reported by line
+struct LineCounter<R> {
+    inner: R,
+
+    /// Line of the next byte that is read, starting at 1
+    line: u64,
+}
+
+impl<R: BufRead> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.line += count_lines(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for LineCounter<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        // the buffer is already filled, so this only returns the bytes that are being consumed
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.line += count_lines(&buf[..amount.min(buf.len())]);
+        }
+        self.inner.consume(amount);
+    }
+}
+
+fn count_lines(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|b| **b == b'\n').count() as u64
+}
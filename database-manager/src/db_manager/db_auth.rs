@@ -0,0 +1,43 @@
+//! Generating and hashing api tokens, and the principal a request is authenticated as
+
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+/// Prefix of every api token, so tokens are easy to recognize if they are leaked
+const TOKEN_PREFIX: &str = "st_";
+
+/// Number of random bytes in an api token
+const TOKEN_BYTES: usize = 32;
+
+/// The user a request is authenticated as, and what the request is allowed to do
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    /// id of the user the request reads and changes the data of
+    pub user_id: i64,
+
+    /// name of the user
+    pub user_name: String,
+
+    /// id of the api token the request was authenticated with
+    pub token_id: i64,
+
+    /// true if the request can only query data
+    pub read_only: bool,
+
+    /// true if the request can also manage the server, ex: add users
+    pub admin: bool,
+}
+
+/// Returns a new random api token
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+/// Returns the hash of an api token that is stored in the database.
+/// Tokens are long random values, so a fast hash is enough to keep a copy of the database from leaking them.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Sqlite, SqlitePool, Transaction};
+use super::db_migrations;
+use super::DBError;
+
+/// Version of the backup document format, increased whenever the fields of a backup change.
+/// Version 2 added the source of sleeps, version 3 added sleep stages and hypnograms, version 4 added tag aliases
+/// and version 5 added tag categories and the parents of tags.
+pub const BACKUP_FORMAT_VERSION: i64 = 5;
+
+/// Every row of the database in a versioned document that can be written as JSON.
+/// Rows keep the ids, nights, times and timestamps exactly as they are stored.
+/// API tokens are not part of a backup, they have to be created again after restoring into a new database.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    /// Version of the backup document format, see [BACKUP_FORMAT_VERSION]
+    pub format_version: i64,
+
+    /// Schema version of the database the backup was made from, the `user_version` of the database
+    pub schema_version: i64,
+
+    pub users: Vec<BackupUser>,
+    pub sleeps: Vec<BackupSleep>,
+    pub sleep_sessions: Vec<BackupSleepSession>,
+    pub tags: Vec<BackupTag>,
+    pub sleep_tags: Vec<BackupSleepTag>,
+    pub comments: Vec<BackupComment>,
+    pub dreams: Vec<BackupDream>,
+    pub dream_tags: Vec<BackupDreamTag>,
+
+    /// Missing from backups of format version 1 and 2
+    #[serde(default)]
+    pub sleep_stages: Vec<BackupSleepStages>,
+
+    /// Missing from backups of format version 1 and 2
+    #[serde(default)]
+    pub hypnogram: Vec<BackupHypnogramInterval>,
+
+    /// Missing from backups of format version 1 to 3
+    #[serde(default)]
+    pub tag_aliases: Vec<BackupTagAlias>,
+
+    /// Missing from backups of format version 1 to 4
+    #[serde(default)]
+    pub tag_categories: Vec<BackupTagCategory>,
+
+    /// Missing from backups of format version 1 to 4
+    #[serde(default)]
+    pub tag_hierarchy: Vec<BackupTagHierarchy>,
+}
+
+/// A row of the user table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupUser {
+    pub id: i64,
+    pub name: String,
+    pub created_on: String,
+    pub updated_on: String,
+}
+
+/// A row of the sleep table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupSleep {
+    pub id: i64,
+    pub user_id: i64,
+    pub night: String,
+    pub amount: f64,
+    pub quality: i64,
+
+    /// Source that imported the sleep, missing from backups of format version 1
+    #[serde(default)]
+    pub source: Option<String>,
+    pub created_on: String,
+    pub updated_on: String,
+}
+
+/// A row of the sleep_session table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupSleepSession {
+    pub id: i64,
+    pub sleep_id: i64,
+    pub kind: String,
+    pub amount: f64,
+    pub bed_time: Option<String>,
+    pub sleep_onset: Option<String>,
+    pub wake_time: Option<String>,
+    pub created_on: String,
+    pub updated_on: String,
+}
+
+/// A row of the tag table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupTag {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub color: i64,
+    pub created_on: String,
+    pub updated_on: String,
+}
+
+/// A row of the tag_alias table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupTagAlias {
+    pub id: i64,
+    pub tag_id: i64,
+    pub name: String,
+    pub created_on: String,
+}
+
+/// A row of the tag_category table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupTagCategory {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub created_on: String,
+    pub updated_on: String,
+}
+
+/// A row of the tag_hierarchy table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupTagHierarchy {
+    pub tag_id: i64,
+    pub parent_id: Option<i64>,
+    pub category_id: Option<i64>,
+}
+
+/// A row of the sleep_tags table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupSleepTag {
+    pub sleep_id: i64,
+    pub tag_id: i64,
+}
+
+/// A row of the comment table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupComment {
+    pub id: i64,
+    pub user_id: i64,
+    pub sleep_id: i64,
+    pub comment: String,
+}
+
+/// A row of the dream table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupDream {
+    pub id: i64,
+    pub sleep_id: i64,
+    pub title: String,
+    pub narrative: String,
+    pub lucid: bool,
+    pub vividness: Option<i64>,
+    pub emotional_tone: Option<i64>,
+    pub recurring: bool,
+    pub created_on: String,
+    pub updated_on: String,
+}
+
+/// A row of the dream_tags table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupDreamTag {
+    pub dream_id: i64,
+    pub tag_id: i64,
+}
+
+/// A row of the sleep_stages table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupSleepStages {
+    pub sleep_id: i64,
+    pub deep_minutes: Option<i64>,
+    pub light_minutes: Option<i64>,
+    pub rem_minutes: Option<i64>,
+    pub awake_minutes: Option<i64>,
+    pub created_on: String,
+    pub updated_on: String,
+}
+
+/// A row of the hypnogram table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow)]
+pub struct BackupHypnogramInterval {
+    pub id: i64,
+    pub sleep_id: i64,
+    pub stage: String,
+    pub start_time: String,
+    pub end_time: String,
+}
+
+/// How a backup is restored into a database that may already have data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestoreMode {
+    /// Keep the data of the database and add the rows of the backup that it doesn't have.
+    /// Users are matched by name, tags and tag categories by user and name, and sleeps by user and night.
+    /// Tags that already exist keep their parent and category.
+    /// A sleep of the backup for a night that already has a sleep is skipped along with its sessions, stages, tags, comments and dreams.
+    #[default]
+    Merge,
+
+    /// Delete the sleeps, tags and tag categories of every user, with everything that belongs to them, and restore the backup with its ids.
+    /// Users are kept, so api tokens keep working, and the users of the backup are matched by name.
+    Replace,
+}
+
+impl FromStr for RestoreMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.to_ascii_lowercase().as_str() {
+            "merge" => Ok(RestoreMode::Merge),
+            "replace" => Ok(RestoreMode::Replace),
+            _ => Err(format!("\"{}\" is not a restore mode, expected merge or replace", mode)),
+        }
+    }
+}
+
+impl fmt::Display for RestoreMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestoreMode::Merge => write!(f, "merge"),
+            RestoreMode::Replace => write!(f, "replace"),
+        }
+    }
+}
+
+/// Number of rows of each table that were restored
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestoreReport {
+    pub mode: RestoreMode,
+
+    /// Users that were created because no user had the name
+    pub users: usize,
+    pub sleeps: usize,
+    pub sleep_sessions: usize,
+    pub tags: usize,
+    pub sleep_tags: usize,
+    pub comments: usize,
+    pub dreams: usize,
+    pub dream_tags: usize,
+    pub sleep_stages: usize,
+    pub hypnogram: usize,
+
+    /// Aliases that were restored, an alias is not restored when the user already has an alias with the name
+    pub tag_aliases: usize,
+
+    /// Categories that were created because the user has no category with the name
+    pub tag_categories: usize,
+
+    /// Tags whose parent or category was restored
+    pub tag_hierarchy: usize,
+
+    /// Sleeps of the backup that were not restored because the user already has a sleep for the night
+    pub skipped_sleeps: usize,
+
+    /// Tags of the backup that were not restored because the user already has a tag with the name
+    pub reused_tags: usize,
+}
+
+impl Backup {
+    /// Reads a backup from a JSON document, returns a [Validation](DBError::Validation) error if it is not a backup
+    pub fn from_json(json: &str) -> Result<Backup, DBError> {
+        serde_json::from_str(json).map_err(|e| DBError::Validation(format!("invalid backup: {}", e)))
+    }
+
+    /// Writes the backup as a pretty printed JSON document
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a backup only holds values that can be written as JSON")
+    }
+}
+
+/// Reads every row of the database inside of a single transaction, so the backup is consistent
+/// even when the database is changed while the backup is made
+pub async fn export(pool: &SqlitePool) -> Result<Backup, DBError> {
+    let mut tx = pool.begin().await?;
+
+    let schema_version = sqlx::query_scalar("PRAGMA user_version").fetch_one(&mut tx).await?;
+    let backup = Backup {
+        format_version: BACKUP_FORMAT_VERSION,
+        schema_version,
+        users: select_all(&mut tx, "SELECT id, name, created_on, updated_on FROM user ORDER BY id").await?,
+        sleeps: select_all(&mut tx,
+            "SELECT id, user_id, night, amount, quality, source, created_on, updated_on FROM sleep ORDER BY id").await?,
+        sleep_sessions: select_all(&mut tx,
+            "SELECT id, sleep_id, kind, amount, bed_time, sleep_onset, wake_time, created_on, updated_on
+            FROM sleep_session ORDER BY id").await?,
+        tags: select_all(&mut tx, "SELECT id, user_id, name, color, created_on, updated_on FROM tag ORDER BY id").await?,
+        sleep_tags: select_all(&mut tx, "SELECT sleep_id, tag_id FROM sleep_tags ORDER BY id").await?,
+        comments: select_all(&mut tx, "SELECT id, user_id, sleep_id, comment FROM comment ORDER BY id").await?,
+        dreams: select_all(&mut tx,
+            "SELECT id, sleep_id, title, narrative, lucid, vividness, emotional_tone, recurring, created_on, updated_on
+            FROM dream ORDER BY id").await?,
+        dream_tags: select_all(&mut tx, "SELECT dream_id, tag_id FROM dream_tags ORDER BY id").await?,
+        sleep_stages: select_all(&mut tx,
+            "SELECT sleep_id, deep_minutes, light_minutes, rem_minutes, awake_minutes, created_on, updated_on
+            FROM sleep_stages ORDER BY sleep_id").await?,
+        hypnogram: select_all(&mut tx, "SELECT id, sleep_id, stage, start_time, end_time FROM hypnogram ORDER BY id").await?,
+        tag_aliases: select_all(&mut tx, "SELECT id, tag_id, name, created_on FROM tag_alias ORDER BY id").await?,
+        tag_categories: select_all(&mut tx, "SELECT id, user_id, name, created_on, updated_on FROM tag_category ORDER BY id").await?,
+        tag_hierarchy: select_all(&mut tx, "SELECT tag_id, parent_id, category_id FROM tag_hierarchy ORDER BY tag_id").await?,
+    };
+
+    tx.commit().await?;
+    Ok(backup)
+}
+
+async fn select_all<T>(tx: &mut Transaction<'_, Sqlite>, sql: &str) -> Result<Vec<T>, sqlx::Error>
+where T: for<'r> FromRow<'r, sqlx::sqlite::SqliteRow> + Send + Unpin {
+    sqlx::query_as::<_, T>(sql).fetch_all(tx).await
+}
+
+/// Restores a backup inside of a single transaction, so either the whole backup is restored or nothing changes.
+/// Returns the number of restored rows, or a [Validation](DBError::Validation) error if the backup
+/// is of an unknown format, from a newer schema, or has rows that reference rows it doesn't have.
+pub async fn restore(pool: &SqlitePool, backup: &Backup, mode: RestoreMode) -> Result<RestoreReport, DBError> {
+    if backup.format_version < 1 || backup.format_version > BACKUP_FORMAT_VERSION {
+        return Err(DBError::Validation(format!("backup format version {} is not supported, expected version 1 to {}",
+            backup.format_version, BACKUP_FORMAT_VERSION)));
+    }
+    if backup.schema_version > db_migrations::latest_version() {
+        return Err(DBError::UnsupportedSchemaVersion { found: backup.schema_version, supported: db_migrations::latest_version() });
+    }
+
+    let mut tx = pool.begin().await?;
+    let mut report = RestoreReport { mode, ..Default::default() };
+    let replace = mode == RestoreMode::Replace;
+
+    if replace {
+        // every other row belongs to a sleep or a tag, and is deleted along with it
+        sqlx::query("DELETE FROM sleep").execute(&mut tx).await?;
+        sqlx::query("DELETE FROM tag").execute(&mut tx).await?;
+        sqlx::query("DELETE FROM tag_category").execute(&mut tx).await?;
+    }
+    // ids of the backup are only kept when replacing, merged rows get new ids
+    let keep_id = |id: i64| if replace { Some(id) } else { None };
+
+    let mut users = HashMap::new();
+    for user in &backup.users {
+        let existing: Option<i64> = sqlx::query_scalar("SELECT id FROM user WHERE name = ?1")
+            .bind(&user.name).fetch_optional(&mut tx).await?;
+        let id = match existing {
+            Some(id) => {
+                if replace {
+                    sqlx::query("UPDATE user SET created_on = ?1, updated_on = ?2 WHERE id = ?3")
+                        .bind(&user.created_on).bind(&user.updated_on).bind(id).execute(&mut tx).await?;
+                }
+                id
+            },
+            None => {
+                let id_taken: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM user WHERE id = ?1)")
+                    .bind(user.id).fetch_one(&mut tx).await?;
+                report.users += 1;
+                sqlx::query("INSERT INTO user ( id, name, created_on, updated_on ) VALUES ( ?1, ?2, ?3, ?4 )")
+                    .bind(keep_id(user.id).filter(|_| !id_taken)).bind(&user.name).bind(&user.created_on).bind(&user.updated_on)
+                    .execute(&mut tx).await?.last_insert_rowid()
+            },
+        };
+        users.insert(user.id, id);
+    }
+
+    // backup tag id to the restored tag id and its user
+    let mut tags = HashMap::new();
+    for tag in &backup.tags {
+        let user_id = *users.get(&tag.user_id).ok_or_else(|| missing(format!("tag {}", tag.id), "user", tag.user_id))?;
+        let existing: Option<i64> = sqlx::query_scalar("SELECT id FROM tag WHERE user_id = ?1 AND name = ?2")
+            .bind(user_id).bind(&tag.name).fetch_optional(&mut tx).await?;
+        let id = match existing {
+            Some(id) => {
+                report.reused_tags += 1;
+                id
+            },
+            None => {
+                report.tags += 1;
+                sqlx::query("INSERT INTO tag ( id, user_id, name, color, created_on, updated_on ) VALUES ( ?1, ?2, ?3, ?4, ?5, ?6 )")
+                    .bind(keep_id(tag.id)).bind(user_id).bind(&tag.name).bind(tag.color).bind(&tag.created_on).bind(&tag.updated_on)
+                    .execute(&mut tx).await?.last_insert_rowid()
+            },
+        };
+        tags.insert(tag.id, (id, user_id));
+    }
+
+    for alias in &backup.tag_aliases {
+        let (tag_id, user_id) = *tags.get(&alias.tag_id).ok_or_else(|| missing(format!("tag alias {}", alias.id), "tag", alias.tag_id))?;
+        let inserted = sqlx::query(
+            "INSERT INTO tag_alias ( id, user_id, tag_id, name, created_on ) VALUES ( ?1, ?2, ?3, ?4, ?5 ) ON CONFLICT DO NOTHING")
+            .bind(keep_id(alias.id)).bind(user_id).bind(tag_id).bind(&alias.name).bind(&alias.created_on)
+            .execute(&mut tx).await?;
+        report.tag_aliases += inserted.rows_affected() as usize;
+    }
+
+    // backup category id to the restored category id and its user
+    let mut categories = HashMap::new();
+    for category in &backup.tag_categories {
+        let user_id = *users.get(&category.user_id)
+            .ok_or_else(|| missing(format!("tag category {}", category.id), "user", category.user_id))?;
+        let existing: Option<i64> = sqlx::query_scalar("SELECT id FROM tag_category WHERE user_id = ?1 AND name = ?2")
+            .bind(user_id).bind(&category.name).fetch_optional(&mut tx).await?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                report.tag_categories += 1;
+                sqlx::query("INSERT INTO tag_category ( id, user_id, name, created_on, updated_on ) VALUES ( ?1, ?2, ?3, ?4, ?5 )")
+                    .bind(keep_id(category.id)).bind(user_id).bind(&category.name).bind(&category.created_on).bind(&category.updated_on)
+                    .execute(&mut tx).await?.last_insert_rowid()
+            },
+        };
+        categories.insert(category.id, (id, user_id));
+    }
+
+    for hierarchy in &backup.tag_hierarchy {
+        let (tag_id, user_id) = *tags.get(&hierarchy.tag_id)
+            .ok_or_else(|| missing(format!("parent of tag {}", hierarchy.tag_id), "tag", hierarchy.tag_id))?;
+        let parent_id = match hierarchy.parent_id {
+            Some(parent_id) => Some(restored_tag(&tags, user_id, "tag", hierarchy.tag_id, parent_id)?),
+            None => None,
+        };
+        let category_id = match hierarchy.category_id {
+            Some(category_id) => match categories.get(&category_id) {
+                Some((id, category_user_id)) if *category_user_id == user_id => Some(*id),
+                Some(_) => return Err(DBError::Validation(
+                    format!("backup adds tag {} to category {} of another user", hierarchy.tag_id, category_id))),
+                None => return Err(missing(format!("tag {}", hierarchy.tag_id), "tag category", category_id)),
+            },
+            None => None,
+        };
+        let inserted = sqlx::query(
+            "INSERT INTO tag_hierarchy ( tag_id, parent_id, category_id ) VALUES ( ?1, ?2, ?3 ) ON CONFLICT DO NOTHING")
+            .bind(tag_id).bind(parent_id).bind(category_id).execute(&mut tx).await?;
+        report.tag_hierarchy += inserted.rows_affected() as usize;
+    }
+
+    // backup sleep id to the restored sleep id and its user, or None if the sleep was skipped
+    let mut sleeps = HashMap::new();
+    for sleep in &backup.sleeps {
+        let user_id = *users.get(&sleep.user_id).ok_or_else(|| missing(format!("sleep {}", sleep.id), "user", sleep.user_id))?;
+        let existing: Option<i64> = sqlx::query_scalar("SELECT id FROM sleep WHERE user_id = ?1 AND night = ?2")
+            .bind(user_id).bind(&sleep.night).fetch_optional(&mut tx).await?;
+        if existing.is_some() {
+            report.skipped_sleeps += 1;
+            sleeps.insert(sleep.id, None);
+            continue;
+        }
+
+        report.sleeps += 1;
+        let id = sqlx::query(
+            "INSERT INTO sleep ( id, user_id, night, amount, quality, source, created_on, updated_on )
+            VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8 )")
+            .bind(keep_id(sleep.id)).bind(user_id).bind(&sleep.night).bind(sleep.amount).bind(sleep.quality).bind(&sleep.source)
+            .bind(&sleep.created_on).bind(&sleep.updated_on)
+            .execute(&mut tx).await?.last_insert_rowid();
+        sleeps.insert(sleep.id, Some((id, user_id)));
+    }
+    let restored_sleep = |row: String, sleep_id: i64| sleeps.get(&sleep_id).copied()
+        .ok_or_else(|| missing(row, "sleep", sleep_id));
+
+    for session in &backup.sleep_sessions {
+        let Some((sleep_id, _)) = restored_sleep(format!("sleep session {}", session.id), session.sleep_id)? else { continue };
+        report.sleep_sessions += 1;
+        sqlx::query(
+            "INSERT INTO sleep_session ( id, sleep_id, kind, amount, bed_time, sleep_onset, wake_time, created_on, updated_on )
+            VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9 )")
+            .bind(keep_id(session.id)).bind(sleep_id).bind(&session.kind).bind(session.amount)
+            .bind(&session.bed_time).bind(&session.sleep_onset).bind(&session.wake_time)
+            .bind(&session.created_on).bind(&session.updated_on)
+            .execute(&mut tx).await?;
+    }
+
+    for stages in &backup.sleep_stages {
+        let Some((sleep_id, _)) = restored_sleep(format!("stages of sleep {}", stages.sleep_id), stages.sleep_id)? else { continue };
+        report.sleep_stages += 1;
+        sqlx::query(
+            "INSERT INTO sleep_stages ( sleep_id, deep_minutes, light_minutes, rem_minutes, awake_minutes, created_on, updated_on )
+            VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7 )")
+            .bind(sleep_id).bind(stages.deep_minutes).bind(stages.light_minutes).bind(stages.rem_minutes).bind(stages.awake_minutes)
+            .bind(&stages.created_on).bind(&stages.updated_on)
+            .execute(&mut tx).await?;
+    }
+
+    for interval in &backup.hypnogram {
+        let Some((sleep_id, _)) = restored_sleep(format!("hypnogram interval {}", interval.id), interval.sleep_id)? else { continue };
+        report.hypnogram += 1;
+        sqlx::query("INSERT INTO hypnogram ( id, sleep_id, stage, start_time, end_time ) VALUES ( ?1, ?2, ?3, ?4, ?5 )")
+            .bind(keep_id(interval.id)).bind(sleep_id).bind(&interval.stage).bind(&interval.start_time).bind(&interval.end_time)
+            .execute(&mut tx).await?;
+    }
+
+    for sleep_tag in &backup.sleep_tags {
+        let Some((sleep_id, user_id)) = restored_sleep(format!("tag {} of a sleep", sleep_tag.tag_id), sleep_tag.sleep_id)? else { continue };
+        let tag_id = restored_tag(&tags, user_id, "sleep", sleep_tag.sleep_id, sleep_tag.tag_id)?;
+        // backups from before the tags of a sleep were unique can have the same tag more than once
+        let inserted = sqlx::query("INSERT INTO sleep_tags ( sleep_id, tag_id ) VALUES ( ?1, ?2 ) ON CONFLICT DO NOTHING")
+            .bind(sleep_id).bind(tag_id).execute(&mut tx).await?;
+        report.sleep_tags += inserted.rows_affected() as usize;
+    }
+
+    for comment in &backup.comments {
+        let Some((sleep_id, user_id)) = restored_sleep(format!("comment {}", comment.id), comment.sleep_id)? else { continue };
+        report.comments += 1;
+        sqlx::query("INSERT INTO comment ( id, user_id, sleep_id, comment ) VALUES ( ?1, ?2, ?3, ?4 )")
+            .bind(keep_id(comment.id)).bind(user_id).bind(sleep_id).bind(&comment.comment)
+            .execute(&mut tx).await?;
+    }
+
+    // backup dream id to the restored dream id and its user, or None if its sleep was skipped
+    let mut dreams = HashMap::new();
+    for dream in &backup.dreams {
+        let Some((sleep_id, user_id)) = restored_sleep(format!("dream {}", dream.id), dream.sleep_id)? else {
+            dreams.insert(dream.id, None);
+            continue;
+        };
+        report.dreams += 1;
+        let id = sqlx::query(
+            "INSERT INTO dream ( id, sleep_id, title, narrative, lucid, vividness, emotional_tone, recurring, created_on, updated_on )
+            VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10 )")
+            .bind(keep_id(dream.id)).bind(sleep_id).bind(&dream.title).bind(&dream.narrative).bind(dream.lucid)
+            .bind(dream.vividness).bind(dream.emotional_tone).bind(dream.recurring)
+            .bind(&dream.created_on).bind(&dream.updated_on)
+            .execute(&mut tx).await?.last_insert_rowid();
+        dreams.insert(dream.id, Some((id, user_id)));
+    }
+
+    for dream_tag in &backup.dream_tags {
+        let restored = dreams.get(&dream_tag.dream_id).copied()
+            .ok_or_else(|| missing(format!("tag {} of a dream", dream_tag.tag_id), "dream", dream_tag.dream_id))?;
+        let Some((dream_id, user_id)) = restored else { continue };
+        let tag_id = restored_tag(&tags, user_id, "dream", dream_tag.dream_id, dream_tag.tag_id)?;
+        report.dream_tags += 1;
+        sqlx::query("INSERT INTO dream_tags ( dream_id, tag_id ) VALUES ( ?1, ?2 )")
+            .bind(dream_id).bind(tag_id).execute(&mut tx).await?;
+    }
+
+    tx.commit().await?;
+    Ok(report)
+}
+
+/// Returns the restored id of a tag that is added to a sleep or dream of the given user, or is the parent of a tag of the user
+fn restored_tag(tags: &HashMap<i64, (i64, i64)>, user_id: i64, entity: &str, entity_id: i64, tag_id: i64) -> Result<i64, DBError> {
+    match tags.get(&tag_id) {
+        Some((id, tag_user_id)) if *tag_user_id == user_id => Ok(*id),
+        Some(_) => Err(DBError::Validation(format!("backup adds tag {} to {} {} of another user", tag_id, entity, entity_id))),
+        None => Err(missing(format!("{} {}", entity, entity_id), "tag", tag_id)),
+    }
+}
+
+/// Error for a row of a backup that references a row the backup doesn't have
+fn missing(row: String, referenced: &str, referenced_id: i64) -> DBError {
+    DBError::Validation(format!("backup {} references {} {} that is not in the backup", row, referenced, referenced_id))
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord, Trim, WriterBuilder};
+use super::db_import::{ImportIssue, ImportIssueKind};
+use super::db_types::{DBComment, DBSleep, DBTag};
+use super::DBError;
+
+/// Format of the nights in exported csv files, and the default format of imported nights
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Separator of the tag names in a cell when no delimiter is given
+pub const DEFAULT_TAG_DELIMITER: char = ';';
+
+/// Names of the columns of a csv file that hold each value of a sleep, matched case insensitively
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvColumns {
+    /// Column with the night of the sleep, required
+    pub night: String,
+
+    /// Column with the amount of sleep, required
+    pub amount: String,
+
+    /// Column with the quality of sleep, required
+    pub quality: String,
+
+    /// Column with the names of the tags of the sleep, separated by the tag delimiter, optional
+    pub tags: String,
+
+    /// Column with the comments of the sleep, one comment per line of the cell, optional
+    pub comment: String,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        CsvColumns {
+            night: String::from("night"),
+            amount: String::from("amount"),
+            quality: String::from("quality"),
+            tags: String::from("tags"),
+            comment: String::from("comment"),
+        }
+    }
+}
+
+/// How a csv file is read when it is imported
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvImportOptions {
+    /// Names of the columns that hold each value
+    pub columns: CsvColumns,
+
+    /// Separator of the tag names in the tags column
+    pub tag_delimiter: char,
+
+    /// chrono format of the nights, ex: "%m/%d/%Y"
+    pub date_format: String,
+
+    /// Report what the import would do without changing the database
+    pub dry_run: bool,
+}
+
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        CsvImportOptions {
+            columns: CsvColumns::default(),
+            tag_delimiter: DEFAULT_TAG_DELIMITER,
+            date_format: String::from(DEFAULT_DATE_FORMAT),
+            dry_run: false,
+        }
+    }
+}
+
+/// A sleep read from a row of a csv file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvRow {
+    /// Line of the row in the file, starting at 1
+    pub line: u64,
+
+    pub night: NaiveDate,
+    pub amount: f64,
+    pub quality: i64,
+
+    /// Names of the tags, without duplicates
+    pub tags: Vec<String>,
+
+    pub comments: Vec<String>,
+}
+
+/// Index of each column in the header of a csv file
+struct ColumnIndexes {
+    night: usize,
+    amount: usize,
+    quality: usize,
+    tags: Option<usize>,
+    comment: Option<usize>,
+}
+
+/// Reads the sleeps from a csv file with a header row.
+/// Returns the rows that could be read and an issue for each row that could not,
+/// or a [Validation](DBError::Validation) error if a required column is missing.
+pub fn parse(csv: &str, options: &CsvImportOptions) -> Result<(Vec<CsvRow>, Vec<ImportIssue>), DBError> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).flexible(true).from_reader(csv.as_bytes());
+    let headers = reader.headers().map_err(|e| DBError::Validation(format!("unable to read the csv header: {}", e)))?;
+
+    let find = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let required = |name: &str| find(name).ok_or_else(|| DBError::Validation(format!("the csv has no \"{}\" column", name)));
+    let columns = ColumnIndexes {
+        night: required(&options.columns.night)?,
+        amount: required(&options.columns.amount)?,
+        quality: required(&options.columns.quality)?,
+        tags: find(&options.columns.tags),
+        comment: find(&options.columns.comment),
+    };
+
+    let mut rows = Vec::new();
+    let mut issues = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        // the header is line 1, a record that can't be read has no position so count the records instead
+        let fallback_line = index as u64 + 2;
+        match record {
+            Ok(record) => {
+                let line = record.position().map_or(fallback_line, |p| p.line());
+                match parse_row(&record, line, &columns, options) {
+                    Ok(row) => rows.push(row),
+                    Err(issue) => issues.push(issue),
+                }
+            },
+            Err(e) => issues.push(ImportIssue::new(fallback_line, None, ImportIssueKind::Invalid, e.to_string())),
+        }
+    }
+
+    Ok((rows, issues))
+}
+
+fn parse_row(record: &StringRecord, line: u64, columns: &ColumnIndexes, options: &CsvImportOptions) -> Result<CsvRow, ImportIssue> {
+    let cell = |index: usize| record.get(index).unwrap_or("");
+    let invalid = |night: Option<NaiveDate>, message: String| ImportIssue::new(line, night, ImportIssueKind::Invalid, message);
+
+    let night = NaiveDate::parse_from_str(cell(columns.night), &options.date_format)
+        .map_err(|_| invalid(None, format!("\"{}\" is not a night in the {} format", cell(columns.night), options.date_format)))?;
+    let amount = cell(columns.amount).parse::<f64>()
+        .map_err(|_| invalid(Some(night), format!("\"{}\" is not an amount", cell(columns.amount))))?;
+    super::validate_amount(amount).map_err(|e| invalid(Some(night), e.to_string()))?;
+    let quality = cell(columns.quality).parse::<i64>()
+        .map_err(|_| invalid(Some(night), format!("\"{}\" is not a quality", cell(columns.quality))))?;
+
+    let mut tags: Vec<String> = Vec::new();
+    if let Some(index) = columns.tags {
+        for tag in cell(index).split(options.tag_delimiter).map(str::trim).filter(|t| !t.is_empty()) {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+
+    let comments = match columns.comment {
+        Some(index) => cell(index).lines().map(str::trim).filter(|c| !c.is_empty()).map(String::from).collect(),
+        None => Vec::new(),
+    };
+
+    Ok(CsvRow { line, night, amount, quality, tags, comments })
+}
+
+/// Writes sleeps with their tags and comments as a csv file with the default columns,
+/// that can be imported with the default options
+pub fn write(sleeps: &[DBSleep], tags: &HashMap<i64, Vec<DBTag>>, comments: &HashMap<i64, Vec<DBComment>>) -> String {
+    let columns = CsvColumns::default();
+    let mut writer = WriterBuilder::new().from_writer(Vec::new());
+    let delimiter = DEFAULT_TAG_DELIMITER.to_string();
+
+    // writing to memory can only fail if a row has a different number of fields, and every row has the same fields
+    writer.write_record([&columns.night, &columns.amount, &columns.quality, &columns.tags, &columns.comment])
+        .expect("csv header is valid");
+    for sleep in sleeps {
+        let tag_names = tags.get(&sleep.id).map_or(Vec::new(), |tags| tags.iter().map(|t| t.name.as_str()).collect());
+        let comment_lines = comments.get(&sleep.id).map_or(Vec::new(), |comments| comments.iter().map(|c| c.comment.as_str()).collect());
+        writer.write_record([
+            sleep.night.format(DEFAULT_DATE_FORMAT).to_string(),
+            sleep.amount.to_string(),
+            sleep.quality.to_string(),
+            tag_names.join(&delimiter),
+            comment_lines.join("\n"),
+        ]).expect("csv row is valid");
+    }
+
+    let bytes = writer.into_inner().expect("csv is written to memory");
+    String::from_utf8(bytes).expect("csv is written from strings")
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+use chrono::NaiveDate;
+
+/// SQLite extended result code for a failed UNIQUE constraint
+const SQLITE_CONSTRAINT_UNIQUE: &str = "2067";
+
+/// SQLite extended result code for a failed FOREIGN KEY constraint
+const SQLITE_CONSTRAINT_FOREIGNKEY: &str = "787";
+
+/// Errors that can be returned by the [DBManager](super::DBManager)
+#[derive(Debug)]
+pub enum DBError {
+    /// The requested row does not exist
+    NotFound {
+        /// Name of the entity that was requested, ex: "sleep"
+        entity: &'static str,
+        /// id of the row that was requested
+        id: i64,
+    },
+
+    /// A sleep already exists for the given night
+    DuplicateNight(NaiveDate),
+
+    /// A tag with the given name already exists
+    DuplicateTagName(String),
+
+    /// A user with the given name already exists
+    DuplicateUserName(String),
+
+    /// A tag category with the given name already exists
+    DuplicateCategoryName(String),
+
+    /// A row references another row that does not exist
+    ForeignKeyViolation(String),
+
+    /// The provided input is invalid
+    Validation(String),
+
+    /// The database schema is newer than the schema this binary knows how to use
+    UnsupportedSchemaVersion {
+        /// Schema version of the database
+        found: i64,
+        /// Newest schema version supported by this binary
+        supported: i64,
+    },
+
+    /// The underlying database returned an error
+    Storage(sqlx::Error),
+}
+
+impl DBError {
+    /// Creates a [NotFound](DBError::NotFound) error for the given entity and id
+    pub fn not_found(entity: &'static str, id: i64) -> DBError {
+        DBError::NotFound { entity, id }
+    }
+
+    /// Converts an error from selecting a single row, mapping a missing row to
+    /// [NotFound](DBError::NotFound) for the given entity and id
+    pub fn from_select(error: sqlx::Error, entity: &'static str, id: i64) -> DBError {
+        match error {
+            sqlx::Error::RowNotFound => DBError::not_found(entity, id),
+            e => e.into()
+        }
+    }
+
+    /// Converts an error from inserting or renaming a tag, mapping a UNIQUE
+    /// constraint failure to [DuplicateTagName](DBError::DuplicateTagName)
+    pub fn from_tag_write(error: sqlx::Error, name: &str) -> DBError {
+        match error {
+            e if DBError::is_unique_violation(&e) => DBError::DuplicateTagName(name.to_string()),
+            e => e.into()
+        }
+    }
+
+    /// Converts an error from inserting or renaming a tag category, mapping a UNIQUE
+    /// constraint failure to [DuplicateCategoryName](DBError::DuplicateCategoryName)
+    pub fn from_category_write(error: sqlx::Error, name: &str) -> DBError {
+        match error {
+            e if DBError::is_unique_violation(&e) => DBError::DuplicateCategoryName(name.to_string()),
+            e => e.into()
+        }
+    }
+
+    /// Converts an error from inserting a user, mapping a UNIQUE
+    /// constraint failure to [DuplicateUserName](DBError::DuplicateUserName)
+    pub fn from_user_write(error: sqlx::Error, name: &str) -> DBError {
+        match error {
+            e if DBError::is_unique_violation(&e) => DBError::DuplicateUserName(name.to_string()),
+            e => e.into()
+        }
+    }
+
+    /// Converts an error from inserting or updating a sleep session, mapping a UNIQUE
+    /// constraint failure to a [Validation](DBError::Validation) error as a night can only have one main session
+    pub fn from_session_write(error: sqlx::Error, sleep_id: i64) -> DBError {
+        match error {
+            e if DBError::is_unique_violation(&e) =>
+                DBError::Validation(format!("sleep {} already has a main session", sleep_id)),
+            e => e.into()
+        }
+    }
+
+    /// Returns true if the sqlx error was caused by a UNIQUE constraint failing
+    pub fn is_unique_violation(error: &sqlx::Error) -> bool {
+        DBError::has_code(error, SQLITE_CONSTRAINT_UNIQUE)
+    }
+
+    /// Returns true if the sqlx error was caused by a FOREIGN KEY constraint failing
+    pub fn is_foreign_key_violation(error: &sqlx::Error) -> bool {
+        DBError::has_code(error, SQLITE_CONSTRAINT_FOREIGNKEY)
+    }
+
+    fn has_code(error: &sqlx::Error, code: &str) -> bool {
+        match error {
+            sqlx::Error::Database(e) => e.code().is_some_and(|c| c == code),
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for DBError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DBError::NotFound { entity, id } => write!(f, "{} with id {} does not exist", entity, id),
+            DBError::DuplicateNight(night) => write!(f, "a sleep for {} already exists", night),
+            DBError::DuplicateTagName(name) => write!(f, "a tag named \"{}\" already exists", name),
+            DBError::DuplicateUserName(name) => write!(f, "a user named \"{}\" already exists", name),
+            DBError::DuplicateCategoryName(name) => write!(f, "a tag category named \"{}\" already exists", name),
+            DBError::ForeignKeyViolation(msg) => write!(f, "{}", msg),
+            DBError::Validation(msg) => write!(f, "{}", msg),
+            DBError::UnsupportedSchemaVersion { found, supported } =>
+                write!(f, "database schema version {} is newer than the supported version {}", found, supported),
+            DBError::Storage(e) => write!(f, "storage failure: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DBError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DBError::Storage(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<sqlx::Error> for DBError {
+    fn from(error: sqlx::Error) -> Self {
+        if DBError::is_foreign_key_violation(&error) {
+            return DBError::ForeignKeyViolation(String::from("referenced row does not exist"));
+        }
+
+        DBError::Storage(error)
+    }
+}
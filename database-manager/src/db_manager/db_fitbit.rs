@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use chrono::{Duration, NaiveDateTime};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use super::db_import::{ImportIssue, ImportIssueKind, ImportedNight};
+use super::db_types::{HypnogramInterval, SleepStage, SleepStages, SleepTimes};
+use super::DBError;
+
+/// Source of the sleeps imported from Fitbit, re-importing an export only updates the sleeps with this source
+pub const FITBIT_SOURCE: &str = "fitbit";
+
+/// Name of the tag that is added to the sleeps imported from Fitbit, it is created if the user has no tag with this name
+pub const FITBIT_TAG: &str = "fitbit";
+
+/// Prefix of the file names of the sleep files of an export, ex: "sleep-2023-05-13.json"
+const SLEEP_FILE_PREFIX: &str = "sleep-";
+
+/// Extension of the file names of the sleep files of an export
+const SLEEP_FILE_EXTENSION: &str = ".json";
+
+/// Format of the start and end times of the sleeps of an export, ex: "2023-05-13T23:10:30.000"
+const SLEEP_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// How a Fitbit export is imported
+#[derive(Debug, Clone, PartialEq)]
+pub struct FitbitOptions {
+    /// Quality of a sleep with an efficiency of 0%
+    pub min_quality: i64,
+
+    /// Quality of a sleep with an efficiency of 100%, the efficiency in between is scaled linearly and rounded
+    pub max_quality: i64,
+
+    /// Report what the import would do without changing the database
+    pub dry_run: bool,
+}
+
+impl Default for FitbitOptions {
+    fn default() -> Self {
+        FitbitOptions { min_quality: 1, max_quality: 5, dry_run: false }
+    }
+}
+
+impl FitbitOptions {
+    /// Returns a [Validation](DBError::Validation) error if the scale of the quality is reversed
+    pub fn validate(&self) -> Result<(), DBError> {
+        if self.min_quality > self.max_quality {
+            return Err(DBError::Validation(format!(
+                "the minimum quality {} is above the maximum quality {}", self.min_quality, self.max_quality)));
+        }
+        Ok(())
+    }
+
+    /// Maps an efficiency between 0 and 100 to the quality scale
+    pub fn quality(&self, efficiency: i64) -> i64 {
+        let scale = (self.max_quality - self.min_quality) as f64;
+        self.min_quality + (efficiency as f64 / 100.0 * scale).round() as i64
+    }
+}
+
+/// A sleep of a sleep file, only the fields that are imported
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FitbitSleep {
+    start_time: String,
+    end_time: String,
+    minutes_asleep: i64,
+    #[serde(default)]
+    minutes_to_fall_asleep: i64,
+    #[serde(default)]
+    minutes_after_wakeup: i64,
+    efficiency: i64,
+
+    /// false for naps, which are not imported
+    main_sleep: bool,
+
+    /// Stages of the sleep, only sleeps of the "stages" type have deep, light, REM and wake levels
+    #[serde(default)]
+    levels: Option<FitbitLevels>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FitbitLevels {
+    /// Minutes of each level, keyed by the name of the level
+    #[serde(default)]
+    summary: HashMap<String, FitbitLevelSummary>,
+
+    /// Levels in the order they happened
+    #[serde(default)]
+    data: Vec<FitbitLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FitbitLevelSummary {
+    minutes: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FitbitLevel {
+    date_time: String,
+    level: String,
+    seconds: i64,
+}
+
+/// Returns the stage of sleep of a level of a "stages" sleep, None for the levels of "classic" sleeps
+fn level_stage(level: &str) -> Option<SleepStage> {
+    match level {
+        "deep" => Some(SleepStage::Deep),
+        "light" => Some(SleepStage::Light),
+        "rem" => Some(SleepStage::Rem),
+        "wake" => Some(SleepStage::Awake),
+        _ => None,
+    }
+}
+
+/// Reads the `sleep-*.json` files of the directory of a Fitbit export, in the order of their names,
+/// and returns the main sleep of each night. Naps are not imported.
+/// The amount of a night is the minutes asleep and the quality is the efficiency mapped to the quality scale.
+/// Sleeps with deep, light, REM and wake levels also have stages and a hypnogram.
+/// Returns the nights and an issue for each sleep that could not be read, or a [Validation](DBError::Validation)
+/// error if the directory can't be read or has no sleep files.
+pub fn parse(dir: &Path, options: &FitbitOptions) -> Result<(Vec<ImportedNight>, Vec<ImportIssue>), DBError> {
+    let unreadable = |e: std::io::Error| DBError::Validation(format!("unable to read {}: {}", dir.display(), e));
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(unreadable)? {
+        let path = entry.map_err(unreadable)?.path();
+        let is_sleep_file = path.file_name().and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(SLEEP_FILE_PREFIX) && name.ends_with(SLEEP_FILE_EXTENSION));
+        if is_sleep_file && path.is_file() {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(DBError::Validation(format!("{} has no {}*{} files", dir.display(), SLEEP_FILE_PREFIX, SLEEP_FILE_EXTENSION)));
+    }
+
+    // the file names hold the date of their first sleep, so they sort by date
+    files.sort();
+
+    let mut nights = Vec::new();
+    let mut issues = Vec::new();
+    for path in files {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+        match fs::read_to_string(&path) {
+            Ok(text) => parse_file(&name, &text, options, &mut nights, &mut issues),
+            Err(e) => issues.push(ImportIssue::new(1, None, ImportIssueKind::Invalid, e.to_string()).in_file(Some(&name))),
+        }
+    }
+
+    Ok((nights, issues))
+}
+
+/// Reads the sleeps of a sleep file, which holds a json array with one object per sleep
+fn parse_file(name: &str, text: &str, options: &FitbitOptions, nights: &mut Vec<ImportedNight>, issues: &mut Vec<ImportIssue>) {
+    let entries: Vec<&RawValue> = match serde_json::from_str(text) {
+        Ok(entries) => entries,
+        Err(e) => {
+            issues.push(ImportIssue::new(e.line() as u64, None, ImportIssueKind::Invalid, e.to_string()).in_file(Some(name)));
+            return;
+        }
+    };
+
+    for entry in entries {
+        // raw values borrow from the text, so their offset in the text gives the line each sleep starts on
+        let offset = entry.get().as_ptr() as usize - text.as_ptr() as usize;
+        let line = text[..offset].matches('\n').count() as u64 + 1;
+
+        let sleep = match serde_json::from_str::<FitbitSleep>(entry.get()) {
+            Ok(sleep) if !sleep.main_sleep => continue,
+            Ok(sleep) => sleep,
+            Err(e) => {
+                issues.push(ImportIssue::new(line, None, ImportIssueKind::Invalid, e.to_string()).in_file(Some(name)));
+                continue;
+            }
+        };
+        match night(&sleep, line, options) {
+            Ok(night) => nights.push(ImportedNight { file: Some(name.to_string()), ..night }),
+            Err(issue) => issues.push(issue.in_file(Some(name))),
+        }
+    }
+}
+
+fn night(sleep: &FitbitSleep, line: u64, options: &FitbitOptions) -> Result<ImportedNight, ImportIssue> {
+    let invalid = |message: String| ImportIssue::new(line, None, ImportIssueKind::Invalid, message);
+    let start = NaiveDateTime::parse_from_str(&sleep.start_time, SLEEP_TIME_FORMAT)
+        .map_err(|_| invalid(format!("\"{}\" is not a startTime in the {} format", sleep.start_time, SLEEP_TIME_FORMAT)))?;
+    let end = NaiveDateTime::parse_from_str(&sleep.end_time, SLEEP_TIME_FORMAT)
+        .map_err(|_| invalid(format!("\"{}\" is not an endTime in the {} format", sleep.end_time, SLEEP_TIME_FORMAT)))?;
+    if end <= start {
+        return Err(invalid(format!("sleep ends at {} before it starts at {}", end, start)));
+    }
+    if !(0..=100).contains(&sleep.efficiency) {
+        return Err(invalid(format!("efficiency {} is not between 0 and 100", sleep.efficiency)));
+    }
+
+    let times = SleepTimes {
+        bed_time: Some(start),
+        sleep_onset: Some(start + Duration::minutes(sleep.minutes_to_fall_asleep)),
+        wake_time: Some(end - Duration::minutes(sleep.minutes_after_wakeup)),
+    };
+    let night = times.night().expect("times with a bed time have a night");
+    times.validate().map_err(|message| ImportIssue::new(line, Some(night), ImportIssueKind::Invalid, message))?;
+    let amount = sleep.minutes_asleep as f64 / 60.0;
+    super::validate_amount(amount)
+        .map_err(|e| ImportIssue::new(line, Some(night), ImportIssueKind::Invalid, e.to_string()))?;
+
+    let (stages, hypnogram) = match &sleep.levels {
+        Some(levels) => stages(levels).map_err(|message| ImportIssue::new(line, Some(night), ImportIssueKind::Invalid, message))?,
+        None => (None, Vec::new()),
+    };
+
+    Ok(ImportedNight { file: None, line, night, amount, quality: Some(options.quality(sleep.efficiency)), times, stages, hypnogram })
+}
+
+/// Reads the stages and hypnogram of the levels of a sleep, returns None and an empty hypnogram for classic sleeps
+fn stages(levels: &FitbitLevels) -> Result<(Option<SleepStages>, Vec<HypnogramInterval>), String> {
+    let minutes = |level: &str| levels.summary.get(level).map(|l| l.minutes);
+    let stages = SleepStages {
+        deep_minutes: minutes("deep"),
+        light_minutes: minutes("light"),
+        rem_minutes: minutes("rem"),
+        awake_minutes: minutes("wake"),
+    };
+    stages.validate()?;
+
+    let mut hypnogram = Vec::new();
+    for level in &levels.data {
+        let Some(stage) = level_stage(&level.level) else {
+            return Ok((None, Vec::new()));
+        };
+        let start = NaiveDateTime::parse_from_str(&level.date_time, SLEEP_TIME_FORMAT)
+            .map_err(|_| format!("\"{}\" is not a level dateTime in the {} format", level.date_time, SLEEP_TIME_FORMAT))?;
+        hypnogram.push(HypnogramInterval { stage, start, end: start + Duration::seconds(level.seconds) });
+    }
+    HypnogramInterval::validate_all(&hypnogram)?;
+
+    Ok(((!stages.is_empty()).then_some(stages), hypnogram))
+}
@@ -0,0 +1,100 @@
+use chrono::NaiveDate;
+use super::db_types::{HypnogramInterval, SleepStages, SleepTimes};
+
+/// Why a row of an import was not imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportIssueKind {
+    /// A sleep already exists for the night of the row
+    DuplicateNight,
+
+    /// An earlier row of the import has the same night
+    RepeatedNight,
+
+    /// The row could not be read or has an invalid value
+    Invalid,
+}
+
+/// A row of an import that was not imported
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportIssue {
+    /// Name of the imported file the row is in, None for imports of a single file
+    pub file: Option<String>,
+
+    /// Line of the row in the imported file, starting at 1
+    pub line: u64,
+
+    /// Night of the row, None if it could not be read
+    pub night: Option<NaiveDate>,
+
+    /// Why the row was not imported
+    pub kind: ImportIssueKind,
+
+    /// Description of the issue
+    pub message: String,
+}
+
+/// Result of an import, or of a dry run of an import
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    /// true if nothing was written to the database, and the report shows what an import would do
+    pub dry_run: bool,
+
+    /// Number of rows that were read
+    pub rows: usize,
+
+    /// Number of rows that were imported, or would be imported by a dry run
+    pub imported: usize,
+
+    /// Number of sleeps imported earlier from the same source that were updated, or would be updated by a dry run
+    pub merged: usize,
+
+    /// Names of the tags that were created because no tag had the name, or would be created by a dry run
+    pub created_tags: Vec<String>,
+
+    /// ids of the sleeps that were created, empty for a dry run
+    pub sleep_ids: Vec<i64>,
+
+    /// ids of the tags that were created, empty for a dry run
+    pub tag_ids: Vec<i64>,
+
+    /// Rows that were not imported, in the order of the files
+    pub issues: Vec<ImportIssue>,
+}
+
+impl ImportIssue {
+    pub fn new(line: u64, night: Option<NaiveDate>, kind: ImportIssueKind, message: impl Into<String>) -> ImportIssue {
+        ImportIssue { file: None, line, night, kind, message: message.into() }
+    }
+
+    /// Sets the name of the imported file the row is in
+    pub fn in_file(mut self, file: Option<&str>) -> ImportIssue {
+        self.file = file.map(String::from);
+        self
+    }
+}
+
+/// A night of sleep read from the export of a device, with the times of the main session
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedNight {
+    /// Name of the file of the export the night is in, None for exports of a single file
+    pub file: Option<String>,
+
+    /// Line of the export the night starts on, starting at 1
+    pub line: u64,
+
+    pub night: NaiveDate,
+
+    /// Hours of sleep
+    pub amount: f64,
+
+    /// Quality of the night, None to use the quality of the import
+    pub quality: Option<i64>,
+
+    pub times: SleepTimes,
+
+    /// Minutes of each stage of sleep, None if the device didn't record stages
+    pub stages: Option<SleepStages>,
+
+    /// Stages of sleep in the order they happened, empty if the device didn't record them
+    pub hypnogram: Vec<HypnogramInterval>,
+}
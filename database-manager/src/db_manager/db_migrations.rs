@@ -1,10 +1,32 @@
-use sqlx::{SqlitePool, sqlite};
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use super::DBError;
 
-pub async fn initalize_db(pool: &SqlitePool) -> Result<sqlite::SqliteQueryResult, sqlx::Error> {
-    let mut query = String::from("PRAGMA foreign_keys = ON;");
+/// A single versioned change to the database schema.
+/// Migrations are applied in order, and each one sets the `user_version` of the database to its version.
+struct Migration {
+    /// Schema version the database is at once the migration has been applied
+    version: i64,
 
-    let create_sleep_table = 
-    "CREATE TABLE IF NOT EXISTS sleep
+    /// Short description of the migration that is printed when it is applied
+    description: &'static str,
+
+    /// Sql statements that make up the migration
+    sql: &'static str,
+
+    /// true if foreign keys are not enforced while the migration runs, which is needed to rebuild a table
+    /// that other tables reference. The foreign keys are checked before the migration is committed.
+    rebuilds_tables: bool,
+}
+
+/// Every migration of the database schema, in the order they are applied.
+/// New migrations must be added to the end of the list with the next version number,
+/// migrations that have already been released should never be edited.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create sleep, tag, sleep_tags and comment tables",
+        sql: "
+        CREATE TABLE IF NOT EXISTS sleep
         (
             id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
             night      TEXT NOT NULL UNIQUE,
@@ -12,53 +34,414 @@ pub async fn initalize_db(pool: &SqlitePool) -> Result<sqlite::SqliteQueryResult
             quality    INTEGER NOT NULL,
             created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
             updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime'))
-        );";
+        );
 
-    let create_tag_table = 
-    "CREATE TABLE IF NOT EXISTS tag
+        CREATE TABLE IF NOT EXISTS tag
         (
             id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
             name       TEXT NOT NULL UNIQUE,
             color      INTEGER NOT NULL,
             created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
             updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime'))
-        );";
+        );
 
-    let create_sleep_tag_table =
-    "CREATE TABLE IF NOT EXISTS sleep_tags 
+        CREATE TABLE IF NOT EXISTS sleep_tags
         (
             id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
             sleep_id   INTEGER NOT NULL,
             tag_id     INTEGER NOT NULL,
             FOREIGN KEY (sleep_id)
-            REFERENCES sleep (id) 
+            REFERENCES sleep (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (tag_id)
+            REFERENCES tag (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS comment
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            sleep_id   INTEGER NOT NULL,
+            comment    TEXT NOT NULL,
+            FOREIGN KEY (sleep_id)
+            REFERENCES sleep (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 2,
+        description: "add bed, sleep onset and wake times to sleep",
+        sql: "
+        ALTER TABLE sleep ADD COLUMN bed_time TEXT;
+        ALTER TABLE sleep ADD COLUMN sleep_onset TEXT;
+        ALTER TABLE sleep ADD COLUMN wake_time TEXT;",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 3,
+        description: "move amount and times of sleeps into sleep sessions",
+        sql: "
+        CREATE TABLE IF NOT EXISTS sleep_session
+        (
+            id          INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            sleep_id    INTEGER NOT NULL,
+            kind        TEXT NOT NULL DEFAULT 'main' CHECK (kind IN ('main', 'nap', 'split')),
+            amount      REAL NOT NULL,
+            bed_time    TEXT,
+            sleep_onset TEXT,
+            wake_time   TEXT,
+            created_on  TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            updated_on  TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            FOREIGN KEY (sleep_id)
+            REFERENCES sleep (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS sleep_session_one_main
+            ON sleep_session (sleep_id) WHERE kind = 'main';
+
+        INSERT INTO sleep_session ( sleep_id, kind, amount, bed_time, sleep_onset, wake_time )
+        SELECT id, 'main', amount, bed_time, sleep_onset, wake_time
+        FROM sleep;
+
+        ALTER TABLE sleep DROP COLUMN bed_time;
+        ALTER TABLE sleep DROP COLUMN sleep_onset;
+        ALTER TABLE sleep DROP COLUMN wake_time;",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 4,
+        description: "create dream and dream_tags tables",
+        sql: "
+        CREATE TABLE IF NOT EXISTS dream
+        (
+            id             INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            sleep_id       INTEGER NOT NULL,
+            title          TEXT NOT NULL,
+            narrative      TEXT NOT NULL DEFAULT '',
+            lucid          INTEGER NOT NULL DEFAULT 0 CHECK (lucid IN (0, 1)),
+            vividness      INTEGER CHECK (vividness BETWEEN 0 AND 10),
+            emotional_tone INTEGER CHECK (emotional_tone BETWEEN -5 AND 5),
+            recurring      INTEGER NOT NULL DEFAULT 0 CHECK (recurring IN (0, 1)),
+            created_on     TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            updated_on     TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            FOREIGN KEY (sleep_id)
+            REFERENCES sleep (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS dream_tags
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            dream_id   INTEGER NOT NULL,
+            tag_id     INTEGER NOT NULL,
+            UNIQUE (dream_id, tag_id),
+            FOREIGN KEY (dream_id)
+            REFERENCES dream (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (tag_id)
+            REFERENCES tag (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 5,
+        description: "add users and make every sleep, tag and comment belong to a user",
+        sql: "
+        CREATE TABLE IF NOT EXISTS user
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name       TEXT NOT NULL UNIQUE,
+            created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime'))
+        );
+
+        INSERT INTO user ( id, name ) VALUES ( 1, 'default' );
+
+        CREATE TABLE new_sleep
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id    INTEGER NOT NULL,
+            night      TEXT NOT NULL,
+            amount     REAL NOT NULL,
+            quality    INTEGER NOT NULL,
+            created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            UNIQUE (user_id, night),
+            UNIQUE (id, user_id),
+            FOREIGN KEY (user_id)
+            REFERENCES user (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        INSERT INTO new_sleep ( id, user_id, night, amount, quality, created_on, updated_on )
+        SELECT id, 1, night, amount, quality, created_on, updated_on
+        FROM sleep;
+
+        DROP TABLE sleep;
+        ALTER TABLE new_sleep RENAME TO sleep;
+
+        CREATE TABLE new_tag
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id    INTEGER NOT NULL,
+            name       TEXT NOT NULL,
+            color      INTEGER NOT NULL,
+            created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            UNIQUE (user_id, name),
+            FOREIGN KEY (user_id)
+            REFERENCES user (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        INSERT INTO new_tag ( id, user_id, name, color, created_on, updated_on )
+        SELECT id, 1, name, color, created_on, updated_on
+        FROM tag;
+
+        DROP TABLE tag;
+        ALTER TABLE new_tag RENAME TO tag;
+
+        CREATE TABLE new_comment
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id    INTEGER NOT NULL,
+            sleep_id   INTEGER NOT NULL,
+            comment    TEXT NOT NULL,
+            FOREIGN KEY (sleep_id, user_id)
+            REFERENCES sleep (id, user_id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        INSERT INTO new_comment ( id, user_id, sleep_id, comment )
+        SELECT id, 1, sleep_id, comment
+        FROM comment;
+
+        DROP TABLE comment;
+        ALTER TABLE new_comment RENAME TO comment;",
+        rebuilds_tables: true,
+    },
+    Migration {
+        version: 6,
+        description: "create api_token table",
+        sql: "
+        CREATE TABLE IF NOT EXISTS api_token
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id    INTEGER NOT NULL,
+            name       TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            read_only  INTEGER NOT NULL DEFAULT 0 CHECK (read_only IN (0, 1)),
+            admin      INTEGER NOT NULL DEFAULT 0 CHECK (admin IN (0, 1)),
+            created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            FOREIGN KEY (user_id)
+            REFERENCES user (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 7,
+        description: "add the source that imported a sleep",
+        sql: "
+        ALTER TABLE sleep ADD COLUMN source TEXT;",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 8,
+        description: "create sleep_stages and hypnogram tables",
+        sql: "
+        CREATE TABLE IF NOT EXISTS sleep_stages
+        (
+            sleep_id      INTEGER NOT NULL PRIMARY KEY,
+            deep_minutes  INTEGER CHECK (deep_minutes >= 0),
+            light_minutes INTEGER CHECK (light_minutes >= 0),
+            rem_minutes   INTEGER CHECK (rem_minutes >= 0),
+            awake_minutes INTEGER CHECK (awake_minutes >= 0),
+            created_on    TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            updated_on    TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            FOREIGN KEY (sleep_id)
+            REFERENCES sleep (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS hypnogram
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            sleep_id   INTEGER NOT NULL,
+            stage      TEXT NOT NULL CHECK (stage IN ('deep', 'light', 'rem', 'awake')),
+            start_time TEXT NOT NULL,
+            end_time   TEXT NOT NULL,
+            FOREIGN KEY (sleep_id)
+            REFERENCES sleep (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS hypnogram_sleep_id ON hypnogram (sleep_id, start_time);",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 9,
+        description: "make the tags of a sleep unique",
+        sql: "
+        DELETE FROM sleep_tags
+        WHERE id NOT IN (SELECT MIN(id) FROM sleep_tags GROUP BY sleep_id, tag_id);
+
+        CREATE UNIQUE INDEX IF NOT EXISTS sleep_tags_sleep_id_tag_id ON sleep_tags (sleep_id, tag_id);",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 10,
+        description: "create tag_alias table for the old names of merged and renamed tags",
+        sql: "
+        CREATE TABLE IF NOT EXISTS tag_alias
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id    INTEGER NOT NULL,
+            tag_id     INTEGER NOT NULL,
+            name       TEXT NOT NULL,
+            created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            UNIQUE (user_id, name),
+            FOREIGN KEY (user_id)
+            REFERENCES user (id)
                 ON UPDATE CASCADE
                 ON DELETE CASCADE,
             FOREIGN KEY (tag_id)
-            REFERENCES tag (id) 
+            REFERENCES tag (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS tag_alias_tag_id ON tag_alias (tag_id);",
+        rebuilds_tables: false,
+    },
+    Migration {
+        version: 11,
+        description: "create tag_category and tag_hierarchy tables for the category and parent of tags",
+        sql: "
+        CREATE TABLE IF NOT EXISTS tag_category
+        (
+            id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            user_id    INTEGER NOT NULL,
+            name       TEXT NOT NULL,
+            created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+            UNIQUE (user_id, name),
+            FOREIGN KEY (user_id)
+            REFERENCES user (id)
                 ON UPDATE CASCADE
                 ON DELETE CASCADE
-        );";
-
-        let create_comment_table =
-        "CREATE TABLE IF NOT EXISTS comment 
-            (
-                id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
-                sleep_id   INTEGER NOT NULL,
-                comment    TEXT NOT NULL,
-                FOREIGN KEY (sleep_id)
-                REFERENCES sleep (id) 
-                    ON UPDATE CASCADE
-                    ON DELETE CASCADE
-            );";
-
-    let set_user_version = "PRAGMA user_version = 1;";
-              
-    query.push_str(create_sleep_table);
-    query.push_str(create_tag_table);
-    query.push_str(create_sleep_tag_table);
-    query.push_str(create_comment_table);
-    query.push_str(set_user_version);
-
-    sqlx::query(query.as_str()).execute(pool).await
-}
\ No newline at end of file
+        );
+
+        CREATE TABLE IF NOT EXISTS tag_hierarchy
+        (
+            tag_id      INTEGER NOT NULL PRIMARY KEY,
+            parent_id   INTEGER,
+            category_id INTEGER,
+            FOREIGN KEY (tag_id)
+            REFERENCES tag (id)
+                ON UPDATE CASCADE
+                ON DELETE CASCADE,
+            FOREIGN KEY (parent_id)
+            REFERENCES tag (id)
+                ON UPDATE CASCADE
+                ON DELETE SET NULL,
+            FOREIGN KEY (category_id)
+            REFERENCES tag_category (id)
+                ON UPDATE CASCADE
+                ON DELETE SET NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS tag_hierarchy_parent_id ON tag_hierarchy (parent_id);
+        CREATE INDEX IF NOT EXISTS tag_hierarchy_category_id ON tag_hierarchy (category_id);",
+        rebuilds_tables: false,
+    },
+];
+
+/// Returns the schema version of the newest migration known to this binary
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Returns the schema version stored in the `user_version` pragma of the database
+pub async fn get_user_version(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("PRAGMA user_version")
+        .fetch_one(pool)
+        .await
+}
+
+/// Migrates the database to the latest schema version.
+/// Every migration newer than the current `user_version` is applied in order, each inside its own transaction,
+/// so a failing migration leaves the database at the last version that was applied successfully.
+/// Returns the version of the database after migrating, or an
+/// [UnsupportedSchemaVersion](DBError::UnsupportedSchemaVersion) error if the database is newer than this binary.
+///
+/// # Arguments
+///
+/// * `pool` - connection pool of the database to migrate
+///
+pub async fn migrate(pool: &SqlitePool) -> Result<i64, DBError> {
+    let current_version = get_user_version(pool).await?;
+    let latest_version = latest_version();
+
+    if current_version > latest_version {
+        return Err(DBError::UnsupportedSchemaVersion { found: current_version, supported: latest_version });
+    }
+
+    let mut conn = pool.acquire().await?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current_version) {
+        // foreign keys can't be turned off inside of a transaction, and must be turned back on
+        // even if the migration fails since the connection is returned to the pool
+        if migration.rebuilds_tables {
+            sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut conn).await?;
+        }
+
+        let result = apply(&mut conn, migration).await;
+
+        if migration.rebuilds_tables {
+            sqlx::query("PRAGMA foreign_keys = ON").execute(&mut conn).await?;
+        }
+
+        result?;
+        println!("Migrated DB to version {}: {}", migration.version, migration.description);
+    }
+
+    Ok(latest_version)
+}
+
+/// Applies a single migration inside of a transaction and sets the `user_version` to the version of the migration
+async fn apply(conn: &mut SqliteConnection, migration: &Migration) -> Result<(), DBError> {
+    let mut tx = conn.begin().await?;
+
+    sqlx::query(migration.sql).execute(&mut tx).await?;
+
+    if migration.rebuilds_tables {
+        let violations = sqlx::query("PRAGMA foreign_key_check").fetch_all(&mut tx).await?;
+        if !violations.is_empty() {
+            return Err(DBError::ForeignKeyViolation(
+                format!("migration to version {} left {} rows referencing rows that do not exist", migration.version, violations.len())));
+        }
+    }
+
+    // pragmas can't be bound as parameters, the version is a constant from the list above
+    let set_user_version = format!("PRAGMA user_version = {};", migration.version);
+    sqlx::query(set_user_version.as_str()).execute(&mut tx).await?;
+
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use super::DBError;
+
+/// Number of rows in a page when neither `first` nor `last` is given
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Largest number of rows that can be requested in a single page
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Arguments of a keyset paginated query, following the relay connection spec.
+/// The rows are ordered by their key, `after` and `before` are exclusive bounds on the key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageArgs<K> {
+    /// Only return rows with a key after this key
+    pub after: Option<K>,
+
+    /// Only return rows with a key before this key
+    pub before: Option<K>,
+
+    /// Return the first n rows after the bounds are applied
+    pub first: Option<usize>,
+
+    /// Return the last n rows after the bounds are applied
+    pub last: Option<usize>,
+}
+
+/// A page of rows from a keyset paginated query
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Page<T> {
+    /// Rows of the page, ordered by their key
+    pub items: Vec<T>,
+
+    /// true if there are rows before the page
+    pub has_previous_page: bool,
+
+    /// true if there are rows after the page
+    pub has_next_page: bool,
+
+    /// Number of rows in the listing, ignoring the bounds and page size
+    pub total_count: i64,
+}
+
+impl<K> PageArgs<K> {
+    /// Returns a [Validation](DBError::Validation) error if both `first` and `last` are given,
+    /// or if more rows than the [max page size](MAX_PAGE_SIZE) are requested
+    pub fn validate(&self) -> Result<(), DBError> {
+        if self.first.is_some() && self.last.is_some() {
+            return Err(DBError::Validation(String::from("first and last can not be used at the same time")));
+        }
+
+        match self.first.or(self.last) {
+            Some(size) if size > MAX_PAGE_SIZE =>
+                Err(DBError::Validation(format!("at most {} rows can be requested, {} were requested", MAX_PAGE_SIZE, size))),
+            _ => Ok(())
+        }
+    }
+
+    /// Returns true if the page is taken from the end of the listing
+    pub fn is_backward(&self) -> bool {
+        self.last.is_some()
+    }
+
+    /// Returns the number of rows in the page
+    pub fn page_size(&self) -> usize {
+        self.first.or(self.last).unwrap_or(DEFAULT_PAGE_SIZE)
+    }
+
+    /// Returns the number of rows to select, one more than the page size
+    /// to know if there are more rows past the page
+    pub fn limit(&self) -> i64 {
+        self.page_size() as i64 + 1
+    }
+}
+
+impl<T> Page<T> {
+    /// Creates a page from rows that were selected with the [limit](PageArgs::limit) of the arguments.
+    /// The rows are in key order when paging forward, and in reverse key order when paging backward.
+    pub fn from_rows<K>(mut rows: Vec<T>, args: &PageArgs<K>, total_count: i64) -> Page<T> {
+        let has_more = rows.len() > args.page_size();
+        rows.truncate(args.page_size());
+
+        if args.is_backward() {
+            rows.reverse();
+            Page { items: rows, has_previous_page: has_more, has_next_page: args.before.is_some(), total_count }
+        }
+        else {
+            Page { items: rows, has_previous_page: args.after.is_some(), has_next_page: has_more, total_count }
+        }
+    }
+
+    /// Converts the rows of the page, keeping the page info
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            has_previous_page: self.has_previous_page,
+            has_next_page: self.has_next_page,
+            total_count: self.total_count,
+        }
+    }
+}
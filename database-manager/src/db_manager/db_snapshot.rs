@@ -0,0 +1,177 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use chrono::{Local, NaiveDateTime};
+use sqlx::{ConnectOptions, Connection};
+use sqlx::sqlite::SqliteConnectOptions;
+use super::{DBError, DBManager};
+
+/// Prefix of the file names of snapshots, only files with this prefix are rotated
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+
+/// Extension of the file names of snapshots
+const SNAPSHOT_EXTENSION: &str = ".db";
+
+/// Where and how often snapshots of the database are taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotSettings {
+    /// Directory the snapshots are written to, it is created if it doesn't exist
+    pub dir: PathBuf,
+
+    /// Time between snapshots
+    pub interval: Duration,
+
+    /// Number of snapshots that are kept, older snapshots are deleted after a new snapshot is taken
+    pub keep: usize,
+}
+
+/// Result of taking a snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotStatus {
+    /// File name of the snapshot in the snapshot directory, None if no snapshot was written
+    pub file: Option<String>,
+
+    /// Local time the snapshot was started
+    pub started_on: NaiveDateTime,
+
+    /// Time it took to write and verify the snapshot
+    pub duration: Duration,
+
+    /// Size of the snapshot in bytes, None if no snapshot was written
+    pub size: Option<u64>,
+
+    /// Why the snapshot failed, None if it succeeded
+    pub error: Option<String>,
+}
+
+impl SnapshotStatus {
+    /// Returns true if the snapshot was written and passed the integrity check
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Results of the snapshots taken so far
+#[derive(Debug, Clone, Default)]
+struct SnapshotHistory {
+    last: Option<SnapshotStatus>,
+    last_success_on: Option<NaiveDateTime>,
+}
+
+/// Takes consistent snapshots of the live database into a directory, keeping a number of the newest snapshots.
+/// Clones share the results of the snapshots, so the status can be read while snapshots are taken by another task.
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    settings: SnapshotSettings,
+    history: Arc<RwLock<SnapshotHistory>>,
+}
+
+impl Snapshots {
+    /// Returns a [Validation](DBError::Validation) error if the interval is zero or no snapshots would be kept
+    pub fn new(settings: SnapshotSettings) -> Result<Snapshots, DBError> {
+        if settings.interval.is_zero() {
+            return Err(DBError::Validation(String::from("the snapshot interval must be longer than zero")));
+        }
+        if settings.keep == 0 {
+            return Err(DBError::Validation(String::from("at least one snapshot must be kept")));
+        }
+
+        Ok(Snapshots { settings, history: Arc::new(RwLock::new(SnapshotHistory::default())) })
+    }
+
+    pub fn settings(&self) -> &SnapshotSettings {
+        &self.settings
+    }
+
+    /// Returns the result of the last snapshot, None if no snapshot was taken yet
+    pub fn last(&self) -> Option<SnapshotStatus> {
+        self.history.read().unwrap().last.clone()
+    }
+
+    /// Returns when the last successful snapshot was started, None if no snapshot succeeded yet
+    pub fn last_success_on(&self) -> Option<NaiveDateTime> {
+        self.history.read().unwrap().last_success_on
+    }
+
+    /// Writes a snapshot of the database with `VACUUM INTO`, verifies it with `PRAGMA integrity_check`,
+    /// then deletes the oldest snapshots beyond the number that are kept.
+    /// A snapshot that fails the integrity check is deleted. Returns the result, which is also kept as the last result.
+    pub async fn take(&self, dbm: &DBManager) -> SnapshotStatus {
+        let started_on = Local::now().naive_local();
+        let timer = Instant::now();
+        let file = format!("{}{}{}", SNAPSHOT_PREFIX, started_on.format("%Y%m%d-%H%M%S%3f"), SNAPSHOT_EXTENSION);
+
+        let result = self.write(dbm, &file).await;
+        let status = SnapshotStatus {
+            file: result.as_ref().ok().map(|_| file),
+            started_on,
+            duration: timer.elapsed(),
+            size: result.as_ref().ok().copied(),
+            error: result.err(),
+        };
+
+        let mut history = self.history.write().unwrap();
+        if status.succeeded() {
+            history.last_success_on = Some(started_on);
+        }
+        history.last = Some(status.clone());
+        status
+    }
+
+    /// Writes and verifies a snapshot, then rotates the snapshots. Returns the size of the snapshot
+    async fn write(&self, dbm: &DBManager, file: &str) -> Result<u64, String> {
+        fs::create_dir_all(&self.settings.dir)
+            .map_err(|e| format!("unable to create the snapshot directory {}: {}", self.settings.dir.display(), e))?;
+        let path = self.settings.dir.join(file);
+
+        dbm.snapshot_into(&path).await.map_err(|e| e.to_string())?;
+        let problems = integrity_check(&path).await.map_err(|e| e.to_string())?;
+        if !problems.is_empty() {
+            // a snapshot that can't be restored must not push a good one out of the rotation
+            let _ = fs::remove_file(&path);
+            return Err(format!("snapshot {} failed the integrity check: {}", file, problems.join("; ")));
+        }
+
+        let size = fs::metadata(&path).map_err(|e| format!("unable to read snapshot {}: {}", file, e))?.len();
+        self.rotate().map_err(|e| format!("unable to delete old snapshots: {}", e))?;
+        Ok(size)
+    }
+
+    /// Returns the paths of the snapshots in the snapshot directory, oldest first
+    pub fn list(&self) -> std::io::Result<Vec<PathBuf>> {
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.settings.dir)? {
+            let path = entry?.path();
+            let is_snapshot = path.file_name().and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(SNAPSHOT_EXTENSION));
+            if is_snapshot && path.is_file() {
+                snapshots.push(path);
+            }
+        }
+
+        // the file names hold the time the snapshot was started, so they sort oldest first
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
+    /// Deletes the oldest snapshots beyond the number of snapshots that are kept
+    fn rotate(&self) -> std::io::Result<()> {
+        let snapshots = self.list()?;
+        let excess = snapshots.len().saturating_sub(self.settings.keep);
+        for path in &snapshots[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `PRAGMA integrity_check` on a database file, opened read-only.
+/// Returns the problems that were found, empty if the database is intact
+pub async fn integrity_check(path: &Path) -> Result<Vec<String>, DBError> {
+    let mut conn = SqliteConnectOptions::new().filename(path).read_only(true).connect().await?;
+    let results: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check").fetch_all(&mut conn).await?;
+    conn.close().await?;
+
+    Ok(results.into_iter().filter(|r| r != "ok").collect())
+}
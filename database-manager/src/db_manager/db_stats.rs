@@ -0,0 +1,395 @@
+use std::collections::{HashMap, HashSet};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use statrs::distribution::{ContinuousCDF, StudentsT};
+use super::db_types::{DBSleep, DBSleepStages, DBSleepTags, DBTag, SleepStage, SleepStages};
+
+/// Number of nights in the short rolling window
+pub const SHORT_WINDOW_DAYS: i64 = 7;
+
+/// Number of nights in the long rolling window
+pub const LONG_WINDOW_DAYS: i64 = 30;
+
+/// How sleeps are grouped when computing statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatsGroupBy {
+    /// One group per night
+    #[default]
+    Day,
+
+    /// One group per ISO week, weeks start on monday
+    Week,
+
+    /// One group per calendar month
+    Month,
+
+    /// One group per day of the week, combining every week of the range
+    Weekday,
+}
+
+/// Summary statistics of a set of values
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Summary {
+    /// Average of the values
+    pub mean: f64,
+
+    /// Middle value, or the average of the two middle values
+    pub median: f64,
+
+    /// Smallest value
+    pub min: f64,
+
+    /// Largest value
+    pub max: f64,
+
+    /// Sample standard deviation, None when there are fewer than two values
+    pub std_dev: Option<f64>,
+}
+
+/// Statistics of the sleeps in a group
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatsGroup {
+    /// Label of the group, ex: "2023-05-13", "2023-W19", "2023-05" or "Saturday"
+    pub key: String,
+
+    /// First date of the group, None when grouped by weekday
+    pub start: Option<NaiveDate>,
+
+    /// Number of sleeps in the group
+    pub count: i64,
+
+    /// Summary of the amount of sleep
+    pub amount: Summary,
+
+    /// Summary of the quality of sleep
+    pub quality: Summary,
+
+    /// Average share of each stage of sleep, None if no sleep in the group has stages
+    pub stages: Option<StageShares>,
+}
+
+/// Average share of the night spent in each stage of sleep, over the sleeps of a group that have stages.
+/// See [share](SleepStages::share) for what each share is of.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageShares {
+    /// Number of sleeps in the group that have stages
+    pub count: i64,
+
+    /// Average share of deep sleep, None if no sleep recorded it
+    pub deep: Option<f64>,
+
+    /// Average share of light sleep, None if no sleep recorded it
+    pub light: Option<f64>,
+
+    /// Average share of REM sleep, None if no sleep recorded it
+    pub rem: Option<f64>,
+
+    /// Average share of time awake, None if no sleep recorded it
+    pub awake: Option<f64>,
+}
+
+/// Rolling averages of the sleeps in the windows of nights that end on a night
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RollingAverage {
+    /// Last night of the windows
+    pub night: NaiveDate,
+
+    /// Average amount of the sleeps in the last 7 nights
+    pub amount_7_day: f64,
+
+    /// Average amount of the sleeps in the last 30 nights
+    pub amount_30_day: f64,
+
+    /// Average quality of the sleeps in the last 7 nights
+    pub quality_7_day: f64,
+
+    /// Average quality of the sleeps in the last 30 nights
+    pub quality_30_day: f64,
+}
+
+/// Statistics of the sleeps within a date range
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SleepStats {
+    /// Statistics of every sleep in the range, None if the range has no sleeps
+    pub overall: Option<StatsGroup>,
+
+    /// Statistics of each group that has sleeps, in chronological order, or monday to sunday for weekdays
+    pub groups: Vec<StatsGroup>,
+
+    /// Rolling averages for each night in the range that has a sleep
+    pub rolling: Vec<RollingAverage>,
+}
+
+/// Result of Welch's t-test comparing the means of two samples
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TTest {
+    /// t statistic, positive when the first sample has the larger mean
+    pub t: f64,
+
+    /// Welch–Satterthwaite degrees of freedom
+    pub degrees_of_freedom: f64,
+
+    /// Two sided p-value, the probability of a difference at least this large if the means are equal
+    pub p_value: f64,
+}
+
+/// Comparison of a value between the sleeps with a tag and the sleeps without it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Comparison {
+    /// Mean of the sleeps with the tag, None if no sleeps have the tag
+    pub mean_with: Option<f64>,
+
+    /// Mean of the sleeps without the tag, None if every sleep has the tag
+    pub mean_without: Option<f64>,
+
+    /// Mean with the tag minus the mean without it, None if either mean is unknown
+    pub difference: Option<f64>,
+
+    /// Significance of the difference, None when either side has fewer than two sleeps or no variance
+    pub t_test: Option<TTest>,
+}
+
+/// How a tag relates to the amount and quality of the sleeps in a date range
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagImpact {
+    /// The tag that is compared
+    pub tag: DBTag,
+
+    /// Number of sleeps with the tag
+    pub count_with: i64,
+
+    /// Number of sleeps without the tag
+    pub count_without: i64,
+
+    /// Comparison of the amount of sleep
+    pub amount: Comparison,
+
+    /// Comparison of the quality of sleep
+    pub quality: Comparison,
+}
+
+impl Summary {
+    /// Summarizes the values, returns None if there are no values
+    pub fn from_values(values: &[f64]) -> Option<Summary> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        let count = sorted.len();
+        let middle = count / 2;
+        let median = if count.is_multiple_of(2) { (sorted[middle - 1] + sorted[middle]) / 2.0 } else { sorted[middle] };
+
+        Some(Summary {
+            mean: mean(&sorted),
+            median,
+            min: sorted[0],
+            max: sorted[count - 1],
+            std_dev: sample_variance(&sorted).map(f64::sqrt),
+        })
+    }
+}
+
+/// Returns the mean of the values, 0 if there are no values
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Returns the unbiased sample variance of the values, None when there are fewer than two values
+pub fn sample_variance(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+
+    let mean = mean(values);
+    let squares = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>();
+    Some(squares / (values.len() - 1) as f64)
+}
+
+/// Performs Welch's t-test on two samples, which doesn't assume the samples have equal variances.
+/// Returns None when either sample has fewer than two values or both samples have no variance.
+pub fn welch_t_test(first: &[f64], second: &[f64]) -> Option<TTest> {
+    let first_error = sample_variance(first)? / first.len() as f64;
+    let second_error = sample_variance(second)? / second.len() as f64;
+    let standard_error = (first_error + second_error).sqrt();
+    if standard_error == 0.0 {
+        return None;
+    }
+
+    let t = (mean(first) - mean(second)) / standard_error;
+    let degrees_of_freedom = (first_error + second_error).powi(2)
+        / (first_error.powi(2) / (first.len() - 1) as f64 + second_error.powi(2) / (second.len() - 1) as f64);
+
+    let distribution = StudentsT::new(0.0, 1.0, degrees_of_freedom).ok()?;
+    let p_value = 2.0 * distribution.sf(t.abs());
+
+    Some(TTest { t, degrees_of_freedom, p_value })
+}
+
+impl Comparison {
+    /// Compares the values of the sleeps with a tag to the values of the sleeps without it
+    pub fn from_values(with: &[f64], without: &[f64]) -> Comparison {
+        let mean_with = (!with.is_empty()).then(|| mean(with));
+        let mean_without = (!without.is_empty()).then(|| mean(without));
+
+        Comparison {
+            mean_with,
+            mean_without,
+            difference: mean_with.zip(mean_without).map(|(w, wo)| w - wo),
+            t_test: welch_t_test(with, without),
+        }
+    }
+}
+
+/// Computes the impact of each tag on the sleeps.
+/// Every sleep either has the tag or not, so each sleep is counted once per tag.
+/// 
+/// # Arguments
+/// 
+/// * `sleeps` - the sleeps to compare
+/// * `sleep_tags` - the relationships between the sleeps and tags
+/// * `tags` - the tags to compute the impact of, in the order they are returned
+/// 
+pub fn tag_impact(sleeps: &[DBSleep], sleep_tags: &[DBSleepTags], tags: &[DBTag]) -> Vec<TagImpact> {
+    let mut tagged: HashMap<i64, HashSet<i64>> = HashMap::new();
+    for sleep_tag in sleep_tags {
+        tagged.entry(sleep_tag.tag_id).or_default().insert(sleep_tag.sleep_id);
+    }
+
+    tags.iter().map(|tag| {
+        let sleep_ids = tagged.remove(&tag.id).unwrap_or_default();
+        let (with, without): (Vec<&DBSleep>, Vec<&DBSleep>) = sleeps.iter().partition(|s| sleep_ids.contains(&s.id));
+
+        let amounts = |sleeps: &[&DBSleep]| sleeps.iter().map(|s| s.amount).collect::<Vec<f64>>();
+        let qualities = |sleeps: &[&DBSleep]| sleeps.iter().map(|s| s.quality as f64).collect::<Vec<f64>>();
+
+        TagImpact {
+            tag: tag.clone(),
+            count_with: with.len() as i64,
+            count_without: without.len() as i64,
+            amount: Comparison::from_values(&amounts(&with), &amounts(&without)),
+            quality: Comparison::from_values(&qualities(&with), &qualities(&without)),
+        }
+    }).collect()
+}
+
+impl StatsGroupBy {
+    /// Returns the key, first date and sort order of the group the night belongs to
+    fn group_of(&self, night: NaiveDate) -> (String, Option<NaiveDate>, i64) {
+        match self {
+            StatsGroupBy::Day => (night.to_string(), Some(night), night.num_days_from_ce() as i64),
+            StatsGroupBy::Week => {
+                let week = night.iso_week();
+                let start = NaiveDate::from_isoywd_opt(week.year(), week.week(), Weekday::Mon);
+                (format!("{}-W{:02}", week.year(), week.week()), start, (week.year() * 100 + week.week() as i32) as i64)
+            },
+            StatsGroupBy::Month => {
+                let start = night.with_day(1);
+                (format!("{}-{:02}", night.year(), night.month()), start, (night.year() * 100 + night.month() as i32) as i64)
+            },
+            StatsGroupBy::Weekday => {
+                let weekday = night.weekday();
+                (weekday_name(weekday).to_string(), None, weekday.num_days_from_monday() as i64)
+            },
+        }
+    }
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+/// Returns the statistics of a group of sleeps, None if there are no sleeps
+pub fn stats_group(key: String, start: Option<NaiveDate>, sleeps: &[&DBSleep], stages: &HashMap<i64, SleepStages>) -> Option<StatsGroup> {
+    let amounts = sleeps.iter().map(|s| s.amount).collect::<Vec<f64>>();
+    let qualities = sleeps.iter().map(|s| s.quality as f64).collect::<Vec<f64>>();
+    let group_stages = sleeps.iter().filter_map(|s| stages.get(&s.id)).collect::<Vec<&SleepStages>>();
+
+    Some(StatsGroup {
+        key,
+        start,
+        count: sleeps.len() as i64,
+        amount: Summary::from_values(&amounts)?,
+        quality: Summary::from_values(&qualities)?,
+        stages: StageShares::from_stages(&group_stages),
+    })
+}
+
+impl StageShares {
+    /// Averages the share of each stage over the stages that recorded it, returns None if there are no stages
+    pub fn from_stages(stages: &[&SleepStages]) -> Option<StageShares> {
+        if stages.is_empty() {
+            return None;
+        }
+
+        let average = |stage: SleepStage| {
+            let shares = stages.iter().filter_map(|s| s.share(stage)).collect::<Vec<f64>>();
+            (!shares.is_empty()).then(|| mean(&shares))
+        };
+
+        Some(StageShares {
+            count: stages.len() as i64,
+            deep: average(SleepStage::Deep),
+            light: average(SleepStage::Light),
+            rem: average(SleepStage::Rem),
+            awake: average(SleepStage::Awake),
+        })
+    }
+}
+
+/// Computes the statistics of the sleeps with a night between the start and end, inclusive.
+/// `sleeps` may contain sleeps from before the start so the rolling averages of the first nights
+/// use complete windows, only sleeps within the range are included in the groups.
+/// `stages` holds the stages of the sleeps that have them.
+pub fn compute(sleeps: &[DBSleep], stages: &[DBSleepStages], start: NaiveDate, end: NaiveDate, group_by: StatsGroupBy) -> SleepStats {
+    let stages = stages.iter().map(|s| (s.sleep_id, s.stages())).collect::<HashMap<i64, SleepStages>>();
+    let in_range = sleeps.iter().filter(|s| s.night >= start && s.night <= end).collect::<Vec<&DBSleep>>();
+
+    let mut groups: Vec<(i64, String, Option<NaiveDate>, Vec<&DBSleep>)> = Vec::new();
+    for sleep in &in_range {
+        let (key, group_start, order) = group_by.group_of(sleep.night);
+        match groups.iter_mut().find(|g| g.0 == order) {
+            Some(group) => group.3.push(sleep),
+            None => groups.push((order, key, group_start, vec![sleep])),
+        }
+    }
+    groups.sort_by_key(|g| g.0);
+
+    let rolling = in_range.iter().map(|s| RollingAverage {
+        night: s.night,
+        amount_7_day: window_average(sleeps, s.night, SHORT_WINDOW_DAYS, |s| s.amount),
+        amount_30_day: window_average(sleeps, s.night, LONG_WINDOW_DAYS, |s| s.amount),
+        quality_7_day: window_average(sleeps, s.night, SHORT_WINDOW_DAYS, |s| s.quality as f64),
+        quality_30_day: window_average(sleeps, s.night, LONG_WINDOW_DAYS, |s| s.quality as f64),
+    }).collect::<Vec<RollingAverage>>();
+
+    SleepStats {
+        overall: stats_group(String::from("all"), Some(start), &in_range, &stages),
+        groups: groups.into_iter().filter_map(|(_, key, start, sleeps)| stats_group(key, start, &sleeps, &stages)).collect(),
+        rolling,
+    }
+}
+
+/// Averages the value of the sleeps in the window of `days` nights that ends on `night`
+fn window_average(sleeps: &[DBSleep], night: NaiveDate, days: i64, value: fn(&DBSleep) -> f64) -> f64 {
+    let first = night - Duration::days(days - 1);
+    let values = sleeps.iter()
+        .filter(|s| s.night >= first && s.night <= night)
+        .map(value)
+        .collect::<Vec<f64>>();
+
+    mean(&values)
+}
@@ -1,6 +1,56 @@
 use std::fs;
+use async_graphql::futures_util::{Stream, StreamExt};
+use chrono::{NaiveDate, NaiveDateTime};
+use sqlx::migrate::MigrateDatabase;
+use sqlx::Sqlite;
+use sqlx::sqlite::SqlitePoolOptions;
 use super::DBManager;
+use super::db_migrations;
 use super::db_types;
+use super::{DBDream, DreamDetails, DreamFilter, DreamUpdate, HypnogramInterval, MergePolicy, SessionKind, SleepStage, SleepStages, SleepTimes, SleepUpsert};
+use super::DBError;
+use super::{AppleHealthOptions, Backup, CsvColumns, CsvImportOptions, FitbitOptions, ImportIssueKind, Page, PageArgs, RestoreMode, SnapshotSettings, Snapshots, StatsGroupBy};
+
+/// Schema and data of a database created by the first release, before migrations were versioned.
+/// This must not change as new migrations are added, it is used to test upgrading old databases.
+const V1_FIXTURE: &str = "
+    CREATE TABLE sleep
+    (
+        id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        night      TEXT NOT NULL UNIQUE,
+        amount     REAL NOT NULL,
+        quality    INTEGER NOT NULL,
+        created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+        updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime'))
+    );
+    CREATE TABLE tag
+    (
+        id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        name       TEXT NOT NULL UNIQUE,
+        color      INTEGER NOT NULL,
+        created_on TEXT NOT NULL DEFAULT (datetime('now','localtime')),
+        updated_on TEXT NOT NULL DEFAULT (datetime('now','localtime'))
+    );
+    CREATE TABLE sleep_tags
+    (
+        id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        sleep_id   INTEGER NOT NULL,
+        tag_id     INTEGER NOT NULL,
+        FOREIGN KEY (sleep_id) REFERENCES sleep (id) ON UPDATE CASCADE ON DELETE CASCADE,
+        FOREIGN KEY (tag_id) REFERENCES tag (id) ON UPDATE CASCADE ON DELETE CASCADE
+    );
+    CREATE TABLE comment
+    (
+        id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        sleep_id   INTEGER NOT NULL,
+        comment    TEXT NOT NULL,
+        FOREIGN KEY (sleep_id) REFERENCES sleep (id) ON UPDATE CASCADE ON DELETE CASCADE
+    );
+    INSERT INTO sleep ( night, amount, quality ) VALUES ( '2022-11-24', 6.0, 2 ), ( '2022-11-25', 7.5, 1 );
+    INSERT INTO tag ( name, color ) VALUES ( 'screen', 9590460 );
+    INSERT INTO sleep_tags ( sleep_id, tag_id ) VALUES ( 2, 1 );
+    INSERT INTO comment ( sleep_id, comment ) VALUES ( 1, 'fixture comment' );
+    PRAGMA user_version = 1;";
 
 /// Creates a test database. If the given database already exists it will be deleted.
 /// Once the database is created, mock data is added, queried, updated and deleted
@@ -22,14 +72,99 @@ pub async fn test_db_queries(db_path: &str) {
     test_sleep_selects(&mut dbm).await;
     test_tag_selects(&mut dbm).await;
     test_comment_selects(&mut dbm).await;
+    test_dreams(&mut dbm).await;
+    test_sleep_times(&mut dbm).await;
+    test_sleep_sessions(&mut dbm).await;
+    test_sleep_stats(&mut dbm).await;
+    test_tag_impact(&mut dbm).await;
+    test_pagination(&mut dbm).await;
     test_updates(&mut dbm).await;
+    test_errors(&mut dbm).await;
     test_deletes(&mut dbm).await;
+    test_batch_loading(&mut dbm).await;
+    test_user_isolation(&mut dbm).await;
+    test_api_tokens(&mut dbm).await;
+    test_subscriptions(&mut dbm).await;
+    test_csv(&mut dbm).await;
+    test_backup(&mut dbm, &format!("{}.restore", db_path)).await;
+    test_snapshots(&mut dbm, &format!("{}.snapshots", db_path)).await;
+    test_apple_health(&mut dbm).await;
+    test_fitbit(&mut dbm, &format!("{}.fitbit", db_path)).await;
+    test_sleep_stages(&mut dbm).await;
+    test_unit_of_work(&mut dbm).await;
+    test_upsert_sleep(&mut dbm).await;
+    test_set_sleep_tags(&mut dbm).await;
+    test_merge_tags(&mut dbm).await;
+    test_tag_hierarchy(&mut dbm).await;
 
     dbm.close_connection().await;
 
     println!("Tests complete!");
 }
 
+/// Creates a database with the version 1 schema and data, and tests that it is migrated to the latest
+/// schema without losing data. Also tests that a database newer than the latest schema is refused.
+/// If the given database already exists it will be deleted.
+/// 
+/// # Arguments
+/// 
+/// * `db_path` - A string slice containing the file path to the test database
+/// 
+pub async fn test_db_migrations(db_path: &str) {
+    if std::path::Path::new(db_path).exists() {
+        println!("Deleting test db and starting fresh");
+        fs::remove_file(db_path).unwrap();
+    }
+
+    Sqlite::create_database(db_path).await.unwrap();
+    let pool = SqlitePoolOptions::new().max_connections(1).connect(db_path).await.unwrap();
+    sqlx::query(V1_FIXTURE).execute(&pool).await.unwrap();
+    assert_eq!(db_migrations::get_user_version(&pool).await.unwrap(), 1);
+    // the v1 schema let a sleep have the same tag more than once
+    sqlx::query("INSERT INTO sleep_tags ( sleep_id, tag_id ) VALUES ( 2, 1 )").execute(&pool).await.unwrap();
+    pool.close().await;
+
+    // upgrade the v1 fixture to the latest schema
+    let dbm = DBManager::init(db_path).await.unwrap();
+    assert_eq!(dbm.schema_version().await.unwrap(), db_migrations::latest_version());
+
+    let sleeps = dbm.get_all_sleeps().await.unwrap();
+    assert_eq!(sleeps.len(), 2);
+    assert_eq!(sleeps[1].sleep.amount, 7.5);
+
+    // every migrated sleep is moved into a single main session
+    for sleep in &sleeps {
+        let sessions = dbm.get_sleep_sessions(sleep.sleep.id).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].kind(), SessionKind::Main);
+        assert_eq!(sessions[0].amount, sleep.sleep.amount);
+        assert_eq!(sessions[0].times(), SleepTimes::default());
+    }
+    assert_eq!(dbm.get_tags_by_sleep(2).await.unwrap().iter().map(|t| t.name.as_str()).collect::<Vec<&str>>(), vec!["screen"]);
+    assert_eq!(dbm.get_comments_by_sleep(1).await.unwrap()[0].comment, "fixture comment");
+
+    // data from before there were users belongs to the default user
+    let users = dbm.get_all_users().await.unwrap();
+    assert_eq!(users.iter().map(|u| u.id).collect::<Vec<i64>>(), vec![super::DEFAULT_USER_ID]);
+    let other = dbm.for_user(dbm.insert_user("other").await.unwrap());
+    assert!(other.get_all_sleeps().await.unwrap().is_empty());
+    assert!(other.get_all_tags().await.unwrap().is_empty());
+
+    // migrating an up to date database is a no-op
+    dbm.close_connection().await;
+    let dbm = DBManager::init(db_path).await.unwrap();
+    assert_eq!(dbm.get_all_sleeps().await.unwrap().len(), 2);
+
+    // a database from a newer binary is refused
+    let newer_version = format!("PRAGMA user_version = {};", db_migrations::latest_version() + 1);
+    sqlx::query(newer_version.as_str()).execute(&dbm.connection_pool).await.unwrap();
+    dbm.close_connection().await;
+    let newer = DBManager::init(db_path).await;
+    assert!(matches!(newer, Err(DBError::UnsupportedSchemaVersion { .. })));
+
+    println!("Migration tests complete!");
+}
+
 /// Creates a test database. If the given database already exists it will be deleted.
 /// Returns a DBManager to manage the test database.
 /// 
@@ -50,30 +185,40 @@ pub async fn create_test_db(db_path: &str) -> DBManager {
     dbm
 }
 
+/// Parses a night in yyyy-mm-dd format
+fn night(date: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+}
+
+/// Parses a time in yyyy-mm-dd hh:mm format
+fn time(time: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+}
+
 async fn test_inserts(dbm: &mut DBManager) {
     // test insert queries while setting up db
     // Sleep valid inserts
-    assert_eq!(dbm.insert_sleep("2022-11-25", 7.5, 1).await, 1);
-    assert_eq!(dbm.insert_sleep("2022-11-24", 6.0, 2).await, 2);
-    assert_eq!(dbm.insert_sleep("2022-11-26", 8.0, 3).await, 3);
+    assert_eq!(dbm.insert_sleep(night("2022-11-25"), 7.5, 1).await.unwrap(), 1);
+    assert_eq!(dbm.insert_sleep(night("2022-11-24"), 6.0, 2).await.unwrap(), 2);
+    assert_eq!(dbm.insert_sleep(night("2022-11-26"), 8.0, 3).await.unwrap(), 3);
 
     // tag valid inserts
-    assert_eq!(dbm.insert_tag("test name", 3713678).await, 1);
-    assert_eq!(dbm.insert_tag("screen", 9590460).await, 2);
+    assert_eq!(dbm.insert_tag("test name", 3713678).await.unwrap(), 1);
+    assert_eq!(dbm.insert_tag("screen", 9590460).await.unwrap(), 2);
 
     // sleep_tag valid inserts
-    assert!(dbm.add_tags_to_sleep(2, vec![2]).await);
-    assert!(dbm.add_tags_to_sleep(1, vec![1,2]).await);
+    assert!(dbm.add_tags_to_sleep(2, vec![2]).await.is_ok());
+    assert!(dbm.add_tags_to_sleep(1, vec![1,2]).await.is_ok());
 
     // comment valid inserts
-    assert_eq!(dbm.insert_comment(1, "First comment").await, 1);
-    assert_eq!(dbm.insert_comment(2, "test comment").await, 2);
-    assert_eq!(dbm.insert_comment(1, "2nd comment on night").await, 3);
+    assert_eq!(dbm.insert_comment(1, "First comment").await.unwrap(), 1);
+    assert_eq!(dbm.insert_comment(2, "test comment").await.unwrap(), 2);
+    assert_eq!(dbm.insert_comment(1, "2nd comment on night").await.unwrap(), 3);
 }
 
 async fn test_sleep_selects(dbm: &mut DBManager) {
     let sleep_test = dbm.get_sleep(2, false).await.expect("Sleep test failed");
-    let expected_sleep = db_types::DBSleep { id: 2, night: String::from("2022-11-24"), amount: 6.0, quality: 2 };
+    let expected_sleep = db_types::DBSleep { id: 2, night: night("2022-11-24"), amount: 6.0, quality: 2 };
     assert_eq!(sleep_test.sleep.id, expected_sleep.id);
     assert_eq!(sleep_test.sleep.night, expected_sleep.night);
     assert_eq!(sleep_test.sleep.amount, expected_sleep.amount);
@@ -81,7 +226,7 @@ async fn test_sleep_selects(dbm: &mut DBManager) {
     assert!(sleep_test.tags.is_none());
 
     let sleep_with_tags_test = dbm.get_sleep(1, true).await.expect("Sleep test failed");
-    let expected_sleep = db_types::DBSleep { id: 1, night: String::from("2022-11-25"), amount: 7.5, quality: 1 };
+    let expected_sleep = db_types::DBSleep { id: 1, night: night("2022-11-25"), amount: 7.5, quality: 1 };
     assert_eq!(sleep_with_tags_test.sleep.id, expected_sleep.id);
     assert_eq!(sleep_with_tags_test.sleep.night, expected_sleep.night);
     assert_eq!(sleep_with_tags_test.sleep.amount, expected_sleep.amount);
@@ -90,7 +235,7 @@ async fn test_sleep_selects(dbm: &mut DBManager) {
     assert_eq!(sleep_with_tags_test.tags.unwrap().len(), 2);
 
     let none_test = dbm.get_sleep(100, false).await;
-    assert!(none_test.is_none());
+    assert!(matches!(none_test, Err(DBError::NotFound { .. })));
 
     let all_sleeps = dbm.get_all_sleeps().await.expect("All sleep test failed");
     assert_eq!(all_sleeps.len(), 3);
@@ -109,6 +254,26 @@ async fn test_sleep_selects(dbm: &mut DBManager) {
 
     let sleep_by_months_none = dbm.get_sleeps_by_month(11, 2023).await.expect("sleep by month test failed");
     assert_eq!(sleep_by_months_none.len(), 0);    
+
+    let sleeps_in_range = dbm.get_sleeps_in_range(night("2022-11-20"), night("2023-01-05")).await.expect("sleep in range test failed");
+    assert_eq!(sleeps_in_range.len(), 3);
+    assert_eq!(sleeps_in_range[0].sleep.night, night("2022-11-24"));
+
+    let sleeps_in_range = dbm.get_sleeps_in_range(night("2022-11-25"), night("2022-11-26")).await.expect("sleep in range test failed");
+    assert_eq!(sleeps_in_range.len(), 2);
+
+    let invalid_range = dbm.get_sleeps_in_range(night("2022-11-26"), night("2022-11-25")).await;
+    assert!(matches!(invalid_range, Err(DBError::Validation(_))));
+
+    // years past the range of a u16 are not truncated
+    let far_night = NaiveDate::from_ymd_opt(70000, 1, 2).unwrap();
+    let far_id = dbm.insert_sleep(far_night, 8.0, 3).await.unwrap();
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let response = schema.execute(format!("{{ sleep(id: {}) {{ night {{ day month year }} }} }}", far_id)).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["sleep"]["night"], serde_json::json!({ "day": 2, "month": 1, "year": 70000 }));
+    dbm.delete_sleep(far_id).await.unwrap();
 }
 
 async fn test_tag_selects(dbm: &mut DBManager) {
@@ -119,7 +284,7 @@ async fn test_tag_selects(dbm: &mut DBManager) {
     assert_eq!(tag_test.color, expected_tag.color);
 
     let none_test = dbm.get_tag(100).await;
-    assert!(none_test.is_none());
+    assert!(matches!(none_test, Err(DBError::NotFound { .. })));
 
     let all_tags = dbm.get_all_tags().await.expect("All tag test failed");
     assert_eq!(all_tags.len(), 2);
@@ -143,45 +308,1491 @@ async fn test_comment_selects(dbm: &mut DBManager) {
     assert_eq!(second_night_comments[0].comment, "test comment");
 }
 
+async fn test_dreams(dbm: &mut DBManager) {
+    let flying = DreamDetails { title: String::from("Flying"), lucid: true, vividness: Some(8), ..Default::default() };
+    let falling = DreamDetails { title: String::from("Falling"), emotional_tone: Some(-4), recurring: true, ..Default::default() };
+    let flying_id = dbm.insert_dream(1, &flying).await.unwrap();
+    let falling_id = dbm.insert_dream(2, &falling).await.unwrap();
+    dbm.insert_dream(1, &DreamDetails { title: String::from("Exam"), ..Default::default() }).await.unwrap();
+
+    let dream = dbm.get_dream(flying_id).await.unwrap();
+    assert_eq!(dream.details(), flying);
+    assert_eq!(dbm.get_dreams_by_sleep(1).await.unwrap().len(), 2);
+
+    // dreams in a range are ordered by night
+    let in_range = dbm.get_dreams_in_range(night("2022-11-24"), night("2022-11-25")).await.unwrap();
+    assert_eq!(in_range.len(), 3);
+    assert_eq!(in_range[0].id, falling_id);
+    assert_eq!(dbm.get_dreams_in_range(night("2022-11-26"), night("2022-11-30")).await.unwrap().len(), 0);
+
+    // dream tags are separate from the tags of the sleep
+    dbm.add_tags_to_dream(falling_id, vec![1]).await.unwrap();
+    dbm.add_tags_to_dream(flying_id, vec![1, 2, 2]).await.unwrap();
+    dbm.add_tags_to_dream(flying_id, vec![1]).await.unwrap();
+    assert_eq!(dbm.get_tags_by_dream(flying_id).await.unwrap().len(), 2);
+    assert_eq!(dbm.get_tags_by_sleep(2).await.unwrap().len(), 1);
+    let by_tag = dbm.get_dreams_by_tag(1).await.unwrap();
+    assert_eq!(by_tag.iter().map(|d| d.id).collect::<Vec<i64>>(), vec![falling_id, flying_id]);
+
+    // pages of dreams are ordered by night and id like the sleeps, and can be filtered by range and tag
+    let dream_ids = |page: &Page<(NaiveDate, DBDream)>| page.items.iter().map(|d| d.1.id).collect::<Vec<i64>>();
+    let range_filter = DreamFilter { range: Some((night("2022-11-24"), night("2022-11-25"))), tag_id: None };
+    let page = dbm.get_dreams_page(&PageArgs { first: Some(2), ..Default::default() }, &range_filter).await.unwrap();
+    assert_eq!(dream_ids(&page), vec![falling_id, flying_id]);
+    assert!(page.has_next_page && !page.has_previous_page);
+    assert_eq!(page.total_count, 3);
+    let after = page.items[1].0;
+    let page = dbm.get_dreams_page(&PageArgs { after: Some((after, flying_id)), first: Some(2), ..Default::default() }, &range_filter).await.unwrap();
+    assert_eq!((page.items.len(), page.items[0].1.title.as_str()), (1, "Exam"));
+    assert!(page.has_previous_page && !page.has_next_page);
+
+    let tag_filter = DreamFilter { range: None, tag_id: Some(1) };
+    let page = dbm.get_dreams_page(&PageArgs { last: Some(1), ..Default::default() }, &tag_filter).await.unwrap();
+    assert_eq!(dream_ids(&page), vec![flying_id]);
+    assert!(page.has_previous_page && !page.has_next_page);
+    assert_eq!(page.total_count, 2);
+    let reversed = DreamFilter { range: Some((night("2022-11-25"), night("2022-11-24"))), tag_id: None };
+    assert!(matches!(dbm.get_dreams_page(&PageArgs::default(), &reversed).await, Err(DBError::Validation(_))));
+
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let response = schema.execute(
+        "{ dreamsByTag(tagId: 1, first: 1) { totalCount pageInfo { hasNextPage endCursor } edges { node { title } } } }").await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    let connection = &data["dreamsByTag"];
+    assert_eq!((connection["totalCount"].as_i64(), connection["edges"][0]["node"]["title"].as_str()), (Some(2), Some("Falling")));
+    assert_eq!(connection["pageInfo"]["hasNextPage"], true);
+    let end_cursor = connection["pageInfo"]["endCursor"].as_str().unwrap();
+    let falling_night = dbm.get_sleep(2, false).await.unwrap().sleep.night;
+    assert_eq!(end_cursor, format!("{}:{}", falling_night, falling_id));
+
+    let query = format!(
+        r#"{{ dreamsInRange(startDate: "2022-11-24", endDate: "2022-11-25", after: "{}") {{ totalCount edges {{ node {{ title }} }} }} }}"#,
+        end_cursor);
+    let data = schema.execute(query.as_str()).await.data.into_json().unwrap();
+    let titles = data["dreamsInRange"]["edges"].as_array().unwrap().iter().map(|e| e["node"]["title"].as_str().unwrap()).collect::<Vec<&str>>();
+    assert_eq!((data["dreamsInRange"]["totalCount"].as_i64(), titles), (Some(3), vec!["Flying", "Exam"]));
+    let response = schema.execute(r#"{ dreamsByTag(tagId: 1, after: "flying") { totalCount } }"#).await;
+    assert!(response.errors[0].message.contains("not a valid dream cursor"), "{:?}", response.errors);
+
+    dbm.remove_tag_from_dream(flying_id, 2).await.unwrap();
+    assert_eq!(dbm.get_dreams_by_tag(2).await.unwrap().len(), 0);
+    assert!(matches!(dbm.remove_tag_from_dream(flying_id, 2).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.add_tags_to_dream(flying_id, vec![100]).await, Err(DBError::ForeignKeyViolation(_))));
+
+    // updates only change the given fields
+    let update = DreamUpdate { narrative: Some(String::from("Over the ocean")), vividness: Some(10), ..Default::default() };
+    dbm.update_dream(flying_id, &update).await.unwrap();
+    let dream = dbm.get_dream(flying_id).await.unwrap();
+    assert_eq!(dream.title, "Flying");
+    assert_eq!(dream.narrative, "Over the ocean");
+    assert_eq!(dream.vividness, Some(10));
+    assert!(dream.lucid);
+
+    // invalid dreams are refused
+    let untitled = DreamDetails { title: String::from(" "), ..Default::default() };
+    assert!(matches!(dbm.insert_dream(1, &untitled).await, Err(DBError::Validation(_))));
+    let too_vivid = DreamUpdate { vividness: Some(11), ..Default::default() };
+    assert!(matches!(dbm.update_dream(flying_id, &too_vivid).await, Err(DBError::Validation(_))));
+    assert!(matches!(dbm.insert_dream(100, &flying).await, Err(DBError::ForeignKeyViolation(_))));
+    assert!(matches!(dbm.update_dream(100, &update).await, Err(DBError::NotFound { .. })));
+
+    dbm.delete_dream(falling_id).await.unwrap();
+    assert!(matches!(dbm.get_dream(falling_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.delete_dream(falling_id).await, Err(DBError::NotFound { .. })));
+}
+
+async fn test_sleep_times(dbm: &mut DBManager) {
+    // bed time after midnight belongs to the previous night, amount is derived from the onset
+    let times = SleepTimes {
+        bed_time: Some(time("2022-12-02 00:30")),
+        sleep_onset: Some(time("2022-12-02 01:00")),
+        wake_time: Some(time("2022-12-02 08:30")),
+    };
+    let id = dbm.insert_sleep_with_times(None, None, 3, &times).await.unwrap();
+    let sleep = dbm.get_sleep(id, false).await.unwrap().sleep;
+    assert_eq!(sleep.night, night("2022-12-01"));
+    assert_eq!(sleep.amount, 7.5);
+    assert_eq!(dbm.get_sleep_sessions(id).await.unwrap()[0].times(), times);
+
+    // bed time before midnight belongs to the same night
+    let times = SleepTimes { bed_time: Some(time("2022-12-02 22:00")), sleep_onset: None, wake_time: Some(time("2022-12-03 06:00")) };
+    let id = dbm.insert_sleep_with_times(Some(night("2022-12-02")), Some(7.0), 3, &times).await.unwrap();
+    assert_eq!(dbm.get_sleep(id, false).await.unwrap().sleep.amount, 7.0);
+
+    let mismatched_night = dbm.insert_sleep_with_times(Some(night("2022-12-04")), None, 3, &times).await;
+    assert!(matches!(mismatched_night, Err(DBError::Validation(_))));
+
+    let out_of_order = SleepTimes { bed_time: Some(time("2022-12-05 22:00")), sleep_onset: None, wake_time: Some(time("2022-12-05 21:00")) };
+    assert!(matches!(dbm.insert_sleep_with_times(None, None, 3, &out_of_order).await, Err(DBError::Validation(_))));
+    assert!(matches!(dbm.insert_sleep_with_times(None, Some(7.0), 3, &SleepTimes::default()).await, Err(DBError::Validation(_))));
+
+    // updating a single time keeps the others
+    let update = SleepTimes { wake_time: Some(time("2022-12-03 07:00")), ..Default::default() };
+    let updated = dbm.update_sleep_times(id, &update).await.unwrap();
+    assert_eq!(updated.bed_time, Some(time("2022-12-02 22:00")));
+    assert_eq!(updated.amount(), Some(9.0));
+
+    let wrong_night = SleepTimes { bed_time: Some(time("2022-12-04 22:00")), ..Default::default() };
+    assert!(matches!(dbm.update_sleep_times(id, &wrong_night).await, Err(DBError::Validation(_))));
+
+    // a sleep without sessions keeps its amount when its main session is created from a single time
+    sqlx::query("DELETE FROM sleep_session WHERE sleep_id = ?1").bind(id - 1).execute(&dbm.connection_pool).await.unwrap();
+    let bed_time = SleepTimes { bed_time: Some(time("2022-12-01 23:30")), ..Default::default() };
+    dbm.update_sleep_times(id - 1, &bed_time).await.unwrap();
+    assert_eq!(dbm.get_sleep(id - 1, false).await.unwrap().sleep.amount, 7.5);
+    let sessions = dbm.get_sleep_sessions(id - 1).await.unwrap();
+    assert_eq!((sessions.len(), sessions[0].amount, sessions[0].bed_time), (1, 7.5, bed_time.bed_time));
+
+    // but can't tell the amount of a main session added next to other sessions
+    let nap_times = SleepTimes { bed_time: None, sleep_onset: Some(time("2022-12-03 13:00")), wake_time: Some(time("2022-12-03 14:00")) };
+    dbm.insert_sleep_session(id, SessionKind::Nap, None, &nap_times).await.unwrap();
+    let main_id = dbm.get_sleep_sessions(id).await.unwrap()[0].id;
+    dbm.delete_sleep_session(main_id).await.unwrap();
+    let bed_time = SleepTimes { bed_time: Some(time("2022-12-02 22:00")), ..Default::default() };
+    assert!(matches!(dbm.update_sleep_times(id, &bed_time).await, Err(DBError::Validation(_))));
+    assert_eq!(dbm.get_sleep_sessions(id).await.unwrap().len(), 1);
+    assert_eq!(dbm.get_sleep(id, false).await.unwrap().sleep.amount, 1.0);
+
+    dbm.delete_sleep(id).await.unwrap();
+    dbm.delete_sleep(id - 1).await.unwrap();
+}
+
+async fn test_sleep_sessions(dbm: &mut DBManager) {
+    let main_times = SleepTimes { bed_time: Some(time("2022-12-10 23:00")), sleep_onset: None, wake_time: Some(time("2022-12-11 05:00")) };
+    let id = dbm.insert_sleep_with_times(None, None, 3, &main_times).await.unwrap();
+    assert_eq!(dbm.get_sleep(id, false).await.unwrap().sleep.amount, 6.0);
+
+    // a nap the next afternoon belongs to the same night and adds to the total
+    let nap_times = SleepTimes { bed_time: None, sleep_onset: Some(time("2022-12-11 13:00")), wake_time: Some(time("2022-12-11 14:30")) };
+    let nap_id = dbm.insert_sleep_session(id, SessionKind::Nap, None, &nap_times).await.unwrap();
+    assert_eq!(dbm.get_sleep(id, false).await.unwrap().sleep.amount, 7.5);
+
+    let sessions = dbm.get_sleep_sessions(id).await.unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].kind(), SessionKind::Main);
+    assert_eq!(sessions[1].id, nap_id);
+    assert_eq!(sessions[1].kind(), SessionKind::Nap);
+
+    // a night has at most one main session, and sessions can't overlap
+    let second_main = dbm.insert_sleep_session(id, SessionKind::Main, Some(1.0), &SleepTimes::default()).await;
+    assert!(matches!(second_main, Err(DBError::Validation(_))));
+    assert!(matches!(dbm.update_sleep_session_kind(nap_id, SessionKind::Main).await, Err(DBError::Validation(_))));
+
+    let overlapping = SleepTimes { bed_time: None, sleep_onset: Some(time("2022-12-11 04:00")), wake_time: Some(time("2022-12-11 06:00")) };
+    assert!(matches!(dbm.insert_sleep_session(id, SessionKind::Split, None, &overlapping).await, Err(DBError::Validation(_))));
+
+    let other_day = SleepTimes { bed_time: None, sleep_onset: Some(time("2022-12-12 13:00")), wake_time: Some(time("2022-12-12 14:00")) };
+    assert!(matches!(dbm.insert_sleep_session(id, SessionKind::Nap, None, &other_day).await, Err(DBError::Validation(_))));
+    assert!(matches!(dbm.insert_sleep_session(9999, SessionKind::Nap, Some(1.0), &SleepTimes::default()).await, Err(DBError::NotFound { .. })));
+
+    // updating a session recomputes the total
+    dbm.update_sleep_session_amount(nap_id, 1.0).await.unwrap();
+    assert_eq!(dbm.get_sleep(id, false).await.unwrap().sleep.amount, 7.0);
+
+    // updating the amount of the sleep only changes the main session
+    dbm.update_sleep_amount(id, 5.0).await.unwrap();
+    assert_eq!(dbm.get_sleep(id, false).await.unwrap().sleep.amount, 6.0);
+
+    dbm.delete_sleep_session(nap_id).await.unwrap();
+    assert_eq!(dbm.get_sleep(id, false).await.unwrap().sleep.amount, 5.0);
+    assert!(matches!(dbm.get_sleep_session(nap_id).await, Err(DBError::NotFound { .. })));
+
+    // deleting the sleep deletes its sessions
+    let main_id = dbm.get_sleep_sessions(id).await.unwrap()[0].id;
+    dbm.delete_sleep(id).await.unwrap();
+    assert!(matches!(dbm.get_sleep_session(main_id).await, Err(DBError::NotFound { .. })));
+}
+
+async fn test_sleep_stats(dbm: &mut DBManager) {
+    let december_id = dbm.insert_sleep(night("2022-12-01"), 9.0, 4).await.unwrap();
+
+    let stats = dbm.get_sleep_stats(night("2022-11-01"), night("2022-11-30"), StatsGroupBy::Day).await.unwrap();
+    let overall = stats.overall.unwrap();
+    assert_eq!(overall.count, 3);
+    assert!((overall.amount.mean - 21.5 / 3.0).abs() < 1e-9);
+    assert_eq!(overall.amount.median, 7.5);
+    assert_eq!(overall.amount.min, 6.0);
+    assert_eq!(overall.amount.max, 8.0);
+    assert!((overall.amount.std_dev.unwrap() - (13.0_f64 / 12.0).sqrt()).abs() < 1e-9);
+    assert_eq!(overall.quality.median, 2.0);
+    assert_eq!(stats.groups.len(), 3);
+    assert_eq!(stats.groups[0].key, "2022-11-24");
+    assert_eq!(stats.groups[0].amount.std_dev, None);
+
+    // rolling averages include the nights before the range
+    let stats = dbm.get_sleep_stats(night("2022-11-26"), night("2022-12-01"), StatsGroupBy::Month).await.unwrap();
+    assert_eq!(stats.groups.iter().map(|g| g.key.as_str()).collect::<Vec<&str>>(), vec!["2022-11", "2022-12"]);
+    assert_eq!(stats.groups[1].start, Some(night("2022-12-01")));
+    assert_eq!(stats.rolling.len(), 2);
+    assert!((stats.rolling[0].amount_7_day - 21.5 / 3.0).abs() < 1e-9);
+    assert_eq!(stats.rolling[1].night, night("2022-12-01"));
+    assert!((stats.rolling[1].amount_7_day - 24.5 / 3.0).abs() < 1e-9);
+    assert_eq!(stats.rolling[1].amount_30_day, 7.625);
+    assert_eq!(stats.rolling[1].quality_30_day, 2.5);
+
+    let stats = dbm.get_sleep_stats(night("2022-11-20"), night("2022-12-04"), StatsGroupBy::Week).await.unwrap();
+    assert_eq!(stats.groups.iter().map(|g| g.key.as_str()).collect::<Vec<&str>>(), vec!["2022-W47", "2022-W48"]);
+    assert_eq!(stats.groups[0].start, Some(night("2022-11-21")));
+    assert_eq!(stats.groups[0].count, 3);
+
+    // 2022-11-24 is a thursday, and weekdays are ordered from monday
+    let stats = dbm.get_sleep_stats(night("2022-11-20"), night("2022-12-04"), StatsGroupBy::Weekday).await.unwrap();
+    assert_eq!(stats.groups.iter().map(|g| g.key.as_str()).collect::<Vec<&str>>(), vec!["Thursday", "Friday", "Saturday"]);
+    assert_eq!(stats.groups[0].count, 2);
+    assert_eq!(stats.groups[0].start, None);
+
+    let empty = dbm.get_sleep_stats(night("2021-01-01"), night("2021-12-31"), StatsGroupBy::Day).await.unwrap();
+    assert!(empty.overall.is_none() && empty.groups.is_empty() && empty.rolling.is_empty());
+    let reversed = dbm.get_sleep_stats(night("2022-12-01"), night("2022-11-01"), StatsGroupBy::Day).await;
+    assert!(matches!(reversed, Err(DBError::Validation(_))));
+
+    dbm.delete_sleep(december_id).await.unwrap();
+}
+
+async fn test_tag_impact(dbm: &mut DBManager) {
+    // sleep 1 has both tags, sleep 2 has the screen tag and sleep 3 has no tags
+    let impact = dbm.get_tag_impact(night("2022-11-01"), night("2022-11-30"), None, false).await.unwrap();
+    assert_eq!(impact.len(), 2);
+    assert_eq!(impact[1].tag.name, "screen");
+    assert_eq!((impact[1].count_with, impact[1].count_without), (2, 1));
+    assert_eq!(impact[1].amount.mean_with, Some(6.75));
+    assert_eq!(impact[1].amount.mean_without, Some(8.0));
+    assert_eq!(impact[1].amount.difference, Some(-1.25));
+    assert_eq!(impact[1].amount.t_test, None);
+
+    // a tag without sleeps in the range has no mean with the tag
+    let impact = dbm.get_tag_impact(night("2022-11-26"), night("2022-11-26"), Some(vec![1]), false).await.unwrap();
+    assert_eq!(impact.len(), 1);
+    assert_eq!((impact[0].count_with, impact[0].count_without), (0, 1));
+    assert_eq!(impact[0].quality.mean_with, None);
+    assert_eq!(impact[0].quality.difference, None);
+
+    // tagged sleeps with amounts 2, 4, 6, 8, 10 and untagged sleeps with amounts 1, 2, 3, 4, 5
+    let mut ids = Vec::new();
+    for day in 1..=5 {
+        let tagged = dbm.insert_sleep(night(&format!("2023-01-{:02}", day)), 2.0 * day as f64, 3).await.unwrap();
+        dbm.add_tags_to_sleep(tagged, vec![1]).await.unwrap();
+        let untagged = dbm.insert_sleep(night(&format!("2023-01-{:02}", day + 5)), day as f64, 3).await.unwrap();
+        ids.extend([tagged, untagged]);
+    }
+
+    let impact = dbm.get_tag_impact(night("2023-01-01"), night("2023-01-31"), Some(vec![1]), false).await.unwrap();
+    assert_eq!((impact[0].count_with, impact[0].count_without), (5, 5));
+    assert_eq!(impact[0].amount.difference, Some(3.0));
+    let t_test = impact[0].amount.t_test.unwrap();
+    assert!((t_test.t - 3.0 / 2.5_f64.sqrt()).abs() < 1e-9);
+    assert!((t_test.degrees_of_freedom - 6.25 / 1.0625).abs() < 1e-9);
+    assert!((t_test.p_value - 0.1075).abs() < 1e-4);
+
+    // qualities are all the same so there is no variance to test
+    assert_eq!(impact[0].quality.difference, Some(0.0));
+    assert_eq!(impact[0].quality.t_test, None);
+
+    assert!(matches!(dbm.get_tag_impact(night("2023-01-01"), night("2023-01-31"), Some(vec![100]), false).await,
+        Err(DBError::NotFound { .. })));
+
+    for id in ids {
+        dbm.delete_sleep(id).await.unwrap();
+    }
+}
+
+async fn test_pagination(dbm: &mut DBManager) {
+    // sleeps are ordered by night, sleep 2 is the first night
+    let args = PageArgs { first: Some(2), ..Default::default() };
+    let page = dbm.get_sleeps_page(&args, None).await.unwrap();
+    assert_eq!(page.items.iter().map(|s| s.sleep.id).collect::<Vec<i64>>(), vec![2, 1]);
+    assert!(page.has_next_page && !page.has_previous_page);
+    assert_eq!(page.total_count, 3);
+
+    let args = PageArgs { after: Some((night("2022-11-25"), 1)), first: Some(2), ..Default::default() };
+    let page = dbm.get_sleeps_page(&args, None).await.unwrap();
+    assert_eq!(page.items.iter().map(|s| s.sleep.id).collect::<Vec<i64>>(), vec![3]);
+    assert!(!page.has_next_page && page.has_previous_page);
+
+    // paging backward returns the last sleeps, still in night order
+    let args = PageArgs { before: Some((night("2022-11-26"), 3)), last: Some(1), ..Default::default() };
+    let page = dbm.get_sleeps_page(&args, None).await.unwrap();
+    assert_eq!(page.items.iter().map(|s| s.sleep.id).collect::<Vec<i64>>(), vec![1]);
+    assert!(page.has_previous_page && page.has_next_page);
+
+    let args: PageArgs<(NaiveDate, i64)> = PageArgs { last: Some(5), ..Default::default() };
+    let page = dbm.get_sleeps_page(&args, Some(2)).await.unwrap();
+    assert_eq!(page.items.iter().map(|s| s.sleep.id).collect::<Vec<i64>>(), vec![2, 1]);
+    assert!(!page.has_previous_page);
+    assert_eq!(page.total_count, 2);
+
+    let page = dbm.get_tags_page(&PageArgs { after: Some(1), ..Default::default() }).await.unwrap();
+    assert_eq!(page.items.len(), 1);
+    assert_eq!(page.items[0].name, "screen");
+    assert_eq!(page.total_count, 2);
+
+    let page = dbm.get_comments_page(Some(1), &PageArgs { first: Some(1), ..Default::default() }).await.unwrap();
+    assert_eq!(page.items[0].comment, "First comment");
+    assert!(page.has_next_page);
+    assert_eq!(page.total_count, 2);
+    let page = dbm.get_comments_page(None, &PageArgs { first: Some(0), ..Default::default() }).await.unwrap();
+    assert!(page.items.is_empty() && page.has_next_page);
+    assert_eq!(page.total_count, 3);
+
+    let both = PageArgs { first: Some(1), last: Some(1), ..Default::default() };
+    assert!(matches!(dbm.get_tags_page(&both).await, Err(DBError::Validation(_))));
+    let too_many = PageArgs { first: Some(10_000), ..Default::default() };
+    assert!(matches!(dbm.get_tags_page(&too_many).await, Err(DBError::Validation(_))));
+}
+
 async fn test_updates(dbm: &mut DBManager) {
     assert_eq!(dbm.get_sleep(1, false).await.unwrap().sleep.amount, 7.5);
-    assert!(dbm.update_sleep_amount(1, 7.0).await);
+    assert!(dbm.update_sleep_amount(1, 7.0).await.is_ok());
     assert_eq!(dbm.get_sleep(1, false).await.unwrap().sleep.amount, 7.0);
 
     assert_eq!(dbm.get_sleep(3, false).await.unwrap().sleep.quality, 3);
-    assert!(dbm.update_sleep_quality(3, 1).await);
+    assert!(dbm.update_sleep_quality(3, 1).await.is_ok());
     assert_eq!(dbm.get_sleep(3, false).await.unwrap().sleep.quality, 1);
 
     assert_eq!(dbm.get_tag(1).await.unwrap().name, "test name");
-    assert!(dbm.update_tag_name(1, "update test").await);
+    assert!(dbm.update_tag_name(1, "update test").await.is_ok());
     assert_eq!(dbm.get_tag(1).await.unwrap().name, "update test");
 
     assert_eq!(dbm.get_tag(2).await.unwrap().color, 9590460);
-    assert!(dbm.update_tag_color(2, 65535).await);
+    assert!(dbm.update_tag_color(2, 65535).await.is_ok());
     assert_eq!(dbm.get_tag(2).await.unwrap().color, 65535);
 
     assert_eq!(dbm.get_comments_by_sleep(1).await.unwrap()[1].comment, "2nd comment on night");
-    assert!(dbm.update_comment(3, "updated_comment").await);
+    assert!(dbm.update_comment(3, "updated_comment").await.is_ok());
     assert_eq!(dbm.get_comments_by_sleep(1).await.unwrap()[1].comment, "updated_comment");
 }
 
+async fn test_errors(dbm: &mut DBManager) {
+    let duplicate_night = dbm.insert_sleep(night("2022-11-25"), 7.0, 1).await;
+    assert!(matches!(duplicate_night, Err(DBError::DuplicateNight(n)) if n == night("2022-11-25")));
+
+    let duplicate_tag = dbm.insert_tag("screen", 0).await;
+    assert!(matches!(duplicate_tag, Err(DBError::DuplicateTagName(n)) if n == "screen"));
+    assert!(matches!(dbm.update_tag_name(1, "screen").await, Err(DBError::DuplicateTagName(_))));
+
+    assert!(matches!(dbm.insert_sleep(night("2022-12-01"), -1.0, 1).await, Err(DBError::Validation(_))));
+    assert!(matches!(dbm.insert_tag(" ", 0).await, Err(DBError::Validation(_))));
+
+    assert!(matches!(dbm.add_tags_to_sleep(100, vec![1]).await, Err(DBError::NotFound { entity: "sleep", id: 100 })));
+    assert!(matches!(dbm.add_tags_to_sleep(2, vec![100]).await, Err(DBError::ForeignKeyViolation(_))));
+    assert!(matches!(dbm.insert_comment(100, "no sleep").await, Err(DBError::ForeignKeyViolation(_))));
+
+    assert!(matches!(dbm.update_sleep_amount(100, 7.0).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.update_comment(100, "missing").await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.remove_tag_from_sleep(3, 1).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.delete_tag(100).await, Err(DBError::NotFound { .. })));
+}
+
 async fn test_deletes(dbm: &mut DBManager) {
     // remove tag from sleep first because cascade with auto delete rows in sleep_tag
     assert_eq!(dbm.get_sleep(1, true).await.unwrap().tags.unwrap().len(), 2);
-    assert!(dbm.remove_tag_from_sleep(1, 1).await);
+    assert!(dbm.remove_tag_from_sleep(1, 1).await.is_ok());
     assert_eq!(dbm.get_sleep(1, true).await.unwrap().tags.unwrap().len(), 1);
 
     assert_eq!(dbm.get_all_sleeps().await.unwrap().len(), 3);
-    assert!(dbm.delete_sleep(1).await);
+    assert!(dbm.delete_sleep(1).await.is_ok());
     assert_eq!(dbm.get_all_sleeps().await.unwrap().len(), 2);
 
     assert_eq!(dbm.get_all_tags().await.unwrap().len(), 2);
-    assert!(dbm.delete_tag(1).await);
+    assert!(dbm.delete_tag(1).await.is_ok());
     assert_eq!(dbm.get_all_tags().await.unwrap().len(), 1);
 
     // test cascade delete from deleting sleep above
     assert_eq!(dbm.get_comments_by_sleep(1).await.unwrap().len(), 0);
+    assert_eq!(dbm.get_dreams_by_sleep(1).await.unwrap().len(), 0);
     assert_eq!(dbm.get_comments_by_sleep(2).await.unwrap().len(), 1);
-    assert!(dbm.delete_comment(2).await);
+    assert!(dbm.delete_comment(2).await.is_ok());
     assert_eq!(dbm.get_comments_by_sleep(2).await.unwrap().len(), 0);
-}
\ No newline at end of file
+}
+
+async fn test_batch_loading(dbm: &mut DBManager) {
+    let tag_id = dbm.insert_tag("batch", 0).await.unwrap();
+    let first_night = night("2023-01-01");
+    for day in 0..30 {
+        let sleep_id = dbm.insert_sleep(first_night + chrono::Duration::days(day), 7.0, 3).await.unwrap();
+        dbm.add_tags_to_sleep(sleep_id, vec![tag_id]).await.unwrap();
+        dbm.insert_comment(sleep_id, "batch comment").await.unwrap();
+    }
+
+    let batch_tags = dbm.get_tags_by_sleeps(&[2, 3, 100]).await.unwrap();
+    assert_eq!(batch_tags[&2].iter().map(|t| t.id).collect::<Vec<i64>>(), vec![2]);
+    assert!(batch_tags[&3].is_empty() && batch_tags[&100].is_empty());
+
+    // the tags and comments of every sleep in the listing are loaded together, so listing more sleeps
+    // must not send more queries: the page, its total count, the tags and the comments
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let mut counts = Vec::new();
+    for size in [1, 5, 30] {
+        let query = format!(
+            r#"{{ sleeps(first: {}, after: "2022-12-31:0") {{ edges {{ node {{ id tags {{ id }} comments {{ id }} }} }} }} }}"#,
+            size);
+
+        let before = dbm.query_count();
+        let response = schema.execute(query.as_str()).await;
+        counts.push(dbm.query_count() - before);
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        let edges = data["sleeps"]["edges"].as_array().unwrap();
+        assert_eq!(edges.len(), size);
+        assert!(edges.iter().all(|e| e["node"]["tags"][0]["id"] == tag_id && e["node"]["comments"].as_array().unwrap().len() == 1));
+    }
+
+    assert_eq!(counts, vec![4; 3]);
+
+    let batch_sessions = dbm.get_sleep_sessions_by_sleeps(&[2, 100]).await.unwrap();
+    assert_eq!(batch_sessions[&2].iter().map(|s| s.kind()).collect::<Vec<SessionKind>>(), vec![SessionKind::Main]);
+    assert!(batch_sessions[&100].is_empty());
+
+    // the times of a sleep come from its main session, the sessions of the listing are loaded once
+    // for all of the times: the page, its total count and the sessions
+    let mut counts = Vec::new();
+    for size in [1, 5, 30] {
+        let query = format!(
+            r#"{{ sleeps(first: {}, after: "2022-12-31:0") {{ edges {{ node {{ bedTime sleepOnset wakeTime sessions {{ amount }} }} }} }} }}"#,
+            size);
+
+        let before = dbm.query_count();
+        let response = schema.execute(query.as_str()).await;
+        counts.push(dbm.query_count() - before);
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        let edges = data["sleeps"]["edges"].as_array().unwrap();
+        assert_eq!(edges.len(), size);
+        assert!(edges.iter().all(|e| e["node"]["sessions"][0]["amount"] == 7.0 && e["node"]["bedTime"].is_null()));
+    }
+
+    assert_eq!(counts, vec![3; 3]);
+
+    let dream_tag_id = dbm.insert_tag("batch dream", 0).await.unwrap();
+    let sleeps = dbm.get_sleeps_in_range(first_night, first_night + chrono::Duration::days(29)).await.unwrap();
+    for sleep in &sleeps {
+        let (id, night) = (sleep.sleep.id, sleep.sleep.night);
+        let dream_id = dbm.insert_dream(id, &DreamDetails { title: format!("Dream of {}", night), ..Default::default() }).await.unwrap();
+        dbm.add_tags_to_dream(dream_id, vec![dream_tag_id]).await.unwrap();
+        dbm.set_sleep_stages(id, &SleepStages { deep_minutes: Some(60), ..Default::default() }).await.unwrap();
+        let start = night.and_hms_opt(23, 0, 0).unwrap();
+        let interval = HypnogramInterval { stage: SleepStage::Light, start, end: start + chrono::Duration::minutes(30) };
+        dbm.set_hypnogram(id, &[interval]).await.unwrap();
+    }
+
+    let first_id = sleeps[0].sleep.id;
+    assert_eq!(dbm.get_dreams_by_sleeps(&[first_id, 9999]).await.unwrap()[&first_id].len(), 1);
+    assert!(dbm.get_dreams_by_sleeps(&[9999]).await.unwrap()[&9999].is_empty());
+    let stages = dbm.get_sleep_stages_by_sleeps(&[first_id, 9999]).await.unwrap();
+    assert_eq!((stages[&first_id].deep_minutes, stages.contains_key(&9999)), (Some(60), false));
+    assert_eq!(dbm.get_hypnograms_by_sleeps(&[first_id]).await.unwrap()[&first_id].len(), 1);
+
+    // the dreams, stages and hypnograms of the listing are loaded together, and so are the nights and tags of the dreams:
+    // the page, its total count, the dreams, stages, hypnograms, the sleeps of the dreams and the tags of the dreams
+    let mut counts = Vec::new();
+    for size in [1, 5, 30] {
+        let query = format!(
+            r#"{{ sleeps(first: {}, after: "2022-12-31:0") {{ edges {{ node {{
+                dreams {{ night {{ day }} tags {{ id }} }} stages {{ deepMinutes }} hypnogram {{ stage }}
+            }} }} }} }}"#,
+            size);
+
+        let before = dbm.query_count();
+        let response = schema.execute(query.as_str()).await;
+        counts.push(dbm.query_count() - before);
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        let edges = data["sleeps"]["edges"].as_array().unwrap();
+        assert_eq!(edges.len(), size);
+        assert_eq!(edges[size - 1]["node"]["dreams"][0]["night"]["day"], size);
+        assert!(edges.iter().all(|e| e["node"]["dreams"][0]["tags"][0]["id"] == dream_tag_id
+            && e["node"]["stages"]["deepMinutes"] == 60 && e["node"]["hypnogram"][0]["stage"] == "LIGHT"));
+    }
+
+    assert_eq!(counts, vec![7; 3]);
+}
+
+
+async fn test_user_isolation(dbm: &mut DBManager) {
+    assert_eq!(dbm.user_id(), super::DEFAULT_USER_ID);
+    assert!(matches!(dbm.insert_user("default").await, Err(DBError::DuplicateUserName(n)) if n == "default"));
+    assert!(matches!(dbm.insert_user(" ").await, Err(DBError::Validation(_))));
+
+    let bob_id = dbm.insert_user("bob").await.unwrap();
+    assert_eq!(dbm.get_user_by_name("bob").await.unwrap().unwrap().id, bob_id);
+    assert!(dbm.get_user_by_name("carol").await.unwrap().is_none());
+    assert_eq!(dbm.get_all_users().await.unwrap().len(), 2);
+    let bob = dbm.for_user(bob_id);
+
+    let sleep_id = dbm.insert_sleep(night("2023-03-01"), 8.0, 3).await.unwrap();
+    let tag_id = dbm.insert_tag("private", 0).await.unwrap();
+    dbm.add_tags_to_sleep(sleep_id, vec![tag_id]).await.unwrap();
+    let comment_id = dbm.insert_comment(sleep_id, "private comment").await.unwrap();
+    let session_id = dbm.insert_sleep_session(sleep_id, SessionKind::Nap, Some(1.0), &SleepTimes::default()).await.unwrap();
+    let dream_id = dbm.insert_dream(sleep_id, &DreamDetails { title: String::from("Private"), ..Default::default() }).await.unwrap();
+    dbm.add_tags_to_dream(dream_id, vec![tag_id]).await.unwrap();
+
+    assert_eq!(dbm.get_multiple_sleeps(vec![sleep_id, 9999]).await.unwrap().iter().map(|s| s.sleep.id).collect::<Vec<i64>>(), vec![sleep_id]);
+    assert_eq!(dbm.get_multiple_tags(vec![9999, tag_id]).await.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>(), vec![tag_id]);
+
+    // another user can't read the rows of the default user
+    assert!(matches!(bob.get_sleep(sleep_id, true).await, Err(DBError::NotFound { entity: "sleep", .. })));
+    assert!(matches!(bob.get_tag(tag_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.get_comment(comment_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.get_sleep_session(session_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.get_dream(dream_id).await, Err(DBError::NotFound { .. })));
+    assert!(bob.get_all_sleeps().await.unwrap().is_empty());
+    assert!(bob.get_all_tags().await.unwrap().is_empty());
+    assert!(bob.get_multiple_sleeps(vec![sleep_id]).await.unwrap().is_empty());
+    assert!(bob.get_multiple_tags(vec![tag_id]).await.unwrap().is_empty());
+    assert!(bob.get_sleeps_in_range(night("2022-01-01"), night("2023-12-31")).await.unwrap().is_empty());
+    assert!(bob.get_sleeps_by_tag(tag_id).await.unwrap().is_empty());
+    assert!(bob.get_comments_by_sleep(sleep_id).await.unwrap().is_empty());
+    assert!(bob.get_dreams_by_sleep(sleep_id).await.unwrap().is_empty());
+    assert!(bob.get_dreams_in_range(night("2022-01-01"), night("2023-12-31")).await.unwrap().is_empty());
+    assert!(bob.get_tags_by_sleeps(&[sleep_id]).await.unwrap()[&sleep_id].is_empty());
+    assert!(bob.get_comments_by_sleeps(&[sleep_id]).await.unwrap()[&sleep_id].is_empty());
+    assert_eq!(bob.get_sleeps_page(&PageArgs::default(), None).await.unwrap().total_count, 0);
+    assert!(bob.get_sleep_stats(night("2022-01-01"), night("2023-12-31"), StatsGroupBy::Month).await.unwrap().overall.is_none());
+
+    // or change them
+    assert!(matches!(bob.update_sleep_amount(sleep_id, 1.0).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.update_tag_name(tag_id, "stolen").await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.update_comment(comment_id, "stolen").await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.update_sleep_session_amount(session_id, 1.0).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.update_dream(dream_id, &DreamUpdate { title: Some(String::from("stolen")), ..Default::default() }).await,
+        Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.remove_tag_from_sleep(sleep_id, tag_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.delete_sleep(sleep_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.delete_tag(tag_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.delete_comment(comment_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.delete_sleep_session(session_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(bob.delete_dream(dream_id).await, Err(DBError::NotFound { .. })));
+
+    // or reference them from their own rows
+    let bob_sleep_id = bob.insert_sleep(night("2023-03-01"), 6.0, 2).await.unwrap();
+    let bob_tag_id = bob.insert_tag("private", 0).await.unwrap();
+    assert!(matches!(bob.add_tags_to_sleep(bob_sleep_id, vec![tag_id]).await, Err(DBError::ForeignKeyViolation(_))));
+    assert!(matches!(bob.insert_comment(sleep_id, "stolen").await, Err(DBError::ForeignKeyViolation(_))));
+    assert!(matches!(bob.insert_dream(sleep_id, &DreamDetails { title: String::from("stolen"), ..Default::default() }).await,
+        Err(DBError::ForeignKeyViolation(_))));
+    assert!(matches!(bob.insert_sleep_session(sleep_id, SessionKind::Nap, Some(1.0), &SleepTimes::default()).await,
+        Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.add_tags_to_dream(dream_id, vec![bob_tag_id]).await, Err(DBError::ForeignKeyViolation(_))));
+
+    // nights and tag names are only unique per user
+    bob.add_tags_to_sleep(bob_sleep_id, vec![bob_tag_id]).await.unwrap();
+    assert_eq!(bob.get_all_sleeps().await.unwrap().len(), 1);
+    assert_eq!(bob.get_tags_by_sleep(bob_sleep_id).await.unwrap()[0].id, bob_tag_id);
+
+    // the rows of the default user are unchanged
+    let sleep = dbm.get_sleep(sleep_id, true).await.unwrap();
+    assert_eq!(sleep.sleep.amount, 9.0);
+    assert_eq!(sleep.tags.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>(), vec![tag_id]);
+    assert_eq!(dbm.get_tag(tag_id).await.unwrap().name, "private");
+    assert_eq!(dbm.get_comment(comment_id).await.unwrap().comment, "private comment");
+    assert_eq!(dbm.get_dream(dream_id).await.unwrap().title, "Private");
+
+    // requests scoped to a user only see that user's data
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let query = r#"{ viewer { name } sleeps(first: 1) { totalCount edges { node { tags { name } } } } }"#;
+    let response = schema.execute(crate::scope_request(query.into(), bob.clone())).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["viewer"]["name"], "bob");
+    assert_eq!(data["sleeps"]["totalCount"], 1);
+    assert_eq!(data["sleeps"]["edges"][0]["node"]["tags"][0]["name"], "private");
+}
+
+async fn test_api_tokens(dbm: &mut DBManager) {
+    let bob = dbm.for_user(dbm.get_user_by_name("bob").await.unwrap().unwrap().id);
+    assert!(matches!(dbm.create_api_token(" ", false, false).await, Err(DBError::Validation(_))));
+
+    let (token_id, token) = dbm.create_api_token("laptop", false, false).await.unwrap();
+    let (read_only_id, read_only_token) = bob.create_api_token("phone", true, false).await.unwrap();
+    assert_ne!(token, read_only_token);
+
+    // only the hash of the token is stored
+    let stored: Vec<String> = sqlx::query_scalar("SELECT token_hash FROM api_token").fetch_all(&dbm.connection_pool).await.unwrap();
+    assert!(!stored.contains(&token) && !stored.contains(&read_only_token));
+
+    let principal = dbm.authenticate(&token).await.unwrap().unwrap();
+    assert_eq!((principal.user_id, principal.user_name.as_str(), principal.token_id, principal.read_only, principal.admin),
+        (super::DEFAULT_USER_ID, "default", token_id, false, false));
+    let read_only = bob.authenticate(&read_only_token).await.unwrap().unwrap();
+    assert_eq!((read_only.user_id, read_only.read_only), (bob.user_id(), true));
+    assert!(dbm.authenticate("st_invalid").await.unwrap().is_none());
+
+    // tokens are listed and revoked per user
+    assert_eq!(dbm.get_api_tokens().await.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>(), vec![token_id]);
+    assert!(matches!(dbm.revoke_api_token(read_only_id).await, Err(DBError::NotFound { entity: "api token", .. })));
+
+    // a read-only token can query but not mutate
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let request = crate::scope_request(r#"{ viewer { name } }"#.into(), bob.clone()).data(read_only.clone());
+    let response = schema.execute(request).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let mutation = r#"mutation { addTag(tagInput: { name: "blocked", color: 0 }) { id } }"#;
+    let response = schema.execute(crate::scope_request(mutation.into(), bob.clone()).data(read_only)).await;
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0].extensions.as_ref().unwrap().get("code"), Some(&async_graphql::Value::from("READ_ONLY")));
+    assert!(bob.get_all_tags().await.unwrap().iter().all(|t| t.name != "blocked"));
+
+    let (_, bob_token) = bob.create_api_token("laptop", false, false).await.unwrap();
+    let read_write = bob.authenticate(&bob_token).await.unwrap().unwrap();
+    let response = schema.execute(crate::scope_request(mutation.into(), bob.clone()).data(read_write.clone())).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    // only an admin token can add users
+    let add_user = r#"mutation { addUser(userInput: { name: "carol" }) { name } }"#;
+    let response = schema.execute(crate::scope_request(add_user.into(), bob.clone()).data(read_write)).await;
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(response.errors[0].extensions.as_ref().unwrap().get("code"), Some(&async_graphql::Value::from("ADMIN_ONLY")));
+    assert!(dbm.get_user_by_name("carol").await.unwrap().is_none());
+
+    let (_, admin_token) = dbm.create_api_token("admin", false, true).await.unwrap();
+    let admin = dbm.authenticate(&admin_token).await.unwrap().unwrap();
+    assert!(admin.admin && dbm.get_api_tokens().await.unwrap().iter().any(|t| t.admin));
+    let response = schema.execute(crate::scope_request(add_user.into(), dbm.clone()).data(admin)).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(dbm.get_user_by_name("carol").await.unwrap().is_some());
+
+    // a revoked token no longer authenticates
+    bob.revoke_api_token(read_only_id).await.unwrap();
+    assert!(dbm.authenticate(&read_only_token).await.unwrap().is_none());
+    assert!(dbm.authenticate(&token).await.unwrap().is_some());
+}
+
+async fn test_subscriptions(dbm: &mut DBManager) {
+    let bob = dbm.for_user(dbm.get_user_by_name("bob").await.unwrap().unwrap().id);
+    let schema = crate::build_schema(dbm.clone()).finish();
+
+    let subscribe = |query: &str| schema.execute_stream(crate::scope_request(query.into(), dbm.clone()));
+    let mut sleeps = subscribe("subscription { sleepChanged { kind id sleep { night { date } tags { name } } } }");
+    let mut tags = subscribe("subscription { tagChanged { kind id tag { name } } }");
+    let mut comments = subscribe("subscription { commentChanged { kind id comment { comment } } }");
+    for stream in [&mut sleeps, &mut tags, &mut comments] {
+        start_subscription(stream).await;
+    }
+
+    let execute = |query: &str, dbm: &DBManager| schema.execute(crate::scope_request(query.into(), dbm.clone()));
+    let tag = execute(r#"mutation { addTag(tagInput: { name: "live", color: 0 }) { id } }"#, dbm).await;
+    let tag_id = tag.data.into_json().unwrap()["addTag"]["id"].as_i64().unwrap();
+    let add_sleep = format!(
+        r#"mutation {{ addSleep(sleepInput: {{ night: "2023-04-01", amount: 7.0, quality: 3, tags: [{}], comments: ["live comment"] }}) {{ id }} }}"#,
+        tag_id);
+    let sleep = execute(&add_sleep, dbm).await;
+    let sleep_id = sleep.data.into_json().unwrap()["addSleep"]["id"].as_i64().unwrap();
+
+    // the fields of a change are resolved when the change is received
+    let created = next_change(&mut sleeps, "sleepChanged").await;
+    assert_eq!((created["kind"].as_str(), created["id"].as_i64()), (Some("CREATED"), Some(sleep_id)));
+    assert_eq!(created["sleep"]["night"]["date"], "2023-04-01");
+    assert_eq!(created["sleep"]["tags"][0]["name"], "live");
+    let created = next_change(&mut tags, "tagChanged").await;
+    assert_eq!((created["kind"].as_str(), created["tag"]["name"].as_str()), (Some("CREATED"), Some("live")));
+    let comment = next_change(&mut comments, "commentChanged").await;
+    assert_eq!((comment["kind"].as_str(), comment["comment"]["comment"].as_str()), (Some("CREATED"), Some("live comment")));
+
+    // changes by other users are not received
+    let response = execute(r#"mutation { addTag(tagInput: { name: "unseen", color: 0 }) { id } }"#, &bob).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let response = execute(&format!("mutation {{ deleteSleep(sleepId: {}) }}", sleep_id), dbm).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let response = execute(&format!("mutation {{ deleteTag(tagId: {}) }}", tag_id), dbm).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let deleted = next_change(&mut sleeps, "sleepChanged").await;
+    assert_eq!((deleted["kind"].as_str(), deleted["id"].as_i64()), (Some("DELETED"), Some(sleep_id)));
+    assert!(deleted["sleep"].is_null());
+
+    let deleted = next_change(&mut tags, "tagChanged").await;
+    assert_eq!((deleted["kind"].as_str(), deleted["id"].as_i64()), (Some("DELETED"), Some(tag_id)));
+
+    // the comments of a deleted sleep are deleted with it
+    let comment_id = comment["id"].as_i64();
+    let deleted = next_change(&mut comments, "commentChanged").await;
+    assert_eq!((deleted["kind"].as_str(), deleted["id"].as_i64()), (Some("DELETED"), comment_id));
+
+    for stream in [&mut sleeps, &mut tags, &mut comments] {
+        start_subscription(stream).await;
+    }
+}
+
+/// Polls a subscription until it waits for changes, so the changes made afterwards are received
+async fn test_csv(dbm: &mut DBManager) {
+    let dave = dbm.for_user(dbm.insert_user("dave").await.unwrap());
+    dave.insert_sleep(night("2023-04-01"), 8.0, 3).await.unwrap();
+    let coffee_id = dave.insert_tag("coffee", 0).await.unwrap();
+
+    // the comment of the second row spans two lines, so the rows after it start a line later
+    let csv = "Night,Amount,Quality,Tags,Comment\n\
+        2023-04-01,7,3,coffee,\n\
+        2023-04-02,7.5,4,coffee; late ;coffee,\"woke up\nslept again\"\n\
+        2023-04-02,6,2,,\n\
+        not-a-date,7,3,,\n\
+        2023-04-03,-1,3,,\n\
+        2023-04-04,8,5,new,\n";
+
+    // a dry run reports the import without changing anything
+    let options = CsvImportOptions { dry_run: true, ..Default::default() };
+    let report = dave.import_csv(csv, &options).await.unwrap();
+    assert!(report.dry_run);
+    assert_eq!((report.rows, report.imported), (6, 2));
+    assert_eq!(report.created_tags, vec!["late", "new"]);
+    assert!(report.sleep_ids.is_empty() && report.tag_ids.is_empty());
+    assert_eq!(report.issues.iter().map(|i| (i.line, i.kind)).collect::<Vec<_>>(), vec![
+        (2, ImportIssueKind::DuplicateNight),
+        (5, ImportIssueKind::RepeatedNight),
+        (6, ImportIssueKind::Invalid),
+        (7, ImportIssueKind::Invalid),
+    ]);
+    assert_eq!(report.issues[2].night, None);
+    assert_eq!(report.issues[3].night, Some(night("2023-04-03")));
+    assert_eq!(dave.get_all_sleeps().await.unwrap().len(), 1);
+    assert_eq!(dave.get_all_tags().await.unwrap().len(), 1);
+
+    let imported = dave.import_csv(csv, &CsvImportOptions::default()).await.unwrap();
+    assert!(!imported.dry_run);
+    assert_eq!(imported.issues, report.issues);
+    assert_eq!(imported.created_tags, report.created_tags);
+    assert_eq!(imported.sleep_ids.len(), 2);
+    assert_eq!(imported.tag_ids.len(), 2);
+
+    let sleep = dave.get_sleep(imported.sleep_ids[0], true).await.unwrap();
+    assert_eq!((sleep.sleep.night, sleep.sleep.amount, sleep.sleep.quality), (night("2023-04-02"), 7.5, 4));
+    assert_eq!(sleep.tags.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>(), vec![coffee_id, imported.tag_ids[0]]);
+    let comments = dave.get_comments_by_sleep(sleep.sleep.id).await.unwrap();
+    assert_eq!(comments.iter().map(|c| c.comment.as_str()).collect::<Vec<&str>>(), vec!["woke up", "slept again"]);
+
+    // importing the same file again only reports duplicates
+    let again = dave.import_csv(csv, &CsvImportOptions::default()).await.unwrap();
+    assert_eq!(again.imported, 0);
+    assert!(again.created_tags.is_empty());
+
+    // the columns and the format of the nights can be changed
+    let options = CsvImportOptions {
+        columns: CsvColumns { night: String::from("date"), amount: String::from("hours"), quality: String::from("rating"), ..Default::default() },
+        tag_delimiter: '|',
+        date_format: String::from("%m/%d/%Y"),
+        dry_run: false,
+    };
+    let custom = dave.import_csv("date,hours,rating,tags\n04/05/2023,6,2,late|new\n", &options).await.unwrap();
+    assert_eq!((custom.imported, custom.created_tags.len()), (1, 0));
+    assert!(matches!(dave.import_csv("night,amount\n2023-04-06,6\n", &CsvImportOptions::default()).await,
+        Err(DBError::Validation(_))));
+
+    // an export can be imported back unchanged
+    let export = dave.export_csv(Some(night("2023-04-02")), Some(night("2023-04-02"))).await.unwrap();
+    assert_eq!(export, "night,amount,quality,tags,comment\n2023-04-02,7.5,4,coffee;late,\"woke up\nslept again\"\n");
+
+    let erin = dbm.for_user(dbm.insert_user("erin").await.unwrap());
+    let export = dave.export_csv(None, None).await.unwrap();
+    let restored = erin.import_csv(&export, &CsvImportOptions::default()).await.unwrap();
+    assert!(restored.issues.is_empty(), "{:?}", restored.issues);
+    assert_eq!(restored.imported, 4);
+    assert_eq!(erin.export_csv(None, None).await.unwrap(), export);
+}
+
+async fn test_backup(dbm: &mut DBManager, restore_path: &str) {
+    let backup = dbm.export_backup().await.unwrap();
+    assert_eq!(backup.schema_version, db_migrations::latest_version());
+    assert!(!backup.sleep_sessions.is_empty() && !backup.comments.is_empty() && !backup.dream_tags.is_empty());
+    let backup = Backup::from_json(&backup.to_json()).unwrap();
+
+    // restoring into an empty database with replace keeps every row as it was
+    if std::path::Path::new(restore_path).exists() {
+        fs::remove_file(restore_path).unwrap();
+    }
+    let restored = DBManager::init(restore_path).await.unwrap();
+    let report = restored.restore_backup(&backup, RestoreMode::Replace).await.unwrap();
+    assert_eq!((report.sleeps, report.tags, report.comments), (backup.sleeps.len(), backup.tags.len(), backup.comments.len()));
+    assert_eq!(report.users, backup.users.len() - 1, "the default user already exists");
+    assert_eq!(restored.export_backup().await.unwrap(), backup);
+
+    // restoring again with replace is the same as restoring once
+    restored.restore_backup(&backup, RestoreMode::Replace).await.unwrap();
+    assert_eq!(restored.export_backup().await.unwrap(), backup);
+
+    // merging only adds what the database doesn't have
+    let report = restored.restore_backup(&backup, RestoreMode::Merge).await.unwrap();
+    assert_eq!((report.users, report.sleeps, report.tags), (0, 0, 0));
+    assert_eq!((report.skipped_sleeps, report.reused_tags), (backup.sleeps.len(), backup.tags.len()));
+    assert_eq!(restored.export_backup().await.unwrap(), backup);
+
+    // a sleep that was deleted is restored with its tags and comments
+    let ids = restored.get_all_sleeps().await.unwrap().iter().map(|s| s.sleep.id).collect::<Vec<i64>>();
+    let all_tags = restored.get_tags_by_sleeps(&ids).await.unwrap();
+    let all_comments = restored.get_comments_by_sleeps(&ids).await.unwrap();
+    let id = *ids.iter().find(|id| !all_tags[id].is_empty() && !all_comments[id].is_empty()).unwrap();
+    let (tags, comments) = (&all_tags[&id], &all_comments[&id]);
+    let sleep = restored.get_sleep(id, false).await.unwrap();
+    restored.delete_sleep(id).await.unwrap();
+    let report = restored.restore_backup(&backup, RestoreMode::Merge).await.unwrap();
+    assert_eq!((report.sleeps, report.skipped_sleeps), (1, backup.sleeps.len() - 1));
+    let merged = restored.get_sleeps_in_range(sleep.sleep.night, sleep.sleep.night).await.unwrap().remove(0).sleep;
+    assert_ne!(merged.id, sleep.sleep.id);
+    assert_eq!(&restored.get_tags_by_sleep(merged.id).await.unwrap(), tags);
+    assert_eq!(restored.get_comments_by_sleep(merged.id).await.unwrap().len(), comments.len());
+
+    // an invalid backup changes nothing, even when replacing
+    let before = restored.export_backup().await.unwrap();
+    let mut broken = backup.clone();
+    broken.sleep_tags[0].tag_id = 0;
+    assert!(matches!(restored.restore_backup(&broken, RestoreMode::Replace).await, Err(DBError::Validation(_))));
+    assert_eq!(restored.export_backup().await.unwrap(), before);
+
+    let unknown_format = Backup { format_version: 0, ..backup.clone() };
+    assert!(matches!(restored.restore_backup(&unknown_format, RestoreMode::Merge).await, Err(DBError::Validation(_))));
+    assert!(matches!(Backup::from_json(r#"{ "format_version": 1 }"#), Err(DBError::Validation(_))));
+
+    restored.close_connection().await;
+    fs::remove_file(restore_path).unwrap();
+}
+
+async fn test_snapshots(dbm: &mut DBManager, snapshot_dir: &str) {
+    let dir = std::path::PathBuf::from(snapshot_dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    let settings = SnapshotSettings { dir: dir.clone(), interval: std::time::Duration::from_secs(60), keep: 2 };
+    assert!(matches!(Snapshots::new(SnapshotSettings { keep: 0, ..settings.clone() }), Err(DBError::Validation(_))));
+    assert!(matches!(Snapshots::new(SnapshotSettings { interval: std::time::Duration::ZERO, ..settings.clone() }),
+        Err(DBError::Validation(_))));
+
+    let snapshots = Snapshots::new(settings).unwrap();
+    assert!(snapshots.last().is_none());
+
+    // only the newest snapshots are kept
+    let mut files = Vec::new();
+    for _ in 0..3 {
+        let status = snapshots.take(dbm).await;
+        assert!(status.succeeded(), "{:?}", status.error);
+        assert!(status.size.unwrap() > 0);
+        files.push(dir.join(status.file.unwrap()));
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+    }
+    assert_eq!(snapshots.list().unwrap(), files[1..].to_vec());
+    assert_eq!(snapshots.last_success_on(), Some(snapshots.last().unwrap().started_on));
+
+    // a snapshot is an intact copy of the database
+    assert!(super::integrity_check(&files[2]).await.unwrap().is_empty());
+    let copy = DBManager::init(files[2].to_str().unwrap()).await.unwrap();
+    assert_eq!(copy.export_backup().await.unwrap(), dbm.export_backup().await.unwrap());
+    copy.close_connection().await;
+    assert!(matches!(dbm.snapshot_into(&files[2]).await, Err(DBError::Validation(_))));
+
+    // the status of the snapshots is available to admin api tokens of the graphql api
+    let (_, admin_token) = dbm.create_api_token("snapshots admin", false, true).await.unwrap();
+    let admin = dbm.authenticate(&admin_token).await.unwrap().unwrap();
+    let schema = crate::build_schema(dbm.clone()).data(snapshots.clone()).finish();
+    let query = "{ snapshots { keep last { file succeeded } } }";
+    let response = schema.execute(async_graphql::Request::new(query).data(admin.clone())).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["snapshots"]["keep"], 2);
+    assert_eq!(data["snapshots"]["last"]["file"], files[2].file_name().unwrap().to_str().unwrap());
+    let response = crate::build_schema(dbm.clone()).finish().execute(async_graphql::Request::new("{ snapshots { keep } }").data(admin)).await;
+    assert_eq!(response.data.into_json().unwrap()["snapshots"], serde_json::Value::Null);
+
+    let (_, token) = dbm.create_api_token("snapshots", false, false).await.unwrap();
+    let response = schema.execute(async_graphql::Request::new(query).data(dbm.authenticate(&token).await.unwrap().unwrap())).await;
+    assert_eq!(response.errors[0].extensions.as_ref().unwrap().get("code"), Some(&async_graphql::Value::from("ADMIN_ONLY")));
+    assert_eq!(schema.execute(query).await.errors.len(), 1);
+
+    // a failed snapshot is reported without forgetting the last successful one
+    let last_success_on = snapshots.last_success_on();
+    fs::remove_dir_all(&dir).unwrap();
+    fs::write(&dir, "not a directory").unwrap();
+    let failed = snapshots.take(dbm).await;
+    assert!(!failed.succeeded() && failed.file.is_none());
+    assert_eq!(snapshots.last(), Some(failed));
+    assert_eq!(snapshots.last_success_on(), last_success_on);
+    fs::remove_file(&dir).unwrap();
+}
+
+/// Apple Health export with overlapping records from a phone and a watch.
+/// The record on line 13 can't be read and the night of line 15 is recorded by hand.
+const APPLE_HEALTH_EXPORT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<HealthData locale="en_US">
+ <ExportDate value="2023-06-06 08:00:00 -0700"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Phone" startDate="2023-06-01 22:30:00 -0700" endDate="2023-06-02 07:00:00 -0700" value="HKCategoryValueSleepAnalysisInBed"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Watch" startDate="2023-06-01 23:00:00 -0700" endDate="2023-06-02 03:00:00 -0700" value="HKCategoryValueSleepAnalysisAsleepCore"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Watch" startDate="2023-06-02 02:30:00 -0700" endDate="2023-06-02 04:00:00 -0700" value="HKCategoryValueSleepAnalysisAsleepDeep"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Watch" startDate="2023-06-02 04:00:00 -0700" endDate="2023-06-02 06:30:00 -0700" value="HKCategoryValueSleepAnalysisAsleepREM"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Watch" startDate="2023-06-02 06:30:00 -0700" endDate="2023-06-02 06:45:00 -0700" value="HKCategoryValueSleepAnalysisAwake"/>
+ <Record type="HKQuantityTypeIdentifierHeartRate" sourceName="Watch" startDate="2023-06-02 03:00:00 -0700" endDate="2023-06-02 03:00:00 -0700" value="52"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Watch" startDate="2023-06-03 00:30:00 +0200" endDate="2023-06-03 06:00:00 +0200" value="HKCategoryValueSleepAnalysisAsleep">
+  <MetadataEntry key="HKTimeZone" value="Europe/Paris"/>
+ </Record>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Phone" startDate="yesterday" endDate="2023-06-04 07:00:00 -0700" value="HKCategoryValueSleepAnalysisInBed"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Phone" startDate="2023-06-03 23:00:00 -0700" endDate="2023-06-04 07:00:00 -0700" value="HKCategoryValueSleepAnalysisInBed"/>
+ <Record type="HKCategoryTypeIdentifierSleepAnalysis" sourceName="Watch" startDate="2023-06-04 23:00:00 -0700" endDate="2023-06-05 06:00:00 -0700" value="HKCategoryValueSleepAnalysisAsleepCore"/>
+</HealthData>
+"#;
+
+async fn test_apple_health(dbm: &mut DBManager) {
+    let frank = dbm.for_user(dbm.insert_user("frank").await.unwrap());
+    let manual_id = frank.insert_sleep(night("2023-06-04"), 6.0, 4).await.unwrap();
+
+    let options = AppleHealthOptions { quality: 3, dry_run: true };
+    let report = frank.import_apple_health(APPLE_HEALTH_EXPORT.as_bytes(), &options).await.unwrap();
+    assert_eq!((report.rows, report.imported, report.merged), (5, 3, 0));
+    assert_eq!(report.issues.iter().map(|i| (i.line, i.kind)).collect::<Vec<_>>(),
+        vec![(13, ImportIssueKind::Invalid), (15, ImportIssueKind::DuplicateNight)]);
+    assert_eq!(frank.get_all_sleeps().await.unwrap().len(), 1);
+
+    let options = AppleHealthOptions { quality: 3, dry_run: false };
+    let report = frank.import_apple_health(APPLE_HEALTH_EXPORT.as_bytes(), &options).await.unwrap();
+    assert_eq!((report.imported, report.sleep_ids.len()), (3, 3));
+
+    // overlapping asleep records are counted once, and awake time is not counted
+    let sleeps = frank.get_multiple_sleeps(report.sleep_ids.clone()).await.unwrap();
+    assert_eq!(sleeps.iter().map(|s| (s.sleep.night, s.sleep.amount, s.sleep.quality)).collect::<Vec<_>>(), vec![
+        (night("2023-06-01"), 7.5, 3),
+        (night("2023-06-02"), 5.5, 3),
+        (night("2023-06-03"), 8.0, 3),
+    ]);
+    let sessions = frank.get_sleep_sessions(report.sleep_ids[0]).await.unwrap();
+    assert_eq!(sessions[0].times(), SleepTimes {
+        bed_time: Some(time("2023-06-01 22:30")),
+        sleep_onset: Some(time("2023-06-01 23:00")),
+        wake_time: Some(time("2023-06-02 06:30")),
+    });
+    // times are the local times of the records, and nights with only time in bed count the time in bed
+    assert_eq!(frank.get_sleep_sessions(report.sleep_ids[1]).await.unwrap()[0].times().sleep_onset, Some(time("2023-06-03 00:30")));
+    assert_eq!(frank.get_sleep_sessions(report.sleep_ids[2]).await.unwrap()[0].times().sleep_onset, None);
+
+    let sources = db_types::DBSleep::select_sources(&mut dbm.conn().await.unwrap(), frank.user_id()).await.unwrap();
+    assert!(sources.iter().all(|(id, _, source)| (*id == manual_id) == source.is_none()));
+    assert_eq!(frank.get_sleep(manual_id, false).await.unwrap().sleep.amount, 6.0);
+
+    // importing the same export again changes nothing, and a longer night updates the imported sleep
+    let again = frank.import_apple_health(APPLE_HEALTH_EXPORT.as_bytes(), &options).await.unwrap();
+    assert_eq!((again.imported, again.merged, again.issues.len()), (0, 0, 2));
+
+    let longer = APPLE_HEALTH_EXPORT.replace(
+        r#"startDate="2023-06-02 04:00:00 -0700" endDate="2023-06-02 06:30:00 -0700""#,
+        r#"startDate="2023-06-02 04:00:00 -0700" endDate="2023-06-02 07:00:00 -0700""#);
+    let merged = frank.import_apple_health(longer.as_bytes(), &options).await.unwrap();
+    assert_eq!((merged.imported, merged.merged), (0, 1));
+    let sleep = frank.get_sleep(report.sleep_ids[0], false).await.unwrap();
+    assert_eq!(sleep.sleep.amount, 8.0);
+    assert_eq!(frank.get_sleep_sessions(sleep.sleep.id).await.unwrap()[0].times().wake_time, Some(time("2023-06-02 07:00")));
+
+    assert!(matches!(frank.import_apple_health("<HealthData><Record></HealthData>".as_bytes(), &options).await,
+        Err(DBError::Validation(_))));
+}
+
+/// Sleep file of a Fitbit export with a nap, a sleep that can't be read on line 4
+/// and a sleep on line 5 for a night that is recorded by hand
+const FITBIT_SLEEPS: &str = r#"[
+{"logId":1,"dateOfSleep":"2023-07-02","startTime":"2023-07-01T23:10:00.000","endTime":"2023-07-02T07:00:00.000","minutesToFallAsleep":10,"minutesAsleep":420,"minutesAwake":25,"minutesAfterWakeup":5,"timeInBed":470,"efficiency":90,"type":"stages","mainSleep":true,"levels":{"summary":{"deep":{"count":3,"minutes":80},"light":{"count":20,"minutes":250},"rem":{"count":5,"minutes":90},"wake":{"count":10,"minutes":25}},"data":[{"dateTime":"2023-07-01T23:10:00.000","level":"wake","seconds":600},{"dateTime":"2023-07-01T23:20:00.000","level":"light","seconds":1800},{"dateTime":"2023-07-01T23:50:00.000","level":"deep","seconds":2400}]}},
+{"logId":2,"dateOfSleep":"2023-07-02","startTime":"2023-07-02T14:00:00.000","endTime":"2023-07-02T14:40:00.000","minutesAsleep":35,"efficiency":95,"type":"classic","mainSleep":false},
+{"logId":3,"dateOfSleep":"2023-07-03","startTime":"noon","endTime":"2023-07-03T07:00:00.000","minutesAsleep":400,"efficiency":80,"mainSleep":true},
+{"logId":4,"dateOfSleep":"2023-07-04","startTime":"2023-07-03T22:00:00.000","endTime":"2023-07-04T06:00:00.000","minutesAsleep":450,"efficiency":85,"mainSleep":true},
+{"logId":5,"dateOfSleep":"2023-07-05","startTime":"2023-07-05T00:30:00.000","endTime":"2023-07-05T07:00:00.000","minutesAsleep":360,"efficiency":50,"mainSleep":true}
+]"#;
+
+async fn test_fitbit(dbm: &mut DBManager, export_dir: &str) {
+    let dir = std::path::PathBuf::from(export_dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("sleep-2023-07-01.json"), FITBIT_SLEEPS).unwrap();
+    fs::write(dir.join("sleep-2023-08-01.json"), "[{\"logId\":6,").unwrap();
+    fs::write(dir.join("heart_rate-2023-07-01.json"), "[]").unwrap();
+
+    let grace = dbm.for_user(dbm.insert_user("grace").await.unwrap());
+    let manual_id = grace.insert_sleep(night("2023-07-03"), 7.5, 2).await.unwrap();
+
+    let options = FitbitOptions { dry_run: true, ..Default::default() };
+    let report = grace.import_fitbit(&dir, &options).await.unwrap();
+    assert_eq!((report.rows, report.imported, report.merged), (5, 2, 0));
+    assert_eq!(report.created_tags, vec![String::from("fitbit")]);
+    assert_eq!(report.issues.iter().map(|i| (i.file.as_deref().unwrap(), i.line, i.kind)).collect::<Vec<_>>(), vec![
+        ("sleep-2023-07-01.json", 4, ImportIssueKind::Invalid),
+        ("sleep-2023-07-01.json", 5, ImportIssueKind::DuplicateNight),
+        ("sleep-2023-08-01.json", 1, ImportIssueKind::Invalid),
+    ]);
+    assert_eq!(grace.get_all_sleeps().await.unwrap().len(), 1);
+    assert!(grace.get_all_tags().await.unwrap().is_empty());
+
+    // the efficiency of 90% and 50% is scaled to the default quality scale of 1 to 5
+    let options = FitbitOptions::default();
+    let report = grace.import_fitbit(&dir, &options).await.unwrap();
+    let sleeps = grace.get_multiple_sleeps(report.sleep_ids.clone()).await.unwrap();
+    assert_eq!(sleeps.iter().map(|s| (s.sleep.night, s.sleep.amount, s.sleep.quality)).collect::<Vec<_>>(), vec![
+        (night("2023-07-01"), 7.0, 5),
+        (night("2023-07-04"), 6.0, 3),
+    ]);
+    assert_eq!(grace.get_sleep_sessions(report.sleep_ids[0]).await.unwrap()[0].times(), SleepTimes {
+        bed_time: Some(time("2023-07-01 23:10")),
+        sleep_onset: Some(time("2023-07-01 23:20")),
+        wake_time: Some(time("2023-07-02 06:55")),
+    });
+    assert_eq!(report.tag_ids.len(), 1);
+    for sleep_id in &report.sleep_ids {
+        assert_eq!(grace.get_tags_by_sleep(*sleep_id).await.unwrap().iter().map(|t| t.id).collect::<Vec<_>>(), report.tag_ids);
+    }
+    assert!(grace.get_tags_by_sleep(manual_id).await.unwrap().is_empty());
+
+    // sleeps with levels have stages and a hypnogram, classic sleeps without levels have neither
+    let stages = grace.get_sleep_stages(report.sleep_ids[0]).await.unwrap().unwrap();
+    assert_eq!(stages.stages(), SleepStages { deep_minutes: Some(80), light_minutes: Some(250), rem_minutes: Some(90), awake_minutes: Some(25) });
+    let hypnogram = grace.get_hypnogram(report.sleep_ids[0]).await.unwrap();
+    assert_eq!(hypnogram.iter().map(|i| (i.stage(), i.start_time, i.end_time)).collect::<Vec<_>>(), vec![
+        (SleepStage::Awake, time("2023-07-01 23:10"), time("2023-07-01 23:20")),
+        (SleepStage::Light, time("2023-07-01 23:20"), time("2023-07-01 23:50")),
+        (SleepStage::Deep, time("2023-07-01 23:50"), time("2023-07-02 00:30")),
+    ]);
+    assert_eq!(grace.get_sleep_stages(report.sleep_ids[1]).await.unwrap(), None);
+    assert!(grace.get_hypnogram(report.sleep_ids[1]).await.unwrap().is_empty());
+
+    // the tag exists now, and a sleep with a lower efficiency updates the quality of the imported sleep
+    let again = grace.import_fitbit(&dir, &options).await.unwrap();
+    assert_eq!((again.imported, again.merged, again.created_tags.len()), (0, 0, 0));
+    fs::write(dir.join("sleep-2023-07-01.json"), FITBIT_SLEEPS.replace(r#""efficiency":90"#, r#""efficiency":70"#)).unwrap();
+    let merged = grace.import_fitbit(&dir, &options).await.unwrap();
+    assert_eq!((merged.imported, merged.merged), (0, 1));
+    assert_eq!(grace.get_sleep(report.sleep_ids[0], false).await.unwrap().sleep.quality, 4);
+
+    let reversed = FitbitOptions { min_quality: 5, max_quality: 1, dry_run: false };
+    assert!(matches!(grace.import_fitbit(&dir, &reversed).await, Err(DBError::Validation(_))));
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(grace.import_fitbit(&dir, &options).await, Err(DBError::Validation(_))));
+}
+
+async fn test_sleep_stages(dbm: &mut DBManager) {
+    let heidi = dbm.for_user(dbm.insert_user("heidi").await.unwrap());
+    let first = heidi.insert_sleep(night("2023-09-01"), 7.0, 3).await.unwrap();
+    let second = heidi.insert_sleep(night("2023-09-02"), 7.5, 4).await.unwrap();
+    let third = heidi.insert_sleep(night("2023-10-01"), 6.0, 2).await.unwrap();
+
+    assert_eq!(heidi.get_sleep_stages(first).await.unwrap(), None);
+    let stages = SleepStages { deep_minutes: Some(60), light_minutes: Some(240), rem_minutes: Some(90), awake_minutes: Some(30) };
+    heidi.set_sleep_stages(first, &stages).await.unwrap();
+    assert_eq!(heidi.get_sleep_stages(first).await.unwrap().unwrap().stages(), stages);
+
+    // stages that weren't recorded are left out of the shares
+    let partial = SleepStages { light_minutes: Some(300), rem_minutes: Some(100), ..Default::default() };
+    heidi.set_sleep_stages(second, &partial).await.unwrap();
+    assert_eq!(partial.share(SleepStage::Rem), Some(0.25));
+    assert_eq!(partial.share(SleepStage::Deep), None);
+
+    // setting the stages again replaces them, and empty stages remove them
+    heidi.set_sleep_stages(third, &partial).await.unwrap();
+    heidi.set_sleep_stages(third, &SleepStages::default()).await.unwrap();
+    assert_eq!(heidi.get_sleep_stages(third).await.unwrap(), None);
+
+    assert!(matches!(heidi.set_sleep_stages(first, &SleepStages { rem_minutes: Some(-5), ..Default::default() }).await,
+        Err(DBError::Validation(_))));
+    assert!(matches!(heidi.set_sleep_stages(-1, &stages).await, Err(DBError::NotFound { .. })));
+    // the stages of another user's sleep can't be read or set
+    assert_eq!(dbm.get_sleep_stages(first).await.unwrap(), None);
+    assert!(matches!(dbm.set_sleep_stages(first, &partial).await, Err(DBError::NotFound { .. })));
+    assert_eq!(heidi.get_sleep_stages(first).await.unwrap().unwrap().stages(), stages);
+
+    // the average share of each stage per month only counts the sleeps with stages
+    let stats = heidi.get_sleep_stats(night("2023-09-01"), night("2023-10-31"), StatsGroupBy::Month).await.unwrap();
+    let september = stats.groups[0].stages.unwrap();
+    assert_eq!((stats.groups[0].key.as_str(), stats.groups[0].count, september.count), ("2023-09", 2, 2));
+    assert_eq!(september.rem, Some((90.0 / 390.0 + 0.25) / 2.0));
+    assert_eq!(september.deep, Some(60.0 / 390.0));
+    assert_eq!(september.awake, Some(30.0 / 420.0));
+    assert_eq!((stats.groups[1].key.as_str(), stats.groups[1].stages), ("2023-10", None));
+    assert_eq!(stats.overall.unwrap().stages.unwrap().count, 2);
+
+    let intervals = vec![
+        HypnogramInterval { stage: SleepStage::Light, start: time("2023-09-01 23:00"), end: time("2023-09-01 23:45") },
+        HypnogramInterval { stage: SleepStage::Deep, start: time("2023-09-01 23:45"), end: time("2023-09-02 00:30") },
+        HypnogramInterval { stage: SleepStage::Rem, start: time("2023-09-02 00:30"), end: time("2023-09-02 00:50") },
+    ];
+    heidi.set_hypnogram(first, &intervals).await.unwrap();
+    assert_eq!(heidi.get_hypnogram(first).await.unwrap().iter().map(|i| i.interval()).collect::<Vec<_>>(), intervals);
+
+    // the intervals must be in order without overlapping, and start on the night of the sleep
+    let overlapping = vec![intervals[1], intervals[0]];
+    assert!(matches!(heidi.set_hypnogram(first, &overlapping).await, Err(DBError::Validation(_))));
+    let backwards = vec![HypnogramInterval { end: intervals[0].start, ..intervals[0] }];
+    assert!(matches!(heidi.set_hypnogram(first, &backwards).await, Err(DBError::Validation(_))));
+    assert!(matches!(heidi.set_hypnogram(second, &intervals).await, Err(DBError::Validation(_))));
+    assert_eq!(heidi.get_hypnogram(first).await.unwrap().len(), 3);
+
+    let backup = Backup::from_json(&heidi.export_backup().await.unwrap().to_json()).unwrap();
+    assert!(backup.sleep_stages.iter().any(|s| s.sleep_id == first && s.rem_minutes == Some(90)));
+    assert_eq!(backup.hypnogram.iter().filter(|i| i.sleep_id == first).count(), 3);
+
+    heidi.set_hypnogram(second, &[]).await.unwrap();
+    heidi.set_hypnogram(first, &[]).await.unwrap();
+    assert!(heidi.get_hypnogram(first).await.unwrap().is_empty());
+
+    // the stages are deleted with the sleep
+    heidi.delete_sleep(second).await.unwrap();
+    assert_eq!(heidi.get_sleep_stages(second).await.unwrap(), None);
+}
+
+async fn test_unit_of_work(dbm: &mut DBManager) {
+    let ivan = dbm.for_user(dbm.insert_user("ivan").await.unwrap());
+    let tag_id = ivan.insert_tag("kept", 0).await.unwrap();
+    assert!(!ivan.in_unit_of_work());
+
+    // a unit of work that is dropped without committing is rolled back
+    let uow = ivan.begin().await.unwrap();
+    let sleep_id = uow.insert_sleep(night("2023-11-01"), 7.0, 3).await.unwrap();
+    uow.insert_comment(sleep_id, "rolled back").await.unwrap();
+    assert!(matches!(uow.add_tags_to_sleep(sleep_id, vec![tag_id, -1]).await, Err(DBError::ForeignKeyViolation(_))));
+    assert_eq!(uow.get_all_sleeps().await.unwrap().len(), 1);
+    drop(uow);
+    assert!(ivan.get_all_sleeps().await.unwrap().is_empty());
+
+    let uow = ivan.begin().await.unwrap();
+    uow.insert_tag("rolled back", 0).await.unwrap();
+    uow.rollback().await.unwrap();
+    assert_eq!(ivan.get_all_tags().await.unwrap().len(), 1);
+
+    // a joined unit of work is committed with the unit of work that started it
+    let uow = ivan.begin().await.unwrap();
+    let joined = uow.begin().await.unwrap();
+    let sleep_id = joined.insert_sleep(night("2023-11-01"), 7.0, 3).await.unwrap();
+    joined.add_tags_to_sleep(sleep_id, vec![tag_id]).await.unwrap();
+    joined.commit().await.unwrap();
+    assert!(ivan.get_all_sleeps().await.unwrap().is_empty());
+    let finished = uow.clone();
+    uow.commit().await.unwrap();
+    assert_eq!(ivan.get_sleep(sleep_id, true).await.unwrap().tags.unwrap().len(), 1);
+
+    assert!(matches!(finished.get_all_sleeps().await, Err(DBError::Validation(_))));
+    assert!(matches!(finished.commit().await, Err(DBError::Validation(_))));
+    assert!(matches!(ivan.clone().commit().await, Err(DBError::Validation(_))));
+
+    // a mutation that fails part way leaves nothing behind and reports the step that failed
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let add_sleep = format!(
+        r#"mutation {{ addSleep(sleepInput: {{ night: "2023-11-02", amount: 7.0, quality: 3, tags: [{}, -1], comments: ["lost"] }}) {{ id }} }}"#,
+        tag_id);
+    let response = schema.execute(crate::scope_request(add_sleep.into(), ivan.clone())).await;
+    let extensions = response.errors[0].extensions.as_ref().unwrap();
+    assert_eq!(extensions.get("step"), Some(&async_graphql::Value::from("tags")));
+    assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("FOREIGN_KEY_VIOLATION")));
+    assert_eq!(ivan.get_all_sleeps().await.unwrap().len(), 1);
+
+    let update_sleep = format!(r#"mutation {{ updateSleep(sleepInput: {{ sleepId: {}, quality: 5, amount: -1.0 }}) {{ id }} }}"#, sleep_id);
+    let response = schema.execute(crate::scope_request(update_sleep.into(), ivan.clone())).await;
+    assert_eq!(response.errors[0].extensions.as_ref().unwrap().get("step"), Some(&async_graphql::Value::from("amount")));
+    assert_eq!(ivan.get_sleep(sleep_id, false).await.unwrap().sleep.quality, 3);
+}
+
+async fn test_upsert_sleep(dbm: &mut DBManager) {
+    let judy = dbm.for_user(dbm.insert_user("judy").await.unwrap());
+    let coffee = judy.insert_tag("coffee", 0).await.unwrap();
+    let gym = judy.insert_tag("gym", 0).await.unwrap();
+    let tags = |sleep_id: i64| {
+        let judy = &judy;
+        async move {
+            let mut ids = judy.get_sleep(sleep_id, true).await.unwrap().tags.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>();
+            ids.sort();
+            ids
+        }
+    };
+    let comments = |sleep_id: i64| {
+        let judy = &judy;
+        async move { judy.get_comments_by_sleep(sleep_id).await.unwrap().into_iter().map(|c| c.comment).collect::<Vec<String>>() }
+    };
+
+    let upsert = SleepUpsert {
+        night: Some(night("2023-12-01")),
+        amount: Some(7.0),
+        quality: 3,
+        tag_ids: vec![coffee, coffee],
+        comments: vec![String::from("late dinner")],
+        ..Default::default()
+    };
+    let created = judy.upsert_sleep(&upsert).await.unwrap();
+    assert!(created.created);
+    assert_eq!(created.created_comment_ids.len(), 1);
+    assert_eq!(tags(created.sleep_id).await, vec![coffee]);
+
+    // repeating an upsert changes nothing
+    let repeated = judy.upsert_sleep(&upsert).await.unwrap();
+    assert_eq!((repeated.sleep_id, repeated.created), (created.sleep_id, false));
+    assert!(repeated.created_comment_ids.is_empty() && repeated.deleted_comment_ids.is_empty());
+    assert_eq!(tags(created.sleep_id).await, vec![coffee]);
+    assert_eq!(comments(created.sleep_id).await, vec!["late dinner"]);
+
+    let appended = SleepUpsert {
+        amount: Some(8.0),
+        quality: 4,
+        tag_ids: vec![gym],
+        comments: vec![String::from("woke up once")],
+        comment_policy: MergePolicy::Replace,
+        ..upsert.clone()
+    };
+    let updated = judy.upsert_sleep(&appended).await.unwrap();
+    assert_eq!((updated.created_comment_ids.len(), updated.deleted_comment_ids.len()), (1, 1));
+    let sleep = judy.get_sleep(created.sleep_id, false).await.unwrap().sleep;
+    assert_eq!((sleep.amount, sleep.quality), (8.0, 4));
+    assert_eq!(tags(created.sleep_id).await, vec![coffee, gym]);
+    assert_eq!(comments(created.sleep_id).await, vec!["woke up once"]);
+
+    judy.upsert_sleep(&SleepUpsert { tag_ids: vec![], tag_policy: MergePolicy::Keep, ..appended.clone() }).await.unwrap();
+    assert_eq!(tags(created.sleep_id).await, vec![coffee, gym]);
+    judy.upsert_sleep(&SleepUpsert { tag_ids: vec![gym], tag_policy: MergePolicy::Replace, ..appended.clone() }).await.unwrap();
+    assert_eq!(tags(created.sleep_id).await, vec![gym]);
+
+    // the times of an existing sleep are updated, and the amount is derived from them
+    let times = SleepTimes { bed_time: Some(time("2023-12-01 23:00")), sleep_onset: None, wake_time: Some(time("2023-12-02 06:30")) };
+    judy.upsert_sleep(&SleepUpsert { night: None, amount: None, times, ..appended.clone() }).await.unwrap();
+    assert_eq!(judy.get_sleep(created.sleep_id, false).await.unwrap().sleep.amount, 7.5);
+
+    // an upsert that fails changes nothing
+    let invalid = SleepUpsert { quality: 1, tag_ids: vec![-1], ..appended.clone() };
+    assert!(matches!(judy.upsert_sleep(&invalid).await, Err(DBError::ForeignKeyViolation(_))));
+    assert_eq!(judy.get_sleep(created.sleep_id, false).await.unwrap().sleep.quality, 4);
+    let other_night = SleepUpsert { night: Some(night("2023-12-05")), times, ..appended.clone() };
+    assert!(matches!(judy.upsert_sleep(&other_night).await, Err(DBError::Validation(_))));
+    assert_eq!(judy.get_all_sleeps().await.unwrap().len(), 1);
+
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let upsert_sleep = format!(
+        r#"mutation {{ upsertSleep(upsertSleepInput: {{ night: "2023-12-03", amount: 6.0, quality: 2, tags: [{}], tagPolicy: REPLACE }}) {{ created sleep {{ tags {{ id }} }} }} }}"#,
+        coffee);
+    for created in [true, false] {
+        let response = schema.execute(crate::scope_request(upsert_sleep.clone().into(), judy.clone())).await;
+        let data = response.data.into_json().unwrap();
+        assert_eq!(data["upsertSleep"]["created"], created);
+        assert_eq!(data["upsertSleep"]["sleep"]["tags"][0]["id"], coffee);
+    }
+}
+
+async fn test_set_sleep_tags(dbm: &mut DBManager) {
+    let karl = dbm.for_user(dbm.insert_user("karl").await.unwrap());
+    let tag_ids = [karl.insert_tag("a", 0).await.unwrap(), karl.insert_tag("b", 0).await.unwrap(), karl.insert_tag("c", 0).await.unwrap()];
+    let sleep_id = karl.insert_sleep(night("2024-01-01"), 7.0, 3).await.unwrap();
+    let tags = |sleep_id: i64| {
+        let karl = &karl;
+        async move { karl.get_tags_by_sleep(sleep_id).await.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>() }
+    };
+
+    // adding a tag the sleep already has is skipped
+    karl.add_tags_to_sleep(sleep_id, vec![tag_ids[0], tag_ids[0]]).await.unwrap();
+    karl.add_tags_to_sleep(sleep_id, vec![tag_ids[0]]).await.unwrap();
+    assert_eq!(tags(sleep_id).await, vec![tag_ids[0]]);
+
+    karl.set_sleep_tags(sleep_id, &[tag_ids[1], tag_ids[2], tag_ids[2]]).await.unwrap();
+    assert_eq!(tags(sleep_id).await, vec![tag_ids[1], tag_ids[2]]);
+
+    // nothing changes when a tag does not exist
+    assert!(matches!(karl.set_sleep_tags(sleep_id, &[tag_ids[0], -1]).await, Err(DBError::ForeignKeyViolation(_))));
+    assert_eq!(tags(sleep_id).await, vec![tag_ids[1], tag_ids[2]]);
+    assert!(matches!(karl.set_sleep_tags(-1, &[tag_ids[0]]).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.set_sleep_tags(sleep_id, &[]).await, Err(DBError::NotFound { .. })));
+
+    karl.set_sleep_tags(sleep_id, &[]).await.unwrap();
+    assert!(tags(sleep_id).await.is_empty());
+
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let set_tags = format!("mutation {{ setSleepTags(sleepId: {}, tagIds: [{}, {}]) {{ tags {{ name }} }} }}", sleep_id, tag_ids[2], tag_ids[0]);
+    let response = schema.execute(crate::scope_request(set_tags.into(), karl.clone())).await;
+    let data = response.data.into_json().unwrap();
+    let mut names = data["setSleepTags"]["tags"].as_array().unwrap().iter().map(|t| t["name"].as_str().unwrap()).collect::<Vec<&str>>();
+    names.sort();
+    assert_eq!(names, vec!["a", "c"]);
+}
+
+async fn test_merge_tags(dbm: &mut DBManager) {
+    let lena = dbm.for_user(dbm.insert_user("lena").await.unwrap());
+    let coffee_id = lena.insert_tag("coffee", 0).await.unwrap();
+    let upper_id = lena.insert_tag("Coffee", 0).await.unwrap();
+    let caffeine_id = lena.insert_tag("caffeine", 0).await.unwrap();
+    let both_id = lena.insert_sleep(night("2024-02-01"), 7.0, 3).await.unwrap();
+    let upper_only_id = lena.insert_sleep(night("2024-02-02"), 6.0, 2).await.unwrap();
+    lena.add_tags_to_sleep(both_id, vec![coffee_id, caffeine_id]).await.unwrap();
+    lena.add_tags_to_sleep(upper_only_id, vec![upper_id]).await.unwrap();
+    let dream_id = lena.insert_dream(both_id, &DreamDetails { title: String::from("Cafe"), ..Default::default() }).await.unwrap();
+    lena.add_tags_to_dream(dream_id, vec![coffee_id, upper_id]).await.unwrap();
+
+    // nothing changes when the target is one of the sources or a tag does not exist
+    assert!(matches!(lena.merge_tags(&[], coffee_id).await, Err(DBError::Validation(_))));
+    assert!(matches!(lena.merge_tags(&[coffee_id], coffee_id).await, Err(DBError::Validation(_))));
+    assert!(matches!(lena.merge_tags(&[upper_id, -1], coffee_id).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.merge_tags(&[upper_id], coffee_id).await, Err(DBError::NotFound { .. })));
+    assert_eq!(lena.get_all_tags().await.unwrap().len(), 3);
+
+    lena.merge_tags(&[upper_id, caffeine_id, upper_id], coffee_id).await.unwrap();
+    let tag_ids = |sleep_id: i64| {
+        let lena = &lena;
+        async move { lena.get_tags_by_sleep(sleep_id).await.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>() }
+    };
+    assert_eq!(tag_ids(both_id).await, vec![coffee_id]);
+    assert_eq!(tag_ids(upper_only_id).await, vec![coffee_id]);
+    assert_eq!(lena.get_tags_by_dream(dream_id).await.unwrap().iter().map(|t| t.id).collect::<Vec<i64>>(), vec![coffee_id]);
+    assert!(matches!(lena.get_tag(caffeine_id).await, Err(DBError::NotFound { .. })));
+    assert_eq!(lena.get_all_tags().await.unwrap().len(), 1);
+
+    let aliases = |tag_id: i64| {
+        let lena = &lena;
+        async move { lena.get_tag_aliases(tag_id).await.unwrap().into_iter().map(|a| a.name).collect::<Vec<String>>() }
+    };
+    assert_eq!(aliases(coffee_id).await, vec!["Coffee", "caffeine"]);
+    assert_eq!(lena.get_tag_by_name("caffeine").await.unwrap().map(|t| t.id), Some(coffee_id));
+    assert_eq!(lena.get_tag_by_name("coffee").await.unwrap().map(|t| t.id), Some(coffee_id));
+    assert!(lena.get_tag_by_name("tea").await.unwrap().is_none());
+    assert!(dbm.get_tag_by_name("caffeine").await.unwrap().is_none());
+
+    // an import with an old name uses the merged tag
+    let report = lena.import_csv("Night,Amount,Quality,Tags,Comment\n2024-02-03,7,3,caffeine,\n", &CsvImportOptions::default()).await.unwrap();
+    assert!(report.created_tags.is_empty());
+    assert_eq!(tag_ids(report.sleep_ids[0]).await, vec![coffee_id]);
+
+    // a renamed tag keeps its old name as an alias, and a name that is taken back stops being an alias
+    lena.update_tag_name(coffee_id, "espresso").await.unwrap();
+    assert_eq!(aliases(coffee_id).await, vec!["Coffee", "caffeine", "coffee"]);
+    assert_eq!(lena.get_tag_by_name("coffee").await.unwrap().map(|t| t.id), Some(coffee_id));
+    lena.update_tag_name(coffee_id, "coffee").await.unwrap();
+    assert_eq!(aliases(coffee_id).await, vec!["Coffee", "caffeine", "espresso"]);
+    let tea_id = lena.insert_tag("caffeine", 0).await.unwrap();
+    assert_eq!(aliases(coffee_id).await, vec!["Coffee", "espresso"]);
+    assert_eq!(lena.get_tag_by_name("caffeine").await.unwrap().map(|t| t.id), Some(tea_id));
+    let backup = dbm.export_backup().await.unwrap();
+    assert_eq!(backup.tag_aliases.iter().filter(|a| a.tag_id == coffee_id).count(), 2);
+
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let merge = format!("mutation {{ mergeTags(sourceIds: [{}], targetId: {}) {{ id aliases }} }}", tea_id, coffee_id);
+    let response = schema.execute(crate::scope_request(merge.into(), lena.clone())).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["mergeTags"]["id"], coffee_id);
+    assert_eq!(data["mergeTags"]["aliases"], serde_json::json!(["Coffee", "espresso", "caffeine"]));
+
+    let response = schema.execute(crate::scope_request(r#"{ tagByName(name: "Coffee") { name } }"#.into(), lena.clone())).await;
+    assert_eq!(response.data.into_json().unwrap()["tagByName"]["name"], "coffee");
+}
+
+async fn test_tag_hierarchy(dbm: &mut DBManager) {
+    let mia = dbm.for_user(dbm.insert_user("mia").await.unwrap());
+    let substances_id = mia.insert_tag_category("Substances").await.unwrap();
+    assert!(matches!(mia.insert_tag_category("Substances").await, Err(DBError::DuplicateCategoryName(n)) if n == "Substances"));
+    assert!(matches!(mia.insert_tag_category(" ").await, Err(DBError::Validation(_))));
+    dbm.insert_tag_category("Substances").await.unwrap();
+
+    let caffeine_id = mia.insert_tag("caffeine", 0).await.unwrap();
+    let coffee_id = mia.insert_tag("coffee", 0).await.unwrap();
+    let espresso_id = mia.insert_tag("espresso", 0).await.unwrap();
+    let tea_id = mia.insert_tag("tea", 0).await.unwrap();
+    let noise_id = mia.insert_tag("noise", 0).await.unwrap();
+    mia.set_tag_parent(coffee_id, Some(caffeine_id)).await.unwrap();
+    mia.set_tag_parent(espresso_id, Some(coffee_id)).await.unwrap();
+    mia.set_tag_parent(tea_id, Some(caffeine_id)).await.unwrap();
+    mia.set_tag_category(caffeine_id, Some(substances_id)).await.unwrap();
+
+    // a tag can't be below itself
+    assert!(matches!(mia.set_tag_parent(caffeine_id, Some(caffeine_id)).await, Err(DBError::Validation(_))));
+    assert!(matches!(mia.set_tag_parent(caffeine_id, Some(espresso_id)).await, Err(DBError::Validation(_))));
+    assert!(matches!(mia.set_tag_parent(caffeine_id, Some(-1)).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(dbm.set_tag_parent(coffee_id, None).await, Err(DBError::NotFound { .. })));
+    assert!(matches!(mia.set_tag_category(noise_id, Some(-1)).await, Err(DBError::NotFound { .. })));
+
+    let ids = |tags: Vec<db_types::DBTag>| tags.iter().map(|t| t.id).collect::<Vec<i64>>();
+    assert_eq!(ids(mia.get_tag_children(caffeine_id).await.unwrap()), vec![coffee_id, tea_id]);
+    assert_eq!(ids(mia.get_tags_by_category(substances_id).await.unwrap()), vec![caffeine_id]);
+    assert_eq!(mia.get_tag_hierarchy(espresso_id).await.unwrap().parent_id, Some(coffee_id));
+    assert_eq!(mia.get_tag_hierarchy(noise_id).await.unwrap(), db_types::DBTagHierarchy { tag_id: noise_id, ..Default::default() });
+
+    // the sleeps of the descendants of a tag roll up to the tag, a sleep with several of them is counted once
+    let first_night = night("2024-03-01");
+    let nights = [vec![coffee_id], vec![espresso_id, coffee_id], vec![tea_id], vec![noise_id]];
+    for (day, tag_ids) in nights.into_iter().enumerate() {
+        let sleep_id = mia.insert_sleep(first_night + chrono::Duration::days(day as i64), 7.0, 3).await.unwrap();
+        mia.add_tags_to_sleep(sleep_id, tag_ids).await.unwrap();
+    }
+    assert_eq!(mia.count_sleeps_by_tag(caffeine_id, false).await.unwrap(), 0);
+    assert_eq!(mia.count_sleeps_by_tag(caffeine_id, true).await.unwrap(), 3);
+    assert_eq!(mia.count_sleeps_by_tag(coffee_id, true).await.unwrap(), 2);
+    assert_eq!(mia.count_sleeps_by_tag(noise_id, true).await.unwrap(), 1);
+
+    let last_night = first_night + chrono::Duration::days(3);
+    let impact = mia.get_tag_impact(first_night, last_night, Some(vec![caffeine_id, coffee_id]), false).await.unwrap();
+    assert_eq!(impact.iter().map(|i| (i.count_with, i.count_without)).collect::<Vec<(i64, i64)>>(), vec![(0, 4), (2, 2)]);
+    let impact = mia.get_tag_impact(first_night, last_night, Some(vec![caffeine_id, coffee_id]), true).await.unwrap();
+    assert_eq!(impact.iter().map(|i| (i.count_with, i.count_without)).collect::<Vec<(i64, i64)>>(), vec![(3, 1), (2, 2)]);
+
+    let backup = dbm.export_backup().await.unwrap();
+    assert!(backup.tag_categories.iter().any(|c| c.id == substances_id));
+    assert!(backup.tag_hierarchy.contains(&super::db_backup::BackupTagHierarchy { tag_id: espresso_id, parent_id: Some(coffee_id), category_id: None }));
+
+    // the children of a merged tag move to the target, and a target below the source takes its place
+    mia.merge_tags(&[coffee_id], caffeine_id).await.unwrap();
+    assert_eq!(ids(mia.get_tag_children(caffeine_id).await.unwrap()), vec![espresso_id, tea_id]);
+    mia.merge_tags(&[caffeine_id], espresso_id).await.unwrap();
+    assert_eq!(mia.get_tag_hierarchy(espresso_id).await.unwrap().parent_id, None);
+    assert_eq!(ids(mia.get_tag_children(espresso_id).await.unwrap()), vec![tea_id]);
+
+    mia.update_tag_category_name(substances_id, "Drinks").await.unwrap();
+    mia.set_tag_category(espresso_id, Some(substances_id)).await.unwrap();
+    mia.delete_tag_category(substances_id).await.unwrap();
+    assert_eq!(mia.get_tag_hierarchy(espresso_id).await.unwrap().category_id, None);
+    assert!(matches!(mia.get_tag_category(substances_id).await, Err(DBError::NotFound { .. })));
+
+    let schema = crate::build_schema(dbm.clone()).finish();
+    let add = r#"mutation { addTagCategory(tagCategoryInput: { name: "Environment" }) { id name } }"#;
+    let response = schema.execute(crate::scope_request(add.into(), mia.clone())).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let category_id = response.data.into_json().unwrap()["addTagCategory"]["id"].as_i64().unwrap();
+
+    let set = format!("mutation {{ setTagCategory(tagId: {}, categoryId: {}) {{ id }} setTagParent(tagId: {}, parentId: {}) {{ id }} }}",
+        noise_id, category_id, noise_id, tea_id);
+    let response = schema.execute(crate::scope_request(set.into(), mia.clone())).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let query = format!("{{ tag(id: {}) {{ category {{ name tags {{ name }} }} parent {{ name }} children {{ name }} sleepCount(rollUp: true) }} }}", tea_id);
+    let response = schema.execute(crate::scope_request(query.into(), mia.clone())).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data.into_json().unwrap()["tag"], serde_json::json!({
+        "category": null,
+        "parent": { "name": "espresso" },
+        "children": [{ "name": "noise" }],
+        "sleepCount": 2,
+    }));
+
+    let response = schema.execute(crate::scope_request("{ tagCategories { name tags { name } } }".into(), mia.clone())).await;
+    assert_eq!(response.data.into_json().unwrap()["tagCategories"], serde_json::json!([{ "name": "Environment", "tags": [{ "name": "noise" }] }]));
+
+    // the hierarchy of every tag in the listing is loaded together, so listing more tags must not send more queries:
+    // the page, its total count, the hierarchies, the parents, the categories, the children and the tags of the categories
+    for i in 0..10 {
+        let tag_id = mia.insert_tag(&format!("sound {}", i), 0).await.unwrap();
+        mia.set_tag_parent(tag_id, Some(noise_id)).await.unwrap();
+        mia.set_tag_category(tag_id, Some(category_id)).await.unwrap();
+    }
+    let mut counts = Vec::new();
+    for size in [1, 5, 10] {
+        let query = format!("{{ tags(last: {}) {{ edges {{ node {{ parent {{ name }} children {{ name }} category {{ tags {{ name }} }} }} }} }} }}", size);
+
+        let before = dbm.query_count();
+        let response = schema.execute(crate::scope_request(query.into(), mia.clone())).await;
+        counts.push(dbm.query_count() - before);
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = response.data.into_json().unwrap();
+        let edges = data["tags"]["edges"].as_array().unwrap();
+        assert_eq!(edges.len(), size);
+        assert!(edges.iter().all(|e| e["node"]["parent"]["name"] == "noise" && e["node"]["category"]["tags"].as_array().unwrap().len() == 11));
+    }
+
+    assert_eq!(counts, vec![7; 3]);
+}
+
+async fn start_subscription<S: Stream<Item = async_graphql::Response> + Unpin>(stream: &mut S) {
+    let next = tokio::time::timeout(std::time::Duration::from_millis(100), stream.next()).await;
+    assert!(next.is_err(), "unexpected change {:?}", next);
+}
+
+/// Returns the next change received by a subscription
+async fn next_change<S: Stream<Item = async_graphql::Response> + Unpin>(stream: &mut S, field: &str) -> serde_json::Value {
+    let response = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next()).await.unwrap().unwrap();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    response.data.into_json().unwrap()[field].clone()
+}
@@ -1,9 +1,36 @@
+mod db_api_token;
 mod db_comment;
+mod db_dream;
+mod db_dream_tags;
+mod db_hypnogram;
 mod db_sleep;
+mod db_sleep_session;
+mod db_sleep_stages;
 mod db_sleep_tags;
 mod db_tag;
+mod db_tag_alias;
+mod db_tag_category;
+mod db_tag_hierarchy;
+mod db_user;
 
+pub use db_api_token::DBApiToken;
 pub use db_comment::DBComment;
-pub use db_sleep::DBSleep;
+pub use db_dream::{DBDream, DreamDetails, DreamFilter, DreamUpdate};
+pub use db_dream_tags::DBDreamTags;
+pub use db_hypnogram::{DBHypnogramInterval, HypnogramInterval};
+pub use db_sleep::{DBSleep, MergePolicy, SleepUpsert, UpsertedSleep};
+pub use db_sleep_session::{DBSleepSession, SessionKind, SleepTimes};
+pub use db_sleep_stages::{DBSleepStages, SleepStage, SleepStages};
 pub use db_sleep_tags::DBSleepTags;
-pub use db_tag::DBTag;
\ No newline at end of file
+pub use db_tag::DBTag;
+pub use db_tag_alias::DBTagAlias;
+pub use db_tag_category::DBTagCategory;
+pub use db_tag_hierarchy::DBTagHierarchy;
+pub use db_user::DBUser;
+
+/// Formats ids as a json array, to bind a set of ids to a query as `IN (SELECT value FROM json_each(?))`
+/// since sqlx v0.6 can't bind a list of values directly
+fn json_ids(ids: &[i64]) -> String {
+    let ids = ids.iter().map(i64::to_string).collect::<Vec<String>>();
+    format!("[{}]", ids.join(","))
+}
@@ -0,0 +1,88 @@
+use sqlx::SqliteConnection;
+
+/// Representation of the api_token table, the token itself is never stored, only its hash
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBApiToken {
+    /// Primary key
+    pub id: i64,
+
+    /// id of the user the token authenticates as
+    pub user_id: i64,
+
+    /// name of the token to tell tokens apart, ex: "phone"
+    pub name: String,
+
+    /// true if the token can only be used to query data
+    pub read_only: bool,
+
+    /// true if the token can also manage the server, ex: add users
+    pub admin: bool,
+}
+
+impl DBApiToken {
+    pub async fn select_all(conn: &mut SqliteConnection, user_id: i64) -> Result<Vec<DBApiToken>, sqlx::Error>  {
+        sqlx::query_as!(DBApiToken,
+            r#"
+            SELECT id, user_id, name, read_only as "read_only: bool", admin as "admin: bool"
+            FROM api_token
+            WHERE user_id = ?1
+            ORDER BY id
+                "#,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_by_hash(conn: &mut SqliteConnection, token_hash: &str) -> Result<Option<DBApiToken>, sqlx::Error>  {
+        sqlx::query_as!(DBApiToken,
+            r#"
+            SELECT id, user_id, name, read_only as "read_only: bool", admin as "admin: bool"
+            FROM api_token
+            WHERE token_hash = ?1
+                "#,
+                token_hash
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    pub async fn insert(conn: &mut SqliteConnection, user_id: i64, name: &str, token_hash: &str, read_only: bool, admin: bool) -> Result<i64, sqlx::Error>  {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO api_token ( user_id, name, token_hash, read_only, admin )
+            VALUES ( ?1, ?2, ?3, ?4, ?5 )
+                "#,
+            user_id,
+            name,
+            token_hash,
+            read_only,
+            admin,
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.last_insert_rowid()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM api_token
+            WHERE id = ?1 AND user_id = ?2
+                "#,
+                id,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+}
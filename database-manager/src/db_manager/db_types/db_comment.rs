@@ -1,4 +1,5 @@
-use sqlx::{SqlitePool};
+use sqlx::SqliteConnection;
+use super::json_ids;
 
 /// Representation of the comment table
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -14,18 +15,17 @@ pub struct DBComment {
 }
 
 impl DBComment {
-    pub async fn insert(pool: &SqlitePool, sleep_id: i64, comment: &str) -> Result<i64, sqlx::Error> {
-        let mut conn = pool.acquire().await?;
-
+    pub async fn insert(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64, comment: &str) -> Result<i64, sqlx::Error> {
         let result = sqlx::query!(
             r#"
-            INSERT INTO comment ( sleep_id, comment )
-            VALUES ( ?1, ?2 )
+            INSERT INTO comment ( user_id, sleep_id, comment )
+            VALUES ( ?1, ?2, ?3 )
                 "#,
+            user_id,
             sleep_id,
             comment,
         )
-        .execute(&mut conn)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -34,45 +34,66 @@ impl DBComment {
         }
     }
 
-    pub async fn select_by_id(pool: &SqlitePool, id: i64) -> Result<DBComment, sqlx::Error> {
+    pub async fn select_by_id(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<DBComment, sqlx::Error> {
         sqlx::query_as!(DBComment,
             r#"
             SELECT id, sleep_id, comment
             FROM comment
-            WHERE id = ?1
+            WHERE id = ?1 AND user_id = ?2
             ORDER BY id
                 "#,
-                id
+                id,
+                user_id
         )
-        .fetch_one(pool)
+        .fetch_one(&mut *conn)
         .await
     }
 
-    pub async fn select_by_sleep_id(pool: &SqlitePool, sleep_id: i64) -> Result<Vec<DBComment>, sqlx::Error> {
+    pub async fn select_by_sleep_id(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64) -> Result<Vec<DBComment>, sqlx::Error> {
         sqlx::query_as!(DBComment,
             r#"
             SELECT id, sleep_id, comment
             FROM comment
-            WHERE sleep_id = ?1
+            WHERE sleep_id = ?1 AND user_id = ?2
             ORDER BY id
                 "#,
-                sleep_id
+                sleep_id,
+                user_id
         )
-        .fetch_all(pool)
+        .fetch_all(&mut *conn)
         .await
     }
 
-    pub async fn update_comment(pool: &SqlitePool, id: i64, comment: &str) -> Result<bool, sqlx::Error> {
+    /// Selects the comments of each of the sleeps in a single query, ordered by sleep id and then comment id
+    pub async fn select_by_sleep_ids(conn: &mut SqliteConnection, user_id: i64, sleep_ids: &[i64]) -> Result<Vec<DBComment>, sqlx::Error> {
+        let sleep_ids = json_ids(sleep_ids);
+
+        sqlx::query_as!(DBComment,
+            r#"
+            SELECT id, sleep_id, comment
+            FROM comment
+            WHERE sleep_id IN (SELECT value FROM json_each(?1)) AND user_id = ?2
+            ORDER BY sleep_id, id
+                "#,
+                sleep_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn update_comment(conn: &mut SqliteConnection, user_id: i64, id: i64, comment: &str) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             UPDATE comment
             SET comment = ?1
-            WHERE id = ?2
+            WHERE id = ?2 AND user_id = ?3
                 "#,
                 comment,
-                id
+                id,
+                user_id
             )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -81,15 +102,16 @@ impl DBComment {
         }
     }
 
-    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             DELETE FROM comment
-            WHERE id = ?1
+            WHERE id = ?1 AND user_id = ?2
                 "#,
                 id,
+                user_id
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -97,4 +119,71 @@ impl DBComment {
             Err(e) => Err(e)
         }
     }
+
+    /// Selects a page of comments ordered by id, optionally only the comments of the given sleep.
+    /// `after` and `before` are exclusive bounds on the id. When paging backward the comments
+    /// are selected from the end of the listing and returned in reverse order.
+    pub async fn select_page(
+        conn: &mut SqliteConnection,
+        user_id: i64,
+        sleep_id: Option<i64>,
+        after: Option<i64>,
+        before: Option<i64>,
+        limit: i64,
+        backward: bool)
+        -> Result<Vec<DBComment>, sqlx::Error> {
+        if backward {
+            sqlx::query_as!(DBComment,
+                r#"
+                SELECT id, sleep_id, comment
+                FROM comment
+                WHERE (?1 IS NULL OR sleep_id = ?1) AND (?2 IS NULL OR id > ?2) AND (?3 IS NULL OR id < ?3)
+                AND user_id = ?5
+                ORDER BY id DESC
+                LIMIT ?4
+                    "#,
+                    sleep_id,
+                    after,
+                    before,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+        else {
+            sqlx::query_as!(DBComment,
+                r#"
+                SELECT id, sleep_id, comment
+                FROM comment
+                WHERE (?1 IS NULL OR sleep_id = ?1) AND (?2 IS NULL OR id > ?2) AND (?3 IS NULL OR id < ?3)
+                AND user_id = ?5
+                ORDER BY id
+                LIMIT ?4
+                    "#,
+                    sleep_id,
+                    after,
+                    before,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+    }
+
+    /// Counts the comments, optionally only the comments of the given sleep
+    pub async fn count(conn: &mut SqliteConnection, user_id: i64, sleep_id: Option<i64>) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!: i64"
+            FROM comment
+            WHERE (?1 IS NULL OR sleep_id = ?1) AND user_id = ?2
+                "#,
+                sleep_id,
+                user_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
 }
\ No newline at end of file
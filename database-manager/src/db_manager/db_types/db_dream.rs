@@ -0,0 +1,417 @@
+use chrono::NaiveDate;
+use sqlx::SqliteConnection;
+use super::json_ids;
+
+/// Representation of the dream table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBDream {
+    /// Primary key
+    pub id: i64,
+
+    /// Fk to the sleep the dream happened during
+    pub sleep_id: i64,
+
+    /// Short title of the dream
+    pub title: String,
+
+    /// Full description of the dream
+    pub narrative: String,
+
+    /// true if the dreamer knew they were dreaming
+    pub lucid: bool,
+
+    /// How vivid the dream was, from 0 to 10
+    pub vividness: Option<i64>,
+
+    /// Emotional tone of the dream, from -5 (very negative) to 5 (very positive)
+    pub emotional_tone: Option<i64>,
+
+    /// true if the dream has happened before
+    pub recurring: bool,
+}
+
+/// Filters of a listing of dreams
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DreamFilter {
+    /// Only list the dreams of the sleeps with a night between the start and end dates, inclusive
+    pub range: Option<(NaiveDate, NaiveDate)>,
+
+    /// Only list the dreams with the tag
+    pub tag_id: Option<i64>,
+}
+
+/// A dream along with the night of its sleep, listings of dreams are ordered by night and then id
+struct NightDreamRow {
+    night: NaiveDate,
+    id: i64,
+    sleep_id: i64,
+    title: String,
+    narrative: String,
+    lucid: bool,
+    vividness: Option<i64>,
+    emotional_tone: Option<i64>,
+    recurring: bool,
+}
+
+impl NightDreamRow {
+    fn into_pair(self) -> (NaiveDate, DBDream) {
+        let dream = DBDream {
+            id: self.id,
+            sleep_id: self.sleep_id,
+            title: self.title,
+            narrative: self.narrative,
+            lucid: self.lucid,
+            vividness: self.vividness,
+            emotional_tone: self.emotional_tone,
+            recurring: self.recurring,
+        };
+        (self.night, dream)
+    }
+}
+
+/// The user provided content of a dream
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DreamDetails {
+    /// Short title of the dream
+    pub title: String,
+
+    /// Full description of the dream
+    pub narrative: String,
+
+    /// true if the dreamer knew they were dreaming
+    pub lucid: bool,
+
+    /// How vivid the dream was, from 0 to 10
+    pub vividness: Option<i64>,
+
+    /// Emotional tone of the dream, from -5 (very negative) to 5 (very positive)
+    pub emotional_tone: Option<i64>,
+
+    /// true if the dream has happened before
+    pub recurring: bool,
+}
+
+/// Changes to the content of a dream, fields that are None keep their current value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DreamUpdate {
+    /// new title of the dream
+    pub title: Option<String>,
+
+    /// new description of the dream
+    pub narrative: Option<String>,
+
+    /// new lucid flag of the dream
+    pub lucid: Option<bool>,
+
+    /// new vividness score of the dream
+    pub vividness: Option<i64>,
+
+    /// new emotional tone score of the dream
+    pub emotional_tone: Option<i64>,
+
+    /// new recurring flag of the dream
+    pub recurring: Option<bool>,
+}
+
+impl DreamDetails {
+    /// Returns an error message if the title is empty or a score is out of range
+    pub fn validate(&self) -> Result<(), String> {
+        if self.title.trim().is_empty() {
+            return Err(String::from("dream title must not be empty"));
+        }
+
+        if let Some(vividness) = self.vividness.filter(|v| !(0..=10).contains(v)) {
+            return Err(format!("vividness {} must be between 0 and 10", vividness));
+        }
+
+        if let Some(tone) = self.emotional_tone.filter(|t| !(-5..=5).contains(t)) {
+            return Err(format!("emotional tone {} must be between -5 and 5", tone));
+        }
+
+        Ok(())
+    }
+}
+
+impl DreamUpdate {
+    /// Returns true if none of the fields are set
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.narrative.is_none() && self.lucid.is_none()
+            && self.vividness.is_none() && self.emotional_tone.is_none() && self.recurring.is_none()
+    }
+
+    /// Returns the details with any fields set in the update replacing the current values
+    pub fn apply(&self, details: &DreamDetails) -> DreamDetails {
+        DreamDetails {
+            title: self.title.clone().unwrap_or_else(|| details.title.clone()),
+            narrative: self.narrative.clone().unwrap_or_else(|| details.narrative.clone()),
+            lucid: self.lucid.unwrap_or(details.lucid),
+            vividness: self.vividness.or(details.vividness),
+            emotional_tone: self.emotional_tone.or(details.emotional_tone),
+            recurring: self.recurring.unwrap_or(details.recurring),
+        }
+    }
+}
+
+impl DBDream {
+    /// Returns the user provided content of the dream
+    pub fn details(&self) -> DreamDetails {
+        DreamDetails {
+            title: self.title.clone(),
+            narrative: self.narrative.clone(),
+            lucid: self.lucid,
+            vividness: self.vividness,
+            emotional_tone: self.emotional_tone,
+            recurring: self.recurring,
+        }
+    }
+
+    pub async fn insert(conn: &mut SqliteConnection, sleep_id: i64, details: &DreamDetails) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO dream ( sleep_id, title, narrative, lucid, vividness, emotional_tone, recurring )
+            VALUES ( ?1, ?2, ?3, ?4, ?5, ?6, ?7 )
+                "#,
+            sleep_id,
+            details.title,
+            details.narrative,
+            details.lucid,
+            details.vividness,
+            details.emotional_tone,
+            details.recurring,
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.last_insert_rowid()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn select_by_id(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<DBDream, sqlx::Error> {
+        sqlx::query_as!(DBDream,
+            r#"
+            SELECT id, sleep_id, title, narrative, lucid as "lucid: bool", vividness, emotional_tone, recurring as "recurring: bool"
+            FROM dream
+            WHERE id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY id
+                "#,
+                id,
+                user_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
+
+    pub async fn select_by_sleep_id(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64) -> Result<Vec<DBDream>, sqlx::Error> {
+        sqlx::query_as!(DBDream,
+            r#"
+            SELECT id, sleep_id, title, narrative, lucid as "lucid: bool", vividness, emotional_tone, recurring as "recurring: bool"
+            FROM dream
+            WHERE sleep_id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY id
+                "#,
+                sleep_id,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects the dreams of each of the sleeps in a single query, ordered by sleep id and then dream id
+    pub async fn select_by_sleep_ids(conn: &mut SqliteConnection, user_id: i64, sleep_ids: &[i64]) -> Result<Vec<DBDream>, sqlx::Error> {
+        let sleep_ids = json_ids(sleep_ids);
+
+        sqlx::query_as!(DBDream,
+            r#"
+            SELECT id, sleep_id, title, narrative, lucid as "lucid: bool", vividness, emotional_tone, recurring as "recurring: bool"
+            FROM dream
+            WHERE sleep_id IN (SELECT value FROM json_each(?1)) AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY sleep_id, id
+                "#,
+                sleep_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects the dreams of the sleeps with a night between the start and end dates, inclusive
+    pub async fn select_in_range(conn: &mut SqliteConnection, user_id: i64, start: NaiveDate, end: NaiveDate) -> Result<Vec<DBDream>, sqlx::Error> {
+        sqlx::query_as!(DBDream,
+            r#"
+            SELECT d.id, d.sleep_id, d.title, d.narrative, d.lucid as "lucid: bool", d.vividness, d.emotional_tone,
+                d.recurring as "recurring: bool"
+            FROM dream d
+            INNER JOIN sleep s ON s.id = d.sleep_id
+            WHERE s.night BETWEEN ?1 AND ?2 AND s.user_id = ?3
+            ORDER BY s.night, d.id
+                "#,
+                start,
+                end,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects the dreams that are associated to the given tag
+    pub async fn select_by_tag_id(conn: &mut SqliteConnection, user_id: i64, tag_id: i64) -> Result<Vec<DBDream>, sqlx::Error> {
+        sqlx::query_as!(DBDream,
+            r#"
+            SELECT d.id, d.sleep_id, d.title, d.narrative, d.lucid as "lucid: bool", d.vividness, d.emotional_tone,
+                d.recurring as "recurring: bool"
+            FROM dream d
+            INNER JOIN sleep s ON s.id = d.sleep_id
+            WHERE d.id IN (SELECT dream_id FROM dream_tags WHERE tag_id = ?1) AND s.user_id = ?2
+            ORDER BY s.night, d.id
+                "#,
+                tag_id,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects a page of the filtered dreams paired with the night of their sleep, ordered by night and id,
+    /// or in reverse when paging backward
+    pub async fn select_page(
+        conn: &mut SqliteConnection,
+        user_id: i64,
+        after: Option<(NaiveDate, i64)>,
+        before: Option<(NaiveDate, i64)>,
+        filter: &DreamFilter,
+        limit: i64,
+        backward: bool)
+        -> Result<Vec<(NaiveDate, DBDream)>, sqlx::Error> {
+        let (after_night, after_id) = (after.map(|a| a.0), after.map(|a| a.1));
+        let (before_night, before_id) = (before.map(|b| b.0), before.map(|b| b.1));
+        let (start, end) = (filter.range.map(|r| r.0), filter.range.map(|r| r.1));
+        let tag_id = filter.tag_id;
+
+        let rows = if backward {
+            sqlx::query_as!(NightDreamRow,
+                r#"
+                SELECT s.night as "night: NaiveDate", d.id, d.sleep_id, d.title, d.narrative, d.lucid as "lucid: bool",
+                    d.vividness, d.emotional_tone, d.recurring as "recurring: bool"
+                FROM dream d
+                INNER JOIN sleep s ON s.id = d.sleep_id
+                WHERE (?1 IS NULL OR (s.night, d.id) > (?1, ?2))
+                AND (?3 IS NULL OR (s.night, d.id) < (?3, ?4))
+                AND (?5 IS NULL OR s.night BETWEEN ?5 AND ?6)
+                AND (?7 IS NULL OR d.id IN (SELECT dream_id FROM dream_tags WHERE tag_id = ?7))
+                AND s.user_id = ?9
+                ORDER BY s.night DESC, d.id DESC
+                LIMIT ?8
+                    "#,
+                    after_night,
+                    after_id,
+                    before_night,
+                    before_id,
+                    start,
+                    end,
+                    tag_id,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await?
+        }
+        else {
+            sqlx::query_as!(NightDreamRow,
+                r#"
+                SELECT s.night as "night: NaiveDate", d.id, d.sleep_id, d.title, d.narrative, d.lucid as "lucid: bool",
+                    d.vividness, d.emotional_tone, d.recurring as "recurring: bool"
+                FROM dream d
+                INNER JOIN sleep s ON s.id = d.sleep_id
+                WHERE (?1 IS NULL OR (s.night, d.id) > (?1, ?2))
+                AND (?3 IS NULL OR (s.night, d.id) < (?3, ?4))
+                AND (?5 IS NULL OR s.night BETWEEN ?5 AND ?6)
+                AND (?7 IS NULL OR d.id IN (SELECT dream_id FROM dream_tags WHERE tag_id = ?7))
+                AND s.user_id = ?9
+                ORDER BY s.night, d.id
+                LIMIT ?8
+                    "#,
+                    after_night,
+                    after_id,
+                    before_night,
+                    before_id,
+                    start,
+                    end,
+                    tag_id,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await?
+        };
+
+        Ok(rows.into_iter().map(NightDreamRow::into_pair).collect())
+    }
+
+    /// Counts the filtered dreams
+    pub async fn count(conn: &mut SqliteConnection, user_id: i64, filter: &DreamFilter) -> Result<i64, sqlx::Error> {
+        let (start, end) = (filter.range.map(|r| r.0), filter.range.map(|r| r.1));
+        let tag_id = filter.tag_id;
+
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!: i64"
+            FROM dream d
+            INNER JOIN sleep s ON s.id = d.sleep_id
+            WHERE (?1 IS NULL OR s.night BETWEEN ?1 AND ?2)
+            AND (?3 IS NULL OR d.id IN (SELECT dream_id FROM dream_tags WHERE tag_id = ?3))
+            AND s.user_id = ?4
+                "#,
+                start,
+                end,
+                tag_id,
+                user_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
+
+    pub async fn update(conn: &mut SqliteConnection, user_id: i64, id: i64, details: &DreamDetails) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE dream
+            SET title = ?1, narrative = ?2, lucid = ?3, vividness = ?4, emotional_tone = ?5, recurring = ?6
+            WHERE id = ?7 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?8)
+                "#,
+                details.title,
+                details.narrative,
+                details.lucid,
+                details.vividness,
+                details.emotional_tone,
+                details.recurring,
+                id,
+                user_id
+            )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM dream
+            WHERE id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+                "#,
+                id,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+}
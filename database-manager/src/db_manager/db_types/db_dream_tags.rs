@@ -0,0 +1,87 @@
+use sqlx::SqliteConnection;
+
+/// Representation of the dream_tags table. Maps the many to many relationships between dreams and tags
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBDreamTags {
+    /// Primary key
+    pub id: i64,
+
+    /// Fk to the dream table
+    pub dream_id: i64,
+
+    /// Fk to the tag table
+    pub tag_id: i64,
+}
+
+impl DBDreamTags {
+    /// Adds the tag to the dream, returns false if the dream already has the tag
+    pub async fn insert(conn: &mut SqliteConnection, dream_id: i64, tag_id: i64) -> Result<bool, sqlx::Error>  {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO dream_tags ( dream_id, tag_id )
+            VALUES ( ?1, ?2 )
+            ON CONFLICT ( dream_id, tag_id ) DO NOTHING
+                "#,
+            dream_id,
+            tag_id,
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds a tag to every dream of another tag, skipping the dreams that already have the tag
+    pub async fn reassign(conn: &mut SqliteConnection, from_tag_id: i64, to_tag_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO dream_tags ( dream_id, tag_id )
+            SELECT dream_id, ?1 FROM dream_tags WHERE tag_id = ?2
+            ON CONFLICT ( dream_id, tag_id ) DO NOTHING
+                "#,
+            to_tag_id,
+            from_tag_id,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, dream_id: i64, tag_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM dream_tags
+            WHERE dream_id = ?1 AND tag_id = ?2 AND tag_id IN (SELECT id FROM tag WHERE user_id = ?3)
+                "#,
+                dream_id,
+                tag_id,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub async fn select_by_dream_id(conn: &mut SqliteConnection, user_id: i64, dream_id: i64) -> Result<Vec<DBDreamTags>, sqlx::Error>  {
+        sqlx::query_as!(DBDreamTags,
+            r#"
+            SELECT id, dream_id, tag_id
+            FROM dream_tags
+            WHERE dream_id = ?1 AND tag_id IN (SELECT id FROM tag WHERE user_id = ?2)
+            ORDER BY id
+                "#,
+                dream_id,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+}
@@ -0,0 +1,127 @@
+use chrono::NaiveDateTime;
+use sqlx::{Connection, SqliteConnection};
+use super::{json_ids, SleepStage};
+
+/// A period of a night spent in one stage of sleep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HypnogramInterval {
+    pub stage: SleepStage,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl HypnogramInterval {
+    /// Returns an error message if the intervals are not a hypnogram.
+    /// Every interval must end after it starts, and each interval must start at or after the end of the one before it.
+    pub fn validate_all(intervals: &[HypnogramInterval]) -> Result<(), String> {
+        for (index, interval) in intervals.iter().enumerate() {
+            if interval.end <= interval.start {
+                return Err(format!("the {} interval at {} must end after it starts", interval.stage.as_str(), interval.start));
+            }
+            if let Some(previous) = index.checked_sub(1).map(|i| &intervals[i]).filter(|p| interval.start < p.end) {
+                return Err(format!("the {} interval at {} starts before the {} interval at {} ends",
+                    interval.stage.as_str(), interval.start, previous.stage.as_str(), previous.start));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Representation of the hypnogram table, the ordered stages of sleep of a night
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBHypnogramInterval {
+    /// Primary key
+    pub id: i64,
+
+    /// Fk to the sleep (night) the interval belongs to
+    pub sleep_id: i64,
+
+    /// stage of sleep, one of deep, light, rem or awake
+    pub stage: String,
+
+    /// time the stage started
+    pub start_time: NaiveDateTime,
+
+    /// time the stage ended
+    pub end_time: NaiveDateTime,
+}
+
+impl DBHypnogramInterval {
+    /// Returns the stage of the interval
+    pub fn stage(&self) -> SleepStage {
+        SleepStage::from_db(self.stage.as_str())
+    }
+
+    /// Returns the stage, start and end of the interval
+    pub fn interval(&self) -> HypnogramInterval {
+        HypnogramInterval { stage: self.stage(), start: self.start_time, end: self.end_time }
+    }
+}
+
+impl DBHypnogramInterval {
+    pub async fn select_by_sleep_id(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64) -> Result<Vec<DBHypnogramInterval>, sqlx::Error>  {
+        sqlx::query_as!(DBHypnogramInterval,
+            r#"
+            SELECT id, sleep_id, stage,
+                start_time as "start_time: NaiveDateTime",
+                end_time as "end_time: NaiveDateTime"
+            FROM hypnogram
+            WHERE sleep_id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY start_time, id
+                "#,
+                sleep_id,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects the intervals of the hypnograms of each of the sleeps in a single query,
+    /// ordered by sleep id and then the time each stage started
+    pub async fn select_by_sleep_ids(conn: &mut SqliteConnection, user_id: i64, sleep_ids: &[i64]) -> Result<Vec<DBHypnogramInterval>, sqlx::Error>  {
+        let sleep_ids = json_ids(sleep_ids);
+
+        sqlx::query_as!(DBHypnogramInterval,
+            r#"
+            SELECT id, sleep_id, stage,
+                start_time as "start_time: NaiveDateTime",
+                end_time as "end_time: NaiveDateTime"
+            FROM hypnogram
+            WHERE sleep_id IN (SELECT value FROM json_each(?1)) AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY sleep_id, start_time, id
+                "#,
+                sleep_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Replaces the hypnogram of a sleep with the intervals inside of a transaction, or a savepoint when the connection
+    /// is already in one. An empty list deletes the hypnogram
+    pub async fn replace(conn: &mut SqliteConnection, sleep_id: i64, intervals: &[HypnogramInterval]) -> Result<(), sqlx::Error>  {
+        let mut tx = conn.begin().await?;
+
+        sqlx::query!("DELETE FROM hypnogram WHERE sleep_id = ?1", sleep_id)
+            .execute(&mut tx)
+            .await?;
+
+        for interval in intervals {
+            let stage = interval.stage.as_str();
+            sqlx::query!(
+                r#"
+                INSERT INTO hypnogram ( sleep_id, stage, start_time, end_time )
+                VALUES ( ?1, ?2, ?3, ?4 )
+                    "#,
+                sleep_id,
+                stage,
+                interval.start,
+                interval.end,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+}
@@ -1,4 +1,6 @@
-use sqlx::{SqlitePool};
+use chrono::NaiveDate;
+use sqlx::SqliteConnection;
+use super::{json_ids, SleepTimes};
 
 /// Representation of the sleep table
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -6,44 +8,138 @@ pub struct DBSleep {
     /// Primary key
     pub id: i64,
 
-    /// date in yyyy-mm-dd format, must be unique
-    pub night: String,
+    /// date of the night, stored in yyyy-mm-dd format, must be unique for each user
+    pub night: NaiveDate,
 
-    /// amount of sleep
+    /// total amount of sleep from all of the sessions of the night
     pub amount: f64,
 
     /// quality of sleep, scale is open ended
     pub quality: i64,
 }
 
+/// How the tags or comments of a night that already has a sleep are merged with those of a [SleepUpsert]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// The sleep ends up with exactly the tags or comments of the upsert
+    Replace,
+
+    /// Add the tags or comments of the upsert that the sleep doesn't have yet, so repeating an upsert adds nothing
+    #[default]
+    Append,
+
+    /// Keep the tags or comments of the sleep, ignoring those of the upsert
+    Keep,
+}
+
+/// A sleep to add, or to update when its night already has a sleep
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SleepUpsert {
+    /// Date of the night, derived from the times when None
+    pub night: Option<NaiveDate>,
+
+    /// Amount of sleep of the main session, derived from the times when None
+    pub amount: Option<f64>,
+
+    /// Quality of sleep
+    pub quality: i64,
+
+    /// Bed, sleep onset and wake times of the main session, times that are None keep their current value
+    pub times: SleepTimes,
+
+    /// ids of the tags of the sleep
+    pub tag_ids: Vec<i64>,
+
+    /// Comments of the sleep
+    pub comments: Vec<String>,
+
+    /// How the tags are merged when the night already has a sleep
+    pub tag_policy: MergePolicy,
+
+    /// How the comments are merged when the night already has a sleep, comments are matched by their text
+    pub comment_policy: MergePolicy,
+}
+
+/// What an upsert of a sleep changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpsertedSleep {
+    /// id of the sleep that was added or updated
+    pub sleep_id: i64,
+
+    /// true if the sleep was added, false if the night already had a sleep
+    pub created: bool,
+
+    /// ids of the comments that were added to the sleep
+    pub created_comment_ids: Vec<i64>,
+
+    /// ids of the comments that were removed from the sleep
+    pub deleted_comment_ids: Vec<i64>,
+}
+
 impl DBSleep {
-    pub async fn select_all(pool: &SqlitePool) -> Result<Vec<DBSleep>, sqlx::Error>  {
+    pub async fn select_all(conn: &mut SqliteConnection, user_id: i64) -> Result<Vec<DBSleep>, sqlx::Error>  {
+        sqlx::query_as!(DBSleep,
+            r#"
+            SELECT id, night as "night: NaiveDate", amount, quality
+            FROM sleep
+            WHERE user_id = ?1
+            ORDER BY id
+                "#,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_one(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<DBSleep, sqlx::Error>  {
         sqlx::query_as!(DBSleep,
             r#"
-            SELECT id, night, amount, quality
+            SELECT id, night as "night: NaiveDate", amount, quality
             FROM sleep
+            WHERE id = ?1 AND user_id = ?2
             ORDER BY id
-                "#
+                "#,
+                id,
+                user_id
         )
-        .fetch_all(pool)
+        .fetch_one(&mut *conn)
         .await
     }
 
-    pub async fn select_one(pool: &SqlitePool, id: i64) -> Result<DBSleep, sqlx::Error>  {
+    /// Selects the sleeps with the given ids in a single query, ordered by id. Ids without a sleep are skipped
+    pub async fn select_by_ids(conn: &mut SqliteConnection, user_id: i64, ids: &[i64]) -> Result<Vec<DBSleep>, sqlx::Error>  {
+        let ids = json_ids(ids);
+
         sqlx::query_as!(DBSleep,
             r#"
-            SELECT id, night, amount, quality
+            SELECT id, night as "night: NaiveDate", amount, quality
             FROM sleep
-            WHERE id = ?1
+            WHERE id IN (SELECT value FROM json_each(?1)) AND user_id = ?2
             ORDER BY id
                 "#,
-                id
+                ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects the sleep of a night, None if the night has no sleep
+    pub async fn select_by_night(conn: &mut SqliteConnection, user_id: i64, night: NaiveDate) -> Result<Option<DBSleep>, sqlx::Error>  {
+        sqlx::query_as!(DBSleep,
+            r#"
+            SELECT id, night as "night: NaiveDate", amount, quality
+            FROM sleep
+            WHERE night = ?1 AND user_id = ?2
+                "#,
+                night,
+                user_id
         )
-        .fetch_one(pool)
+        .fetch_optional(&mut *conn)
         .await
     }
 
-    pub async fn select_by_month(pool: &SqlitePool, month: u8, year: u16) -> Result<Vec<DBSleep>, sqlx::Error>  {
+    pub async fn select_by_month(conn: &mut SqliteConnection, user_id: i64, month: u8, year: u16) -> Result<Vec<DBSleep>, sqlx::Error>  {
         // format month to match the expected yyyy-mm-dd format
         let  month = format!("{:02}", month);
         let year = year.to_string();
@@ -52,30 +148,46 @@ impl DBSleep {
 
         sqlx::query_as!(DBSleep,
             r#"
-            SELECT id, night, amount, quality
+            SELECT id, night as "night: NaiveDate", amount, quality
             FROM sleep
-            WHERE night LIKE ?1
+            WHERE night LIKE ?1 AND user_id = ?2
             ORDER BY id
                 "#,
-                date
+                date,
+                user_id
         )
-        .fetch_all(pool)
+        .fetch_all(&mut *conn)
         .await
     }
 
-    pub async fn insert(pool: &SqlitePool, night: &str, amount: f64, quality: i64) -> Result<i64, sqlx::Error>  {
-        let mut conn = pool.acquire().await?;
+    pub async fn select_in_range(conn: &mut SqliteConnection, user_id: i64, start: NaiveDate, end: NaiveDate) -> Result<Vec<DBSleep>, sqlx::Error>  {
+        sqlx::query_as!(DBSleep,
+            r#"
+            SELECT id, night as "night: NaiveDate", amount, quality
+            FROM sleep
+            WHERE night BETWEEN ?1 AND ?2 AND user_id = ?3
+            ORDER BY night
+                "#,
+                start,
+                end,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
 
+    pub async fn insert(conn: &mut SqliteConnection, user_id: i64, night: NaiveDate, amount: f64, quality: i64) -> Result<i64, sqlx::Error>  {
         let result = sqlx::query!(
             r#"
-            INSERT INTO sleep ( night, amount, quality )
-            VALUES ( ?1, ?2, ?3 )
+            INSERT INTO sleep ( user_id, night, amount, quality )
+            VALUES ( ?1, ?2, ?3, ?4 )
                 "#,
+            user_id,
             night,
             amount,
             quality,
         )
-        .execute(&mut conn)
+        .execute(&mut *conn)
         .await;
         
         match result {
@@ -84,17 +196,18 @@ impl DBSleep {
         }
     }
 
-    pub async fn update_amount(pool: &SqlitePool, id: i64, amount: f64) -> Result<bool, sqlx::Error> {
+    pub async fn update_quality(conn: &mut SqliteConnection, user_id: i64, id: i64, quality: i64) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             UPDATE sleep
-            SET amount = ?1
-            WHERE id = ?2
+            SET quality = ?1
+            WHERE id = ?2 AND user_id = ?3
                 "#,
-                amount,
-                id
-        )
-        .execute(pool)
+                quality,
+                id,
+                user_id
+            )
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -103,17 +216,56 @@ impl DBSleep {
         }
     }
 
-    pub async fn update_quality(pool: &SqlitePool, id: i64, quality: i64) -> Result<bool, sqlx::Error> {
+    /// Sets the amount of the sleep to the total amount of all of its sessions
+    pub async fn update_total_amount(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             UPDATE sleep
-            SET quality = ?1
-            WHERE id = ?2
+            SET amount = (SELECT COALESCE(SUM(amount), 0.0) FROM sleep_session WHERE sleep_id = ?1)
+            WHERE id = ?1 AND user_id = ?2
                 "#,
-                quality,
-                id
+                id,
+                user_id
+            )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Selects the id, night and source of every sleep of the user, the source is None for sleeps that were not imported
+    pub async fn select_sources(conn: &mut SqliteConnection, user_id: i64) -> Result<Vec<(i64, NaiveDate, Option<String>)>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, night as "night: NaiveDate", source
+            FROM sleep
+            WHERE user_id = ?1
+            ORDER BY id
+                "#,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.id, r.night, r.source)).collect())
+    }
+
+    /// Sets the source that imported the sleep, ex: "apple_health"
+    pub async fn update_source(conn: &mut SqliteConnection, user_id: i64, id: i64, source: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sleep
+            SET source = ?1
+            WHERE id = ?2 AND user_id = ?3
+                "#,
+                source,
+                id,
+                user_id
             )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -122,15 +274,16 @@ impl DBSleep {
         }
     }
 
-    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             DELETE FROM sleep
-            WHERE id = ?1
+            WHERE id = ?1 AND user_id = ?2
                 "#,
-                id
+                id,
+                user_id
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -138,4 +291,82 @@ impl DBSleep {
             Err(e) => Err(e)
         }
     }
+
+    /// Selects a page of sleeps ordered by night and id, optionally only the sleeps with the given tag.
+    /// `after` and `before` are exclusive bounds on the night and id. When paging backward the sleeps
+    /// are selected from the end of the listing and returned in reverse order.
+    pub async fn select_page(
+        conn: &mut SqliteConnection,
+        user_id: i64,
+        after: Option<(NaiveDate, i64)>,
+        before: Option<(NaiveDate, i64)>,
+        tag_id: Option<i64>,
+        limit: i64,
+        backward: bool)
+        -> Result<Vec<DBSleep>, sqlx::Error> {
+        let (after_night, after_id) = (after.map(|a| a.0), after.map(|a| a.1));
+        let (before_night, before_id) = (before.map(|b| b.0), before.map(|b| b.1));
+
+        if backward {
+            sqlx::query_as!(DBSleep,
+                r#"
+                SELECT id, night as "night: NaiveDate", amount, quality
+                FROM sleep
+                WHERE (?1 IS NULL OR (night, id) > (?1, ?2))
+                AND (?3 IS NULL OR (night, id) < (?3, ?4))
+                AND (?5 IS NULL OR id IN (SELECT sleep_id FROM sleep_tags WHERE tag_id = ?5))
+                AND user_id = ?7
+                ORDER BY night DESC, id DESC
+                LIMIT ?6
+                    "#,
+                    after_night,
+                    after_id,
+                    before_night,
+                    before_id,
+                    tag_id,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+        else {
+            sqlx::query_as!(DBSleep,
+                r#"
+                SELECT id, night as "night: NaiveDate", amount, quality
+                FROM sleep
+                WHERE (?1 IS NULL OR (night, id) > (?1, ?2))
+                AND (?3 IS NULL OR (night, id) < (?3, ?4))
+                AND (?5 IS NULL OR id IN (SELECT sleep_id FROM sleep_tags WHERE tag_id = ?5))
+                AND user_id = ?7
+                ORDER BY night, id
+                LIMIT ?6
+                    "#,
+                    after_night,
+                    after_id,
+                    before_night,
+                    before_id,
+                    tag_id,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+    }
+
+    /// Counts the sleeps, optionally only the sleeps with the given tag
+    pub async fn count(conn: &mut SqliteConnection, user_id: i64, tag_id: Option<i64>) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!: i64"
+            FROM sleep
+            WHERE (?1 IS NULL OR id IN (SELECT sleep_id FROM sleep_tags WHERE tag_id = ?1)) AND user_id = ?2
+                "#,
+                tag_id,
+                user_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
 }
\ No newline at end of file
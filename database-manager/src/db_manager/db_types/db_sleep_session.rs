@@ -0,0 +1,316 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use sqlx::SqliteConnection;
+use super::json_ids;
+
+/// Hour of the day that separates one night from the next.
+/// Going to bed before this hour counts towards the previous night, ex: going to bed at
+/// 1am on 2023-05-14 is the night of 2023-05-13, while a nap at 3pm on 2023-05-14 is part of the night of 2023-05-14.
+const NIGHT_CUTOFF_HOUR: u32 = 12;
+
+/// The kind of a sleep session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionKind {
+    /// The main sleep of the night, a night has at most one
+    #[default]
+    Main,
+
+    /// A nap during the day
+    Nap,
+
+    /// Part of a night of sleep that was split into multiple sessions
+    Split,
+}
+
+impl SessionKind {
+    /// Returns the value stored in the kind column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionKind::Main => "main",
+            SessionKind::Nap => "nap",
+            SessionKind::Split => "split",
+        }
+    }
+
+    /// Parses the value stored in the kind column, unknown values are treated as the main sleep
+    pub fn from_db(kind: &str) -> SessionKind {
+        match kind {
+            "nap" => SessionKind::Nap,
+            "split" => SessionKind::Split,
+            _ => SessionKind::Main,
+        }
+    }
+}
+
+/// Optional bed, sleep onset and wake times of a sleep session
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SleepTimes {
+    /// time the sleeper went to bed
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// time the sleeper fell asleep
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// time the sleeper woke up
+    pub wake_time: Option<NaiveDateTime>,
+}
+
+impl SleepTimes {
+    /// Returns true if none of the times are set
+    pub fn is_empty(&self) -> bool {
+        self.bed_time.is_none() && self.sleep_onset.is_none() && self.wake_time.is_none()
+    }
+
+    /// Returns the times with any times set in `other` replacing the current times
+    pub fn merge(&self, other: &SleepTimes) -> SleepTimes {
+        SleepTimes {
+            bed_time: other.bed_time.or(self.bed_time),
+            sleep_onset: other.sleep_onset.or(self.sleep_onset),
+            wake_time: other.wake_time.or(self.wake_time),
+        }
+    }
+
+    /// Returns an error message if the times are out of order.
+    /// The sleeper must fall asleep at or after going to bed, and wake up after both.
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(bed), Some(onset)) = (self.bed_time, self.sleep_onset) {
+            if onset < bed {
+                return Err(format!("sleep onset {} is before bed time {}", onset, bed));
+            }
+        }
+
+        if let (Some(start), Some(wake)) = (self.sleep_onset.or(self.bed_time), self.wake_time) {
+            if wake <= start {
+                return Err(format!("wake time {} must be after {}", wake, start));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the night the times belong to, if any times are set.
+    /// The night is the date the sleep started, unless it started after midnight and before
+    /// the [cutoff](NIGHT_CUTOFF_HOUR), in which case it belongs to the previous date.
+    pub fn night(&self) -> Option<NaiveDate> {
+        match self.bed_time.or(self.sleep_onset) {
+            Some(start) if start.hour() < NIGHT_CUTOFF_HOUR => Some(start.date() - Duration::days(1)),
+            Some(start) => Some(start.date()),
+            None => self.wake_time.map(|w| w.date() - Duration::days(1)),
+        }
+    }
+
+    /// Returns the amount of sleep in hours, from falling asleep, or going to bed if the
+    /// sleep onset is unknown, until waking up. None if the times are not known.
+    pub fn amount(&self) -> Option<f64> {
+        let start = self.sleep_onset.or(self.bed_time)?;
+        let wake = self.wake_time?;
+        Some((wake - start).num_seconds() as f64 / 3600.0)
+    }
+
+    /// Returns true if both sets of times are complete and the time in bed of one overlaps the other
+    pub fn overlaps(&self, other: &SleepTimes) -> bool {
+        match (self.bed_time.or(self.sleep_onset), self.wake_time, other.bed_time.or(other.sleep_onset), other.wake_time) {
+            (Some(start), Some(end), Some(other_start), Some(other_end)) => start < other_end && other_start < end,
+            _ => false
+        }
+    }
+}
+
+/// Representation of the sleep_session table. A night has one or more sessions of sleep
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBSleepSession {
+    /// Primary key
+    pub id: i64,
+
+    /// Fk to the sleep (night) the session belongs to
+    pub sleep_id: i64,
+
+    /// kind of session, one of main, nap or split
+    pub kind: String,
+
+    /// amount of sleep during the session
+    pub amount: f64,
+
+    /// time the sleeper went to bed
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// time the sleeper fell asleep
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// time the sleeper woke up
+    pub wake_time: Option<NaiveDateTime>,
+}
+
+impl DBSleepSession {
+    /// Returns the kind of the session
+    pub fn kind(&self) -> SessionKind {
+        SessionKind::from_db(self.kind.as_str())
+    }
+
+    /// Returns the bed, sleep onset and wake times of the session
+    pub fn times(&self) -> SleepTimes {
+        SleepTimes { bed_time: self.bed_time, sleep_onset: self.sleep_onset, wake_time: self.wake_time }
+    }
+}
+
+impl DBSleepSession {
+    pub async fn select_one(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<DBSleepSession, sqlx::Error>  {
+        sqlx::query_as!(DBSleepSession,
+            r#"
+            SELECT id, sleep_id, kind, amount,
+                bed_time as "bed_time?: NaiveDateTime",
+                sleep_onset as "sleep_onset?: NaiveDateTime",
+                wake_time as "wake_time?: NaiveDateTime"
+            FROM sleep_session
+            WHERE id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+                "#,
+                id,
+                user_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
+
+    pub async fn select_by_sleep_id(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64) -> Result<Vec<DBSleepSession>, sqlx::Error>  {
+        sqlx::query_as!(DBSleepSession,
+            r#"
+            SELECT id, sleep_id, kind, amount,
+                bed_time as "bed_time?: NaiveDateTime",
+                sleep_onset as "sleep_onset?: NaiveDateTime",
+                wake_time as "wake_time?: NaiveDateTime"
+            FROM sleep_session
+            WHERE sleep_id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY COALESCE(bed_time, sleep_onset, wake_time), id
+                "#,
+                sleep_id,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects the sessions of each of the sleeps in a single query, ordered by sleep id and then the time they started
+    pub async fn select_by_sleep_ids(conn: &mut SqliteConnection, user_id: i64, sleep_ids: &[i64]) -> Result<Vec<DBSleepSession>, sqlx::Error>  {
+        let sleep_ids = json_ids(sleep_ids);
+
+        sqlx::query_as!(DBSleepSession,
+            r#"
+            SELECT id, sleep_id, kind, amount,
+                bed_time as "bed_time?: NaiveDateTime",
+                sleep_onset as "sleep_onset?: NaiveDateTime",
+                wake_time as "wake_time?: NaiveDateTime"
+            FROM sleep_session
+            WHERE sleep_id IN (SELECT value FROM json_each(?1)) AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY sleep_id, COALESCE(bed_time, sleep_onset, wake_time), id
+                "#,
+                sleep_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn insert(conn: &mut SqliteConnection, sleep_id: i64, kind: SessionKind, amount: f64, times: &SleepTimes) -> Result<i64, sqlx::Error>  {
+        let kind = kind.as_str();
+
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO sleep_session ( sleep_id, kind, amount, bed_time, sleep_onset, wake_time )
+            VALUES ( ?1, ?2, ?3, ?4, ?5, ?6 )
+                "#,
+            sleep_id,
+            kind,
+            amount,
+            times.bed_time,
+            times.sleep_onset,
+            times.wake_time,
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.last_insert_rowid()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn update_kind(conn: &mut SqliteConnection, user_id: i64, id: i64, kind: SessionKind) -> Result<bool, sqlx::Error> {
+        let kind = kind.as_str();
+        let result = sqlx::query!(
+            r#"
+            UPDATE sleep_session
+            SET kind = ?1
+            WHERE id = ?2 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?3)
+                "#,
+                kind,
+                id,
+                user_id
+            )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub async fn update_amount(conn: &mut SqliteConnection, user_id: i64, id: i64, amount: f64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sleep_session
+            SET amount = ?1
+            WHERE id = ?2 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?3)
+                "#,
+                amount,
+                id,
+                user_id
+            )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub async fn update_times(conn: &mut SqliteConnection, user_id: i64, id: i64, times: &SleepTimes) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE sleep_session
+            SET bed_time = ?1, sleep_onset = ?2, wake_time = ?3
+            WHERE id = ?4 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?5)
+                "#,
+                times.bed_time,
+                times.sleep_onset,
+                times.wake_time,
+                id,
+                user_id
+            )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM sleep_session
+            WHERE id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+                "#,
+                id,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+}
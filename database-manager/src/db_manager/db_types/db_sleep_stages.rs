@@ -0,0 +1,212 @@
+use chrono::NaiveDate;
+use sqlx::SqliteConnection;
+use super::json_ids;
+
+/// A stage of sleep recorded by a wearable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SleepStage {
+    Deep,
+
+    /// Light sleep, called core sleep by some devices
+    Light,
+
+    Rem,
+
+    /// Awake after falling asleep
+    Awake,
+}
+
+impl SleepStage {
+    /// Returns the value stored in the stage column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SleepStage::Deep => "deep",
+            SleepStage::Light => "light",
+            SleepStage::Rem => "rem",
+            SleepStage::Awake => "awake",
+        }
+    }
+
+    /// Parses the value stored in the stage column, unknown values are treated as light sleep
+    pub fn from_db(stage: &str) -> SleepStage {
+        match stage {
+            "deep" => SleepStage::Deep,
+            "rem" => SleepStage::Rem,
+            "awake" => SleepStage::Awake,
+            _ => SleepStage::Light,
+        }
+    }
+}
+
+/// Minutes spent in each stage of sleep during a night, None for stages the device didn't record
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SleepStages {
+    pub deep_minutes: Option<i64>,
+    pub light_minutes: Option<i64>,
+    pub rem_minutes: Option<i64>,
+    pub awake_minutes: Option<i64>,
+}
+
+impl SleepStages {
+    /// Returns true if none of the stages are recorded
+    pub fn is_empty(&self) -> bool {
+        self.deep_minutes.is_none() && self.light_minutes.is_none() && self.rem_minutes.is_none() && self.awake_minutes.is_none()
+    }
+
+    /// Returns an error message if any of the minutes are negative
+    pub fn validate(&self) -> Result<(), String> {
+        for stage in [SleepStage::Deep, SleepStage::Light, SleepStage::Rem, SleepStage::Awake] {
+            if let Some(minutes) = self.minutes(stage).filter(|m| *m < 0) {
+                return Err(format!("{} minutes {} must not be negative", stage.as_str(), minutes));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the minutes spent in the stage, None if it wasn't recorded
+    pub fn minutes(&self, stage: SleepStage) -> Option<i64> {
+        match stage {
+            SleepStage::Deep => self.deep_minutes,
+            SleepStage::Light => self.light_minutes,
+            SleepStage::Rem => self.rem_minutes,
+            SleepStage::Awake => self.awake_minutes,
+        }
+    }
+
+    /// Returns the share of the night spent in the stage, between 0 and 1.
+    /// Deep, light and REM sleep are a share of the recorded minutes asleep, while awake is a share of the
+    /// recorded minutes asleep and awake. None if the stage wasn't recorded or nothing was recorded to share.
+    pub fn share(&self, stage: SleepStage) -> Option<f64> {
+        let minutes = self.minutes(stage)?;
+        let asleep = [self.deep_minutes, self.light_minutes, self.rem_minutes].iter().flatten().sum::<i64>();
+        let total = match stage {
+            SleepStage::Awake => asleep + minutes,
+            _ => asleep,
+        };
+        (total > 0).then(|| minutes as f64 / total as f64)
+    }
+}
+
+/// Representation of the sleep_stages table, a sleep has at most one row of stages
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBSleepStages {
+    /// Primary key and fk to the sleep (night) the stages belong to
+    pub sleep_id: i64,
+
+    /// minutes of deep sleep
+    pub deep_minutes: Option<i64>,
+
+    /// minutes of light sleep
+    pub light_minutes: Option<i64>,
+
+    /// minutes of REM sleep
+    pub rem_minutes: Option<i64>,
+
+    /// minutes awake after falling asleep
+    pub awake_minutes: Option<i64>,
+}
+
+impl DBSleepStages {
+    /// Returns the minutes of each stage
+    pub fn stages(&self) -> SleepStages {
+        SleepStages {
+            deep_minutes: self.deep_minutes,
+            light_minutes: self.light_minutes,
+            rem_minutes: self.rem_minutes,
+            awake_minutes: self.awake_minutes,
+        }
+    }
+}
+
+impl DBSleepStages {
+    pub async fn select_by_sleep_id(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64) -> Result<Option<DBSleepStages>, sqlx::Error>  {
+        sqlx::query_as!(DBSleepStages,
+            r#"
+            SELECT sleep_id, deep_minutes, light_minutes, rem_minutes, awake_minutes
+            FROM sleep_stages
+            WHERE sleep_id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+                "#,
+                sleep_id,
+                user_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    /// Selects the stages of each of the sleeps in a single query, ordered by sleep id. Sleeps without stages are skipped
+    pub async fn select_by_sleep_ids(conn: &mut SqliteConnection, user_id: i64, sleep_ids: &[i64]) -> Result<Vec<DBSleepStages>, sqlx::Error>  {
+        let sleep_ids = json_ids(sleep_ids);
+
+        sqlx::query_as!(DBSleepStages,
+            r#"
+            SELECT sleep_id, deep_minutes, light_minutes, rem_minutes, awake_minutes
+            FROM sleep_stages
+            WHERE sleep_id IN (SELECT value FROM json_each(?1)) AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+            ORDER BY sleep_id
+                "#,
+                sleep_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Selects the stages of the sleeps with a night between the start and end, inclusive, ordered by sleep id
+    pub async fn select_in_range(conn: &mut SqliteConnection, user_id: i64, start: NaiveDate, end: NaiveDate) -> Result<Vec<DBSleepStages>, sqlx::Error>  {
+        sqlx::query_as!(DBSleepStages,
+            r#"
+            SELECT ss.sleep_id, ss.deep_minutes, ss.light_minutes, ss.rem_minutes, ss.awake_minutes
+            FROM sleep_stages ss
+            INNER JOIN sleep s ON s.id = ss.sleep_id
+            WHERE s.night BETWEEN ?1 AND ?2 AND s.user_id = ?3
+            ORDER BY ss.sleep_id
+                "#,
+                start,
+                end,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Inserts the stages of a sleep, or replaces the stages if the sleep already has them
+    pub async fn upsert(conn: &mut SqliteConnection, sleep_id: i64, stages: &SleepStages) -> Result<(), sqlx::Error>  {
+        sqlx::query!(
+            r#"
+            INSERT INTO sleep_stages ( sleep_id, deep_minutes, light_minutes, rem_minutes, awake_minutes )
+            VALUES ( ?1, ?2, ?3, ?4, ?5 )
+            ON CONFLICT ( sleep_id ) DO UPDATE
+            SET deep_minutes = excluded.deep_minutes, light_minutes = excluded.light_minutes,
+                rem_minutes = excluded.rem_minutes, awake_minutes = excluded.awake_minutes,
+                updated_on = datetime('now','localtime')
+                "#,
+            sleep_id,
+            stages.deep_minutes,
+            stages.light_minutes,
+            stages.rem_minutes,
+            stages.awake_minutes,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM sleep_stages
+            WHERE sleep_id = ?1 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?2)
+                "#,
+                sleep_id,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+}
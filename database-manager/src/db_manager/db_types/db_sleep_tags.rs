@@ -1,4 +1,6 @@
-use sqlx::{SqlitePool};
+use chrono::NaiveDate;
+use sqlx::SqliteConnection;
+use super::json_ids;
 
 /// Representation of the sleep_tag table. Maps the many to many relationships between sleeps and tags
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -14,36 +16,54 @@ pub struct DBSleepTags {
 }
 
 impl DBSleepTags {
-    pub async fn insert(pool: &SqlitePool, sleep_id: i64, tag_id: i64) -> Result<i64, sqlx::Error>  {
-        let mut conn = pool.acquire().await?;
-
+    /// Adds the tag to the sleep, returns false if the sleep already has the tag
+    pub async fn insert(conn: &mut SqliteConnection, sleep_id: i64, tag_id: i64) -> Result<bool, sqlx::Error>  {
         let result = sqlx::query!(
             r#"
             INSERT INTO sleep_tags ( sleep_id, tag_id )
             VALUES ( ?1, ?2 )
+            ON CONFLICT ( sleep_id, tag_id ) DO NOTHING
                 "#,
             sleep_id,
             tag_id,
         )
-        .execute(&mut conn)
+        .execute(&mut *conn)
         .await;
 
         match result {
-            Ok(r) => Ok(r.last_insert_rowid()),
+            Ok(r) => Ok(r.rows_affected() > 0),
             Err(e) => Err(e),
         }
     }
 
-    pub async fn delete(pool: &SqlitePool, sleep_id: i64, tag_id: i64) -> Result<bool, sqlx::Error> {
+    /// Adds a tag to every sleep of another tag, skipping the sleeps that already have the tag
+    pub async fn reassign(conn: &mut SqliteConnection, from_tag_id: i64, to_tag_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO sleep_tags ( sleep_id, tag_id )
+            SELECT sleep_id, ?1 FROM sleep_tags WHERE tag_id = ?2
+            ON CONFLICT ( sleep_id, tag_id ) DO NOTHING
+                "#,
+            to_tag_id,
+            from_tag_id,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, sleep_id: i64, tag_id: i64) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             DELETE FROM sleep_tags
-            WHERE sleep_id = ?1 AND tag_id = ?2
+            WHERE sleep_id = ?1 AND tag_id = ?2 AND sleep_id IN (SELECT id FROM sleep WHERE user_id = ?3)
                 "#,
                 sleep_id,
-                tag_id
+                tag_id,
+                user_id
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -52,31 +72,54 @@ impl DBSleepTags {
         }
     }
 
-    pub async fn select_by_sleep_id(pool: &SqlitePool, sleep_id: i64) -> Result<Vec<DBSleepTags>, sqlx::Error>  {
+    pub async fn select_by_tag_id(conn: &mut SqliteConnection, user_id: i64, tag_id: i64) -> Result<Vec<DBSleepTags>, sqlx::Error>  {
         sqlx::query_as!(DBSleepTags,
             r#"
             SELECT id, sleep_id, tag_id
             FROM sleep_tags
-            WHERE sleep_id = ?1
+            WHERE tag_id = ?1 AND tag_id IN (SELECT id FROM tag WHERE user_id = ?2)
             ORDER BY id
                 "#,
-                sleep_id
+                tag_id,
+                user_id
         )
-        .fetch_all(pool)
+        .fetch_all(&mut *conn)
         .await
     }
 
-    pub async fn select_by_tag_id(pool: &SqlitePool, tag_id: i64) -> Result<Vec<DBSleepTags>, sqlx::Error>  {
+    /// Selects the sleep tags of the sleeps with a night between the start and end dates, inclusive
+    pub async fn select_in_range(conn: &mut SqliteConnection, user_id: i64, start: NaiveDate, end: NaiveDate) -> Result<Vec<DBSleepTags>, sqlx::Error>  {
         sqlx::query_as!(DBSleepTags,
             r#"
-            SELECT id, sleep_id, tag_id
-            FROM sleep_tags
-            WHERE tag_id = ?1
-            ORDER BY id
+            SELECT st.id, st.sleep_id, st.tag_id
+            FROM sleep_tags st
+            INNER JOIN sleep s ON s.id = st.sleep_id
+            WHERE s.night BETWEEN ?1 AND ?2 AND s.user_id = ?3
+            ORDER BY st.id
+                "#,
+                start,
+                end,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Counts the sleeps that have at least one of the tags
+    pub async fn count_sleeps(conn: &mut SqliteConnection, user_id: i64, tag_ids: &[i64]) -> Result<i64, sqlx::Error> {
+        let tag_ids = json_ids(tag_ids);
+
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(DISTINCT st.sleep_id) as "count!: i64"
+            FROM sleep_tags st
+            INNER JOIN sleep s ON s.id = st.sleep_id
+            WHERE st.tag_id IN (SELECT value FROM json_each(?1)) AND s.user_id = ?2
                 "#,
-                tag_id
+                tag_ids,
+                user_id
         )
-        .fetch_all(pool)
+        .fetch_one(&mut *conn)
         .await
     }
-}
\ No newline at end of file
+}
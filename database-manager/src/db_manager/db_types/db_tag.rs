@@ -1,4 +1,5 @@
-use sqlx::{SqlitePool};
+use sqlx::SqliteConnection;
+use super::json_ids;
 
 /// Representation of the tag table
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -6,7 +7,7 @@ pub struct DBTag {
     /// Primary key
     pub id: i64,
 
-    /// name of the tag, must be unique
+    /// name of the tag, must be unique for each user
     pub name: String,
 
     /// decimal representation of the rgb color value for the tag ex: Red (0xFF0000) is 16711680
@@ -14,44 +15,79 @@ pub struct DBTag {
 }
 
 impl DBTag {
-    pub async fn select_all(pool: &SqlitePool) -> Result<Vec<DBTag>, sqlx::Error>  {
+    pub async fn select_all(conn: &mut SqliteConnection, user_id: i64) -> Result<Vec<DBTag>, sqlx::Error>  {
         sqlx::query_as!(DBTag,
             r#"
             SELECT id, name, color
             FROM tag
+            WHERE user_id = ?1
             ORDER BY id
-                "#
+                "#,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_one(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<DBTag, sqlx::Error>  {
+        sqlx::query_as!(DBTag,
+            r#"
+            SELECT id, name, color
+            FROM tag
+            WHERE id = ?1 AND user_id = ?2
+            ORDER BY id
+                "#,
+                id,
+                user_id
         )
-        .fetch_all(pool)
+        .fetch_one(&mut *conn)
         .await
     }
 
-    pub async fn select_one(pool: &SqlitePool, id: i64) -> Result<DBTag, sqlx::Error>  {
+    /// Selects the tags with the given ids in a single query, ordered by id. Ids without a tag are skipped
+    pub async fn select_by_ids(conn: &mut SqliteConnection, user_id: i64, ids: &[i64]) -> Result<Vec<DBTag>, sqlx::Error>  {
+        let ids = json_ids(ids);
+
         sqlx::query_as!(DBTag,
             r#"
             SELECT id, name, color
             FROM tag
-            WHERE id = ?1
+            WHERE id IN (SELECT value FROM json_each(?1)) AND user_id = ?2
             ORDER BY id
                 "#,
-                id
+                ids,
+                user_id
         )
-        .fetch_one(pool)
+        .fetch_all(&mut *conn)
         .await
     }
 
-    pub async fn insert(pool: &SqlitePool, name: &str, color: i64) -> Result<i64, sqlx::Error>  {
-        let mut conn = pool.acquire().await?;
+    /// Selects the tag with the name, None if the user has no tag with the name
+    pub async fn select_by_name(conn: &mut SqliteConnection, user_id: i64, name: &str) -> Result<Option<DBTag>, sqlx::Error>  {
+        sqlx::query_as!(DBTag,
+            r#"
+            SELECT id, name, color
+            FROM tag
+            WHERE name = ?1 AND user_id = ?2
+                "#,
+                name,
+                user_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
 
+    pub async fn insert(conn: &mut SqliteConnection, user_id: i64, name: &str, color: i64) -> Result<i64, sqlx::Error>  {
         let result = sqlx::query!(
             r#"
-            INSERT INTO tag ( name, color )
-            VALUES ( ?1, ?2 )
+            INSERT INTO tag ( user_id, name, color )
+            VALUES ( ?1, ?2, ?3 )
                 "#,
+            user_id,
             name,
             color,
         )
-        .execute(&mut conn)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -60,17 +96,18 @@ impl DBTag {
         }
     }
 
-    pub async fn update_name(pool: &SqlitePool, id: i64, name: &str) -> Result<bool, sqlx::Error> {
+    pub async fn update_name(conn: &mut SqliteConnection, user_id: i64, id: i64, name: &str) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             UPDATE tag
             SET name = ?1
-            WHERE id = ?2
+            WHERE id = ?2 AND user_id = ?3
                 "#,
                 name,
-                id
+                id,
+                user_id
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -79,17 +116,18 @@ impl DBTag {
         }
     }
 
-    pub async fn update_color(pool: &SqlitePool, id: i64, color: i64) -> Result<bool, sqlx::Error> {
+    pub async fn update_color(conn: &mut SqliteConnection, user_id: i64, id: i64, color: i64) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             UPDATE tag
             SET color = ?1
-            WHERE id = ?2
+            WHERE id = ?2 AND user_id = ?3
                 "#,
                 color,
-                id
+                id,
+                user_id
             )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -98,15 +136,16 @@ impl DBTag {
         }
     }
 
-    pub async fn delete(pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
         let result = sqlx::query!(
             r#"
             DELETE FROM tag
-            WHERE id = ?1
+            WHERE id = ?1 AND user_id = ?2
                 "#,
-                id
+                id,
+                user_id
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await;
 
         match result {
@@ -114,4 +153,150 @@ impl DBTag {
             Err(e) => Err(e)
         }
     }
-}
\ No newline at end of file
+
+    /// Selects a page of tags ordered by id. `after` and `before` are exclusive bounds on the id.
+    /// When paging backward the tags are selected from the end of the listing and returned in reverse order.
+    pub async fn select_page(
+        conn: &mut SqliteConnection,
+        user_id: i64,
+        after: Option<i64>,
+        before: Option<i64>,
+        limit: i64,
+        backward: bool)
+        -> Result<Vec<DBTag>, sqlx::Error> {
+        if backward {
+            sqlx::query_as!(DBTag,
+                r#"
+                SELECT id as "id!", name as "name!", color as "color!"
+                FROM tag
+                WHERE (?1 IS NULL OR id > ?1) AND (?2 IS NULL OR id < ?2) AND user_id = ?4
+                ORDER BY id DESC
+                LIMIT ?3
+                    "#,
+                    after,
+                    before,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+        else {
+            sqlx::query_as!(DBTag,
+                r#"
+                SELECT id as "id!", name as "name!", color as "color!"
+                FROM tag
+                WHERE (?1 IS NULL OR id > ?1) AND (?2 IS NULL OR id < ?2) AND user_id = ?4
+                ORDER BY id
+                LIMIT ?3
+                    "#,
+                    after,
+                    before,
+                    limit,
+                    user_id
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+    }
+
+    /// Counts the tags
+    pub async fn count(conn: &mut SqliteConnection, user_id: i64) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) as "count!: i64"
+            FROM tag
+            WHERE user_id = ?1
+                "#,
+                user_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
+
+    /// Selects the tags of each of the sleeps in a single query, paired with the id of the sleep.
+    /// Ordered by sleep id and then tag id, a tag is only returned once per sleep.
+    pub async fn select_by_sleep_ids(conn: &mut SqliteConnection, user_id: i64, sleep_ids: &[i64]) -> Result<Vec<(i64, DBTag)>, sqlx::Error> {
+        let sleep_ids = json_ids(sleep_ids);
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT DISTINCT st.sleep_id, t.id, t.name, t.color
+            FROM sleep_tags st
+            INNER JOIN tag t ON t.id = st.tag_id
+            WHERE st.sleep_id IN (SELECT value FROM json_each(?1)) AND t.user_id = ?2
+            ORDER BY st.sleep_id, t.id
+                "#,
+                sleep_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.sleep_id, DBTag { id: r.id, name: r.name, color: r.color })).collect())
+    }
+
+    /// Selects the tags of each of the dreams in a single query, paired with the id of the dream.
+    /// Ordered by dream id and then tag id.
+    pub async fn select_by_dream_ids(conn: &mut SqliteConnection, user_id: i64, dream_ids: &[i64]) -> Result<Vec<(i64, DBTag)>, sqlx::Error> {
+        let dream_ids = json_ids(dream_ids);
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT dt.dream_id, t.id, t.name, t.color
+            FROM dream_tags dt
+            INNER JOIN tag t ON t.id = dt.tag_id
+            WHERE dt.dream_id IN (SELECT value FROM json_each(?1)) AND t.user_id = ?2
+            ORDER BY dt.dream_id, t.id
+                "#,
+                dream_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.dream_id, DBTag { id: r.id, name: r.name, color: r.color })).collect())
+    }
+
+    /// Selects the tags whose parent is one of the given tags, paired with the id of the parent
+    pub async fn select_by_parent_ids(conn: &mut SqliteConnection, user_id: i64, parent_ids: &[i64]) -> Result<Vec<(i64, DBTag)>, sqlx::Error> {
+        let parent_ids = json_ids(parent_ids);
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT h.parent_id as "parent_id!", t.id, t.name, t.color
+            FROM tag_hierarchy h
+            INNER JOIN tag t ON t.id = h.tag_id
+            WHERE h.parent_id IN (SELECT value FROM json_each(?1)) AND t.user_id = ?2
+            ORDER BY h.parent_id, t.id
+                "#,
+                parent_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.parent_id, DBTag { id: r.id, name: r.name, color: r.color })).collect())
+    }
+
+    /// Selects the tags of the given categories, paired with the id of the category
+    pub async fn select_by_category_ids(conn: &mut SqliteConnection, user_id: i64, category_ids: &[i64]) -> Result<Vec<(i64, DBTag)>, sqlx::Error> {
+        let category_ids = json_ids(category_ids);
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT h.category_id as "category_id!", t.id, t.name, t.color
+            FROM tag_hierarchy h
+            INNER JOIN tag t ON t.id = h.tag_id
+            WHERE h.category_id IN (SELECT value FROM json_each(?1)) AND t.user_id = ?2
+            ORDER BY h.category_id, t.id
+                "#,
+                category_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| (r.category_id, DBTag { id: r.id, name: r.name, color: r.color })).collect())
+    }
+}
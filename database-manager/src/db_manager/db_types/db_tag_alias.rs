@@ -0,0 +1,114 @@
+use sqlx::SqliteConnection;
+
+/// Representation of the tag_alias table, an old name of a tag that was merged into another tag or renamed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBTagAlias {
+    /// Primary key
+    pub id: i64,
+
+    /// Fk to the tag the name resolves to
+    pub tag_id: i64,
+
+    /// old name of the tag, an alias name is unique for each user
+    pub name: String,
+}
+
+impl DBTagAlias {
+    pub async fn select_all(conn: &mut SqliteConnection, user_id: i64) -> Result<Vec<DBTagAlias>, sqlx::Error>  {
+        sqlx::query_as!(DBTagAlias,
+            r#"
+            SELECT id, tag_id, name
+            FROM tag_alias
+            WHERE user_id = ?1
+            ORDER BY id
+                "#,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_by_tag_id(conn: &mut SqliteConnection, user_id: i64, tag_id: i64) -> Result<Vec<DBTagAlias>, sqlx::Error>  {
+        sqlx::query_as!(DBTagAlias,
+            r#"
+            SELECT id, tag_id, name
+            FROM tag_alias
+            WHERE tag_id = ?1 AND user_id = ?2
+            ORDER BY id
+                "#,
+                tag_id,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_by_name(conn: &mut SqliteConnection, user_id: i64, name: &str) -> Result<Option<DBTagAlias>, sqlx::Error>  {
+        sqlx::query_as!(DBTagAlias,
+            r#"
+            SELECT id, tag_id, name
+            FROM tag_alias
+            WHERE name = ?1 AND user_id = ?2
+                "#,
+                name,
+                user_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    /// Makes the name an alias of the tag, an alias with the same name is moved to the tag
+    pub async fn upsert(conn: &mut SqliteConnection, user_id: i64, tag_id: i64, name: &str) -> Result<(), sqlx::Error>  {
+        sqlx::query!(
+            r#"
+            INSERT INTO tag_alias ( user_id, tag_id, name )
+            VALUES ( ?1, ?2, ?3 )
+            ON CONFLICT ( user_id, name ) DO UPDATE
+            SET tag_id = excluded.tag_id
+                "#,
+            user_id,
+            tag_id,
+            name,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves the aliases of a tag to another tag of the user
+    pub async fn reassign(conn: &mut SqliteConnection, user_id: i64, from_tag_id: i64, to_tag_id: i64) -> Result<(), sqlx::Error>  {
+        sqlx::query!(
+            r#"
+            UPDATE tag_alias
+            SET tag_id = ?1
+            WHERE tag_id = ?2 AND user_id = ?3
+                "#,
+            to_tag_id,
+            from_tag_id,
+            user_id,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_name(conn: &mut SqliteConnection, user_id: i64, name: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM tag_alias
+            WHERE name = ?1 AND user_id = ?2
+                "#,
+                name,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use sqlx::SqliteConnection;
+
+/// Representation of the tag_category table, a group of tags ex: "Substances"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBTagCategory {
+    /// Primary key
+    pub id: i64,
+
+    /// name of the category, must be unique for each user
+    pub name: String,
+}
+
+impl DBTagCategory {
+    pub async fn select_all(conn: &mut SqliteConnection, user_id: i64) -> Result<Vec<DBTagCategory>, sqlx::Error>  {
+        sqlx::query_as!(DBTagCategory,
+            r#"
+            SELECT id, name
+            FROM tag_category
+            WHERE user_id = ?1
+            ORDER BY id
+                "#,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_one(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<DBTagCategory, sqlx::Error>  {
+        sqlx::query_as!(DBTagCategory,
+            r#"
+            SELECT id, name
+            FROM tag_category
+            WHERE id = ?1 AND user_id = ?2
+                "#,
+                id,
+                user_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
+
+    pub async fn insert(conn: &mut SqliteConnection, user_id: i64, name: &str) -> Result<i64, sqlx::Error>  {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO tag_category ( user_id, name )
+            VALUES ( ?1, ?2 )
+                "#,
+            user_id,
+            name,
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.last_insert_rowid()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn update_name(conn: &mut SqliteConnection, user_id: i64, id: i64, name: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE tag_category
+            SET name = ?1, updated_on = datetime('now','localtime')
+            WHERE id = ?2 AND user_id = ?3
+                "#,
+                name,
+                id,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Deletes the category, its tags are kept without a category
+    pub async fn delete(conn: &mut SqliteConnection, user_id: i64, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM tag_category
+            WHERE id = ?1 AND user_id = ?2
+                "#,
+                id,
+                user_id
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.rows_affected() > 0),
+            Err(e) => Err(e)
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::{HashMap, HashSet};
+use sqlx::SqliteConnection;
+use super::json_ids;
+
+/// Representation of the tag_hierarchy table, the parent and category of a tag.
+/// A tag without a row has neither a parent nor a category.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBTagHierarchy {
+    /// Primary key and fk to the tag table
+    pub tag_id: i64,
+
+    /// Fk to the parent tag, None for a tag at the top of the hierarchy
+    pub parent_id: Option<i64>,
+
+    /// Fk to the tag_category table
+    pub category_id: Option<i64>,
+}
+
+impl DBTagHierarchy {
+    /// Selects the parent and category of every tag of the user that has one
+    pub async fn select_all(conn: &mut SqliteConnection, user_id: i64) -> Result<Vec<DBTagHierarchy>, sqlx::Error>  {
+        sqlx::query_as!(DBTagHierarchy,
+            r#"
+            SELECT h.tag_id, h.parent_id, h.category_id
+            FROM tag_hierarchy h
+            INNER JOIN tag t ON t.id = h.tag_id
+            WHERE t.user_id = ?1 AND (h.parent_id IS NOT NULL OR h.category_id IS NOT NULL)
+            ORDER BY h.tag_id
+                "#,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_one(conn: &mut SqliteConnection, user_id: i64, tag_id: i64) -> Result<Option<DBTagHierarchy>, sqlx::Error>  {
+        sqlx::query_as!(DBTagHierarchy,
+            r#"
+            SELECT h.tag_id, h.parent_id, h.category_id
+            FROM tag_hierarchy h
+            INNER JOIN tag t ON t.id = h.tag_id
+            WHERE h.tag_id = ?1 AND t.user_id = ?2
+                "#,
+                tag_id,
+                user_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    /// Selects the parent and category of each of the given tags of the user that has one
+    pub async fn select_by_tag_ids(conn: &mut SqliteConnection, user_id: i64, tag_ids: &[i64]) -> Result<Vec<DBTagHierarchy>, sqlx::Error>  {
+        let tag_ids = json_ids(tag_ids);
+
+        sqlx::query_as!(DBTagHierarchy,
+            r#"
+            SELECT h.tag_id, h.parent_id, h.category_id
+            FROM tag_hierarchy h
+            INNER JOIN tag t ON t.id = h.tag_id
+            WHERE h.tag_id IN (SELECT value FROM json_each(?1)) AND t.user_id = ?2
+            ORDER BY h.tag_id
+                "#,
+                tag_ids,
+                user_id
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    /// Sets the parent of the tag, None moves the tag to the top of the hierarchy
+    pub async fn upsert_parent(conn: &mut SqliteConnection, tag_id: i64, parent_id: Option<i64>) -> Result<(), sqlx::Error>  {
+        sqlx::query!(
+            r#"
+            INSERT INTO tag_hierarchy ( tag_id, parent_id )
+            VALUES ( ?1, ?2 )
+            ON CONFLICT ( tag_id ) DO UPDATE
+            SET parent_id = excluded.parent_id
+                "#,
+            tag_id,
+            parent_id,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets the category of the tag, None removes the tag from its category
+    pub async fn upsert_category(conn: &mut SqliteConnection, tag_id: i64, category_id: Option<i64>) -> Result<(), sqlx::Error>  {
+        sqlx::query!(
+            r#"
+            INSERT INTO tag_hierarchy ( tag_id, category_id )
+            VALUES ( ?1, ?2 )
+            ON CONFLICT ( tag_id ) DO UPDATE
+            SET category_id = excluded.category_id
+                "#,
+            tag_id,
+            category_id,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves the children of a tag to another tag, except for that tag itself
+    pub async fn reassign_children(conn: &mut SqliteConnection, from_tag_id: i64, to_tag_id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE tag_hierarchy
+            SET parent_id = ?1
+            WHERE parent_id = ?2 AND tag_id != ?1
+                "#,
+            to_tag_id,
+            from_tag_id,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Maps each tag that has a parent to its parent
+    pub fn parents(rows: &[DBTagHierarchy]) -> HashMap<i64, i64> {
+        rows.iter().filter_map(|r| r.parent_id.map(|parent_id| (r.tag_id, parent_id))).collect()
+    }
+
+    /// Returns the parent of the tag, the parent of the parent and so on up to the top of the hierarchy
+    pub fn ancestors(parents: &HashMap<i64, i64>, tag_id: i64) -> Vec<i64> {
+        let mut ancestors = Vec::new();
+        let mut current = tag_id;
+        while let Some(parent_id) = parents.get(&current) {
+            if *parent_id == tag_id || ancestors.contains(parent_id) {
+                break;
+            }
+            ancestors.push(*parent_id);
+            current = *parent_id;
+        }
+        ancestors
+    }
+
+    /// Returns the tag followed by its children, the children of its children and so on
+    pub fn with_descendants(parents: &HashMap<i64, i64>, tag_id: i64) -> Vec<i64> {
+        let mut tag_ids = vec![tag_id];
+        let mut seen = HashSet::from([tag_id]);
+        let mut next = 0;
+        while next < tag_ids.len() {
+            let parent_id = tag_ids[next];
+            let mut children = parents.iter().filter(|(_, p)| **p == parent_id).map(|(c, _)| *c).collect::<Vec<i64>>();
+            children.sort();
+            tag_ids.extend(children.into_iter().filter(|c| seen.insert(*c)));
+            next += 1;
+        }
+        tag_ids
+    }
+}
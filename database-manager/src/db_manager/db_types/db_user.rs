@@ -0,0 +1,68 @@
+use sqlx::SqliteConnection;
+
+/// Representation of the user table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DBUser {
+    /// Primary key
+    pub id: i64,
+
+    /// name of the user, must be unique
+    pub name: String,
+}
+
+impl DBUser {
+    pub async fn select_all(conn: &mut SqliteConnection) -> Result<Vec<DBUser>, sqlx::Error>  {
+        sqlx::query_as!(DBUser,
+            r#"
+            SELECT id, name
+            FROM user
+            ORDER BY id
+                "#
+        )
+        .fetch_all(&mut *conn)
+        .await
+    }
+
+    pub async fn select_one(conn: &mut SqliteConnection, id: i64) -> Result<DBUser, sqlx::Error>  {
+        sqlx::query_as!(DBUser,
+            r#"
+            SELECT id, name
+            FROM user
+            WHERE id = ?1
+                "#,
+                id
+        )
+        .fetch_one(&mut *conn)
+        .await
+    }
+
+    pub async fn select_by_name(conn: &mut SqliteConnection, name: &str) -> Result<Option<DBUser>, sqlx::Error>  {
+        sqlx::query_as!(DBUser,
+            r#"
+            SELECT id, name
+            FROM user
+            WHERE name = ?1
+                "#,
+                name
+        )
+        .fetch_optional(&mut *conn)
+        .await
+    }
+
+    pub async fn insert(conn: &mut SqliteConnection, name: &str) -> Result<i64, sqlx::Error>  {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO user ( name )
+            VALUES ( ?1 )
+                "#,
+            name,
+        )
+        .execute(&mut *conn)
+        .await;
+
+        match result {
+            Ok(r) => Ok(r.last_insert_rowid()),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use sqlx::pool::PoolConnection;
+use sqlx::{Sqlite, SqliteConnection, SqlitePool, Transaction};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use super::DBError;
+
+/// A transaction that the queries of a manager, and its clones, run inside of until it is committed or rolled back
+#[derive(Debug, Clone)]
+pub struct UnitOfWork {
+    /// The transaction, None once it was committed or rolled back
+    transaction: Arc<Mutex<Option<Transaction<'static, Sqlite>>>>,
+
+    /// false for a unit of work that was joined instead of started, committing it is left to the unit of work that started it
+    owner: bool,
+}
+
+impl UnitOfWork {
+    /// Starts a transaction on a connection of the pool, waiting for the transactions of other connections that write to end
+    pub async fn begin(pool: &SqlitePool) -> Result<UnitOfWork, DBError> {
+        let mut transaction = pool.begin().await?;
+        // sqlite only waits for the write lock when it is taken by the first statement of a transaction,
+        // a transaction that reads first fails right away when another connection is writing,
+        // so take the lock with a write that changes nothing. sqlx 0.6 can't start the transaction with BEGIN IMMEDIATE.
+        sqlx::query("UPDATE user SET id = id WHERE 0").execute(&mut transaction).await?;
+        Ok(UnitOfWork { transaction: Arc::new(Mutex::new(Some(transaction))), owner: true })
+    }
+
+    /// Returns a unit of work that runs inside of the same transaction, but doesn't commit it
+    pub fn join(&self) -> UnitOfWork {
+        UnitOfWork { transaction: self.transaction.clone(), owner: false }
+    }
+
+    /// Returns the connection of the transaction, the queries of the other clones wait until it is dropped.
+    /// Returns a [Validation](DBError::Validation) error if the unit of work already ended.
+    pub async fn connection(&self) -> Result<DBConnection, DBError> {
+        let transaction = self.transaction.clone().lock_owned().await;
+        if transaction.is_none() {
+            return Err(ended());
+        }
+        Ok(DBConnection::Transaction(transaction))
+    }
+
+    /// Commits the transaction if this unit of work started it, a joined unit of work is committed with the one that started it
+    pub async fn commit(self) -> Result<(), DBError> {
+        if !self.owner {
+            let active = self.transaction.lock().await.is_some();
+            return if active { Ok(()) } else { Err(ended()) };
+        }
+        match self.transaction.lock().await.take() {
+            Some(transaction) => Ok(transaction.commit().await?),
+            None => Err(ended()),
+        }
+    }
+
+    /// Rolls back the transaction, rolling back a joined unit of work also rolls back the unit of work that started it
+    pub async fn rollback(self) -> Result<(), DBError> {
+        match self.transaction.lock().await.take() {
+            Some(transaction) => Ok(transaction.rollback().await?),
+            None => Err(ended()),
+        }
+    }
+}
+
+fn ended() -> DBError {
+    DBError::Validation(String::from("the unit of work was already committed or rolled back"))
+}
+
+/// A connection that a query runs on, either a connection of the pool or the connection of a unit of work
+pub enum DBConnection {
+    Pooled(PoolConnection<Sqlite>),
+    Transaction(OwnedMutexGuard<Option<Transaction<'static, Sqlite>>>),
+}
+
+impl Deref for DBConnection {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        match self {
+            DBConnection::Pooled(connection) => connection,
+            DBConnection::Transaction(transaction) => transaction.as_ref().expect("the unit of work has a transaction"),
+        }
+    }
+}
+
+impl DerefMut for DBConnection {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        match self {
+            DBConnection::Pooled(connection) => connection,
+            DBConnection::Transaction(transaction) => transaction.as_mut().expect("the unit of work has a transaction"),
+        }
+    }
+}
@@ -1,26 +1,40 @@
-/// Exposes database manager, queries and mutations to the server
+//! Exposes database manager, queries and mutations to the server
 
-use db_manager::DBManager;
+use db_manager::{DBError, DBManager};
 
 /// Module that manages the database connection, calls, models, and gql api
 pub mod db_manager;
 
 mod model;
-pub use model::{QueryRoot, MutationRoot};
+pub use model::{build_schema, scope_data, scope_request, QueryRoot, MutationRoot, SubscriptionRoot, SleepSchema};
 
 /// Initializes and returns a database manager to manage db calls.
-pub async fn init_db() -> DBManager {
-    DBManager::init("sqlite://sleep.db").await.unwrap()
+/// 
+/// # Arguments
+/// 
+/// * `database_url` - url of the sqlite database, ex: "sqlite://sleep.db"
+/// * `pool_size` - maximum number of connections to the database
+/// 
+pub async fn init_db(database_url: &str, pool_size: u32) -> Result<DBManager, DBError> {
+    DBManager::init_with_pool_size(database_url, pool_size).await
 }
 
-/// Initializes and returns a test database.
-pub async fn _init_test_db() -> DBManager {
-    db_manager::db_tests::create_test_db("test.db").await
+/// Initializes and returns a test database filled with mock data.
+/// Any existing database at the url is deleted first.
+/// 
+/// # Arguments
+/// 
+/// * `database_url` - url of the sqlite test database, ex: "sqlite://test.db"
+/// 
+pub async fn init_test_db(database_url: &str) -> DBManager {
+    let db_path = database_url.trim_start_matches("sqlite:").trim_start_matches("//");
+    db_manager::db_tests::create_test_db(db_path).await
 }
 
 /// Creates a mock database and tests all of the queries and mutations
 async fn _test_db() {
     db_manager::db_tests::test_db_queries("test.db").await;
+    db_manager::db_tests::test_db_migrations("migration_test.db").await;
 
     // SQLX sometimes keeps a handle on the file for longer than expected,
     // so removing this until I get a better understanding of the issue
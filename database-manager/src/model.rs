@@ -1,7 +1,145 @@
+use async_graphql::{Data, Request, Schema, SchemaBuilder};
+use std::time::Duration;
+use async_graphql::dataloader::{DataLoader, Loader};
+use crate::DBManager;
+use crate::db_manager::MAX_PAGE_SIZE;
+
+mod gql_auth;
+mod gql_changes;
+mod gql_errors;
+mod gql_loaders;
+mod gql_pagination;
 mod gql_types;
 
 mod queries;
 pub use queries::QueryRoot;
 
 mod mutations;
-pub use mutations::MutationRoot;
\ No newline at end of file
+pub use mutations::MutationRoot;
+
+mod subscriptions;
+pub use subscriptions::SubscriptionRoot;
+
+/// Time a data loader waits for more keys before loading a batch
+const LOADER_DELAY: Duration = Duration::from_millis(5);
+
+/// The graphql schema of the sleep tracker
+pub type SleepSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Returns a schema builder with the database manager, the data loaders used by the resolvers and the channel
+/// that mutations publish changes to for subscriptions set as the context.
+/// Requests that are not scoped with [scope_request] use the data of the user of the database manager.
+/// 
+/// # Arguments
+/// 
+/// * `dbm` - database manager that the queries and mutations use
+/// 
+pub fn build_schema(dbm: DBManager) -> SchemaBuilder<QueryRoot, MutationRoot, SubscriptionRoot> {
+    let loaders = Loaders::new(&dbm);
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .extension(gql_auth::ReadOnlyGuard)
+        .data(gql_changes::ChangeChannel::new())
+        .data(loaders.sleep)
+        .data(loaders.sleep_tags)
+        .data(loaders.sleep_comments)
+        .data(loaders.sleep_sessions)
+        .data(loaders.sleep_dreams)
+        .data(loaders.sleep_stages)
+        .data(loaders.hypnogram)
+        .data(loaders.dream_tags)
+        .data(loaders.tag_hierarchy)
+        .data(loaders.tag_children)
+        .data(loaders.category_tags)
+        .data(dbm)
+}
+
+/// Scopes a request to the user of the given database manager.
+/// The database manager and data loaders are added to the request, where they take precedence over the ones
+/// set on the schema, so every query and mutation of the request only reads and changes the data of that user.
+/// The [Principal](crate::db_manager::Principal) of the request should be added as request data as well,
+/// requests without a principal are not limited to queries.
+/// 
+/// # Arguments
+/// 
+/// * `request` - the graphql request to scope
+/// * `dbm` - database manager scoped to the user making the request, see [DBManager::for_user]
+/// 
+pub fn scope_request(request: Request, dbm: DBManager) -> Request {
+    let loaders = Loaders::new(&dbm);
+    request.data(loaders.sleep)
+        .data(loaders.sleep_tags)
+        .data(loaders.sleep_comments)
+        .data(loaders.sleep_sessions)
+        .data(loaders.sleep_dreams)
+        .data(loaders.sleep_stages)
+        .data(loaders.hypnogram)
+        .data(loaders.dream_tags)
+        .data(loaders.tag_hierarchy)
+        .data(loaders.tag_children)
+        .data(loaders.category_tags)
+        .data(dbm)
+}
+
+/// Returns the data that scopes the subscriptions of a websocket connection to the user of the given database manager,
+/// the same way [scope_request] scopes a single request
+/// 
+/// # Arguments
+/// 
+/// * `dbm` - database manager scoped to the user of the connection, see [DBManager::for_user]
+/// 
+pub fn scope_data(dbm: DBManager) -> Data {
+    let loaders = Loaders::new(&dbm);
+    let mut data = Data::default();
+    data.insert(loaders.sleep);
+    data.insert(loaders.sleep_tags);
+    data.insert(loaders.sleep_comments);
+    data.insert(loaders.sleep_sessions);
+    data.insert(loaders.sleep_dreams);
+    data.insert(loaders.sleep_stages);
+    data.insert(loaders.hypnogram);
+    data.insert(loaders.dream_tags);
+    data.insert(loaders.tag_hierarchy);
+    data.insert(loaders.tag_children);
+    data.insert(loaders.category_tags);
+    data.insert(dbm);
+    data
+}
+
+/// The data loaders used by the resolvers, loading the data of the user of a database manager
+struct Loaders {
+    sleep: DataLoader<gql_loaders::SleepLoader>,
+    sleep_tags: DataLoader<gql_loaders::SleepTagsLoader>,
+    sleep_comments: DataLoader<gql_loaders::SleepCommentsLoader>,
+    sleep_sessions: DataLoader<gql_loaders::SleepSessionsLoader>,
+    sleep_dreams: DataLoader<gql_loaders::SleepDreamsLoader>,
+    sleep_stages: DataLoader<gql_loaders::SleepStagesLoader>,
+    hypnogram: DataLoader<gql_loaders::HypnogramLoader>,
+    dream_tags: DataLoader<gql_loaders::DreamTagsLoader>,
+    tag_hierarchy: DataLoader<gql_loaders::TagHierarchyLoader>,
+    tag_children: DataLoader<gql_loaders::TagChildrenLoader>,
+    category_tags: DataLoader<gql_loaders::CategoryTagsLoader>,
+}
+
+impl Loaders {
+    fn new(dbm: &DBManager) -> Loaders {
+        Loaders {
+            sleep: data_loader(gql_loaders::SleepLoader::new(dbm.clone())),
+            sleep_tags: data_loader(gql_loaders::SleepTagsLoader::new(dbm.clone())),
+            sleep_comments: data_loader(gql_loaders::SleepCommentsLoader::new(dbm.clone())),
+            sleep_sessions: data_loader(gql_loaders::SleepSessionsLoader::new(dbm.clone())),
+            sleep_dreams: data_loader(gql_loaders::SleepDreamsLoader::new(dbm.clone())),
+            sleep_stages: data_loader(gql_loaders::SleepStagesLoader::new(dbm.clone())),
+            hypnogram: data_loader(gql_loaders::HypnogramLoader::new(dbm.clone())),
+            dream_tags: data_loader(gql_loaders::DreamTagsLoader::new(dbm.clone())),
+            tag_hierarchy: data_loader(gql_loaders::TagHierarchyLoader::new(dbm.clone())),
+            tag_children: data_loader(gql_loaders::TagChildrenLoader::new(dbm.clone())),
+            category_tags: data_loader(gql_loaders::CategoryTagsLoader::new(dbm.clone())),
+        }
+    }
+}
+
+/// Returns a data loader whose batch holds a full page of sleeps, dreams or tags, and waits a little longer than the default
+/// for the other keys of a listing
+fn data_loader<T: Loader<i64>>(loader: T) -> DataLoader<T> {
+    DataLoader::new(loader, tokio::spawn).delay(LOADER_DELAY).max_batch_size(MAX_PAGE_SIZE)
+}
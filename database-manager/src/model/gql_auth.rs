@@ -0,0 +1,49 @@
+use std::sync::Arc;
+use async_graphql::{async_trait, Context, ErrorExtensions, Guard, PathSegment, Result, ServerError, ServerResult, Value};
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextResolve, ResolveInfo};
+use crate::db_manager::Principal;
+
+/// Schema extension that refuses every mutation of a request authenticated with a read-only api token
+pub struct ReadOnlyGuard;
+
+impl ExtensionFactory for ReadOnlyGuard {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ReadOnlyGuardExtension)
+    }
+}
+
+struct ReadOnlyGuardExtension;
+
+#[async_trait::async_trait]
+impl Extension for ReadOnlyGuardExtension {
+    async fn resolve(&self, ctx: &ExtensionContext<'_>, info: ResolveInfo<'_>, next: NextResolve<'_>) -> ServerResult<Option<Value>> {
+        let read_only = ctx.data_opt::<Principal>().is_some_and(|p| p.read_only);
+        if read_only && info.parent_type == "MutationRoot" {
+            let error = async_graphql::Error::new(format!("{} is not allowed with a read-only api token", info.name))
+                .extend_with(|_, e| e.set("code", "READ_ONLY"));
+
+            let mut server_error = ServerError::new(error.message, None);
+            server_error.extensions = error.extensions;
+            server_error.path = vec![PathSegment::Field(info.path_node.to_string())];
+            return Err(server_error);
+        }
+
+        next.run(ctx, info).await
+    }
+}
+
+/// Field guard that refuses the field unless the request is authenticated with an admin api token
+pub struct AdminGuard;
+
+#[async_trait::async_trait]
+impl Guard for AdminGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if ctx.data_opt::<Principal>().is_some_and(|p| p.admin) {
+            return Ok(());
+        }
+
+        let field = ctx.path_node.map(|node| node.field_name().to_string()).unwrap_or_default();
+        Err(async_graphql::Error::new(format!("{} is only allowed with an admin api token", field))
+            .extend_with(|_, e| e.set("code", "ADMIN_ONLY")))
+    }
+}
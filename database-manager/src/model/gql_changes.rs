@@ -0,0 +1,173 @@
+use async_graphql::{async_stream, Enum, SimpleObject};
+use async_graphql::futures_util::Stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use super::gql_types::{Comment, Sleep, Tag};
+
+/// Number of changes kept for subscribers that fall behind, older changes are skipped by those subscribers
+const CHANGE_CAPACITY: usize = 256;
+
+/// How a row was changed
+#[derive(Debug, Enum, Copy, Clone, Eq, PartialEq)]
+pub enum ChangeKind {
+    /// The row was added
+    Created,
+
+    /// The row, or a row that belongs to it, was changed
+    Updated,
+
+    /// The row was deleted
+    Deleted,
+}
+
+/// A sleep that was created, updated or deleted
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct SleepChanged {
+    /// How the sleep was changed
+    pub kind: ChangeKind,
+
+    /// id of the sleep
+    pub id: i64,
+
+    /// The sleep after the change, null if the sleep was deleted
+    pub sleep: Option<Sleep>,
+}
+
+/// A tag that was created, updated or deleted
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct TagChanged {
+    /// How the tag was changed
+    pub kind: ChangeKind,
+
+    /// id of the tag
+    pub id: i64,
+
+    /// The tag after the change, null if the tag was deleted
+    pub tag: Option<Tag>,
+}
+
+/// A comment that was created, updated or deleted
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct CommentChanged {
+    /// How the comment was changed
+    pub kind: ChangeKind,
+
+    /// id of the comment
+    pub id: i64,
+
+    /// The comment after the change, null if the comment was deleted
+    pub comment: Option<Comment>,
+}
+
+impl SleepChanged {
+    pub fn created(sleep: &Sleep) -> SleepChanged {
+        SleepChanged { kind: ChangeKind::Created, id: sleep.id, sleep: Some(sleep.clone()) }
+    }
+
+    pub fn updated(sleep: &Sleep) -> SleepChanged {
+        SleepChanged { kind: ChangeKind::Updated, id: sleep.id, sleep: Some(sleep.clone()) }
+    }
+
+    pub fn deleted(id: i64) -> SleepChanged {
+        SleepChanged { kind: ChangeKind::Deleted, id, sleep: None }
+    }
+}
+
+impl TagChanged {
+    pub fn created(tag: &Tag) -> TagChanged {
+        TagChanged { kind: ChangeKind::Created, id: tag.id, tag: Some(tag.clone()) }
+    }
+
+    pub fn updated(tag: &Tag) -> TagChanged {
+        TagChanged { kind: ChangeKind::Updated, id: tag.id, tag: Some(tag.clone()) }
+    }
+
+    pub fn deleted(id: i64) -> TagChanged {
+        TagChanged { kind: ChangeKind::Deleted, id, tag: None }
+    }
+}
+
+impl CommentChanged {
+    pub fn created(comment: &Comment) -> CommentChanged {
+        CommentChanged { kind: ChangeKind::Created, id: comment.id, comment: Some(comment.clone()) }
+    }
+
+    pub fn updated(comment: &Comment) -> CommentChanged {
+        CommentChanged { kind: ChangeKind::Updated, id: comment.id, comment: Some(comment.clone()) }
+    }
+
+    pub fn deleted(id: i64) -> CommentChanged {
+        CommentChanged { kind: ChangeKind::Deleted, id, comment: None }
+    }
+}
+
+/// A change that is published to subscribers
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Sleep(SleepChanged),
+    Tag(TagChanged),
+    Comment(CommentChanged),
+}
+
+impl From<SleepChanged> for Change {
+    fn from(change: SleepChanged) -> Self {
+        Change::Sleep(change)
+    }
+}
+
+impl From<TagChanged> for Change {
+    fn from(change: TagChanged) -> Self {
+        Change::Tag(change)
+    }
+}
+
+impl From<CommentChanged> for Change {
+    fn from(change: CommentChanged) -> Self {
+        Change::Comment(change)
+    }
+}
+
+/// A change and the user whose data was changed
+#[derive(Debug, Clone)]
+struct UserChange {
+    user_id: i64,
+    change: Change,
+}
+
+/// Broadcast channel that mutations publish changes to, and subscriptions receive changes from
+#[derive(Debug, Clone)]
+pub struct ChangeChannel {
+    sender: broadcast::Sender<UserChange>,
+}
+
+impl ChangeChannel {
+    pub fn new() -> ChangeChannel {
+        let (sender, _) = broadcast::channel(CHANGE_CAPACITY);
+        ChangeChannel { sender }
+    }
+
+    /// Publishes a change of the data of a user to every subscriber of that user
+    pub fn publish(&self, user_id: i64, change: impl Into<Change>) {
+        // sending only fails when nobody is subscribed, and then there is nobody to tell
+        let _ = self.sender.send(UserChange { user_id, change: change.into() });
+    }
+
+    /// Returns a stream of every change to the data of a user published after subscribing
+    pub fn subscribe(&self, user_id: i64) -> impl Stream<Item = Change> {
+        let mut receiver = self.sender.subscribe();
+        async_stream::stream! {
+            loop {
+                match receiver.recv().await {
+                    Ok(published) if published.user_id == user_id => yield published.change,
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+}
+
+impl Default for ChangeChannel {
+    fn default() -> Self {
+        ChangeChannel::new()
+    }
+}
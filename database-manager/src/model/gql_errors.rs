@@ -0,0 +1,60 @@
+use async_graphql::{Error, ErrorExtensions};
+use crate::db_manager::DBError;
+
+impl DBError {
+    /// Machine readable code that is added to the `extensions.code` of a graphql error
+    pub fn code(&self) -> &'static str {
+        match self {
+            DBError::NotFound { .. } => "NOT_FOUND",
+            DBError::DuplicateNight(_) => "DUPLICATE_NIGHT",
+            DBError::DuplicateTagName(_) => "DUPLICATE_TAG_NAME",
+            DBError::DuplicateUserName(_) => "DUPLICATE_USER_NAME",
+            DBError::DuplicateCategoryName(_) => "DUPLICATE_CATEGORY_NAME",
+            DBError::ForeignKeyViolation(_) => "FOREIGN_KEY_VIOLATION",
+            DBError::Validation(_) => "VALIDATION_FAILED",
+            DBError::UnsupportedSchemaVersion { .. } => "UNSUPPORTED_SCHEMA_VERSION",
+            DBError::Storage(_) => "STORAGE_FAILURE",
+        }
+    }
+}
+
+impl ErrorExtensions for DBError {
+    fn extend(&self) -> Error {
+        Error::new(self.to_string()).extend_with(|_, e| {
+            e.set("code", self.code());
+
+            match self {
+                DBError::NotFound { entity, id } => {
+                    e.set("entity", *entity);
+                    e.set("id", *id);
+                },
+                DBError::DuplicateNight(night) => e.set("night", night.to_string()),
+                DBError::DuplicateTagName(name) | DBError::DuplicateUserName(name) | DBError::DuplicateCategoryName(name) =>
+                    e.set("name", name.as_str()),
+                _ => {}
+            }
+        })
+    }
+}
+
+/// Converts the result of looking up a single row into an optional value for queries,
+/// where a row that does not exist is returned as null instead of an error
+pub fn optional<T>(result: Result<T, DBError>) -> async_graphql::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(DBError::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e.extend())
+    }
+}
+
+/// Adds the step of a mutation that failed to the `extensions.step` of the graphql error, ex: "tags".
+/// Mutations made of many steps run in a unit of work, so when a step fails none of the steps are saved.
+pub trait StepResultExt<T> {
+    fn in_step(self, step: &'static str) -> async_graphql::Result<T>;
+}
+
+impl<T> StepResultExt<T> for Result<T, DBError> {
+    fn in_step(self, step: &'static str) -> async_graphql::Result<T> {
+        self.map_err(|e| e.extend_with(|_, extensions| extensions.set("step", step)))
+    }
+}
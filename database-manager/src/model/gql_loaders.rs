@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_graphql::async_trait;
+use async_graphql::dataloader::Loader;
+use crate::db_manager::DBError;
+use crate::DBManager;
+use super::gql_types::{Comment, Dream, HypnogramInterval, Sleep, SleepSession, SleepStages, Tag, TagCategory};
+
+/// Batches loading sleeps by id, so a listing of dreams loads the sleep of every dream with one query
+pub struct SleepLoader {
+    /// Database manager used to query the sleeps
+    dbm: DBManager,
+}
+
+/// Batches loading the tags of sleeps, so a listing of sleeps loads the tags of every sleep with one query
+pub struct SleepTagsLoader {
+    /// Database manager used to query the tags
+    dbm: DBManager,
+}
+
+/// Batches loading the comments of sleeps, so a listing of sleeps loads the comments of every sleep with one query
+pub struct SleepCommentsLoader {
+    /// Database manager used to query the comments
+    dbm: DBManager,
+}
+
+/// Batches loading the sessions of sleeps, so a listing of sleeps loads the sessions of every sleep with one query
+pub struct SleepSessionsLoader {
+    /// Database manager used to query the sessions
+    dbm: DBManager,
+}
+
+/// Batches loading the dreams of sleeps, so a listing of sleeps loads the dreams of every sleep with one query
+pub struct SleepDreamsLoader {
+    /// Database manager used to query the dreams
+    dbm: DBManager,
+}
+
+/// Batches loading the stages of sleeps, so a listing of sleeps loads the stages of every sleep with one query
+pub struct SleepStagesLoader {
+    /// Database manager used to query the stages
+    dbm: DBManager,
+}
+
+/// Batches loading the hypnograms of sleeps, so a listing of sleeps loads the hypnogram of every sleep with one query
+pub struct HypnogramLoader {
+    /// Database manager used to query the hypnograms
+    dbm: DBManager,
+}
+
+/// Batches loading the tags of dreams, so a listing of dreams loads the tags of every dream with one query
+pub struct DreamTagsLoader {
+    /// Database manager used to query the tags
+    dbm: DBManager,
+}
+
+/// Batches loading the parent and category of tags, so a listing of tags loads them with a query for each
+pub struct TagHierarchyLoader {
+    /// Database manager used to query the hierarchy
+    dbm: DBManager,
+}
+
+/// Batches loading the children of tags, so a listing of tags loads the children of every tag with one query
+pub struct TagChildrenLoader {
+    /// Database manager used to query the children
+    dbm: DBManager,
+}
+
+/// Batches loading the tags of categories, so a listing of categories loads the tags of every category with one query
+pub struct CategoryTagsLoader {
+    /// Database manager used to query the tags
+    dbm: DBManager,
+}
+
+/// The parent and category of a tag
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagHierarchy {
+    /// Parent of the tag, None for a tag at the top of the hierarchy
+    pub parent: Option<Tag>,
+
+    /// Category of the tag, None for a tag without a category
+    pub category: Option<TagCategory>,
+}
+
+impl SleepLoader {
+    pub fn new(dbm: DBManager) -> SleepLoader {
+        SleepLoader { dbm }
+    }
+}
+
+impl SleepTagsLoader {
+    pub fn new(dbm: DBManager) -> SleepTagsLoader {
+        SleepTagsLoader { dbm }
+    }
+}
+
+impl SleepCommentsLoader {
+    pub fn new(dbm: DBManager) -> SleepCommentsLoader {
+        SleepCommentsLoader { dbm }
+    }
+}
+
+impl SleepSessionsLoader {
+    pub fn new(dbm: DBManager) -> SleepSessionsLoader {
+        SleepSessionsLoader { dbm }
+    }
+}
+
+impl SleepDreamsLoader {
+    pub fn new(dbm: DBManager) -> SleepDreamsLoader {
+        SleepDreamsLoader { dbm }
+    }
+}
+
+impl SleepStagesLoader {
+    pub fn new(dbm: DBManager) -> SleepStagesLoader {
+        SleepStagesLoader { dbm }
+    }
+}
+
+impl HypnogramLoader {
+    pub fn new(dbm: DBManager) -> HypnogramLoader {
+        HypnogramLoader { dbm }
+    }
+}
+
+impl DreamTagsLoader {
+    pub fn new(dbm: DBManager) -> DreamTagsLoader {
+        DreamTagsLoader { dbm }
+    }
+}
+
+impl TagHierarchyLoader {
+    pub fn new(dbm: DBManager) -> TagHierarchyLoader {
+        TagHierarchyLoader { dbm }
+    }
+}
+
+impl TagChildrenLoader {
+    pub fn new(dbm: DBManager) -> TagChildrenLoader {
+        TagChildrenLoader { dbm }
+    }
+}
+
+impl CategoryTagsLoader {
+    pub fn new(dbm: DBManager) -> CategoryTagsLoader {
+        CategoryTagsLoader { dbm }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for SleepLoader {
+    type Value = Sleep;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let sleeps = self.dbm.get_multiple_sleeps(sleep_ids.to_vec()).await?;
+        Ok(sleeps.iter().map(|s| (s.sleep.id, Sleep::from_db(s))).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for SleepTagsLoader {
+    type Value = Vec<Tag>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let tags = self.dbm.get_tags_by_sleeps(sleep_ids).await?;
+        Ok(tags.into_iter().map(|(sleep_id, tags)| {
+            let tags = tags.into_iter().map(|t| Tag { id: t.id, name: t.name, color: t.color }).collect();
+            (sleep_id, tags)
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for SleepCommentsLoader {
+    type Value = Vec<Comment>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let comments = self.dbm.get_comments_by_sleeps(sleep_ids).await?;
+        Ok(comments.into_iter().map(|(sleep_id, comments)| {
+            let comments = comments.into_iter().map(|c| Comment { id: c.id, sleep_id: c.sleep_id, comment: c.comment }).collect();
+            (sleep_id, comments)
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for SleepSessionsLoader {
+    type Value = Vec<SleepSession>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let sessions = self.dbm.get_sleep_sessions_by_sleeps(sleep_ids).await?;
+        Ok(sessions.into_iter().map(|(sleep_id, sessions)| {
+            (sleep_id, sessions.iter().map(SleepSession::from_db).collect())
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for SleepDreamsLoader {
+    type Value = Vec<Dream>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let dreams = self.dbm.get_dreams_by_sleeps(sleep_ids).await?;
+        Ok(dreams.into_iter().map(|(sleep_id, dreams)| {
+            (sleep_id, dreams.iter().map(Dream::from_db).collect())
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for SleepStagesLoader {
+    type Value = SleepStages;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let stages = self.dbm.get_sleep_stages_by_sleeps(sleep_ids).await?;
+        Ok(stages.into_iter().map(|(sleep_id, stages)| (sleep_id, SleepStages::from(&stages.stages()))).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for HypnogramLoader {
+    type Value = Vec<HypnogramInterval>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, sleep_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let intervals = self.dbm.get_hypnograms_by_sleeps(sleep_ids).await?;
+        Ok(intervals.into_iter().map(|(sleep_id, intervals)| {
+            (sleep_id, intervals.iter().map(HypnogramInterval::from).collect())
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for DreamTagsLoader {
+    type Value = Vec<Tag>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, dream_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let tags = self.dbm.get_tags_by_dreams(dream_ids).await?;
+        Ok(tags.into_iter().map(|(dream_id, tags)| {
+            let tags = tags.into_iter().map(|t| Tag { id: t.id, name: t.name, color: t.color }).collect();
+            (dream_id, tags)
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for TagHierarchyLoader {
+    type Value = TagHierarchy;
+    type Error = Arc<DBError>;
+
+    /// Loads the hierarchies of the tags, then the parents and categories they refer to, one query each
+    async fn load(&self, tag_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let hierarchies = self.dbm.get_tag_hierarchies(tag_ids).await?;
+
+        let parent_ids: Vec<i64> = hierarchies.values().filter_map(|h| h.parent_id).collect();
+        let parents: HashMap<i64, Tag> = if parent_ids.is_empty() {
+            HashMap::new()
+        } else {
+            self.dbm.get_multiple_tags(parent_ids).await?.into_iter()
+                .map(|t| (t.id, Tag { id: t.id, name: t.name, color: t.color })).collect()
+        };
+
+        let categories: HashMap<i64, TagCategory> = if hierarchies.values().all(|h| h.category_id.is_none()) {
+            HashMap::new()
+        } else {
+            self.dbm.get_tag_categories().await?.into_iter()
+                .map(|c| (c.id, TagCategory { id: c.id, name: c.name })).collect()
+        };
+
+        Ok(hierarchies.into_iter().map(|(tag_id, h)| {
+            let hierarchy = TagHierarchy {
+                parent: h.parent_id.and_then(|id| parents.get(&id).cloned()),
+                category: h.category_id.and_then(|id| categories.get(&id).cloned()),
+            };
+            (tag_id, hierarchy)
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for TagChildrenLoader {
+    type Value = Vec<Tag>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, tag_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let children = self.dbm.get_tag_children_by_parents(tag_ids).await?;
+        Ok(children.into_iter().map(|(tag_id, tags)| {
+            let tags = tags.into_iter().map(|t| Tag { id: t.id, name: t.name, color: t.color }).collect();
+            (tag_id, tags)
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<i64> for CategoryTagsLoader {
+    type Value = Vec<Tag>;
+    type Error = Arc<DBError>;
+
+    async fn load(&self, category_ids: &[i64]) -> Result<HashMap<i64, Self::Value>, Self::Error> {
+        let tags = self.dbm.get_tags_by_categories(category_ids).await?;
+        Ok(tags.into_iter().map(|(category_id, tags)| {
+            let tags = tags.into_iter().map(|t| Tag { id: t.id, name: t.name, color: t.color }).collect();
+            (category_id, tags)
+        }).collect())
+    }
+}
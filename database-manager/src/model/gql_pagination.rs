@@ -0,0 +1,114 @@
+use async_graphql::SimpleObject;
+use async_graphql::connection::{Connection, CursorType, Edge, EmptyFields};
+use chrono::NaiveDate;
+use crate::db_manager::{DBDream, DbmSleep, Page, PageArgs};
+
+/// Additional fields of every connection
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct ConnectionFields {
+    /// Number of items in the listing, ignoring the cursors and page size
+    pub total_count: i64,
+}
+
+/// Relay connection with a total count of the items
+pub type CountedConnection<Cursor, Node> = Connection<Cursor, Node, ConnectionFields, EmptyFields>;
+
+/// Cursor of a sleep, sleeps are ordered by night and then id.
+/// Serialized as the night and id separated by a colon, ex: "2023-05-13:42"
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SleepCursor {
+    /// Night of the sleep
+    pub night: NaiveDate,
+
+    /// id of the sleep
+    pub id: i64,
+}
+
+impl SleepCursor {
+    pub fn from_db(sleep: &DbmSleep) -> SleepCursor {
+        SleepCursor { night: sleep.sleep.night, id: sleep.sleep.id }
+    }
+
+    /// Returns the key of the sleep the cursor points to
+    pub fn key(&self) -> (NaiveDate, i64) {
+        (self.night, self.id)
+    }
+}
+
+impl CursorType for SleepCursor {
+    type Error = String;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        let (night, id) = decode_night_cursor(s, "sleep")?;
+        Ok(SleepCursor { night, id })
+    }
+
+    fn encode_cursor(&self) -> String {
+        format!("{}:{}", self.night, self.id)
+    }
+}
+
+/// Cursor of a dream, dreams are ordered by the night of their sleep and then id.
+/// Serialized the same way as a [SleepCursor], with the id of the dream, ex: "2023-05-13:7"
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DreamCursor {
+    /// Night of the sleep the dream happened during
+    pub night: NaiveDate,
+
+    /// id of the dream
+    pub id: i64,
+}
+
+impl DreamCursor {
+    pub fn from_db(dream: &(NaiveDate, DBDream)) -> DreamCursor {
+        DreamCursor { night: dream.0, id: dream.1.id }
+    }
+
+    /// Returns the key of the dream the cursor points to
+    pub fn key(&self) -> (NaiveDate, i64) {
+        (self.night, self.id)
+    }
+}
+
+impl CursorType for DreamCursor {
+    type Error = String;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        let (night, id) = decode_night_cursor(s, "dream")?;
+        Ok(DreamCursor { night, id })
+    }
+
+    fn encode_cursor(&self) -> String {
+        format!("{}:{}", self.night, self.id)
+    }
+}
+
+/// Decodes a cursor of a night and id separated by a colon, the entity names the cursor in the error
+fn decode_night_cursor(s: &str, entity: &str) -> Result<(NaiveDate, i64), String> {
+    let invalid = || format!("\"{}\" is not a valid {} cursor", s, entity);
+
+    let (night, id) = s.split_once(':').ok_or_else(invalid)?;
+    let night = NaiveDate::parse_from_str(night, "%Y-%m-%d").map_err(|_| invalid())?;
+    Ok((night, id.parse().map_err(|_| invalid())?))
+}
+
+/// Creates page arguments from the decoded arguments of a connection field
+pub fn page_args<K>(after: Option<K>, before: Option<K>, first: Option<usize>, last: Option<usize>) -> PageArgs<K> {
+    PageArgs { after, before, first, last }
+}
+
+/// Converts a page of rows into a connection, using `cursor` and `node` to create each edge
+pub fn to_connection<T, Cursor, Node>(
+    page: Page<T>,
+    cursor: impl Fn(&T) -> Cursor,
+    node: impl Fn(&T) -> Node)
+    -> CountedConnection<Cursor, Node>
+where
+    Cursor: CursorType + Send + Sync,
+    Node: async_graphql::OutputType,
+{
+    let fields = ConnectionFields { total_count: page.total_count };
+    let mut connection = Connection::with_additional_fields(page.has_previous_page, page.has_next_page, fields);
+    connection.edges.extend(page.items.iter().map(|item| Edge::new(cursor(item), node(item))));
+    connection
+}
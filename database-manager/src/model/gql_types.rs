@@ -1,6 +1,11 @@
-use async_graphql::{Context, Object, SimpleObject, InputObject};
-use crate::db_manager::DbmSleep;
+use async_graphql::{ComplexObject, Context, Enum, ErrorExtensions, Object, SimpleObject, InputObject, Result, ResultExt};
+use async_graphql::dataloader::DataLoader;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use crate::db_manager::{DbmSleep, DBApiToken, DBDream, DBError, DBHypnogramInterval, DBSleepSession, DreamDetails, DreamUpdate, SessionKind, SleepTimes};
+use crate::db_manager;
 use crate::DBManager;
+use super::gql_loaders::{CategoryTagsLoader, DreamTagsLoader, HypnogramLoader, SleepCommentsLoader, SleepDreamsLoader, SleepLoader,
+    SleepSessionsLoader, SleepStagesLoader, SleepTagsLoader, TagChildrenLoader, TagHierarchyLoader};
 
 /// Graphql representation of a sleep
 #[derive(Debug, Clone, Default, PartialEq)]
@@ -11,53 +16,27 @@ pub struct Sleep {
     /// Date of the night
     pub night: Night,
 
-    /// Amount of sleep
+    /// Total amount of sleep from all sessions of the night
     pub amount: f64,
 
     /// Quality of sleep, scale is flexible
     pub quality: i64,
-
-    /// Tags assoicated to the sleep
-    pub tags: Option<Vec<Tag>>,
-
-    /// Comments associated to the sleep
-    pub comments: Option<Vec<Comment>>
 }
 
 impl Sleep {
     pub fn from_db(db_sleep: &DbmSleep) -> Sleep {
-        let tags = match &db_sleep.tags {
-            Some(v) => {
-                let tag = v.iter().map(|t| Tag { id: t.id, name: t.name.clone(), color: t.color }).collect::<Vec<Tag>>();
-                Some(tag)
-            },
-            None => None,
-        };
-
         Sleep {
             id: db_sleep.sleep.id,
-            night: Night::from_string(db_sleep.sleep.night.clone()),
+            night: Night::from_date(db_sleep.sleep.night),
             amount: db_sleep.sleep.amount,
             quality: db_sleep.sleep.quality,
-            tags,
-            comments: None
         }
     }
 
-    pub async fn from_sleep_id(dbm: &DBManager, sleep_id: i64) -> Option<Sleep> {
+    pub async fn from_sleep_id(dbm: &DBManager, sleep_id: i64) -> Result<Sleep, DBError> {
         let sleep = dbm.get_sleep(sleep_id, false).await;
         sleep.map(|s| Sleep::from_db(&s)) 
     }
-
-    pub fn filter_sleeps_by_date(
-        sleeps: Option<Vec<Sleep>>,
-        start_date: &SleepsInRangeInput,
-        end_date: &SleepsInRangeInput)
-        -> Option<Vec<Sleep>> {
-            sleeps.map(|v| v.into_iter()
-                .filter(|s| s.night.in_date_range(start_date, end_date))
-                .collect::<Vec<Sleep>>())
-        }
 }
 
 #[Object]
@@ -70,6 +49,7 @@ impl Sleep {
         self.night.clone()
     }
 
+    /// Total amount of sleep from all sessions of the night
     async fn amount(&self) -> f64 {
         self.amount
     }
@@ -78,35 +58,229 @@ impl Sleep {
         self.quality
     }
 
-    async fn tags(&self, ctx: &Context<'_>) -> Option<Vec<Tag>> {
-        let dbm = ctx.data_unchecked::<DBManager>();
-        let tags = dbm.get_tags_by_sleep(self.id).await;
-        let tags = match tags {
-            Some(v) => {
-                let tag = v.iter().map(|t| Tag { id: t.id, name: t.name.clone(), color: t.color }).collect::<Vec<Tag>>();
-                Some(tag)
-            },
-            None => None,
-        };
-        tags
+    /// Time the sleeper went to bed for the main session
+    #[graphql(deprecation = "Use the times of the sessions")]
+    async fn bed_time(&self, ctx: &Context<'_>) -> Result<Option<NaiveDateTime>> {
+        Ok(self.main_session(ctx).await?.and_then(|s| s.bed_time))
     }
 
-    async fn comments(&self, ctx: &Context<'_>) -> Option<Vec<Comment>> {
-        let dbm = ctx.data_unchecked::<DBManager>();
-        let comments = dbm.get_comments_by_sleep(self.id).await;
-        match comments {
-            Some(v) => {
-                let comment = v.iter().map(|c| 
-                    Comment { id: c.id, sleep_id: c.sleep_id, comment: c.comment.clone() }).collect::<Vec<Comment>>();
-                Some(comment)
-            },
-            None => None,
+    /// Time the sleeper fell asleep for the main session
+    #[graphql(deprecation = "Use the times of the sessions")]
+    async fn sleep_onset(&self, ctx: &Context<'_>) -> Result<Option<NaiveDateTime>> {
+        Ok(self.main_session(ctx).await?.and_then(|s| s.sleep_onset))
+    }
+
+    /// Time the sleeper woke up from the main session
+    #[graphql(deprecation = "Use the times of the sessions")]
+    async fn wake_time(&self, ctx: &Context<'_>) -> Result<Option<NaiveDateTime>> {
+        Ok(self.main_session(ctx).await?.and_then(|s| s.wake_time))
+    }
+
+    /// Sessions of sleep during the night, ordered by the time they started
+    async fn sessions(&self, ctx: &Context<'_>) -> Result<Vec<SleepSession>> {
+        let loader = ctx.data_unchecked::<DataLoader<SleepSessionsLoader>>();
+        let sessions = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(sessions.unwrap_or_default())
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<Tag>> {
+        let loader = ctx.data_unchecked::<DataLoader<SleepTagsLoader>>();
+        let tags = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(tags.unwrap_or_default())
+    }
+
+    async fn comments(&self, ctx: &Context<'_>) -> Result<Vec<Comment>> {
+        let loader = ctx.data_unchecked::<DataLoader<SleepCommentsLoader>>();
+        let comments = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(comments.unwrap_or_default())
+    }
+
+    /// Dreams that happened during the sleep
+    async fn dreams(&self, ctx: &Context<'_>) -> Result<Vec<Dream>> {
+        let loader = ctx.data_unchecked::<DataLoader<SleepDreamsLoader>>();
+        let dreams = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(dreams.unwrap_or_default())
+    }
+
+    /// Minutes spent in each stage of sleep, null if no stages were recorded for the night
+    async fn stages(&self, ctx: &Context<'_>) -> Result<Option<SleepStages>> {
+        let loader = ctx.data_unchecked::<DataLoader<SleepStagesLoader>>();
+        loader.load_one(self.id).await.map_err(|e| e.extend())
+    }
+
+    /// Stages of sleep in the order they happened, empty if no hypnogram was recorded for the night
+    async fn hypnogram(&self, ctx: &Context<'_>) -> Result<Vec<HypnogramInterval>> {
+        let loader = ctx.data_unchecked::<DataLoader<HypnogramLoader>>();
+        let intervals = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(intervals.unwrap_or_default())
+    }
+}
+
+impl Sleep {
+    /// Returns the main session of the sleep, the sessions are loaded once for all of the times of a listing of sleeps
+    async fn main_session(&self, ctx: &Context<'_>) -> Result<Option<SleepSession>> {
+        let sessions = self.sessions(ctx).await?;
+        Ok(sessions.into_iter().find(|s| s.kind == SleepKind::Main))
+    }
+}
+
+/// The kind of a sleep session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Enum)]
+pub enum SleepKind {
+    /// The main sleep of the night, a night has at most one
+    #[default]
+    Main,
+
+    /// A nap during the day
+    Nap,
+
+    /// Part of a night of sleep that was split into multiple sessions
+    Split,
+}
+
+impl From<SessionKind> for SleepKind {
+    fn from(kind: SessionKind) -> Self {
+        match kind {
+            SessionKind::Main => SleepKind::Main,
+            SessionKind::Nap => SleepKind::Nap,
+            SessionKind::Split => SleepKind::Split,
+        }
+    }
+}
+
+impl From<SleepKind> for SessionKind {
+    fn from(kind: SleepKind) -> Self {
+        match kind {
+            SleepKind::Main => SessionKind::Main,
+            SleepKind::Nap => SessionKind::Nap,
+            SleepKind::Split => SessionKind::Split,
+        }
+    }
+}
+
+/// Graphql representation of a single period of sleep during a night
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct SleepSession {
+    /// Primary key
+    pub id: i64,
+
+    /// id of the sleep (night) the session belongs to
+    pub sleep_id: i64,
+
+    /// Kind of session
+    pub kind: SleepKind,
+
+    /// Amount of sleep during the session
+    pub amount: f64,
+
+    /// Time the sleeper went to bed
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// Time the sleeper fell asleep
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// Time the sleeper woke up
+    pub wake_time: Option<NaiveDateTime>,
+}
+
+impl SleepSession {
+    pub fn from_db(session: &DBSleepSession) -> SleepSession {
+        SleepSession {
+            id: session.id,
+            sleep_id: session.sleep_id,
+            kind: session.kind().into(),
+            amount: session.amount,
+            bed_time: session.bed_time,
+            sleep_onset: session.sleep_onset,
+            wake_time: session.wake_time,
         }
     }
+
+    pub async fn from_session_id(dbm: &DBManager, session_id: i64) -> Result<SleepSession, DBError> {
+        let session = dbm.get_sleep_session(session_id).await;
+        session.map(|s| SleepSession::from_db(&s))
+    }
+}
+
+/// A stage of sleep recorded by a wearable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(remote = "db_manager::SleepStage")]
+pub enum SleepStage {
+    Deep,
+
+    /// Light sleep, called core sleep by some devices
+    Light,
+
+    Rem,
+
+    /// Awake after falling asleep
+    Awake,
+}
+
+/// Graphql representation of the minutes spent in each stage of sleep during a night
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct SleepStages {
+    /// Minutes of deep sleep, null if not recorded
+    pub deep_minutes: Option<i64>,
+
+    /// Minutes of light sleep, null if not recorded
+    pub light_minutes: Option<i64>,
+
+    /// Minutes of REM sleep, null if not recorded
+    pub rem_minutes: Option<i64>,
+
+    /// Minutes awake after falling asleep, null if not recorded
+    pub awake_minutes: Option<i64>,
+
+    /// Share of the minutes asleep spent in deep sleep, between 0 and 1
+    pub deep_share: Option<f64>,
+
+    /// Share of the minutes asleep spent in light sleep, between 0 and 1
+    pub light_share: Option<f64>,
+
+    /// Share of the minutes asleep spent in REM sleep, between 0 and 1
+    pub rem_share: Option<f64>,
+
+    /// Share of the minutes asleep and awake spent awake, between 0 and 1
+    pub awake_share: Option<f64>,
+}
+
+impl From<&db_manager::SleepStages> for SleepStages {
+    fn from(stages: &db_manager::SleepStages) -> Self {
+        SleepStages {
+            deep_minutes: stages.deep_minutes,
+            light_minutes: stages.light_minutes,
+            rem_minutes: stages.rem_minutes,
+            awake_minutes: stages.awake_minutes,
+            deep_share: stages.share(db_manager::SleepStage::Deep),
+            light_share: stages.share(db_manager::SleepStage::Light),
+            rem_share: stages.share(db_manager::SleepStage::Rem),
+            awake_share: stages.share(db_manager::SleepStage::Awake),
+        }
+    }
+}
+
+/// Graphql representation of a period of a night spent in one stage of sleep
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct HypnogramInterval {
+    pub stage: SleepStage,
+
+    /// Time the stage started
+    pub start: NaiveDateTime,
+
+    /// Time the stage ended
+    pub end: NaiveDateTime,
+}
+
+impl From<&DBHypnogramInterval> for HypnogramInterval {
+    fn from(interval: &DBHypnogramInterval) -> Self {
+        HypnogramInterval { stage: interval.stage().into(), start: interval.start_time, end: interval.end_time }
+    }
 }
 
 /// Graphql representation of a tag
 #[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+#[graphql(complex)]
 pub struct Tag {
     /// Primary key
     pub id: i64,
@@ -119,15 +293,130 @@ pub struct Tag {
 }
 
 impl Tag {
-    pub async fn from_tag_id(dbm: &DBManager, tag_id: i64) -> Option<Tag> {
+    pub async fn from_tag_id(dbm: &DBManager, tag_id: i64) -> Result<Tag, DBError> {
         let tag = dbm.get_tag(tag_id).await;
-        match tag {
-            Some(t) => Some(Tag { id: t.id, name: t.name.clone(), color: t.color }),
-            None => None,
-        } 
+        tag.map(|t| Tag { id: t.id, name: t.name, color: t.color })
     }
 }
 
+#[ComplexObject]
+impl Tag {
+    /// Old names of the tag, from tags merged into it and from renames, that resolve to the tag
+    async fn aliases(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        let aliases = dbm.get_tag_aliases(self.id).await.extend()?;
+        Ok(aliases.into_iter().map(|a| a.name).collect::<Vec<String>>())
+    }
+
+    /// Parent of the tag, null for a tag at the top of the hierarchy
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<Tag>> {
+        let loader = ctx.data_unchecked::<DataLoader<TagHierarchyLoader>>();
+        let hierarchy = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(hierarchy.and_then(|h| h.parent))
+    }
+
+    /// Tags whose parent is the tag, ordered by id
+    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<Tag>> {
+        let loader = ctx.data_unchecked::<DataLoader<TagChildrenLoader>>();
+        let children = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(children.unwrap_or_default())
+    }
+
+    /// Category of the tag, null for a tag without a category
+    async fn category(&self, ctx: &Context<'_>) -> Result<Option<TagCategory>> {
+        let loader = ctx.data_unchecked::<DataLoader<TagHierarchyLoader>>();
+        let hierarchy = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(hierarchy.and_then(|h| h.category))
+    }
+
+    /// Number of sleeps with the tag
+    async fn sleep_count(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Also count the sleeps with the children of the tag, and their children.", default)] roll_up: bool)
+        -> Result<i64> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        dbm.count_sleeps_by_tag(self.id, roll_up).await.extend()
+    }
+}
+
+/// Graphql representation of a tag category, a group of tags ex: "Substances"
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+#[graphql(complex)]
+pub struct TagCategory {
+    /// Primary key
+    pub id: i64,
+
+    /// Name of the category
+    pub name: String,
+}
+
+impl TagCategory {
+    pub async fn from_category_id(dbm: &DBManager, category_id: i64) -> Result<TagCategory, DBError> {
+        let category = dbm.get_tag_category(category_id).await;
+        category.map(|c| TagCategory { id: c.id, name: c.name })
+    }
+}
+
+#[ComplexObject]
+impl TagCategory {
+    /// Tags of the category, ordered by id
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<Tag>> {
+        let loader = ctx.data_unchecked::<DataLoader<CategoryTagsLoader>>();
+        let tags = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(tags.unwrap_or_default())
+    }
+}
+
+/// Graphql representation of a user, every sleep, tag and comment belongs to a user
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct User {
+    /// Primary key
+    pub id: i64,
+
+    /// Unique name of the user
+    pub name: String,
+}
+
+impl User {
+    pub async fn from_user_id(dbm: &DBManager, user_id: i64) -> Result<User, DBError> {
+        let user = dbm.get_user(user_id).await;
+        user.map(|u| User { id: u.id, name: u.name })
+    }
+}
+
+/// Graphql representation of an api token, the token itself is only returned when it is created
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct ApiToken {
+    /// Primary key
+    pub id: i64,
+
+    /// Name of the token to tell tokens apart, ex: "phone"
+    pub name: String,
+
+    /// true if the token can only be used to query data
+    pub read_only: bool,
+
+    /// true if the token can also manage the server, ex: add users
+    pub admin: bool,
+}
+
+impl ApiToken {
+    pub fn from_db(token: &DBApiToken) -> ApiToken {
+        ApiToken { id: token.id, name: token.name.clone(), read_only: token.read_only, admin: token.admin }
+    }
+}
+
+/// Graphql representation of a newly created api token
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct CreatedApiToken {
+    /// The created api token
+    pub api_token: ApiToken,
+
+    /// Bearer token to send in the authorization header, it can't be shown again
+    pub token: String,
+}
+
 /// Graphql representation of a comment
 #[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
 pub struct Comment {
@@ -142,13 +431,108 @@ pub struct Comment {
 }
 
 impl Comment {
-    pub async fn from_comment_id(dbm: &DBManager, comment_id: i64) -> Option<Comment> {
+    pub async fn from_comment_id(dbm: &DBManager, comment_id: i64) -> Result<Comment, DBError> {
         let comment = dbm.get_comment(comment_id).await;
-        match comment {
-            Some(c) => Some(Comment {id: c.id, sleep_id: c.sleep_id, comment: c.comment}),
-            None => None
+        comment.map(|c| Comment {id: c.id, sleep_id: c.sleep_id, comment: c.comment})
+    }
+}
+
+/// Graphql representation of a dream from a sleep
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dream {
+    /// Primary key
+    pub id: i64,
+
+    /// id of the sleep the dream happened during
+    pub sleep_id: i64,
+
+    /// Short title of the dream
+    pub title: String,
+
+    /// Full description of the dream
+    pub narrative: String,
+
+    /// true if the dreamer knew they were dreaming
+    pub lucid: bool,
+
+    /// How vivid the dream was, from 0 to 10
+    pub vividness: Option<i64>,
+
+    /// Emotional tone of the dream, from -5 (very negative) to 5 (very positive)
+    pub emotional_tone: Option<i64>,
+
+    /// true if the dream has happened before
+    pub recurring: bool,
+}
+
+impl Dream {
+    pub fn from_db(dream: &DBDream) -> Dream {
+        Dream {
+            id: dream.id,
+            sleep_id: dream.sleep_id,
+            title: dream.title.clone(),
+            narrative: dream.narrative.clone(),
+            lucid: dream.lucid,
+            vividness: dream.vividness,
+            emotional_tone: dream.emotional_tone,
+            recurring: dream.recurring,
         }
     }
+
+    pub async fn from_dream_id(dbm: &DBManager, dream_id: i64) -> Result<Dream, DBError> {
+        let dream = dbm.get_dream(dream_id).await;
+        dream.map(|d| Dream::from_db(&d))
+    }
+}
+
+#[Object]
+impl Dream {
+    async fn id(&self) -> i64 {
+        self.id
+    }
+
+    async fn sleep_id(&self) -> i64 {
+        self.sleep_id
+    }
+
+    async fn title(&self) -> &str {
+        &self.title
+    }
+
+    async fn narrative(&self) -> &str {
+        &self.narrative
+    }
+
+    async fn lucid(&self) -> bool {
+        self.lucid
+    }
+
+    /// How vivid the dream was, from 0 to 10
+    async fn vividness(&self) -> Option<i64> {
+        self.vividness
+    }
+
+    /// Emotional tone of the dream, from -5 (very negative) to 5 (very positive)
+    async fn emotional_tone(&self) -> Option<i64> {
+        self.emotional_tone
+    }
+
+    async fn recurring(&self) -> bool {
+        self.recurring
+    }
+
+    /// Night of the sleep the dream happened during
+    async fn night(&self, ctx: &Context<'_>) -> Result<Night> {
+        let loader = ctx.data_unchecked::<DataLoader<SleepLoader>>();
+        let sleep = loader.load_one(self.sleep_id).await.map_err(|e| e.extend())?;
+        sleep.map(|s| s.night).ok_or_else(|| DBError::not_found("sleep", self.sleep_id).extend())
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<Tag>> {
+        let loader = ctx.data_unchecked::<DataLoader<DreamTagsLoader>>();
+        let tags = loader.load_one(self.id).await.map_err(|e| e.extend())?;
+        Ok(tags.unwrap_or_default())
+    }
 }
 
 /// Graphql representation of the date
@@ -161,51 +545,441 @@ pub struct Night {
     pub month: u8,
 
     /// Year portion of the date
-    pub year: u16,
+    pub year: i32,
 
-    /// String representation of the date in yyyy-mm-dd format
-    pub date: String,
+    /// The date, serialized in yyyy-mm-dd format
+    pub date: NaiveDate,
 }
 
 impl Night {
-    pub fn from_string(night: impl Into<String>) -> Night {
-        let date = night.into();
-        let night: Vec<&str> = date.split('-').collect();
-        
-        // TODO: Unwrapping here is not safe, so this should be handled better
+    pub fn from_date(date: NaiveDate) -> Night {
         Night {
-            day: night[2].parse::<u8>().unwrap(),
-            month: night[1].parse::<u8>().unwrap(),
-            year: night[0].parse::<u16>().unwrap(),
+            day: date.day() as u8,
+            month: date.month() as u8,
+            year: date.year(),
             date
         }
     }
+}
+
+/// How sleeps are grouped by the sleep statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Enum)]
+#[graphql(remote = "db_manager::StatsGroupBy")]
+pub enum StatsGroupBy {
+    /// One group per night
+    #[default]
+    Day,
+
+    /// One group per ISO week, weeks start on monday
+    Week,
+
+    /// One group per calendar month
+    Month,
+
+    /// One group per day of the week, combining every week of the range
+    Weekday,
+}
+
+/// Graphql representation of summary statistics of a set of values
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct StatsSummary {
+    /// Average of the values
+    pub mean: f64,
+
+    /// Middle value, or the average of the two middle values
+    pub median: f64,
+
+    /// Smallest value
+    pub min: f64,
+
+    /// Largest value
+    pub max: f64,
+
+    /// Sample standard deviation, null when there are fewer than two values
+    pub std_dev: Option<f64>,
+}
+
+impl From<&db_manager::Summary> for StatsSummary {
+    fn from(summary: &db_manager::Summary) -> Self {
+        StatsSummary {
+            mean: summary.mean,
+            median: summary.median,
+            min: summary.min,
+            max: summary.max,
+            std_dev: summary.std_dev,
+        }
+    }
+}
+
+/// Graphql representation of the statistics of a group of sleeps
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct StatsGroup {
+    /// Label of the group, ex: "2023-05-13", "2023-W19", "2023-05" or "Saturday"
+    pub key: String,
+
+    /// First date of the group, null when grouped by weekday
+    pub start: Option<NaiveDate>,
+
+    /// Number of sleeps in the group
+    pub count: i64,
+
+    /// Summary of the amount of sleep
+    pub amount: StatsSummary,
+
+    /// Summary of the quality of sleep
+    pub quality: StatsSummary,
+
+    /// Average share of each stage of sleep, null if no sleep in the group has stages
+    pub stages: Option<StageShares>,
+}
+
+impl From<&db_manager::StatsGroup> for StatsGroup {
+    fn from(group: &db_manager::StatsGroup) -> Self {
+        StatsGroup {
+            key: group.key.clone(),
+            start: group.start,
+            count: group.count,
+            amount: StatsSummary::from(&group.amount),
+            quality: StatsSummary::from(&group.quality),
+            stages: group.stages.as_ref().map(StageShares::from),
+        }
+    }
+}
+
+/// Graphql representation of the average share of the night spent in each stage of sleep,
+/// over the sleeps of a group that have stages
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct StageShares {
+    /// Number of sleeps in the group that have stages
+    pub count: i64,
+
+    /// Average share of the minutes asleep spent in deep sleep, null if no sleep recorded it
+    pub deep: Option<f64>,
+
+    /// Average share of the minutes asleep spent in light sleep, null if no sleep recorded it
+    pub light: Option<f64>,
+
+    /// Average share of the minutes asleep spent in REM sleep, null if no sleep recorded it
+    pub rem: Option<f64>,
+
+    /// Average share of the minutes asleep and awake spent awake, null if no sleep recorded it
+    pub awake: Option<f64>,
+}
+
+impl From<&db_manager::StageShares> for StageShares {
+    fn from(shares: &db_manager::StageShares) -> Self {
+        StageShares {
+            count: shares.count,
+            deep: shares.deep,
+            light: shares.light,
+            rem: shares.rem,
+            awake: shares.awake,
+        }
+    }
+}
+
+/// Graphql representation of the rolling averages of the nights that end on a night
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct RollingAverage {
+    /// Last night of the windows
+    pub night: NaiveDate,
+
+    /// Average amount of the sleeps in the last 7 nights
+    pub amount_7_day: f64,
+
+    /// Average amount of the sleeps in the last 30 nights
+    pub amount_30_day: f64,
+
+    /// Average quality of the sleeps in the last 7 nights
+    pub quality_7_day: f64,
+
+    /// Average quality of the sleeps in the last 30 nights
+    pub quality_30_day: f64,
+}
+
+impl From<&db_manager::RollingAverage> for RollingAverage {
+    fn from(average: &db_manager::RollingAverage) -> Self {
+        RollingAverage {
+            night: average.night,
+            amount_7_day: average.amount_7_day,
+            amount_30_day: average.amount_30_day,
+            quality_7_day: average.quality_7_day,
+            quality_30_day: average.quality_30_day,
+        }
+    }
+}
+
+/// Graphql representation of the statistics of the sleeps in a date range
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct SleepStats {
+    /// Statistics of every sleep in the range, null if the range has no sleeps
+    pub overall: Option<StatsGroup>,
+
+    /// Statistics of each group that has sleeps, in chronological order, or monday to sunday for weekdays
+    pub groups: Vec<StatsGroup>,
+
+    /// 7 and 30 day rolling averages for each night in the range that has a sleep
+    pub rolling: Vec<RollingAverage>,
+}
+
+impl From<&db_manager::SleepStats> for SleepStats {
+    fn from(stats: &db_manager::SleepStats) -> Self {
+        SleepStats {
+            overall: stats.overall.as_ref().map(StatsGroup::from),
+            groups: stats.groups.iter().map(StatsGroup::from).collect(),
+            rolling: stats.rolling.iter().map(RollingAverage::from).collect(),
+        }
+    }
+}
+
+/// Graphql representation of Welch's t-test comparing two means
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct TTest {
+    /// t statistic, positive when the sleeps with the tag have the larger mean
+    pub t: f64,
+
+    /// Welch–Satterthwaite degrees of freedom
+    pub degrees_of_freedom: f64,
+
+    /// Two sided p-value, the probability of a difference at least this large if the tag had no impact
+    pub p_value: f64,
+}
+
+/// Graphql representation of a value compared between the sleeps with a tag and the sleeps without it
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct TagComparison {
+    /// Mean of the sleeps with the tag, null if no sleeps have the tag
+    pub mean_with: Option<f64>,
+
+    /// Mean of the sleeps without the tag, null if every sleep has the tag
+    pub mean_without: Option<f64>,
+
+    /// Mean with the tag minus the mean without it
+    pub difference: Option<f64>,
+
+    /// Significance of the difference, null when either side has fewer than two sleeps or no variance
+    pub t_test: Option<TTest>,
+}
+
+impl From<&db_manager::Comparison> for TagComparison {
+    fn from(comparison: &db_manager::Comparison) -> Self {
+        TagComparison {
+            mean_with: comparison.mean_with,
+            mean_without: comparison.mean_without,
+            difference: comparison.difference,
+            t_test: comparison.t_test.map(|t| TTest { t: t.t, degrees_of_freedom: t.degrees_of_freedom, p_value: t.p_value }),
+        }
+    }
+}
+
+/// Graphql representation of how a tag relates to the amount and quality of sleep
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct TagImpact {
+    /// The tag that is compared
+    pub tag: Tag,
+
+    /// Number of sleeps with the tag
+    pub count_with: i64,
+
+    /// Number of sleeps without the tag
+    pub count_without: i64,
+
+    /// Comparison of the amount of sleep
+    pub amount: TagComparison,
+
+    /// Comparison of the quality of sleep
+    pub quality: TagComparison,
+}
+
+impl From<&db_manager::TagImpact> for TagImpact {
+    fn from(impact: &db_manager::TagImpact) -> Self {
+        TagImpact {
+            tag: Tag { id: impact.tag.id, name: impact.tag.name.clone(), color: impact.tag.color },
+            count_with: impact.count_with,
+            count_without: impact.count_without,
+            amount: TagComparison::from(&impact.amount),
+            quality: TagComparison::from(&impact.quality),
+        }
+    }
+}
+
+/// Why a row of an import was not imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(remote = "db_manager::ImportIssueKind")]
+pub enum ImportIssueKind {
+    /// A sleep already exists for the night of the row
+    DuplicateNight,
+
+    /// An earlier row of the import has the same night
+    RepeatedNight,
+
+    /// The row could not be read or has an invalid value
+    Invalid,
+}
+
+/// Graphql representation of a row of an import that was not imported
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct ImportIssue {
+    /// Name of the imported file the row is in, null for imports of a single file
+    pub file: Option<String>,
+
+    /// Line of the row in the imported file, starting at 1
+    pub line: i64,
+
+    /// Night of the row, null if it could not be read
+    pub night: Option<NaiveDate>,
+
+    /// Why the row was not imported
+    pub kind: ImportIssueKind,
+
+    /// Description of the issue
+    pub message: String,
+}
+
+impl From<&db_manager::ImportIssue> for ImportIssue {
+    fn from(issue: &db_manager::ImportIssue) -> Self {
+        ImportIssue {
+            file: issue.file.clone(),
+            line: issue.line as i64,
+            night: issue.night,
+            kind: issue.kind.into(),
+            message: issue.message.clone(),
+        }
+    }
+}
+
+/// Graphql representation of the result of an import, or of a dry run of an import
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct ImportReport {
+    /// true if nothing was written, and the report shows what the import would do
+    pub dry_run: bool,
+
+    /// Number of rows that were read
+    pub rows: i64,
 
-    pub fn in_date_range(&self, start_date: &SleepsInRangeInput, end_date: &SleepsInRangeInput) -> bool {
+    /// Number of rows that were imported, or would be imported by a dry run
+    pub imported: i64,
 
-        let in_year_range = self.year >= start_date.year && self.year <= end_date.year;
-        let in_month_range = self.month >= start_date.month && self.month <= end_date.month;
-        let in_day_range = match (start_date.day, end_date.day) {
-            (Some(s), Some(e)) => self.day >= s && self.day <= e, 
-            (_, _) => true
-        };
+    /// Number of sleeps imported earlier from the same source that were updated, or would be updated by a dry run
+    pub merged: i64,
 
-        in_year_range && in_month_range && in_day_range
+    /// Names of the tags that were created, or would be created by a dry run
+    pub created_tags: Vec<String>,
+
+    /// ids of the sleeps that were created, empty for a dry run
+    pub sleep_ids: Vec<i64>,
+
+    /// Rows that were not imported, in the order of the file
+    pub issues: Vec<ImportIssue>,
+}
+
+impl From<&db_manager::ImportReport> for ImportReport {
+    fn from(report: &db_manager::ImportReport) -> Self {
+        ImportReport {
+            dry_run: report.dry_run,
+            rows: report.rows as i64,
+            imported: report.imported as i64,
+            merged: report.merged as i64,
+            created_tags: report.created_tags.clone(),
+            sleep_ids: report.sleep_ids.clone(),
+            issues: report.issues.iter().map(ImportIssue::from).collect(),
+        }
     }
 }
 
+/// Graphql representation of the result of taking a snapshot of the database
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct SnapshotStatus {
+    /// File name of the snapshot in the snapshot directory, null if no snapshot was written
+    pub file: Option<String>,
+
+    /// Local time the snapshot was started
+    pub started_on: NaiveDateTime,
+
+    /// Milliseconds it took to write and verify the snapshot
+    pub duration_ms: i64,
+
+    /// Size of the snapshot in bytes, null if no snapshot was written
+    pub size: Option<i64>,
+
+    /// true if the snapshot was written and passed the integrity check
+    pub succeeded: bool,
+
+    /// Why the snapshot failed, null if it succeeded
+    pub error: Option<String>,
+}
+
+impl From<&db_manager::SnapshotStatus> for SnapshotStatus {
+    fn from(status: &db_manager::SnapshotStatus) -> Self {
+        SnapshotStatus {
+            file: status.file.clone(),
+            started_on: status.started_on,
+            duration_ms: status.duration.as_millis() as i64,
+            size: status.size.map(|s| s as i64),
+            succeeded: status.succeeded(),
+            error: status.error.clone(),
+        }
+    }
+}
+
+/// Graphql representation of the snapshots of the database that are taken on a schedule
+#[derive(Debug, Clone, PartialEq, SimpleObject)]
+pub struct Snapshots {
+    /// Minutes between snapshots
+    pub interval_minutes: i64,
+
+    /// Number of snapshots that are kept
+    pub keep: i64,
+
+    /// Local time the last successful snapshot was started, null if no snapshot succeeded yet
+    pub last_success_on: Option<NaiveDateTime>,
+
+    /// Result of the last snapshot, null if no snapshot was taken yet
+    pub last: Option<SnapshotStatus>,
+}
+
+impl From<&db_manager::Snapshots> for Snapshots {
+    fn from(snapshots: &db_manager::Snapshots) -> Self {
+        Snapshots {
+            interval_minutes: (snapshots.settings().interval.as_secs() / 60) as i64,
+            keep: snapshots.settings().keep as i64,
+            last_success_on: snapshots.last_success_on(),
+            last: snapshots.last().as_ref().map(SnapshotStatus::from),
+        }
+    }
+}
+
+/// Graphql input for an inclusive range of nights
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct DateRangeInput {
+    /// Inclusive start date of the range in yyyy-mm-dd format
+    pub start: NaiveDate,
+
+    /// Inclusive end date of the range in yyyy-mm-dd format
+    pub end: NaiveDate,
+}
+
 /// Graphql representation for inputting a sleep to the database
 #[derive(Debug, Clone, Default, PartialEq, InputObject)]
 pub struct SleepInput {
-    /// String representation of the date in yyyy-mm-dd format
-    pub night: String,
+    /// Date of the night in yyyy-mm-dd format, derived from the bed time when omitted
+    pub night: Option<NaiveDate>,
 
-    /// Amount of sleep
-    pub amount: f64,
+    /// Amount of sleep of the main session, derived from the sleep onset or bed time and the wake time when omitted
+    pub amount: Option<f64>,
 
     /// Quality of sleep
     pub quality: i64,
 
+    /// Time the sleeper went to bed for the main session
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// Time the sleeper fell asleep for the main session
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// Time the sleeper woke up from the main session
+    pub wake_time: Option<NaiveDateTime>,
+
     /// Tags to associate to the sleep
     pub tags: Option<Vec<i64>>,
 
@@ -213,6 +987,187 @@ pub struct SleepInput {
     pub comments: Option<Vec<String>>
 }
 
+/// How the tags or comments of a night that already has a sleep are merged with those of an upsert
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Enum)]
+#[graphql(remote = "db_manager::MergePolicy")]
+pub enum MergePolicy {
+    /// The sleep ends up with exactly the tags or comments of the upsert
+    Replace,
+
+    /// Add the tags or comments of the upsert that the sleep doesn't have yet, so repeating an upsert adds nothing
+    #[default]
+    Append,
+
+    /// Keep the tags or comments of the sleep, ignoring those of the upsert
+    Keep,
+}
+
+/// Graphql input for adding a sleep, or updating the sleep its night already has
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct UpsertSleepInput {
+    /// Date of the night in yyyy-mm-dd format, derived from the bed time when omitted
+    pub night: Option<NaiveDate>,
+
+    /// Amount of sleep of the main session, derived from the sleep onset or bed time and the wake time when omitted
+    pub amount: Option<f64>,
+
+    /// Quality of sleep
+    pub quality: i64,
+
+    /// Time the sleeper went to bed for the main session, an existing sleep keeps its bed time when omitted
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// Time the sleeper fell asleep for the main session, an existing sleep keeps its sleep onset when omitted
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// Time the sleeper woke up from the main session, an existing sleep keeps its wake time when omitted
+    pub wake_time: Option<NaiveDateTime>,
+
+    /// Tags of the sleep, an existing sleep keeps its tags when omitted
+    pub tags: Option<Vec<i64>>,
+
+    /// Comments of the sleep, an existing sleep keeps its comments when omitted
+    pub comments: Option<Vec<String>>,
+
+    /// How the tags are merged when the night already has a sleep
+    #[graphql(default)]
+    pub tag_policy: MergePolicy,
+
+    /// How the comments are merged when the night already has a sleep, comments are matched by their text
+    #[graphql(default)]
+    pub comment_policy: MergePolicy,
+}
+
+/// Graphql representation of a sleep that was added or updated by an upsert
+#[derive(Debug, Clone, Default, PartialEq, SimpleObject)]
+pub struct UpsertedSleep {
+    /// The sleep after the upsert
+    pub sleep: Sleep,
+
+    /// true if the sleep was added, false if the night already had a sleep
+    pub created: bool,
+}
+
+/// Graphql input for adding a session of sleep, such as a nap, to a sleep
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct SleepSessionInput {
+    /// id of the sleep (night) to add the session to
+    pub sleep_id: i64,
+
+    /// Kind of session
+    pub kind: SleepKind,
+
+    /// Amount of sleep, derived from the sleep onset or bed time and the wake time when omitted
+    pub amount: Option<f64>,
+
+    /// Time the sleeper went to bed
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// Time the sleeper fell asleep
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// Time the sleeper woke up
+    pub wake_time: Option<NaiveDateTime>,
+}
+
+impl SleepSessionInput {
+    /// Returns the bed, sleep onset and wake times of the input
+    pub fn times(&self) -> SleepTimes {
+        SleepTimes { bed_time: self.bed_time, sleep_onset: self.sleep_onset, wake_time: self.wake_time }
+    }
+}
+
+/// Graphql input to update a sleep session
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct UpdateSleepSessionInput {
+    /// id of the session to update
+    pub session_id: i64,
+
+    /// Optionally update the kind of session
+    pub kind: Option<SleepKind>,
+
+    /// Optionally update the amount of sleep
+    pub amount: Option<f64>,
+
+    /// Optionally update the time the sleeper went to bed
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// Optionally update the time the sleeper fell asleep
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// Optionally update the time the sleeper woke up.
+    /// When times are updated without an amount, the amount is derived from the times
+    pub wake_time: Option<NaiveDateTime>,
+}
+
+impl UpdateSleepSessionInput {
+    /// Returns the bed, sleep onset and wake times to update
+    pub fn times(&self) -> SleepTimes {
+        SleepTimes { bed_time: self.bed_time, sleep_onset: self.sleep_onset, wake_time: self.wake_time }
+    }
+}
+
+/// Graphql input to set the minutes spent in each stage of sleep during the night of a sleep
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct SleepStagesInput {
+    /// id of the sleep the stages belong to
+    pub sleep_id: i64,
+
+    /// Minutes of deep sleep
+    pub deep_minutes: Option<i64>,
+
+    /// Minutes of light sleep
+    pub light_minutes: Option<i64>,
+
+    /// Minutes of REM sleep
+    pub rem_minutes: Option<i64>,
+
+    /// Minutes awake after falling asleep.
+    /// Stages that are omitted are not recorded, omitting every stage removes the stages from the sleep
+    pub awake_minutes: Option<i64>,
+}
+
+impl SleepStagesInput {
+    /// Returns the minutes of each stage of the input
+    pub fn stages(&self) -> db_manager::SleepStages {
+        db_manager::SleepStages {
+            deep_minutes: self.deep_minutes,
+            light_minutes: self.light_minutes,
+            rem_minutes: self.rem_minutes,
+            awake_minutes: self.awake_minutes,
+        }
+    }
+}
+
+/// Graphql input to replace the hypnogram of a sleep
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct HypnogramInput {
+    /// id of the sleep the hypnogram belongs to
+    pub sleep_id: i64,
+
+    /// Stages of sleep ordered by the time they started, an empty list removes the hypnogram from the sleep
+    pub intervals: Vec<HypnogramIntervalInput>,
+}
+
+/// Graphql input of a period of a night spent in one stage of sleep
+#[derive(Debug, Clone, PartialEq, InputObject)]
+pub struct HypnogramIntervalInput {
+    pub stage: SleepStage,
+
+    /// Time the stage started
+    pub start: NaiveDateTime,
+
+    /// Time the stage ended
+    pub end: NaiveDateTime,
+}
+
+impl HypnogramInput {
+    /// Returns the intervals of the input
+    pub fn intervals(&self) -> Vec<db_manager::HypnogramInterval> {
+        self.intervals.iter().map(|i| db_manager::HypnogramInterval { stage: i.stage.into(), start: i.start, end: i.end }).collect()
+    }
+}
+
 /// Graphql representation for inputting a tag to the database
 #[derive(Debug, Clone, Default, PartialEq, InputObject)]
 pub struct TagInput {
@@ -223,6 +1178,30 @@ pub struct TagInput {
     pub color: i64,
 }
 
+/// Graphql representation for inputting a tag category to the database
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct TagCategoryInput {
+    /// Name of the category, category names are unique
+    pub name: String,
+}
+
+/// Graphql representation for inputting a user to the database
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct UserInput {
+    /// Name of the user, user names are unique
+    pub name: String,
+}
+
+/// Graphql representation for creating an api token
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct ApiTokenInput {
+    /// Name of the token to tell tokens apart, ex: "phone"
+    pub name: String,
+
+    /// true if the token can only be used to query data, defaults to false
+    pub read_only: Option<bool>,
+}
+
 /// Graphql input for adding tags to a sleep
 #[derive(Debug, Clone, Default, PartialEq, InputObject)]
 pub struct AddTagsToSleepInput {
@@ -249,11 +1228,51 @@ pub struct UpdateSleepInput {
     /// id of the sleep to update
     pub sleep_id: i64,
 
-    /// Optionally update the amount of sleep
+    /// Optionally update the amount of sleep of the main session
     pub amount: Option<f64>,
 
     /// Optionally update the quality of the sleep
     pub quality: Option<i64>,
+
+    /// Optionally update the time the sleeper went to bed for the main session
+    pub bed_time: Option<NaiveDateTime>,
+
+    /// Optionally update the time the sleeper fell asleep for the main session
+    pub sleep_onset: Option<NaiveDateTime>,
+
+    /// Optionally update the time the sleeper woke up from the main session.
+    /// When times are updated without an amount, the amount is derived from the times
+    pub wake_time: Option<NaiveDateTime>,
+}
+
+impl SleepInput {
+    /// Returns the bed, sleep onset and wake times of the input
+    pub fn times(&self) -> SleepTimes {
+        SleepTimes { bed_time: self.bed_time, sleep_onset: self.sleep_onset, wake_time: self.wake_time }
+    }
+}
+
+impl UpsertSleepInput {
+    /// Returns the sleep to add or update, omitted tags or comments are kept
+    pub fn upsert(&self) -> db_manager::SleepUpsert {
+        db_manager::SleepUpsert {
+            night: self.night,
+            amount: self.amount,
+            quality: self.quality,
+            times: SleepTimes { bed_time: self.bed_time, sleep_onset: self.sleep_onset, wake_time: self.wake_time },
+            tag_ids: self.tags.clone().unwrap_or_default(),
+            comments: self.comments.clone().unwrap_or_default(),
+            tag_policy: if self.tags.is_some() { self.tag_policy.into() } else { db_manager::MergePolicy::Keep },
+            comment_policy: if self.comments.is_some() { self.comment_policy.into() } else { db_manager::MergePolicy::Keep },
+        }
+    }
+}
+
+impl UpdateSleepInput {
+    /// Returns the bed, sleep onset and wake times to update
+    pub fn times(&self) -> SleepTimes {
+        SleepTimes { bed_time: self.bed_time, sleep_onset: self.sleep_onset, wake_time: self.wake_time }
+    }
 }
 
 /// Graphql input to update a tag
@@ -269,6 +1288,120 @@ pub struct UpdateTagInput {
     pub color: Option<i64>,
 }
 
+/// Graphql input to rename a tag category
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct UpdateTagCategoryInput {
+    /// id of the category to update
+    pub category_id: i64,
+
+    /// New name of the category
+    pub name: String,
+}
+
+/// Graphql input for adding a dream to a sleep
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct AddDreamToSleepInput {
+    /// id of the sleep to add the dream to
+    pub sleep_id: i64,
+
+    /// Short title of the dream
+    pub title: String,
+
+    /// Full description of the dream
+    #[graphql(default)]
+    pub narrative: String,
+
+    /// true if the dreamer knew they were dreaming
+    #[graphql(default)]
+    pub lucid: bool,
+
+    /// How vivid the dream was, from 0 to 10
+    pub vividness: Option<i64>,
+
+    /// Emotional tone of the dream, from -5 (very negative) to 5 (very positive)
+    pub emotional_tone: Option<i64>,
+
+    /// true if the dream has happened before
+    #[graphql(default)]
+    pub recurring: bool,
+
+    /// Tags to associate to the dream
+    pub tags: Option<Vec<i64>>,
+}
+
+impl AddDreamToSleepInput {
+    /// Returns the content of the dream to add
+    pub fn details(&self) -> DreamDetails {
+        DreamDetails {
+            title: self.title.clone(),
+            narrative: self.narrative.clone(),
+            lucid: self.lucid,
+            vividness: self.vividness,
+            emotional_tone: self.emotional_tone,
+            recurring: self.recurring,
+        }
+    }
+}
+
+/// Graphql input for adding tags to a dream
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct AddTagsToDreamInput {
+    /// id of the dream to add the tags to
+    pub dream_id: i64,
+
+    /// ids of the tags to add to the dream
+    pub tag_ids: Vec<i64>,
+}
+
+/// Graphql input to update a dream
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct UpdateDreamInput {
+    /// id of the dream to update
+    pub dream_id: i64,
+
+    /// Optionally update the title of the dream
+    pub title: Option<String>,
+
+    /// Optionally update the description of the dream
+    pub narrative: Option<String>,
+
+    /// Optionally update if the dream was lucid
+    pub lucid: Option<bool>,
+
+    /// Optionally update the vividness of the dream, from 0 to 10
+    pub vividness: Option<i64>,
+
+    /// Optionally update the emotional tone of the dream, from -5 to 5
+    pub emotional_tone: Option<i64>,
+
+    /// Optionally update if the dream has happened before
+    pub recurring: Option<bool>,
+}
+
+impl UpdateDreamInput {
+    /// Returns the fields of the dream to update
+    pub fn update(&self) -> DreamUpdate {
+        DreamUpdate {
+            title: self.title.clone(),
+            narrative: self.narrative.clone(),
+            lucid: self.lucid,
+            vividness: self.vividness,
+            emotional_tone: self.emotional_tone,
+            recurring: self.recurring,
+        }
+    }
+}
+
+/// Graphql input for removing a tag from a dream
+#[derive(Debug, Clone, Default, PartialEq, InputObject)]
+pub struct RemoveTagFromDreamInput {
+    /// id of the dream to remove the tag from
+    pub dream_id: i64,
+
+    /// the id of the tag to remove
+    pub tag_id: i64
+}
+
 /// Graphql input to update a comment
 #[derive(Debug, Clone, Default, PartialEq, InputObject)]
 pub struct UpdateCommentInput {
@@ -299,15 +1432,53 @@ pub struct SleepsByMonthInput {
     pub year: u16
 }
 
-/// Graphql input to to query sleeps in a given date range
+/// Graphql input for importing sleeps from a csv file with a header row
 #[derive(Debug, Clone, Default, PartialEq, InputObject)]
-pub struct SleepsInRangeInput {
-    /// month at either the start or end of the range
-    pub month: u8,
+pub struct CsvImportInput {
+    /// Contents of the csv file
+    pub csv: String,
+
+    /// Column with the night of each sleep, defaults to "night"
+    pub night_column: Option<String>,
+
+    /// Column with the amount of sleep, defaults to "amount"
+    pub amount_column: Option<String>,
+
+    /// Column with the quality of sleep, defaults to "quality"
+    pub quality_column: Option<String>,
+
+    /// Column with the names of the tags, defaults to "tags"
+    pub tags_column: Option<String>,
+
+    /// Column with the comments, one per line of the cell, defaults to "comment"
+    pub comment_column: Option<String>,
 
-    /// year at either the start or end of the range
-    pub year: u16,
+    /// Separator of the tag names in the tags column, defaults to ";"
+    pub tag_delimiter: Option<char>,
 
-    /// Optionally include a specific day at the start or end of the range
-    pub day: Option<u8>
-}
\ No newline at end of file
+    /// chrono format of the nights, defaults to "%Y-%m-%d"
+    pub date_format: Option<String>,
+
+    /// Report what the import would do without changing anything
+    #[graphql(default)]
+    pub dry_run: bool,
+}
+
+impl CsvImportInput {
+    /// Options of the import, with the default of every option that was not given
+    pub fn options(&self) -> db_manager::CsvImportOptions {
+        let defaults = db_manager::CsvColumns::default();
+        db_manager::CsvImportOptions {
+            columns: db_manager::CsvColumns {
+                night: self.night_column.clone().unwrap_or(defaults.night),
+                amount: self.amount_column.clone().unwrap_or(defaults.amount),
+                quality: self.quality_column.clone().unwrap_or(defaults.quality),
+                tags: self.tags_column.clone().unwrap_or(defaults.tags),
+                comment: self.comment_column.clone().unwrap_or(defaults.comment),
+            },
+            tag_delimiter: self.tag_delimiter.unwrap_or(db_manager::DEFAULT_TAG_DELIMITER),
+            date_format: self.date_format.clone().unwrap_or_else(|| String::from(db_manager::DEFAULT_DATE_FORMAT)),
+            dry_run: self.dry_run,
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use crate::DBManager;
+use crate::db_manager::DBError;
+use super::gql_auth::AdminGuard;
+use super::gql_changes::*;
+use super::gql_errors::StepResultExt;
 use super::gql_types::*;
 
-use async_graphql::{Context, Object};
-
-// TODO: I think errors should be handled better. Currently, when something fails at the database layer,
-// the gql api will return None/null instead of a descriptive error explaining the problem.
-// Example: Adding duplciates to unique columns
+use async_graphql::{Context, Object, Result, ResultExt};
 
 /// Contains the Mutation defintions for the graphql api
+/// Failures are returned as graphql errors with a machine readable `code` in the error extensions
+/// Mutations made of many steps run in a unit of work, nothing is saved when a step fails and the `step` that failed is added to the error extensions
+/// Changes to sleeps, tags and comments are published to the subscriptions of the user
 pub struct MutationRoot;
 
 #[Object]
@@ -16,176 +19,567 @@ impl MutationRoot {
         &self,
         ctx: &Context<'_>,
         #[graphql(desc = "Sleep input containing a night's data")] sleep_input: SleepInput)
-        -> Option<Sleep> {
-            let dbm = ctx.data_unchecked::<DBManager>();
-            let sleep_id = dbm.insert_sleep(sleep_input.night.as_str(), sleep_input.amount, sleep_input.quality).await;
+        -> Result<Sleep> {
+            let uow = ctx.data_unchecked::<DBManager>().begin().await.extend()?;
+            let times = sleep_input.times();
+            let sleep_id = uow.insert_sleep_with_times(sleep_input.night, sleep_input.amount, sleep_input.quality, &times)
+                .await.in_step("sleep")?;
 
             if let Some(tags) = sleep_input.tags {
-                dbm.add_tags_to_sleep(sleep_id, tags).await;
+                uow.add_tags_to_sleep(sleep_id, tags).await.in_step("tags")?;
             }
 
+            let mut created_comments = Vec::new();
             if let Some(comments) = sleep_input.comments {
                 for comment in comments {
-                    dbm.insert_comment(sleep_id, comment.as_str()).await;
+                    let comment_id = uow.insert_comment(sleep_id, comment.as_str()).await.in_step("comments")?;
+                    created_comments.push(Comment { id: comment_id, sleep_id, comment });
                 }
             }
 
-            Sleep::from_sleep_id(dbm, sleep_id).await 
+            let sleep = Sleep::from_sleep_id(&uow, sleep_id).await.extend()?;
+            uow.commit().await.extend()?;
+            publish(ctx, SleepChanged::created(&sleep));
+            for comment in &created_comments {
+                publish(ctx, CommentChanged::created(comment));
+            }
+            Ok(sleep)
+        }
+
+        async fn upsert_sleep(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Sleep to add, or to update when its night already has a sleep")] upsert_sleep_input: UpsertSleepInput)
+            -> Result<UpsertedSleep> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let upserted = dbm.upsert_sleep(&upsert_sleep_input.upsert()).await.extend()?;
+
+                let sleep = Sleep::from_sleep_id(dbm, upserted.sleep_id).await.extend()?;
+                publish(ctx, if upserted.created { SleepChanged::created(&sleep) } else { SleepChanged::updated(&sleep) });
+                for comment_id in upserted.created_comment_ids {
+                    publish(ctx, CommentChanged::created(&Comment::from_comment_id(dbm, comment_id).await.extend()?));
+                }
+                for comment_id in upserted.deleted_comment_ids {
+                    publish(ctx, CommentChanged::deleted(comment_id));
+                }
+                Ok(UpsertedSleep { sleep, created: upserted.created })
+            }
+
+        #[graphql(guard = "AdminGuard")]
+        async fn add_user(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "User input containing the name of the user")] user_input: UserInput)
+            -> Result<User> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let user_id = dbm.insert_user(user_input.name.as_str()).await.extend()?;
+
+                User::from_user_id(dbm, user_id).await.extend()
+            }
+
+        async fn create_api_token(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Name and scope of the api token")] api_token_input: ApiTokenInput)
+            -> Result<CreatedApiToken> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let read_only = api_token_input.read_only.unwrap_or(false);
+                let (id, token) = dbm.create_api_token(api_token_input.name.as_str(), read_only, false).await.extend()?;
+
+                let api_token = ApiToken { id, name: api_token_input.name, read_only, admin: false };
+                Ok(CreatedApiToken { api_token, token })
+            }
+
+        async fn revoke_api_token(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "api token id to revoke.")] token_id: i64)
+            -> Result<bool> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                dbm.revoke_api_token(token_id).await.extend()?;
+                Ok(true)
         }
 
         async fn add_tag(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Tag input containing a tag's data")] tag_input: TagInput)
-            -> Option<Tag> {
+            -> Result<Tag> {
                 let dbm = ctx.data_unchecked::<DBManager>();
-                let tag_id = dbm.insert_tag(tag_input.name.as_str(), tag_input.color).await;
+                let tag_id = dbm.insert_tag(tag_input.name.as_str(), tag_input.color).await.extend()?;
     
-                Tag::from_tag_id(dbm, tag_id).await 
+                let tag = Tag::from_tag_id(dbm, tag_id).await.extend()?;
+                publish(ctx, TagChanged::created(&tag));
+                Ok(tag)
+            }
+
+        async fn add_tag_category(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Category input containing the name of the category")] tag_category_input: TagCategoryInput)
+            -> Result<TagCategory> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let category_id = dbm.insert_tag_category(tag_category_input.name.as_str()).await.extend()?;
+
+                TagCategory::from_category_id(dbm, category_id).await.extend()
+            }
+
+        async fn set_tag_parent(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Tag id to set the parent of.")] tag_id: i64,
+            #[graphql(desc = "id of the parent tag, the tag is moved to the top of the hierarchy when omitted.")] parent_id: Option<i64>)
+            -> Result<Tag> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                dbm.set_tag_parent(tag_id, parent_id).await.extend()?;
+
+                let tag = Tag::from_tag_id(dbm, tag_id).await.extend()?;
+                publish(ctx, TagChanged::updated(&tag));
+                Ok(tag)
+            }
+
+        async fn set_tag_category(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Tag id to set the category of.")] tag_id: i64,
+            #[graphql(desc = "id of the category, the tag is removed from its category when omitted.")] category_id: Option<i64>)
+            -> Result<Tag> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                dbm.set_tag_category(tag_id, category_id).await.extend()?;
+
+                let tag = Tag::from_tag_id(dbm, tag_id).await.extend()?;
+                publish(ctx, TagChanged::updated(&tag));
+                Ok(tag)
             }
 
         async fn add_tags_to_sleep(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Contains Sleep id and tags to add to sleep.")] add_tags_to_sleep_input: AddTagsToSleepInput)
-            -> Option<Sleep> {
+            -> Result<Sleep> {
                 let dbm = ctx.data_unchecked::<DBManager>();
                 let sleep_id = add_tags_to_sleep_input.sleep_id;
                 let tag_ids = add_tags_to_sleep_input.tag_ids;
-                dbm.add_tags_to_sleep(sleep_id, tag_ids).await;
+                dbm.add_tags_to_sleep(sleep_id, tag_ids).await.extend()?;
 
-                Sleep::from_sleep_id(dbm, sleep_id).await 
+                let sleep = Sleep::from_sleep_id(dbm, sleep_id).await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(sleep)
+            }
+        
+        async fn set_sleep_tags(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Sleep id to set the tags of.")] sleep_id: i64,
+            #[graphql(desc = "ids of every tag the sleep should have, the other tags are removed.")] tag_ids: Vec<i64>)
+            -> Result<Sleep> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                dbm.set_sleep_tags(sleep_id, &tag_ids).await.extend()?;
+
+                let sleep = Sleep::from_sleep_id(dbm, sleep_id).await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(sleep)
             }
         
         async fn add_comment_to_sleep(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Contains Sleep id and comment to add to sleep")] add_comment_to_sleep_input: AddCommentToSleepInput)
-            -> Option<Sleep> {
+            -> Result<Sleep> {
                 let dbm = ctx.data_unchecked::<DBManager>();
                 let sleep_id = add_comment_to_sleep_input.sleep_id;
                 let comment = add_comment_to_sleep_input.comment;
-                dbm.insert_comment(sleep_id, comment.as_str()).await;
+                let comment_id = dbm.insert_comment(sleep_id, comment.as_str()).await.extend()?;
+                publish(ctx, CommentChanged::created(&Comment { id: comment_id, sleep_id, comment }));
+
+                Sleep::from_sleep_id(dbm, sleep_id).await.extend()
+            }
+
+        async fn add_dream_to_sleep(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Contains Sleep id and dream to add to sleep")] add_dream_to_sleep_input: AddDreamToSleepInput)
+            -> Result<Sleep> {
+                let uow = ctx.data_unchecked::<DBManager>().begin().await.extend()?;
+                let sleep_id = add_dream_to_sleep_input.sleep_id;
+                let dream_id = uow.insert_dream(sleep_id, &add_dream_to_sleep_input.details()).await.in_step("dream")?;
+
+                if let Some(tags) = add_dream_to_sleep_input.tags {
+                    uow.add_tags_to_dream(dream_id, tags).await.in_step("tags")?;
+                }
+
+                let sleep = Sleep::from_sleep_id(&uow, sleep_id).await.extend()?;
+                uow.commit().await.extend()?;
+                Ok(sleep)
+            }
+
+        async fn add_tags_to_dream(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Contains Dream id and tag ids to add to dream")] add_tags_to_dream_input: AddTagsToDreamInput)
+            -> Result<Dream> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let dream_id = add_tags_to_dream_input.dream_id;
+                dbm.add_tags_to_dream(dream_id, add_tags_to_dream_input.tag_ids).await.extend()?;
 
-                Sleep::from_sleep_id(dbm, sleep_id).await 
+                Dream::from_dream_id(dbm, dream_id).await.extend()
+            }
+
+        async fn add_sleep_session(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Contains Sleep id and the session, such as a nap, to add to the sleep")] session_input: SleepSessionInput)
+            -> Result<Sleep> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let sleep_id = session_input.sleep_id;
+                let times = session_input.times();
+                dbm.insert_sleep_session(sleep_id, session_input.kind.into(), session_input.amount, &times).await.extend()?;
+
+                let sleep = Sleep::from_sleep_id(dbm, sleep_id).await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(sleep)
             }
 
         async fn delete_sleep(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Sleep id to delete.")] sleep_id: i64)
-            -> bool {
+            -> Result<bool> {
                 let dbm = ctx.data_unchecked::<DBManager>();
-                dbm.delete_sleep(sleep_id).await
+                // the comments of the sleep are deleted with it
+                let comments = dbm.get_comments_by_sleep(sleep_id).await.extend()?;
+                dbm.delete_sleep(sleep_id).await.extend()?;
+
+                publish(ctx, SleepChanged::deleted(sleep_id));
+                for comment in comments {
+                    publish(ctx, CommentChanged::deleted(comment.id));
+                }
+                Ok(true)
         }
 
         async fn delete_tag(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "tag id to delete.")] tag_id: i64)
-            -> bool {
+            -> Result<bool> {
                 let dbm = ctx.data_unchecked::<DBManager>();
-                dbm.delete_tag(tag_id).await
+                // the tag is removed from every sleep that has it
+                let sleeps = dbm.get_sleeps_by_tag(tag_id).await.extend()?;
+                dbm.delete_tag(tag_id).await.extend()?;
+
+                publish(ctx, TagChanged::deleted(tag_id));
+                for sleep in &sleeps {
+                    publish(ctx, SleepChanged::updated(&Sleep::from_db(sleep)));
+                }
+                Ok(true)
+        }
+
+        async fn merge_tags(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "ids of the tags to merge into the target, they are deleted.")] source_ids: Vec<i64>,
+            #[graphql(desc = "id of the tag to keep.")] target_id: i64)
+            -> Result<Tag> {
+                let uow = ctx.data_unchecked::<DBManager>().begin().await.extend()?;
+                // the sleeps of the source tags get the target tag instead
+                let mut sleep_ids = Vec::new();
+                for source_id in &source_ids {
+                    for sleep in uow.get_sleeps_by_tag(*source_id).await.extend()? {
+                        if !sleep_ids.contains(&sleep.sleep.id) {
+                            sleep_ids.push(sleep.sleep.id);
+                        }
+                    }
+                }
+                uow.merge_tags(&source_ids, target_id).await.extend()?;
+
+                let tag = Tag::from_tag_id(&uow, target_id).await.extend()?;
+                let sleeps = uow.get_multiple_sleeps(sleep_ids).await.extend()?;
+                uow.commit().await.extend()?;
+
+                for source_id in source_ids {
+                    publish(ctx, TagChanged::deleted(source_id));
+                }
+                publish(ctx, TagChanged::updated(&tag));
+                for sleep in &sleeps {
+                    publish(ctx, SleepChanged::updated(&Sleep::from_db(sleep)));
+                }
+                Ok(tag)
+        }
+
+        async fn delete_tag_category(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "category id to delete, its tags are kept without a category.")] category_id: i64)
+            -> Result<bool> {
+                let uow = ctx.data_unchecked::<DBManager>().begin().await.extend()?;
+                let tags = uow.get_tags_by_category(category_id).await.extend()?;
+                uow.delete_tag_category(category_id).await.extend()?;
+                uow.commit().await.extend()?;
+
+                for tag in tags {
+                    publish(ctx, TagChanged::updated(&Tag { id: tag.id, name: tag.name, color: tag.color }));
+                }
+                Ok(true)
         }
 
         async fn delete_comment(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "comment id to delete.")] comment_id: i64)
-            -> bool {
+            -> Result<bool> {
                 let dbm = ctx.data_unchecked::<DBManager>();
-                dbm.delete_comment(comment_id).await
+                dbm.delete_comment(comment_id).await.extend()?;
+                publish(ctx, CommentChanged::deleted(comment_id));
+                Ok(true)
+        }
+
+        async fn delete_dream(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "dream id to delete.")] dream_id: i64)
+            -> Result<bool> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                dbm.delete_dream(dream_id).await.extend()?;
+                Ok(true)
+        }
+
+        async fn delete_sleep_session(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "sleep session id to delete.")] session_id: i64)
+            -> Result<bool> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let session = dbm.get_sleep_session(session_id).await.extend()?;
+                dbm.delete_sleep_session(session_id).await.extend()?;
+
+                publish(ctx, SleepChanged::updated(&Sleep::from_sleep_id(dbm, session.sleep_id).await.extend()?));
+                Ok(true)
         }
 
         async fn update_sleep(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Sleep to edit. Non none fields will be updated.")] sleep_input: UpdateSleepInput)
-            -> Option<Sleep> {
-                let dbm = ctx.data_unchecked::<DBManager>();
+            -> Result<Sleep> {
+                let uow = ctx.data_unchecked::<DBManager>().begin().await.extend()?;
                 
                 let sleep_id = sleep_input.sleep_id;
                 let optional_quality = sleep_input.quality;
                 let optional_amount = sleep_input.amount;
+                let times = sleep_input.times();
+
+                if optional_quality.is_none() && optional_amount.is_none() && times.is_empty() {
+                    return Err(DBError::Validation(String::from("no fields to update were provided"))).extend();
+                }
+
+                if let Some(quality) = optional_quality {
+                    uow.update_sleep_quality(sleep_id, quality).await.in_step("quality")?;
+                }
 
-                let quality_updated = match optional_quality {
-                    Some(quality) => dbm.update_sleep_quality(sleep_id, quality).await,
-                    None => false
-                };
+                // derive the amount from the updated times when an amount wasn't provided
+                let mut optional_amount = optional_amount;
+                if !times.is_empty() {
+                    let updated_times = uow.update_sleep_times(sleep_id, &times).await.in_step("times")?;
+                    optional_amount = optional_amount.or_else(|| updated_times.amount());
+                }
+
+                if let Some(amount) = optional_amount {
+                    uow.update_sleep_amount(sleep_id, amount).await.in_step("amount")?;
+                }
 
-                let amount_updated = match optional_amount {
-                    Some(amount) => dbm.update_sleep_amount(sleep_id, amount).await,
-                    None => false
-                };
+                let sleep = Sleep::from_sleep_id(&uow, sleep_id).await.extend()?;
+                uow.commit().await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(sleep)
+            }
 
-                if quality_updated || amount_updated {
-                    Sleep::from_sleep_id(dbm, sleep_id).await
+        async fn update_sleep_session(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Session to edit. Non none fields will be updated.")] session_input: UpdateSleepSessionInput)
+            -> Result<SleepSession> {
+                let uow = ctx.data_unchecked::<DBManager>().begin().await.extend()?;
+
+                let session_id = session_input.session_id;
+                let optional_kind = session_input.kind;
+                let times = session_input.times();
+
+                if optional_kind.is_none() && session_input.amount.is_none() && times.is_empty() {
+                    return Err(DBError::Validation(String::from("no fields to update were provided"))).extend();
                 }
-                else {
-                    None
+
+                if let Some(kind) = optional_kind {
+                    uow.update_sleep_session_kind(session_id, kind.into()).await.in_step("kind")?;
+                }
+
+                // derive the amount from the updated times when an amount wasn't provided
+                let mut optional_amount = session_input.amount;
+                if !times.is_empty() {
+                    let updated_times = uow.update_sleep_session_times(session_id, &times).await.in_step("times")?;
+                    optional_amount = optional_amount.or_else(|| updated_times.amount());
                 }
+
+                if let Some(amount) = optional_amount {
+                    uow.update_sleep_session_amount(session_id, amount).await.in_step("amount")?;
+                }
+
+                let session = SleepSession::from_session_id(&uow, session_id).await.extend()?;
+                let sleep = Sleep::from_sleep_id(&uow, session.sleep_id).await.extend()?;
+                uow.commit().await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(session)
+            }
+
+        async fn set_sleep_stages(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Minutes of each stage of sleep, replacing the stages the sleep had.")] stages_input: SleepStagesInput)
+            -> Result<Sleep> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                dbm.set_sleep_stages(stages_input.sleep_id, &stages_input.stages()).await.extend()?;
+
+                let sleep = Sleep::from_sleep_id(dbm, stages_input.sleep_id).await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(sleep)
+            }
+
+        async fn set_hypnogram(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Stages of sleep of the night, replacing the hypnogram the sleep had.")] hypnogram_input: HypnogramInput)
+            -> Result<Sleep> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                dbm.set_hypnogram(hypnogram_input.sleep_id, &hypnogram_input.intervals()).await.extend()?;
+
+                let sleep = Sleep::from_sleep_id(dbm, hypnogram_input.sleep_id).await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(sleep)
             }
 
         async fn update_tag(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Tag to edit. Non none fields will be updated.")] tag_input: UpdateTagInput)
-            -> Option<Tag> {
-                let dbm = ctx.data_unchecked::<DBManager>();
+            -> Result<Tag> {
+                let uow = ctx.data_unchecked::<DBManager>().begin().await.extend()?;
                 
                 let tag_id = tag_input.tag_id;
                 let optional_name = tag_input.name;
                 let optional_color = tag_input.color;
 
-                let name_updated = match optional_name {
-                    Some(name) => dbm.update_tag_name(tag_id, name.as_str()).await,
-                    None => false
-                };
-
-                let color_updated = match optional_color {
-                    Some(color) => dbm.update_tag_color(tag_id, color).await,
-                    None => false
-                };
+                if optional_name.is_none() && optional_color.is_none() {
+                    return Err(DBError::Validation(String::from("no fields to update were provided"))).extend();
+                }
 
-                if name_updated || color_updated {
-                    Tag::from_tag_id(dbm, tag_id).await
+                if let Some(name) = optional_name {
+                    uow.update_tag_name(tag_id, name.as_str()).await.in_step("name")?;
                 }
-                else {
-                    None
+
+                if let Some(color) = optional_color {
+                    uow.update_tag_color(tag_id, color).await.in_step("color")?;
                 }
+
+                let tag = Tag::from_tag_id(&uow, tag_id).await.extend()?;
+                uow.commit().await.extend()?;
+                publish(ctx, TagChanged::updated(&tag));
+                Ok(tag)
+            }
+
+        async fn update_tag_category(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Category to rename.")] update_tag_category_input: UpdateTagCategoryInput)
+            -> Result<TagCategory> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let category_id = update_tag_category_input.category_id;
+                dbm.update_tag_category_name(category_id, update_tag_category_input.name.as_str()).await.extend()?;
+
+                TagCategory::from_category_id(dbm, category_id).await.extend()
             }
 
         async fn update_comment(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Comment to edit.")] comment_input: UpdateCommentInput)
-            -> Option<Comment> {
+            -> Result<Comment> {
                 let dbm = ctx.data_unchecked::<DBManager>();
-                let comment_updated = dbm.update_comment(comment_input.comment_id, comment_input.comment.as_str()).await;
-                if comment_updated {
-                    Comment::from_comment_id(dbm, comment_input.comment_id).await
-                }
-                else {
-                    None 
+                dbm.update_comment(comment_input.comment_id, comment_input.comment.as_str()).await.extend()?;
+
+                let comment = Comment::from_comment_id(dbm, comment_input.comment_id).await.extend()?;
+                publish(ctx, CommentChanged::updated(&comment));
+                Ok(comment)
+            }
+
+        async fn update_dream(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Dream to edit. Non none fields will be updated.")] dream_input: UpdateDreamInput)
+            -> Result<Dream> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let update = dream_input.update();
+
+                if update.is_empty() {
+                    return Err(DBError::Validation(String::from("no fields to update were provided"))).extend();
                 }
+
+                dbm.update_dream(dream_input.dream_id, &update).await.extend()?;
+
+                Dream::from_dream_id(dbm, dream_input.dream_id).await.extend()
             }
 
         async fn remove_tag_from_sleep(
             &self,
             ctx: &Context<'_>,
             #[graphql(desc = "Provides Sleep to remove given tag from.")] remove_tag_input: RemoveTagFromSleepInput)
-            -> Option<Sleep> {
+            -> Result<Sleep> {
                 let dbm = ctx.data_unchecked::<DBManager>();
                 let sleep_id = remove_tag_input.sleep_id;
                 let tag_id = remove_tag_input.tag_id;
 
-                let tag_removed = dbm.remove_tag_from_sleep(sleep_id, tag_id).await;
+                dbm.remove_tag_from_sleep(sleep_id, tag_id).await.extend()?;
+
+                let sleep = Sleep::from_sleep_id(dbm, sleep_id).await.extend()?;
+                publish(ctx, SleepChanged::updated(&sleep));
+                Ok(sleep)
+            }
+
+        async fn remove_tag_from_dream(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Provides Dream to remove given tag from.")] remove_tag_input: RemoveTagFromDreamInput)
+            -> Result<Dream> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let dream_id = remove_tag_input.dream_id;
 
-                if tag_removed {
-                    Sleep::from_sleep_id(dbm, sleep_id).await
+                dbm.remove_tag_from_dream(dream_id, remove_tag_input.tag_id).await.extend()?;
+
+                Dream::from_dream_id(dbm, dream_id).await.extend()
+            }
+
+        async fn import_csv(
+            &self,
+            ctx: &Context<'_>,
+            #[graphql(desc = "Contents of the csv file and how it is read")] csv_import_input: CsvImportInput)
+            -> Result<ImportReport> {
+                let dbm = ctx.data_unchecked::<DBManager>();
+                let report = dbm.import_csv(&csv_import_input.csv, &csv_import_input.options()).await.extend()?;
+
+                for tag in dbm.get_multiple_tags(report.tag_ids.clone()).await.extend()? {
+                    publish(ctx, TagChanged::created(&Tag { id: tag.id, name: tag.name, color: tag.color }));
+                }
+                for sleep in dbm.get_multiple_sleeps(report.sleep_ids.clone()).await.extend()? {
+                    publish(ctx, SleepChanged::created(&Sleep::from_db(&sleep)));
                 }
-                else {
-                    None
+                for comment in dbm.get_comments_by_sleeps(&report.sleep_ids).await.extend()?.into_values().flatten() {
+                    publish(ctx, CommentChanged::created(&Comment { id: comment.id, sleep_id: comment.sleep_id, comment: comment.comment }));
                 }
+
+                Ok(ImportReport::from(&report))
             }
-}
\ No newline at end of file
+}
+
+/// Publishes a change to the subscriptions of the user of the request
+fn publish(ctx: &Context<'_>, change: impl Into<Change>) {
+    let dbm = ctx.data_unchecked::<DBManager>();
+    ctx.data_unchecked::<ChangeChannel>().publish(dbm.user_id(), change);
+}
@@ -1,7 +1,13 @@
 use crate::DBManager;
+use crate::db_manager;
+use super::gql_auth::AdminGuard;
+use super::gql_errors::optional;
+use super::gql_pagination::*;
 use super::gql_types::*;
 
-use async_graphql::{Context, Object};
+use async_graphql::{Context, Error, Object, Result, ResultExt};
+use async_graphql::connection::query;
+use chrono::NaiveDate;
 
 /// Contains the query definitions for the graphql api.
 pub struct QueryRoot;
@@ -9,21 +15,60 @@ pub struct QueryRoot;
 #[Object]
 impl QueryRoot {
 
+    /// Get the user whose data the request reads and changes
+    async fn viewer<'a>(&self, ctx: &Context<'a>) -> Result<User> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        User::from_user_id(dbm, dbm.user_id()).await.extend()
+    }
+
+    /// Get the api tokens of the user
+    async fn api_tokens<'a>(&self, ctx: &Context<'a>) -> Result<Vec<ApiToken>> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        let tokens = dbm.get_api_tokens().await.extend()?;
+        Ok(tokens.iter().map(ApiToken::from_db).collect::<Vec<ApiToken>>())
+    }
+
+    /// Get the status of the snapshots of the database, null if the server doesn't take snapshots.
+    /// Only allowed with an admin api token.
+    #[graphql(guard = "AdminGuard")]
+    async fn snapshots<'a>(&self, ctx: &Context<'a>) -> Result<Option<Snapshots>> {
+        Ok(ctx.data_opt::<db_manager::Snapshots>().map(Snapshots::from))
+    }
+
     /// Get all sleeps
-    async fn all_sleeps<'a>(&self, ctx: &Context<'a>) -> Option<Vec<Sleep>> {
+    #[graphql(deprecation = "Use the paginated sleeps query")]
+    async fn all_sleeps<'a>(&self, ctx: &Context<'a>) -> Result<Vec<Sleep>> {
         let dbm = ctx.data_unchecked::<DBManager>();
-        let sleeps = dbm.get_all_sleeps().await;
-        sleeps.map(|v| v.iter().map(Sleep::from_db).collect::<Vec<Sleep>>())
+        let sleeps = dbm.get_all_sleeps().await.extend()?;
+        Ok(sleeps.iter().map(Sleep::from_db).collect::<Vec<Sleep>>())
     }
 
+    /// Get a page of sleeps ordered by night, optionally only the sleeps with a tag
+    async fn sleeps<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Only return sleeps after this cursor.")] after: Option<String>,
+        #[graphql(desc = "Only return sleeps before this cursor.")] before: Option<String>,
+        #[graphql(desc = "Return the first n sleeps.")] first: Option<i32>,
+        #[graphql(desc = "Return the last n sleeps.")] last: Option<i32>,
+        #[graphql(desc = "Only return sleeps that have this tag.")] tag_id: Option<i64>)
+        -> Result<CountedConnection<SleepCursor, Sleep>> {
+            let dbm = ctx.data_unchecked::<DBManager>();
+            query(after, before, first, last, |after: Option<SleepCursor>, before: Option<SleepCursor>, first, last| async move {
+                let args = page_args(after.map(|c| c.key()), before.map(|c| c.key()), first, last);
+                let page = dbm.get_sleeps_page(&args, tag_id).await.extend()?;
+                Ok::<_, Error>(to_connection(page, SleepCursor::from_db, Sleep::from_db))
+            }).await
+        }
+
     /// Get the sleep with the given id
     async fn sleep<'a>(
         &self,
         ctx: &Context<'a>,
         #[graphql(desc = "id of the sleep")] id: i64) 
-        -> Option<Sleep> {
+        -> Result<Option<Sleep>> {
         let dbm = ctx.data_unchecked::<DBManager>();
-        Sleep::from_sleep_id(dbm, id).await
+        optional(Sleep::from_sleep_id(dbm, id).await)
     }
 
     /// Get Sleeps in a given month
@@ -31,23 +76,134 @@ impl QueryRoot {
         &self,
         ctx: &Context<'a>,
         #[graphql(desc = "Month and year to get sleeps from.")] month: SleepsByMonthInput)
-        -> Option<Vec<Sleep>> {
+        -> Result<Vec<Sleep>> {
             let dbm = ctx.data_unchecked::<DBManager>();
-            let sleeps = dbm.get_sleeps_by_month(month.month, month.year).await;
-            sleeps.map(|v| v.iter().map(Sleep::from_db).collect::<Vec<Sleep>>())
+            let sleeps = dbm.get_sleeps_by_month(month.month, month.year).await.extend()?;
+            Ok(sleeps.iter().map(Sleep::from_db).collect::<Vec<Sleep>>())
         }
 
     /// Get sleeps in a given date range. Dates are inclusive
     async fn sleeps_in_range<'a>(
         &self,
         ctx: &Context<'a>,
-        #[graphql(desc = "Inclusive start date of date range.")] start_date: SleepsInRangeInput,
-        #[graphql(desc = "Inclusive end date of date range.")] end_date: SleepsInRangeInput)
-        -> Option<Vec<Sleep>> {
+        #[graphql(desc = "Inclusive start date of date range.")] start_date: NaiveDate,
+        #[graphql(desc = "Inclusive end date of date range.")] end_date: NaiveDate)
+        -> Result<Vec<Sleep>> {
+            let dbm = ctx.data_unchecked::<DBManager>();
+            let sleeps = dbm.get_sleeps_in_range(start_date, end_date).await.extend()?;
+            Ok(sleeps.iter().map(Sleep::from_db).collect::<Vec<Sleep>>())
+        }
+
+    /// Get statistics of the sleeps in a date range, grouped by day, week, month or weekday,
+    /// along with the 7 and 30 day rolling averages
+    async fn sleep_stats<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Inclusive range of nights to compute statistics for.")] range: DateRangeInput,
+        #[graphql(desc = "How the sleeps are grouped.", default)] group_by: StatsGroupBy)
+        -> Result<SleepStats> {
+            let dbm = ctx.data_unchecked::<DBManager>();
+            let stats = dbm.get_sleep_stats(range.start, range.end, group_by.into()).await.extend()?;
+            Ok(SleepStats::from(&stats))
+        }
+
+    /// Compare the sleeps with each tag to the sleeps without it in a date range,
+    /// returning the mean amount and quality with and without the tag and the significance of the difference
+    async fn tag_impact<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Inclusive range of nights to compare.")] range: DateRangeInput,
+        #[graphql(desc = "Tags to compare, all tags are compared when omitted.")] tag_ids: Option<Vec<i64>>,
+        #[graphql(desc = "Compare the sleeps with the children of a tag, and their children, as sleeps with the tag.", default)] roll_up: bool)
+        -> Result<Vec<TagImpact>> {
+            let dbm = ctx.data_unchecked::<DBManager>();
+            let impact = dbm.get_tag_impact(range.start, range.end, tag_ids, roll_up).await.extend()?;
+            Ok(impact.iter().map(TagImpact::from).collect::<Vec<TagImpact>>())
+        }
+
+    /// Export the sleeps with their tags and comments as a csv file ordered by night,
+    /// in the format that importCsv reads by default
+    async fn export_csv<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Inclusive range of nights to export, all sleeps are exported when omitted.")] range: Option<DateRangeInput>)
+        -> Result<String> {
+            let dbm = ctx.data_unchecked::<DBManager>();
+            let (start, end) = range.map_or((None, None), |r| (Some(r.start), Some(r.end)));
+            dbm.export_csv(start, end).await.extend()
+        }
+
+    /// Get the dream with the given id
+    async fn dream<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "id of the dream")] id: i64)
+        -> Result<Option<Dream>> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        optional(Dream::from_dream_id(dbm, id).await)
+    }
+
+    /// Get a page of the dreams of the sleeps in a given date range, ordered by night and id. Dates are inclusive
+    #[allow(clippy::too_many_arguments)]
+    async fn dreams_in_range<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Inclusive start date of date range.")] start_date: NaiveDate,
+        #[graphql(desc = "Inclusive end date of date range.")] end_date: NaiveDate,
+        #[graphql(desc = "Only return dreams after this cursor.")] after: Option<String>,
+        #[graphql(desc = "Only return dreams before this cursor.")] before: Option<String>,
+        #[graphql(desc = "Return the first n dreams.")] first: Option<i32>,
+        #[graphql(desc = "Return the last n dreams.")] last: Option<i32>)
+        -> Result<CountedConnection<DreamCursor, Dream>> {
+            let filter = db_manager::DreamFilter { range: Some((start_date, end_date)), tag_id: None };
+            dreams_page(ctx, &filter, after, before, first, last).await
+        }
+
+    /// Get a page of the dreams associated to the given tag, ordered by night and id
+    async fn dreams_by_tag<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "id of the tag")] tag_id: i64,
+        #[graphql(desc = "Only return dreams after this cursor.")] after: Option<String>,
+        #[graphql(desc = "Only return dreams before this cursor.")] before: Option<String>,
+        #[graphql(desc = "Return the first n dreams.")] first: Option<i32>,
+        #[graphql(desc = "Return the last n dreams.")] last: Option<i32>)
+        -> Result<CountedConnection<DreamCursor, Dream>> {
+            let filter = db_manager::DreamFilter { range: None, tag_id: Some(tag_id) };
+            dreams_page(ctx, &filter, after, before, first, last).await
+        }
+
+    /// Get a page of tags ordered by id
+    async fn tags<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Only return tags after this cursor.")] after: Option<String>,
+        #[graphql(desc = "Only return tags before this cursor.")] before: Option<String>,
+        #[graphql(desc = "Return the first n tags.")] first: Option<i32>,
+        #[graphql(desc = "Return the last n tags.")] last: Option<i32>)
+        -> Result<CountedConnection<i64, Tag>> {
             let dbm = ctx.data_unchecked::<DBManager>();
-            let sleeps = dbm.get_all_sleeps().await;
-            let sleeps = sleeps.map(|v| v.iter().map(Sleep::from_db).collect::<Vec<Sleep>>());
-            Sleep::filter_sleeps_by_date(sleeps, &start_date, &end_date)
+            query(after, before, first, last, |after, before, first, last| async move {
+                let page = dbm.get_tags_page(&page_args(after, before, first, last)).await.extend()?;
+                Ok::<_, Error>(to_connection(page, |t| t.id, |t| Tag { id: t.id, name: t.name.clone(), color: t.color }))
+            }).await
+        }
+
+    /// Get a page of comments ordered by id, optionally only the comments of a sleep
+    async fn comments<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "Only return comments after this cursor.")] after: Option<String>,
+        #[graphql(desc = "Only return comments before this cursor.")] before: Option<String>,
+        #[graphql(desc = "Return the first n comments.")] first: Option<i32>,
+        #[graphql(desc = "Return the last n comments.")] last: Option<i32>,
+        #[graphql(desc = "Only return comments of this sleep.")] sleep_id: Option<i64>)
+        -> Result<CountedConnection<i64, Comment>> {
+            let dbm = ctx.data_unchecked::<DBManager>();
+            query(after, before, first, last, |after, before, first, last| async move {
+                let page = dbm.get_comments_page(sleep_id, &page_args(after, before, first, last)).await.extend()?;
+                Ok::<_, Error>(to_connection(page, |c| c.id, |c| Comment { id: c.id, sleep_id: c.sleep_id, comment: c.comment.clone() }))
+            }).await
         }
 
     /// Get the tag with the given id
@@ -55,20 +211,62 @@ impl QueryRoot {
         &self,
         ctx: &Context<'a>,
         #[graphql(desc = "id of the tag")] id: i64) 
-        -> Option<Tag> {
+        -> Result<Option<Tag>> {
         let dbm = ctx.data_unchecked::<DBManager>();
-        let tag = dbm.get_tag(id).await;
-        match tag {
-            Some(t) => Some(Tag { id: t.id, name: t.name.clone(), color: t.color}),
-            None => None,
-        }
+        optional(Tag::from_tag_id(dbm, id).await)
+    }
+
+    /// Get the tag with the given name, or the tag that the name is an old name of
+    async fn tag_by_name<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "name or alias of the tag")] name: String)
+        -> Result<Option<Tag>> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        let tag = dbm.get_tag_by_name(&name).await.extend()?;
+        Ok(tag.map(|t| Tag { id: t.id, name: t.name, color: t.color }))
+    }
+
+    /// Get all tag categories ordered by id
+    async fn tag_categories<'a>(&self, ctx: &Context<'a>) -> Result<Vec<TagCategory>> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        let categories = dbm.get_tag_categories().await.extend()?;
+        Ok(categories.into_iter().map(|c| TagCategory { id: c.id, name: c.name }).collect::<Vec<TagCategory>>())
+    }
+
+    /// Get the tag category with the given id
+    async fn tag_category<'a>(
+        &self,
+        ctx: &Context<'a>,
+        #[graphql(desc = "id of the category")] id: i64)
+        -> Result<Option<TagCategory>> {
+        let dbm = ctx.data_unchecked::<DBManager>();
+        optional(TagCategory::from_category_id(dbm, id).await)
     }
 
     /// Get all tags
-    async fn all_tags<'a>(&self, ctx: &Context<'a>) -> Option<Vec<Tag>> {
+    #[graphql(deprecation = "Use the paginated tags query")]
+    async fn all_tags<'a>(&self, ctx: &Context<'a>) -> Result<Vec<Tag>> {
         let dbm = ctx.data_unchecked::<DBManager>();
-        let tags = dbm.get_all_tags().await;
-        tags.map(|v| v.iter().map(|t| Tag { id: t.id, name: t.name.clone(), color: t.color})
+        let tags = dbm.get_all_tags().await.extend()?;
+        Ok(tags.iter().map(|t| Tag { id: t.id, name: t.name.clone(), color: t.color})
             .collect::<Vec<Tag>>())
     }
-}
\ No newline at end of file
+}
+
+/// Returns a page of the filtered dreams as a connection
+async fn dreams_page(
+    ctx: &Context<'_>,
+    filter: &db_manager::DreamFilter,
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>)
+    -> Result<CountedConnection<DreamCursor, Dream>> {
+    let dbm = ctx.data_unchecked::<DBManager>();
+    query(after, before, first, last, |after: Option<DreamCursor>, before: Option<DreamCursor>, first, last| async move {
+        let args = page_args(after.map(|c| c.key()), before.map(|c| c.key()), first, last);
+        let page = dbm.get_dreams_page(&args, filter).await.extend()?;
+        Ok::<_, Error>(to_connection(page, DreamCursor::from_db, |d| Dream::from_db(&d.1)))
+    }).await
+}
@@ -0,0 +1,42 @@
+use crate::DBManager;
+use super::gql_changes::*;
+
+use async_graphql::{Context, Subscription};
+use async_graphql::futures_util::{future, Stream, StreamExt};
+
+/// Contains the subscription definitions for the graphql api.
+/// Each subscription receives the changes made by mutations to the data of the subscribing user.
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Sleeps that are created, deleted, or updated including changes to their sessions and tags
+    async fn sleep_changed<'a>(&self, ctx: &Context<'a>) -> impl Stream<Item = SleepChanged> {
+        changes(ctx).filter_map(|change| future::ready(match change {
+            Change::Sleep(change) => Some(change),
+            _ => None,
+        }))
+    }
+
+    /// Tags that are created, updated or deleted
+    async fn tag_changed<'a>(&self, ctx: &Context<'a>) -> impl Stream<Item = TagChanged> {
+        changes(ctx).filter_map(|change| future::ready(match change {
+            Change::Tag(change) => Some(change),
+            _ => None,
+        }))
+    }
+
+    /// Comments that are created, updated or deleted, including the comments deleted with their sleep
+    async fn comment_changed<'a>(&self, ctx: &Context<'a>) -> impl Stream<Item = CommentChanged> {
+        changes(ctx).filter_map(|change| future::ready(match change {
+            Change::Comment(change) => Some(change),
+            _ => None,
+        }))
+    }
+}
+
+/// Returns the stream of changes to the data of the user of the subscription
+fn changes(ctx: &Context<'_>) -> impl Stream<Item = Change> {
+    let dbm = ctx.data_unchecked::<DBManager>();
+    ctx.data_unchecked::<ChangeChannel>().subscribe(dbm.user_id())
+}
@@ -0,0 +1,335 @@
+//! Admin commands to manage users and api tokens, import and export sleeps, and back up the database from the command line
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use chrono::NaiveDate;
+use clap::Subcommand;
+use database_manager::db_manager::{
+    AppleHealthOptions, Backup, CsvColumns, CsvImportOptions, DBManager, FitbitOptions, ImportReport, RestoreMode, DEFAULT_DATE_FORMAT, DEFAULT_TAG_DELIMITER
+};
+
+/// Admin commands, the server is not started when a command is given
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Manage users
+    #[command(subcommand)]
+    User(UserCommand),
+
+    /// Manage api tokens
+    #[command(subcommand)]
+    Token(TokenCommand),
+
+    /// Import and export sleeps as csv files
+    #[command(subcommand)]
+    Csv(CsvCommand),
+
+    /// Back up and restore the data of every user as a JSON document
+    #[command(subcommand)]
+    Backup(BackupCommand),
+
+    /// Import sleeps from the exports of devices
+    #[command(subcommand)]
+    Import(ImportCommand),
+}
+
+/// Commands to manage users
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum UserCommand {
+    /// Add a user
+    Add {
+        /// Unique name of the user
+        name: String,
+    },
+
+    /// List every user
+    List,
+}
+
+/// Commands to manage the api tokens of a user
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum TokenCommand {
+    /// Create an api token and print it, the token can't be shown again
+    Create {
+        /// Name of the user the token authenticates as
+        #[arg(long, default_value = "default")]
+        user: String,
+
+        /// Only allow queries with the token
+        #[arg(long)]
+        read_only: bool,
+
+        /// Allow the token to manage the server, ex: add users and see the status of the snapshots
+        #[arg(long)]
+        admin: bool,
+
+        /// Name of the token to tell tokens apart, ex: "phone"
+        name: String,
+    },
+
+    /// List the api tokens of a user
+    List {
+        /// Name of the user to list the tokens of
+        #[arg(long, default_value = "default")]
+        user: String,
+    },
+
+    /// Revoke an api token of a user
+    Revoke {
+        /// Name of the user the token belongs to
+        #[arg(long, default_value = "default")]
+        user: String,
+
+        /// id of the token to revoke
+        id: i64,
+    },
+}
+
+/// Commands to import and export the sleeps of a user as csv files
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum CsvCommand {
+    /// Import sleeps from a csv file with a header row, creating the tags that don't exist yet
+    Import {
+        /// Name of the user to import the sleeps for
+        #[arg(long, default_value = "default")]
+        user: String,
+
+        /// Print what would be imported without changing the database
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Column with the night of each sleep
+        #[arg(long, default_value = "night")]
+        night_column: String,
+
+        /// Column with the amount of sleep
+        #[arg(long, default_value = "amount")]
+        amount_column: String,
+
+        /// Column with the quality of sleep
+        #[arg(long, default_value = "quality")]
+        quality_column: String,
+
+        /// Column with the names of the tags
+        #[arg(long, default_value = "tags")]
+        tags_column: String,
+
+        /// Column with the comments, one per line of the cell
+        #[arg(long, default_value = "comment")]
+        comment_column: String,
+
+        /// Separator of the tag names in the tags column
+        #[arg(long, default_value_t = DEFAULT_TAG_DELIMITER)]
+        tag_delimiter: char,
+
+        /// chrono format of the nights, ex: "%m/%d/%Y"
+        #[arg(long, default_value = DEFAULT_DATE_FORMAT)]
+        date_format: String,
+
+        /// csv file to import
+        file: PathBuf,
+    },
+
+    /// Export sleeps with their tags and comments as a csv file
+    Export {
+        /// Name of the user to export the sleeps of
+        #[arg(long, default_value = "default")]
+        user: String,
+
+        /// First night to export, in yyyy-mm-dd format
+        #[arg(long)]
+        start: Option<NaiveDate>,
+
+        /// Last night to export, in yyyy-mm-dd format
+        #[arg(long)]
+        end: Option<NaiveDate>,
+
+        /// File to write the csv to instead of printing it
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Commands to back up and restore the whole database
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum BackupCommand {
+    /// Write every user, sleep, tag, comment and dream to a JSON document
+    Export {
+        /// File to write the backup to instead of printing it
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Restore a JSON backup, either everything is restored or nothing changes
+    Restore {
+        /// merge keeps the data of the database and adds what it doesn't have,
+        /// replace deletes the sleeps and tags of every user before restoring
+        #[arg(long, default_value = "merge")]
+        mode: RestoreMode,
+
+        /// JSON backup to restore
+        file: PathBuf,
+    },
+}
+
+/// Commands to import the sleeps recorded by devices
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum ImportCommand {
+    /// Import the sleep analysis of an Apple Health export.xml, nights imported before are updated
+    AppleHealth {
+        /// Name of the user to import the sleeps for
+        #[arg(long, default_value = "default")]
+        user: String,
+
+        /// Print what would be imported without changing the database
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Quality of the imported sleeps
+        #[arg(long, default_value_t = 0)]
+        quality: i64,
+
+        /// export.xml file of an Apple Health export
+        file: PathBuf,
+    },
+
+    /// Import the sleep-*.json files of a Fitbit export, tagged "fitbit", nights imported before are updated
+    Fitbit {
+        /// Name of the user to import the sleeps for
+        #[arg(long, default_value = "default")]
+        user: String,
+
+        /// Print what would be imported without changing the database
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Quality of a sleep with an efficiency of 0%
+        #[arg(long, default_value_t = FitbitOptions::default().min_quality)]
+        min_quality: i64,
+
+        /// Quality of a sleep with an efficiency of 100%
+        #[arg(long, default_value_t = FitbitOptions::default().max_quality)]
+        max_quality: i64,
+
+        /// Directory of the export with the sleep files, ex: "Fitbit/Sleep" of a Google Takeout
+        dir: PathBuf,
+    },
+}
+
+/// Runs an admin command against the database.
+/// Returns an error message if the command failed.
+pub async fn run(command: &Command, dbm: &DBManager) -> Result<(), String> {
+    match command {
+        Command::User(UserCommand::Add { name }) => {
+            let id = dbm.insert_user(name).await.map_err(|e| e.to_string())?;
+            println!("Added user {} with id {}", name, id);
+        },
+        Command::User(UserCommand::List) => {
+            for user in dbm.get_all_users().await.map_err(|e| e.to_string())? {
+                println!("{}\t{}", user.id, user.name);
+            }
+        },
+        Command::Token(TokenCommand::Create { user, read_only, admin, name }) => {
+            let (id, token) = user_dbm(dbm, user).await?.create_api_token(name, *read_only, *admin).await.map_err(|e| e.to_string())?;
+            println!("Created api token {} for {}, it can't be shown again:", id, user);
+            println!("{}", token);
+        },
+        Command::Token(TokenCommand::List { user }) => {
+            for token in user_dbm(dbm, user).await?.get_api_tokens().await.map_err(|e| e.to_string())? {
+                let scope = if token.read_only { "read-only" } else { "read-write" };
+                let admin = if token.admin { "\tadmin" } else { "" };
+                println!("{}\t{}\t{}{}", token.id, token.name, scope, admin);
+            }
+        },
+        Command::Token(TokenCommand::Revoke { user, id }) => {
+            user_dbm(dbm, user).await?.revoke_api_token(*id).await.map_err(|e| e.to_string())?;
+            println!("Revoked api token {}", id);
+        },
+        Command::Csv(CsvCommand::Import {
+            user, dry_run, night_column, amount_column, quality_column, tags_column, comment_column, tag_delimiter, date_format, file
+        }) => {
+            let csv = fs::read_to_string(file).map_err(|e| format!("unable to read {}: {}", file.display(), e))?;
+            let options = CsvImportOptions {
+                columns: CsvColumns {
+                    night: night_column.clone(),
+                    amount: amount_column.clone(),
+                    quality: quality_column.clone(),
+                    tags: tags_column.clone(),
+                    comment: comment_column.clone(),
+                },
+                tag_delimiter: *tag_delimiter,
+                date_format: date_format.clone(),
+                dry_run: *dry_run,
+            };
+            let report = user_dbm(dbm, user).await?.import_csv(&csv, &options).await.map_err(|e| e.to_string())?;
+            print_report(&report);
+        },
+        Command::Csv(CsvCommand::Export { user, start, end, output }) => {
+            let csv = user_dbm(dbm, user).await?.export_csv(*start, *end).await.map_err(|e| e.to_string())?;
+            match output {
+                Some(path) => fs::write(path, csv).map_err(|e| format!("unable to write {}: {}", path.display(), e))?,
+                None => print!("{}", csv),
+            }
+        },
+        Command::Import(ImportCommand::AppleHealth { user, dry_run, quality, file }) => {
+            let export = File::open(file).map_err(|e| format!("unable to read {}: {}", file.display(), e))?;
+            let options = AppleHealthOptions { quality: *quality, dry_run: *dry_run };
+            let report = user_dbm(dbm, user).await?.import_apple_health(BufReader::new(export), &options).await
+                .map_err(|e| e.to_string())?;
+            print_report(&report);
+        },
+        Command::Import(ImportCommand::Fitbit { user, dry_run, min_quality, max_quality, dir }) => {
+            let options = FitbitOptions { min_quality: *min_quality, max_quality: *max_quality, dry_run: *dry_run };
+            let report = user_dbm(dbm, user).await?.import_fitbit(dir, &options).await.map_err(|e| e.to_string())?;
+            print_report(&report);
+        },
+        Command::Backup(BackupCommand::Export { output }) => {
+            let json = dbm.export_backup().await.map_err(|e| e.to_string())?.to_json();
+            match output {
+                Some(path) => fs::write(path, json).map_err(|e| format!("unable to write {}: {}", path.display(), e))?,
+                None => println!("{}", json),
+            }
+        },
+        Command::Backup(BackupCommand::Restore { mode, file }) => {
+            let json = fs::read_to_string(file).map_err(|e| format!("unable to read {}: {}", file.display(), e))?;
+            let backup = Backup::from_json(&json).map_err(|e| e.to_string())?;
+            let report = dbm.restore_backup(&backup, *mode).await.map_err(|e| e.to_string())?;
+
+            println!("Restored {} with {}: {} users, {} sleeps, {} sleep sessions, {} tags, {} comments, {} dreams",
+                file.display(), report.mode, report.users, report.sleeps, report.sleep_sessions, report.tags, report.comments, report.dreams);
+            if report.skipped_sleeps > 0 || report.reused_tags > 0 {
+                println!("Kept {} sleeps and {} tags that already existed", report.skipped_sleeps, report.reused_tags);
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Prints what an import did, or would do for a dry run
+fn print_report(report: &ImportReport) {
+    let verb = if report.dry_run { "Would import" } else { "Imported" };
+    println!("{} {} of {} rows", verb, report.imported, report.rows);
+    if report.merged > 0 {
+        let verb = if report.dry_run { "Would update" } else { "Updated" };
+        println!("{} {} sleeps that were imported before", verb, report.merged);
+    }
+    if !report.created_tags.is_empty() {
+        let verb = if report.dry_run { "Would create" } else { "Created" };
+        println!("{} tags: {}", verb, report.created_tags.join(", "));
+    }
+    for issue in &report.issues {
+        match &issue.file {
+            Some(file) => println!("{} line {}: {:?}: {}", file, issue.line, issue.kind, issue.message),
+            None => println!("line {}: {:?}: {}", issue.line, issue.kind, issue.message),
+        }
+    }
+}
+
+/// Returns a database manager scoped to the user with the given name
+async fn user_dbm(dbm: &DBManager, name: &str) -> Result<DBManager, String> {
+    match dbm.get_user_by_name(name).await.map_err(|e| e.to_string())? {
+        Some(user) => Ok(dbm.for_user(user.id)),
+        None => Err(format!("user {} does not exist", name)),
+    }
+}
@@ -0,0 +1,307 @@
+//! Configuration of the server, loaded from command line arguments, environment variables and a config file
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use clap::Parser;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use crate::admin::Command;
+
+/// Address the server binds to when none is configured
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8000";
+
+/// Database used when none is configured
+const DEFAULT_DATABASE_URL: &str = "sqlite://sleep.db";
+
+/// Database used in test data mode when none is configured, so the mock data never replaces real data
+const DEFAULT_TEST_DATABASE_URL: &str = "sqlite://test.db";
+
+/// Minutes between snapshots of the database when none is configured
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 60;
+
+/// Number of snapshots of the database that are kept when none is configured
+const DEFAULT_SNAPSHOT_KEEP: usize = 24;
+
+/// Command line arguments of the server.
+/// Each setting can also be set with an environment variable, the command line takes precedence over
+/// the environment, which takes precedence over the config file, which takes precedence over the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Parser)]
+#[command(version, about = "GraphQL server to track sleep")]
+pub struct Args {
+    /// Path to a TOML config file
+    #[arg(long, env = "SLEEP_TRACKER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address and port to listen on [default: 127.0.0.1:8000]
+    #[arg(long, env = "SLEEP_TRACKER_BIND_ADDRESS")]
+    pub bind_address: Option<SocketAddr>,
+
+    /// Url of the sqlite database [default: sqlite://sleep.db, or sqlite://test.db in test data mode]
+    #[arg(long, env = "SLEEP_TRACKER_DATABASE_URL")]
+    pub database_url: Option<String>,
+
+    /// Maximum number of connections to the database [default: 4]
+    #[arg(long, env = "SLEEP_TRACKER_POOL_SIZE")]
+    pub pool_size: Option<u32>,
+
+    /// Serve the GraphiQL IDE on GET requests [default: true]
+    #[arg(long, env = "SLEEP_TRACKER_GRAPHIQL", value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub graphiql: Option<bool>,
+
+    /// Minimum level of the logs that are written: off, error, warn, info, debug or trace [default: info]
+    #[arg(long, env = "SLEEP_TRACKER_LOG_LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Replace the database with mock data on start up [default: false]
+    #[arg(long, env = "SLEEP_TRACKER_TEST_DATA", value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub test_data: Option<bool>,
+
+    /// Directory to write snapshots of the database to, snapshots are only taken when it is set
+    #[arg(long, env = "SLEEP_TRACKER_SNAPSHOT_DIR")]
+    pub snapshot_dir: Option<PathBuf>,
+
+    /// Minutes between snapshots of the database [default: 60]
+    #[arg(long, env = "SLEEP_TRACKER_SNAPSHOT_INTERVAL", value_name = "MINUTES")]
+    pub snapshot_interval: Option<u64>,
+
+    /// Number of snapshots of the database that are kept, older snapshots are deleted [default: 24]
+    #[arg(long, env = "SLEEP_TRACKER_SNAPSHOT_KEEP")]
+    pub snapshot_keep: Option<usize>,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Admin command to run instead of starting the server
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Settings that can be set in the config file, every setting is optional
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    bind_address: Option<SocketAddr>,
+    database_url: Option<String>,
+    pool_size: Option<u32>,
+    graphiql: Option<bool>,
+    log_level: Option<String>,
+    test_data: Option<bool>,
+    snapshot_dir: Option<PathBuf>,
+    snapshot_interval: Option<u64>,
+    snapshot_keep: Option<usize>,
+}
+
+/// Effective configuration of the server
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Config {
+    /// Address and port the server listens on
+    pub bind_address: SocketAddr,
+
+    /// Url of the sqlite database
+    pub database_url: String,
+
+    /// Maximum number of connections to the database
+    pub pool_size: u32,
+
+    /// true if the GraphiQL IDE is served
+    pub graphiql: bool,
+
+    /// Minimum level of the logs that are written
+    #[serde(serialize_with = "serialize_level")]
+    pub log_level: LevelFilter,
+
+    /// true if the database is replaced with mock data on start up
+    pub test_data: bool,
+
+    /// Directory snapshots of the database are written to, None if no snapshots are taken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_dir: Option<PathBuf>,
+
+    /// Minutes between snapshots of the database
+    pub snapshot_interval: u64,
+
+    /// Number of snapshots of the database that are kept
+    pub snapshot_keep: usize,
+}
+
+impl Config {
+    /// Combines the command line arguments and environment variables with the config file, if one is given.
+    /// Returns an error message if the config file can't be read or a setting is invalid.
+    pub fn load(args: &Args) -> Result<Config, String> {
+        let file = match &args.config {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("unable to read config file {}: {}", path.display(), e))?;
+                toml::from_str::<FileConfig>(&contents)
+                    .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?
+            },
+            None => FileConfig::default(),
+        };
+
+        let file_log_level = file.log_level.as_deref()
+            .map(|level| level.parse::<LevelFilter>().map_err(|_| format!("invalid log level \"{}\" in the config file", level)))
+            .transpose()?;
+
+        let test_data = args.test_data.or(file.test_data).unwrap_or(false);
+        let default_database_url = if test_data { DEFAULT_TEST_DATABASE_URL } else { DEFAULT_DATABASE_URL };
+
+        let config = Config {
+            bind_address: args.bind_address.or(file.bind_address)
+                .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.parse().unwrap()),
+            database_url: args.database_url.clone().or(file.database_url)
+                .unwrap_or_else(|| String::from(default_database_url)),
+            pool_size: args.pool_size.or(file.pool_size).unwrap_or(database_manager::db_manager::DEFAULT_POOL_SIZE),
+            graphiql: args.graphiql.or(file.graphiql).unwrap_or(true),
+            log_level: args.log_level.or(file_log_level).unwrap_or(LevelFilter::Info),
+            test_data,
+            snapshot_dir: args.snapshot_dir.clone().or(file.snapshot_dir),
+            snapshot_interval: args.snapshot_interval.or(file.snapshot_interval).unwrap_or(DEFAULT_SNAPSHOT_INTERVAL),
+            snapshot_keep: args.snapshot_keep.or(file.snapshot_keep).unwrap_or(DEFAULT_SNAPSHOT_KEEP),
+        };
+
+        if config.pool_size == 0 {
+            return Err(String::from("pool size must be at least 1"));
+        }
+        if config.snapshot_interval == 0 {
+            return Err(String::from("snapshot interval must be at least 1 minute"));
+        }
+        if config.snapshot_keep == 0 {
+            return Err(String::from("at least 1 snapshot must be kept"));
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the configuration formatted as a TOML config file
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("config is always valid toml")
+    }
+}
+
+/// Writes a log level in lowercase, the same way it is written in the config file
+fn serialize_level<S: serde::Serializer>(level: &LevelFilter, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&level.to_string().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::sync::Mutex;
+    use super::*;
+
+    /// Serializes the tests that parse the command line, which reads the process wide environment
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Writes a config file with the given contents to the temp directory and returns its path
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("sleep-tracker-config-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(std::iter::once("graphql-server").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::load(&Args::default()).unwrap();
+        assert_eq!(config, Config {
+            bind_address: DEFAULT_BIND_ADDRESS.parse().unwrap(),
+            database_url: String::from(DEFAULT_DATABASE_URL),
+            pool_size: database_manager::db_manager::DEFAULT_POOL_SIZE,
+            graphiql: true,
+            log_level: LevelFilter::Info,
+            test_data: false,
+            snapshot_dir: None,
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            snapshot_keep: DEFAULT_SNAPSHOT_KEEP,
+        });
+
+        let test_config = Config::load(&Args { test_data: Some(true), ..Args::default() }).unwrap();
+        assert_eq!(test_config.database_url, DEFAULT_TEST_DATABASE_URL);
+    }
+
+    #[test]
+    fn precedence() {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let path = write_config("precedence", r#"
+            bind_address = "0.0.0.0:9000"
+            database_url = "sqlite://file.db"
+            pool_size = 2
+            graphiql = false
+            log_level = "warn"
+            snapshot_interval = 30
+            snapshot_keep = 5
+        "#);
+        let config_arg = path.to_str().unwrap();
+
+        // The config file overrides the defaults
+        let config = Config::load(&parse(&["--config", config_arg])).unwrap();
+        assert_eq!(config.bind_address, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.database_url, "sqlite://file.db");
+        assert_eq!(config.pool_size, 2);
+        assert!(!config.graphiql);
+        assert_eq!(config.log_level, LevelFilter::Warn);
+        assert!(!config.test_data);
+        assert_eq!(config.snapshot_interval, 30);
+        assert_eq!(config.snapshot_keep, 5);
+
+        // The environment overrides the config file
+        env::set_var("SLEEP_TRACKER_DATABASE_URL", "sqlite://env.db");
+        env::set_var("SLEEP_TRACKER_POOL_SIZE", "3");
+        env::set_var("SLEEP_TRACKER_LOG_LEVEL", "debug");
+        let config = Config::load(&parse(&["--config", config_arg])).unwrap();
+        assert_eq!(config.bind_address, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.database_url, "sqlite://env.db");
+        assert_eq!(config.pool_size, 3);
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert_eq!(config.snapshot_keep, 5);
+
+        // The command line overrides the environment
+        let config = Config::load(&parse(&[
+            "--config", config_arg, "--pool-size", "8", "--log-level", "error", "--snapshot-keep", "7",
+        ])).unwrap();
+        assert_eq!(config.database_url, "sqlite://env.db");
+        assert_eq!(config.pool_size, 8);
+        assert_eq!(config.log_level, LevelFilter::Error);
+        assert_eq!(config.snapshot_keep, 7);
+        assert_eq!(config.snapshot_interval, 30);
+
+        env::remove_var("SLEEP_TRACKER_DATABASE_URL");
+        env::remove_var("SLEEP_TRACKER_POOL_SIZE");
+        env::remove_var("SLEEP_TRACKER_LOG_LEVEL");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_zero_values() {
+        for args in [
+            Args { pool_size: Some(0), ..Args::default() },
+            Args { snapshot_interval: Some(0), ..Args::default() },
+            Args { snapshot_keep: Some(0), ..Args::default() },
+        ] {
+            assert!(Config::load(&args).is_err(), "{:?} should be rejected", args);
+        }
+
+        let path = write_config("zero", "pool_size = 0");
+        let result = Config::load(&Args { config: Some(path.clone()), ..Args::default() });
+        fs::remove_file(path).unwrap();
+        assert_eq!(result, Err(String::from("pool size must be at least 1")));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let path = write_config("unknown", "pool = 4");
+        let result = Config::load(&Args { config: Some(path.clone()), ..Args::default() });
+        fs::remove_file(path).unwrap();
+        assert!(result.is_err());
+
+        let path = write_config("level", "log_level = \"loud\"");
+        let result = Config::load(&Args { config: Some(path.clone()), ..Args::default() });
+        fs::remove_file(path).unwrap();
+        assert_eq!(result, Err(String::from("invalid log level \"loud\" in the config file")));
+    }
+}
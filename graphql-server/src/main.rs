@@ -1,53 +1,242 @@
-/// Main entry point and managing of the server itself
+//! Main entry point and managing of the server itself
 
-use async_graphql::{http::GraphiQLSource, EmptySubscription, Schema};
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use async_graphql::{http::{GraphiQLSource, ALL_WEBSOCKET_PROTOCOLS}, Data, ErrorExtensions, ServerError};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
 use axum::{
-    extract::Extension,
-    response::{self, IntoResponse},
-    routing::get,
+    extract::{Extension, WebSocketUpgrade},
+    http::{header, HeaderMap, StatusCode},
+    response::{self, IntoResponse, Response},
+    routing::{get, post},
     Router, Server,
 };
-use tokio::signal;
+use clap::Parser;
+use log::LevelFilter;
+use std::process;
+use std::time::Duration;
+use tokio::{signal, time::{self, MissedTickBehavior}};
 
-use database_manager::{QueryRoot, MutationRoot};
+use database_manager::{db_manager::{DBManager, Principal, SnapshotSettings, Snapshots}, SleepSchema};
+
+mod admin;
+mod config;
+use config::{Args, Config};
 
 #[tokio::main]
 async fn main() {
-     let dbm = database_manager::init_db().await;
-    //let dbm = database_manager::_init_test_db().await;
+    let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    // sqlx logs every statement at the info level, only show them when debugging, slow statements are still warned about
+    let query_level = if config.log_level >= LevelFilter::Debug { config.log_level } else { LevelFilter::Warn.min(config.log_level) };
+    env_logger::Builder::new()
+        .filter_level(config.log_level)
+        .filter_module("sqlx::query", query_level)
+        .init();
 
-    // Build schema with queries and mutations, then set the database manager as the context
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
-        .data(dbm)
-        .finish();
+    let dbm = if config.test_data {
+        database_manager::init_test_db(&config.database_url).await
+    }
+    else {
+        match database_manager::init_db(&config.database_url, config.pool_size).await {
+            Ok(dbm) => dbm,
+            Err(e) => {
+                log::error!("Unable to open database {}: {}", config.database_url, e);
+                process::exit(1);
+            }
+        }
+    };
+
+    if let Some(command) = &args.command {
+        if let Err(e) = admin::run(command, &dbm).await {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // the mock data has no api tokens, so create one to be able to use the test database
+    if config.test_data {
+        match dbm.create_api_token("test data", false, false).await {
+            Ok((_, token)) => log::info!("Test data api token: {}", token),
+            Err(e) => log::error!("Unable to create an api token for the test data: {}", e),
+        }
+    }
 
-    // setup the axum app with the schema, and setup the graphiql editor
+    // Build schema with queries, mutations and subscriptions, then set the database manager and data loaders as the context
+    let mut schema = database_manager::build_schema(dbm.clone());
+
+    // take snapshots of the database in the background, the snapshots query reads their status
+    if let Some(dir) = &config.snapshot_dir {
+        let settings = SnapshotSettings {
+            dir: dir.clone(),
+            interval: Duration::from_secs(config.snapshot_interval * 60),
+            keep: config.snapshot_keep,
+        };
+        let snapshots = match Snapshots::new(settings) {
+            Ok(snapshots) => snapshots,
+            Err(e) => {
+                log::error!("Unable to take snapshots: {}", e);
+                process::exit(2);
+            }
+        };
+        log::info!("Taking a snapshot every {} minutes into {}", config.snapshot_interval, dir.display());
+        tokio::spawn(take_snapshots(snapshots.clone(), dbm.clone()));
+        schema = schema.data(snapshots);
+    }
+    let schema = schema.finish();
+
+    // setup the axum app with the schema, and setup the graphiql editor if it is enabled
+    let route = if config.graphiql { get(graphiql).post(graphql_handler) } else { post(graphql_handler) };
     let app = Router::new()
-        .route("/", get(graphiql).post(graphql_handler))
-        .layer(Extension(schema));
+        .route("/", route)
+        .route("/ws", get(graphql_ws_handler))
+        .layer(Extension(schema))
+        .layer(Extension(dbm));
 
-    println!("GraphiQL IDE: http://localhost:8000");
+    if config.graphiql {
+        log::info!("GraphiQL IDE: http://{}", config.bind_address);
+    }
+    log::info!("Listening on {}", config.bind_address);
 
-    // Bind server to local host port 8000, provide handler for graceful shutdown
-    Server::bind(&"127.0.0.1:8000".parse().unwrap())
+    // Bind server to the configured address, provide handler for graceful shutdown
+    Server::bind(&config.bind_address)
         .serve(app.into_make_service())
         .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
 }
 
-/// Basic and default graphql handler from the axum/async-graphql docs
+/// Graphql handler that authenticates each request with the bearer token in the authorization header,
+/// and scopes the request to the user of the token
 async fn graphql_handler(
-    schema: Extension<Schema<QueryRoot, MutationRoot, EmptySubscription>>,
+    schema: Extension<SleepSchema>,
+    dbm: Extension<DBManager>,
+    headers: HeaderMap,
     req: GraphQLRequest,
-) -> GraphQLResponse {
-    schema.execute(req.into_inner()).await.into()
+) -> Response {
+    let principal = match authenticate(&dbm, &headers).await {
+        Ok(principal) => principal,
+        Err(response) => return response,
+    };
+
+    let request = database_manager::scope_request(req.into_inner(), dbm.for_user(principal.user_id)).data(principal);
+    GraphQLResponse::from(schema.execute(request).await).into_response()
+}
+
+/// Graphql websocket handler that serves subscriptions with the graphql-ws protocol.
+/// The connection is authenticated with the bearer token in the authorization header of the upgrade request, or in the
+/// `Authorization` field of the connection init payload since browsers can't set headers on websocket requests.
+async fn graphql_ws_handler(
+    schema: Extension<SleepSchema>,
+    dbm: Extension<DBManager>,
+    headers: HeaderMap,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let schema = schema.0.clone();
+    let dbm = dbm.0.clone();
+    let header_token = bearer_token(headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()));
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .on_connection_init(move |payload| connection_data(dbm, header_token, payload))
+                .serve()
+        })
+}
+
+/// Returns the data that scopes the subscriptions of a websocket connection to the user of its bearer token
+async fn connection_data(dbm: DBManager, header_token: Option<String>, payload: serde_json::Value) -> async_graphql::Result<Data> {
+    let token = header_token.or_else(|| {
+        let authorization = payload.get("Authorization").or_else(|| payload.get("authorization"));
+        bearer_token(authorization.and_then(|value| value.as_str()))
+    });
+
+    let principal = match principal(&dbm, token.as_deref()).await {
+        Ok(principal) => principal,
+        Err((_, code, message)) => return Err(async_graphql::Error::new(message).extend_with(|_, e| e.set("code", code))),
+    };
+
+    let mut data = database_manager::scope_data(dbm.for_user(principal.user_id));
+    data.insert(principal);
+    Ok(data)
+}
+
+/// Returns the principal of the bearer token in the authorization header,
+/// or an error response if the token is missing or invalid
+async fn authenticate(dbm: &DBManager, headers: &HeaderMap) -> Result<Principal, Response> {
+    let token = bearer_token(headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()));
+    principal(dbm, token.as_deref()).await
+        .map_err(|(status, code, message)| error_response(status, code, message))
+}
+
+/// Returns the token of an authorization value in the `Bearer <token>` format
+fn bearer_token(authorization: Option<&str>) -> Option<String> {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+/// Returns the principal of an api token, or the status code, error code and message to fail the request with
+async fn principal(dbm: &DBManager, token: Option<&str>) -> Result<Principal, (StatusCode, &'static str, &'static str)> {
+    let token = token.ok_or((StatusCode::UNAUTHORIZED, "UNAUTHENTICATED", "a bearer token is required"))?;
+
+    match dbm.authenticate(token).await {
+        Ok(Some(principal)) => Ok(principal),
+        Ok(None) => Err((StatusCode::UNAUTHORIZED, "UNAUTHENTICATED", "invalid api token")),
+        Err(e) => {
+            log::error!("Unable to authenticate request: {}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e.code(), "unable to authenticate request"))
+        }
+    }
+}
+
+/// Returns a graphql response with a single error, sent with the given status code
+fn error_response(status: StatusCode, code: &'static str, message: &str) -> Response {
+    let error = async_graphql::Error::new(message).extend_with(|_, e| e.set("code", code));
+    let mut server_error = ServerError::new(error.message, None);
+    server_error.extensions = error.extensions;
+    let body = GraphQLResponse::from(async_graphql::Response::from_errors(vec![server_error]));
+
+    if status == StatusCode::UNAUTHORIZED {
+        (status, [(header::WWW_AUTHENTICATE, "Bearer")], body).into_response()
+    }
+    else {
+        (status, body).into_response()
+    }
+}
+
+/// Takes a snapshot of the database right away and then every snapshot interval, logging the result of each snapshot
+async fn take_snapshots(snapshots: Snapshots, dbm: DBManager) {
+    let mut interval = time::interval(snapshots.settings().interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        let status = snapshots.take(&dbm).await;
+        match &status.error {
+            None => log::info!("Took snapshot {} of {} bytes in {} ms",
+                status.file.as_deref().unwrap_or_default(), status.size.unwrap_or_default(), status.duration.as_millis()),
+            Some(e) => log::error!("Unable to take a snapshot: {}", e),
+        }
+    }
 }
 
 /// binds graphiql to default url
 async fn graphiql() -> impl IntoResponse {
-    response::Html(GraphiQLSource::build().endpoint("/").finish())
+    response::Html(GraphiQLSource::build().endpoint("/").subscription_endpoint("/ws").finish())
 }
 
 /// handle graceful shutdown, in this case, ctrl+c is only supported graceful shutdown
@@ -69,6 +258,6 @@ async fn shutdown_signal() {
     // are closed when the connection pool is dropped,
     // but there are reports of sqlx not closing sqlite connections
     // when dropped. I'll need to investigate further
-    println!("signal received, starting graceful shutdown");
+    log::info!("signal received, starting graceful shutdown");
 }
 